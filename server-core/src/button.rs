@@ -0,0 +1,54 @@
+/// Contacts settle within this, a level that doesn't last this long is a bounce
+pub const DEBOUNCE_MS: u64 = 20;
+/// Held at least this long it is a long press
+pub const LONG_PRESS_MS: u64 = 3_000;
+/// Held at least this long it is a very long press
+pub const VERY_LONG_PRESS_MS: u64 = 10_000;
+
+/// A press of the button, by how long it was held
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Press {
+    /// Toggles the LED
+    Short,
+    /// Rejoins the Wi-Fi network
+    Long,
+    /// Clears the stored Wi-Fi credentials and reboots into the setup access point
+    VeryLong,
+}
+
+impl Press {
+    /// The press held for `held_ms`, `None` for a bounce shorter than `DEBOUNCE_MS`
+    pub fn classify(held_ms: u64) -> Option<Self> {
+        match held_ms {
+            held if held >= VERY_LONG_PRESS_MS => Some(Press::VeryLong),
+            held if held >= LONG_PRESS_MS => Some(Press::Long),
+            held if held >= DEBOUNCE_MS => Some(Press::Short),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Press::Short => "short",
+            Press::Long => "long",
+            Press::VeryLong => "very long",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_by_duration() {
+        assert_eq!(Press::classify(0), None);
+        assert_eq!(Press::classify(19), None);
+        assert_eq!(Press::classify(20), Some(Press::Short));
+        assert_eq!(Press::classify(2_999), Some(Press::Short));
+        assert_eq!(Press::classify(3_000), Some(Press::Long));
+        assert_eq!(Press::classify(9_999), Some(Press::Long));
+        assert_eq!(Press::classify(10_000), Some(Press::VeryLong));
+        assert_eq!(Press::classify(u64::MAX), Some(Press::VeryLong));
+    }
+}
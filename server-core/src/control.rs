@@ -0,0 +1,235 @@
+//! What the bodies of the control endpoints ask for. Each comes as a form or as a flat JSON object, the fields are
+//! taken out with `http::body_field` so every endpoint reads both the same way.
+
+use {
+    core::str::from_utf8,
+    crate::{http, pattern::Blink, pixel::Rgb},
+};
+
+/// What the LED task is asked to do. On, off and toggle change the manual state, which shows whenever no
+/// pattern does. A Wi-Fi or alert pattern wins over a blink, which wins over the manual state, and a blink ends in
+/// the manual state.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LedCommand {
+    On,
+    Off,
+    Toggle,
+    Pattern(Blink),
+}
+
+impl LedCommand {
+    /// `state` set to `on`, `off`, `toggle` or `blink`, or `on` set to `true` or `false`
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        let body = from_utf8(body).ok()?.trim();
+        if let Some(on) = http::body_field(body, "on") {
+            return flag(on).map(|on| if on { LedCommand::On } else { LedCommand::Off });
+        }
+
+        match http::body_text(body, "state")? {
+            "on" => Some(LedCommand::On),
+            "off" => Some(LedCommand::Off),
+            "toggle" => Some(LedCommand::Toggle),
+            "blink" => Some(LedCommand::Pattern(Blink::IDENTIFY)),
+            _ => None,
+        }
+    }
+}
+
+/// What an output is switched to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Switch {
+    On,
+    Off,
+    Toggle,
+}
+
+impl Switch {
+    /// `on`, `off` or `toggle` as a bare word or in `state`, or `on` set to `true` or `false`
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        let body = from_utf8(body).ok()?.trim();
+        if let Some(on) = http::body_field(body, "on") {
+            return flag(on).map(|on| if on { Switch::On } else { Switch::Off });
+        }
+
+        match http::body_text(body, "state").unwrap_or(body) {
+            "on" => Some(Switch::On),
+            "off" => Some(Switch::Off),
+            "toggle" => Some(Switch::Toggle),
+            _ => None,
+        }
+    }
+}
+
+/// What `POST /api/fan` asks for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FanRequest {
+    Auto,
+    /// Manual at this duty in percent
+    Duty(u8),
+}
+
+impl FanRequest {
+    /// `duty` from 0 to 100, or `mode` set to `auto`
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        let body = from_utf8(body).ok()?.trim();
+        if let Some(duty) = http::body_field(body, "duty") {
+            return duty.parse().ok().filter(|duty| *duty <= 100).map(FanRequest::Duty);
+        }
+
+        auto(body).then_some(FanRequest::Auto)
+    }
+}
+
+/// What `POST /api/servo` asks for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ServoRequest {
+    Auto,
+    /// Manual at this angle, the servo clamps it to its travel
+    Angle(u8),
+}
+
+impl ServoRequest {
+    /// `angle` in degrees, or `mode` set to `auto`. Angles beyond the travel are clamped, not rejected.
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        let body = from_utf8(body).ok()?.trim();
+        if let Some(angle) = http::body_field(body, "angle") {
+            let angle: i32 = angle.parse().ok()?;
+            return Some(ServoRequest::Angle(angle.clamp(0, u8::MAX.into()) as u8));
+        }
+
+        auto(body).then_some(ServoRequest::Auto)
+    }
+}
+
+/// What `POST /api/pixel` asks for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PixelRequest {
+    Auto,
+    Color(Rgb),
+}
+
+impl PixelRequest {
+    /// `color` as `rrggbb` or `#rrggbb`, or `mode` set to `auto`
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        let body = from_utf8(body).ok()?.trim();
+        if let Some(color) = http::body_text(body, "color") {
+            // A form encodes the `#` as `%23`
+            return Rgb::parse(color.strip_prefix("%23").unwrap_or(color)).map(PixelRequest::Color);
+        }
+
+        auto(body).then_some(PixelRequest::Auto)
+    }
+}
+
+/// `mode` set to `auto`, which hands an output back to its own control loop
+fn auto(body: &str) -> bool {
+    http::body_text(body, "mode") == Some("auto")
+}
+
+/// A JSON boolean
+fn flag(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn led_commands() {
+        assert_eq!(LedCommand::from_body(b"state=on"), Some(LedCommand::On));
+        assert_eq!(LedCommand::from_body(b"state=blink"), Some(LedCommand::Pattern(Blink::IDENTIFY)));
+        assert_eq!(LedCommand::from_body(b" {\"on\": false} "), Some(LedCommand::Off));
+        assert_eq!(LedCommand::from_body(b"{\"state\": \"toggle\"}"), Some(LedCommand::Toggle));
+        // Malformed, out of range and missing
+        assert_eq!(LedCommand::from_body(b"{\"on\" true}"), None);
+        assert_eq!(LedCommand::from_body(b"{\"on\": \"true\"}"), None);
+        assert_eq!(LedCommand::from_body(b"{\"on\": 1}"), None);
+        assert_eq!(LedCommand::from_body(b"state=dim"), None);
+        assert_eq!(LedCommand::from_body(b"state=on\xff"), None);
+        assert_eq!(LedCommand::from_body(b"{}"), None);
+        assert_eq!(LedCommand::from_body(b""), None);
+    }
+
+    #[test]
+    fn switches() {
+        assert_eq!(Switch::from_body(b"toggle\r\n"), Some(Switch::Toggle));
+        assert_eq!(Switch::from_body(b"state=off"), Some(Switch::Off));
+        assert_eq!(Switch::from_body(b"name=fan&state=on"), Some(Switch::On));
+        assert_eq!(Switch::from_body(b"{\"on\": true}"), Some(Switch::On));
+        assert_eq!(Switch::from_body(b"{\"state\": \"toggle\"}"), Some(Switch::Toggle));
+        // Malformed, out of range and missing
+        assert_eq!(Switch::from_body(b"{\"on\": yes}"), None);
+        assert_eq!(Switch::from_body(b"{\"on\" true}"), None);
+        assert_eq!(Switch::from_body(b"state=blink"), None);
+        assert_eq!(Switch::from_body(b"ON"), None);
+        assert_eq!(Switch::from_body(b"\xffon"), None);
+        assert_eq!(Switch::from_body(b"state="), None);
+        assert_eq!(Switch::from_body(b"{}"), None);
+        assert_eq!(Switch::from_body(b""), None);
+    }
+
+    #[test]
+    fn fan_requests() {
+        assert_eq!(FanRequest::from_body(b"duty=0"), Some(FanRequest::Duty(0)));
+        assert_eq!(FanRequest::from_body(b"duty=100"), Some(FanRequest::Duty(100)));
+        assert_eq!(FanRequest::from_body(b"{\"duty\": 40}"), Some(FanRequest::Duty(40)));
+        assert_eq!(FanRequest::from_body(b"mode=auto"), Some(FanRequest::Auto));
+        assert_eq!(FanRequest::from_body(b"{\"mode\": \"auto\"}"), Some(FanRequest::Auto));
+        // A duty wins over the mode
+        assert_eq!(FanRequest::from_body(b"{\"mode\": \"auto\", \"duty\": 40}"), Some(FanRequest::Duty(40)));
+        // Malformed, out of range and missing
+        assert_eq!(FanRequest::from_body(b"duty=101"), None);
+        assert_eq!(FanRequest::from_body(b"duty=-1"), None);
+        assert_eq!(FanRequest::from_body(b"{\"duty\": 40.5}"), None);
+        assert_eq!(FanRequest::from_body(b"{\"duty\": \"40\"}"), None);
+        assert_eq!(FanRequest::from_body(b"duty="), None);
+        assert_eq!(FanRequest::from_body(b"mode=manual"), None);
+        assert_eq!(FanRequest::from_body(b"40"), None);
+        assert_eq!(FanRequest::from_body(b"{}"), None);
+        assert_eq!(FanRequest::from_body(b""), None);
+    }
+
+    #[test]
+    fn servo_requests() {
+        assert_eq!(ServoRequest::from_body(b"angle=0"), Some(ServoRequest::Angle(0)));
+        assert_eq!(ServoRequest::from_body(b"angle=180"), Some(ServoRequest::Angle(180)));
+        assert_eq!(ServoRequest::from_body(b"{\"angle\": 45}"), Some(ServoRequest::Angle(45)));
+        assert_eq!(ServoRequest::from_body(b"mode=auto"), Some(ServoRequest::Auto));
+        assert_eq!(ServoRequest::from_body(b"{\"mode\": \"auto\"}"), Some(ServoRequest::Auto));
+        // Out of range is clamped
+        assert_eq!(ServoRequest::from_body(b"angle=-30"), Some(ServoRequest::Angle(0)));
+        assert_eq!(ServoRequest::from_body(b"{\"angle\": 1000}"), Some(ServoRequest::Angle(255)));
+        // Malformed and missing
+        assert_eq!(ServoRequest::from_body(b"angle=45deg"), None);
+        assert_eq!(ServoRequest::from_body(b"{\"angle\": 4.5}"), None);
+        assert_eq!(ServoRequest::from_body(b"angle=99999999999"), None);
+        assert_eq!(ServoRequest::from_body(b"angle="), None);
+        assert_eq!(ServoRequest::from_body(b"mode=sweep"), None);
+        assert_eq!(ServoRequest::from_body(b"{}"), None);
+        assert_eq!(ServoRequest::from_body(b""), None);
+    }
+
+    #[test]
+    fn pixel_requests() {
+        let orange = Some(PixelRequest::Color(Rgb::ORANGE));
+        assert_eq!(PixelRequest::from_body(b"color=ff5000"), orange);
+        assert_eq!(PixelRequest::from_body(b"color=%23FF5000"), orange);
+        assert_eq!(PixelRequest::from_body(b"{\"color\": \"#ff5000\"}"), orange);
+        assert_eq!(PixelRequest::from_body(b"mode=auto"), Some(PixelRequest::Auto));
+        assert_eq!(PixelRequest::from_body(b"{\"mode\": \"auto\"}"), Some(PixelRequest::Auto));
+        // Malformed, out of range and missing
+        assert_eq!(PixelRequest::from_body(b"color=ff50"), None);
+        assert_eq!(PixelRequest::from_body(b"color=ff50000"), None);
+        assert_eq!(PixelRequest::from_body(b"color=gg5000"), None);
+        assert_eq!(PixelRequest::from_body(b"color=%2523ff5000"), None);
+        assert_eq!(PixelRequest::from_body(b"{\"color\": \"#ff5000}"), None);
+        assert_eq!(PixelRequest::from_body(b"mode=rainbow"), None);
+        assert_eq!(PixelRequest::from_body(b"{}"), None);
+        assert_eq!(PixelRequest::from_body(b""), None);
+    }
+}
@@ -0,0 +1,34 @@
+/// CRC-32 (IEEE), bit by bit since it only runs on a hundred bytes at a time
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_continue(0, bytes)
+}
+
+/// The CRC-32 of the bytes behind `previous` followed by `bytes`, for data that comes in pieces like a firmware upload.
+/// `crc32_continue(0, bytes)` is `crc32(bytes)`.
+pub fn crc32_continue(previous: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !previous;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn continues_across_pieces() {
+        assert_eq!(crc32_continue(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+        assert_eq!(crc32_continue(crc32(b"123456789"), b""), 0xcbf4_3926);
+    }
+}
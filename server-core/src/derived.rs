@@ -0,0 +1,192 @@
+use libm::{expf, logf, sqrtf};
+
+/// Magnus coefficients over water (Alduchov and Eskridge), valid from -45 to 60 °C
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+const MAGNUS_MIN_C: f32 = -45.0;
+const MAGNUS_MAX_C: f32 = 60.0;
+
+/// Dew point in °C, `None` outside the range of the Magnus formula or for a humidity of 0
+pub fn dew_point(temperature: f32, humidity: f32) -> Option<f32> {
+    let in_range = (MAGNUS_MIN_C..=MAGNUS_MAX_C).contains(&temperature) && humidity > 0.0 && humidity <= 100.0;
+    if !in_range {
+        return None;
+    }
+
+    let gamma = logf(humidity / 100.0) + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    let dew_point = MAGNUS_B * gamma / (MAGNUS_A - gamma);
+
+    dew_point.is_finite().then_some(dew_point)
+}
+
+/// Lower bound of the NOAA heat index, below it equals the air temperature
+const HEAT_INDEX_MIN_C: f32 = 26.7;
+
+/// NOAA heat index ("feels like") in °C, the algorithm of the Weather Prediction Center's calculator
+pub fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    if temperature < HEAT_INDEX_MIN_C {
+        return temperature;
+    }
+
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+
+    // Steadman's simple formula, used as long as its average with the temperature stays below 80 °F
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let heat_index = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        // Rothfusz regression
+        let mut regression = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+            - 0.224_755_4 * t * rh - 0.006_837_83 * t * t - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh + 0.000_852_82 * t * rh * rh - 0.000_001_99 * t * t * rh * rh;
+
+        // Dry air feels cooler, very humid air hotter than the regression gives
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            regression -= (13.0 - rh) / 4.0 * sqrtf((17.0 - (t - 95.0).abs()) / 17.0);
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            regression += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+
+        regression
+    };
+
+    (heat_index - 32.0) * 5.0 / 9.0
+}
+
+/// Absolute humidity in g/m³ from the saturation vapour pressure (Bolton), within 0.1 % over -30 to 35 °C
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let saturation_hpa = 6.112 * expf(17.67 * temperature / (temperature + 243.5));
+    let vapour_hpa = saturation_hpa * humidity / 100.0;
+
+    // Ideal gas law with the specific gas constant of water vapour, 216.7 = 100 / 461.5 * 1000
+    216.7 * vapour_hpa / (temperature + 273.15)
+}
+
+pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+/// Changes over the trend window smaller than these count as steady, in °C and percentage points
+pub const TEMP_TREND_DEADBAND: f32 = 0.3;
+pub const HUMID_TREND_DEADBAND: f32 = 2.0;
+
+/// Direction a value moved over the trend window
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl Trend {
+    /// A change of exactly `deadband` still counts as steady
+    pub fn classify(change: f32, deadband: f32) -> Self {
+        if change > deadband {
+            Trend::Rising
+        } else if change < -deadband {
+            Trend::Falling
+        } else {
+            Trend::Steady
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Trend::Rising => "rising",
+            Trend::Steady => "steady",
+            Trend::Falling => "falling",
+        }
+    }
+
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Trend::Rising => "▲",
+            Trend::Steady => "▬",
+            Trend::Falling => "▼",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f32, expected: f32, tolerance: f32) -> bool {
+        (actual - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn fahrenheit_conversion() {
+        assert_eq!(celsius_to_fahrenheit(0.0), 32.0);
+        assert_eq!(celsius_to_fahrenheit(100.0), 212.0);
+        assert_eq!(celsius_to_fahrenheit(-40.0), -40.0);
+    }
+
+    #[test]
+    fn dew_point_range() {
+        assert!(close(dew_point(20.0, 50.0).unwrap(), 9.3, 0.1));
+        assert!(close(dew_point(25.0, 100.0).unwrap(), 25.0, 0.01));
+        assert_eq!(dew_point(20.0, 0.0), None);
+        assert_eq!(dew_point(80.0, 50.0), None);
+        assert_eq!(dew_point(20.0, 101.0), None);
+    }
+
+    #[test]
+    fn heat_index_below_thresholds_is_the_temperature() {
+        assert_eq!(heat_index(20.0, 90.0), 20.0);
+        assert_eq!(heat_index(26.6, 30.0), 26.6);
+        assert!(heat_index(32.0, 70.0) > 32.0);
+    }
+
+    #[test]
+    fn heat_index_matches_the_noaa_table() {
+        let celsius = |fahrenheit: f32| (fahrenheit - 32.0) * 5.0 / 9.0;
+        // °F, % and the heat index in °F from the NOAA chart
+        let table = [
+            (82.0, 40.0, 81.0), (82.0, 60.0, 84.0), (82.0, 80.0, 89.0),
+            (84.0, 50.0, 85.0), (84.0, 70.0, 90.0), (84.0, 85.0, 96.0),
+            (86.0, 40.0, 85.0), (86.0, 65.0, 93.0), (86.0, 80.0, 100.0),
+            (90.0, 40.0, 91.0), (90.0, 60.0, 100.0), (90.0, 80.0, 113.0), (90.0, 100.0, 132.0),
+            (96.0, 50.0, 108.0),
+            (100.0, 40.0, 109.0), (100.0, 55.0, 124.0), (100.0, 65.0, 136.0),
+            // Above 85 % between 80 and 87 °F the high humidity adjustment is added
+            (84.0, 90.0, 98.0), (84.0, 100.0, 103.0), (86.0, 95.0, 108.0), (86.0, 100.0, 112.0),
+            // Below 13 % between 80 and 112 °F the low humidity adjustment is subtracted, from the NOAA calculator
+            (95.0, 5.0, 88.2), (100.0, 10.0, 94.1), (104.0, 8.0, 97.2), (90.0, 12.0, 85.7),
+        ];
+        for (temperature, humidity, expected) in table {
+            let actual = heat_index(celsius(temperature), humidity);
+            assert!(close(actual, celsius(expected), 0.5), "{} °F {} %: {} °C, expected {} °C", temperature, humidity, actual, celsius(expected));
+        }
+    }
+
+    #[test]
+    fn absolute_humidity_grows_with_temperature() {
+        assert!(close(absolute_humidity(20.0, 50.0), 8.6, 0.1));
+        assert!(absolute_humidity(30.0, 50.0) > absolute_humidity(20.0, 50.0));
+    }
+
+    #[test]
+    fn trend_deadband_is_steady() {
+        assert_eq!(Trend::classify(0.3, TEMP_TREND_DEADBAND), Trend::Steady);
+        assert_eq!(Trend::classify(0.31, TEMP_TREND_DEADBAND), Trend::Rising);
+        assert_eq!(Trend::classify(-2.5, HUMID_TREND_DEADBAND), Trend::Falling);
+    }
+
+    #[test]
+    fn trend_at_and_around_the_deadband() {
+        // Exactly at the deadband in either direction is still steady
+        assert_eq!(Trend::classify(HUMID_TREND_DEADBAND, HUMID_TREND_DEADBAND), Trend::Steady);
+        assert_eq!(Trend::classify(-HUMID_TREND_DEADBAND, HUMID_TREND_DEADBAND), Trend::Steady);
+        assert_eq!(Trend::classify(-TEMP_TREND_DEADBAND, TEMP_TREND_DEADBAND), Trend::Steady);
+        // Just under it
+        assert_eq!(Trend::classify(0.29, TEMP_TREND_DEADBAND), Trend::Steady);
+        assert_eq!(Trend::classify(-0.29, TEMP_TREND_DEADBAND), Trend::Steady);
+        assert_eq!(Trend::classify(1.99, HUMID_TREND_DEADBAND), Trend::Steady);
+        // Falling past it
+        assert_eq!(Trend::classify(-0.31, TEMP_TREND_DEADBAND), Trend::Falling);
+        assert_eq!(Trend::classify(-2.01, HUMID_TREND_DEADBAND), Trend::Falling);
+        assert_eq!(Trend::classify(0.0, TEMP_TREND_DEADBAND), Trend::Steady);
+    }
+}
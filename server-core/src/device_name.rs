@@ -0,0 +1,66 @@
+use core::{fmt, str::from_utf8};
+
+/// Longest name, the limit DHCP puts on a hostname
+pub const MAX_LEN: usize = 32;
+
+/// The name the device shows to people: the page title and header, the mDNS service instance, the MQTT topics and
+/// the Home Assistant device, the syslog hostname. A single DNS label, so it is safe in all of them: letters,
+/// digits and hyphens, no hyphen at either end, at most `MAX_LEN` characters. Fixed size, so the settings holding
+/// it stay `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DeviceName {
+    bytes: [u8; MAX_LEN],
+    len: u8,
+}
+
+impl DeviceName {
+    /// `None` for anything that isn't such a label, nothing is trimmed
+    pub fn parse(text: &str) -> Option<Self> {
+        let valid = (1..=MAX_LEN).contains(&text.len())
+            && text.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+            && !text.starts_with('-')
+            && !text.ends_with('-');
+        if !valid {
+            return None;
+        }
+
+        let mut bytes = [0; MAX_LEN];
+        bytes[..text.len()].copy_from_slice(text.as_bytes());
+        Some(Self { bytes, len: text.len() as u8 })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever holds ASCII
+        from_utf8(&self.bytes[..usize::from(self.len)]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for DeviceName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for DeviceName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_a_dns_label() {
+        assert_eq!(DeviceName::parse("attic-2").unwrap().as_str(), "attic-2");
+        assert_eq!(DeviceName::parse(&"a".repeat(MAX_LEN)).unwrap().to_string().len(), MAX_LEN);
+        assert_eq!(DeviceName::parse(&"a".repeat(MAX_LEN + 1)), None);
+        assert_eq!(DeviceName::parse(""), None);
+        assert_eq!(DeviceName::parse("-attic"), None);
+        assert_eq!(DeviceName::parse("attic-"), None);
+        assert_eq!(DeviceName::parse("attic 2"), None);
+        assert_eq!(DeviceName::parse("dachböden"), None);
+        assert_eq!(DeviceName::parse("attic.local"), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Text on a 128×64 monochrome display laid out like the SSD1306's memory: eight pages of eight pixel rows, one
+//! byte per column and page with the top row in bit 0. Characters come from a 5×8 font of printable ASCII and `°`.
+
+/// Columns of the display
+pub const WIDTH: usize = 128;
+/// Rows of eight pixels
+pub const PAGES: usize = 8;
+/// Columns a character takes at scale 1, the glyph and one blank column
+pub const CHAR_WIDTH: usize = 6;
+
+/// Column bytes of `' '` to `'~'`, bit 0 on top
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x01, 0x01], // F
+    [0x3e, 0x41, 0x41, 0x51, 0x32], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x04, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x7f, 0x20, 0x18, 0x20, 0x7f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x03, 0x04, 0x78, 0x04, 0x03], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x08, 0x14, 0x54, 0x54, 0x3c], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x00, 0x7f, 0x10, 0x28, 0x44], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+const DEGREE: [u8; 5] = [0x00, 0x06, 0x09, 0x09, 0x06];
+
+/// Shown for characters outside the font
+fn glyph(char: char) -> [u8; 5] {
+    match char {
+        ' '..='~' => FONT[char as usize - ' ' as usize],
+        '°' => DEGREE,
+        _ => FONT['?' as usize - ' ' as usize],
+    }
+}
+
+/// Spreads the bits of a column byte to twice the height, bit `n` to bits `2n` and `2n + 1`
+fn double(column: u8) -> u16 {
+    (0..8).filter(|bit| column & (1 << bit) != 0).fold(0, |doubled, bit| doubled | 0b11 << (2 * bit))
+}
+
+/// A frame to send to the display as it is
+pub struct Framebuffer {
+    bytes: [u8; WIDTH * PAGES],
+}
+
+impl Framebuffer {
+    pub const fn new() -> Self {
+        Self { bytes: [0; WIDTH * PAGES] }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.fill(0);
+    }
+
+    /// Page after page, left to right, as the display takes them in horizontal addressing mode
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        self.bytes[y / 8 * WIDTH + x] & (1 << (y % 8)) != 0
+    }
+
+    fn column(&mut self, x: usize, page: usize, bits: u8) {
+        if x < WIDTH && page < PAGES {
+            self.bytes[page * WIDTH + x] |= bits;
+        }
+    }
+
+    /// Draw `text` from column `x` with its top on `page`, `scale` 2 doubles it to two pages. What doesn't fit is
+    /// cut off. Returns the column after the text.
+    pub fn text(&mut self, x: usize, page: usize, text: &str, scale: usize) -> usize {
+        let scale = scale.clamp(1, 2);
+        let mut x = x;
+        for char in text.chars() {
+            for column in glyph(char) {
+                for _ in 0..scale {
+                    match scale {
+                        1 => self.column(x, page, column),
+                        _ => {
+                            let [top, bottom] = double(column).to_le_bytes();
+                            self.column(x, page, top);
+                            self.column(x, page + 1, bottom);
+                        },
+                    }
+                    x += 1;
+                }
+            }
+            x += scale;
+        }
+        x
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_character_is_drawn_column_by_column() {
+        let mut frame = Framebuffer::new();
+        assert_eq!(frame.text(0, 0, "1", 1), CHAR_WIDTH);
+        assert_eq!(&frame.as_bytes()[..6], [0x00, 0x42, 0x7f, 0x40, 0x00, 0x00]);
+        // The stem of the 1 on every row but the last
+        assert!((0..7).all(|y| frame.is_set(2, y)));
+        assert!(!frame.is_set(2, 7));
+    }
+
+    #[test]
+    fn text_goes_on_the_page_asked_for() {
+        let mut frame = Framebuffer::new();
+        frame.text(6, 3, "AB", 1);
+        assert_eq!(frame.as_bytes()[3 * WIDTH + 6], 0x7e);
+        assert_eq!(frame.as_bytes()[3 * WIDTH + 12], 0x7f);
+        assert_eq!(frame.as_bytes()[..3 * WIDTH].iter().filter(|byte| **byte != 0).count(), 0);
+    }
+
+    #[test]
+    fn scale_two_doubles_both_ways() {
+        let mut frame = Framebuffer::new();
+        assert_eq!(frame.text(0, 0, "-", 2), 2 * CHAR_WIDTH);
+        // The bar of the minus sits on row 3, doubled to rows 6 and 7
+        assert!(frame.is_set(0, 6) && frame.is_set(0, 7) && frame.is_set(9, 7));
+        assert!(!frame.is_set(0, 5) && !frame.is_set(0, 8) && !frame.is_set(10, 6));
+        assert_eq!(double(0x81), 0xc003);
+    }
+
+    #[test]
+    fn text_past_the_edge_is_cut_off() {
+        let mut frame = Framebuffer::new();
+        frame.text(WIDTH - 3, 7, "MM", 1);
+        frame.text(0, 7, "x", 2);
+        assert_eq!(frame.as_bytes()[WIDTH * PAGES - 3], 0x7f);
+    }
+
+    #[test]
+    fn unknown_characters_show_as_question_marks() {
+        assert_eq!(glyph('é'), glyph('?'));
+        assert_eq!(glyph('°'), DEGREE);
+        assert_eq!(glyph('~'), FONT[94]);
+    }
+
+    #[test]
+    fn clear_blanks_the_frame() {
+        let mut frame = Framebuffer::new();
+        frame.text(0, 0, "23.5 °C", 2);
+        frame.clear();
+        assert!(frame.as_bytes().iter().all(|byte| *byte == 0));
+    }
+}
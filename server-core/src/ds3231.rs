@@ -0,0 +1,103 @@
+use crate::calendar::{civil_from_days, days_from_civil};
+
+/// The DS3231's fixed bus address
+pub const ADDRESS: u8 = 0x68;
+/// Seconds, minutes, hours, day of the week, date, month with the century bit and year, all BCD, from here on
+pub const TIME_REGISTER: u8 = 0x00;
+pub const STATUS_REGISTER: u8 = 0x0f;
+/// Status bit set once the oscillator stopped, e.g. after the backup cell ran flat, the time means nothing then
+pub const OSCILLATOR_STOPPED: u8 = 0x80;
+/// 2000-01-01, the chip counts the years 2000 to 2099
+pub const FIRST_UNIX: u64 = 946_684_800;
+/// 2100-01-01, one past the last second the chip holds
+pub const END_UNIX: u64 = 4_102_444_800;
+
+pub type TimeRegisters = [u8; 7];
+
+/// A BCD byte, `None` when a digit is above 9
+fn from_bcd(byte: u8) -> Option<u64> {
+    let (tens, ones) = (byte >> 4, byte & 0x0f);
+    (tens < 10 && ones < 10).then(|| u64::from(tens * 10 + ones))
+}
+
+fn to_bcd(value: u64) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
+/// Unix time held by the time registers, `None` when they don't hold a valid date and time. The century bit is
+/// ignored, every year is taken as 20xx.
+pub fn decode(registers: &TimeRegisters) -> Option<u64> {
+    let seconds = from_bcd(registers[0] & 0x7f).filter(|seconds| *seconds < 60)?;
+    let minutes = from_bcd(registers[1] & 0x7f).filter(|minutes| *minutes < 60)?;
+    // Bit 6 selects the 12 hour mode, bit 5 is PM in it
+    let hours = if registers[2] & 0x40 != 0 {
+        let hours = from_bcd(registers[2] & 0x1f).filter(|hours| (1..=12).contains(hours))?;
+        hours % 12 + if registers[2] & 0x20 != 0 { 12 } else { 0 }
+    } else {
+        from_bcd(registers[2] & 0x3f).filter(|hours| *hours < 24)?
+    };
+    let day = from_bcd(registers[4] & 0x3f)?;
+    let month = from_bcd(registers[5] & 0x1f).filter(|month| (1..=12).contains(month))?;
+    let year = 2000 + from_bcd(registers[6])?;
+
+    // A day past the end of the month comes back as another date
+    let days = days_from_civil(year, month, day.max(1));
+    if day == 0 || civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// The time registers for a Unix time from `FIRST_UNIX` to before `END_UNIX`, in 24 hour mode with Monday as day 1
+pub fn encode(unix: u64) -> TimeRegisters {
+    let (days, secs) = (unix / 86_400, unix % 86_400);
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday
+    let weekday = (days + 3) % 7 + 1;
+    [
+        to_bcd(secs % 60),
+        to_bcd(secs / 60 % 60),
+        to_bcd(secs / 3600),
+        weekday as u8,
+        to_bcd(day),
+        to_bcd(month),
+        to_bcd(year - 2000),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_24_and_12_hour_mode() {
+        // 2026-10-14 13:45:09, a Wednesday
+        let registers = [0x09, 0x45, 0x13, 0x03, 0x14, 0x10, 0x26];
+        assert_eq!(decode(&registers), Some(1_791_985_509));
+        assert_eq!(encode(1_791_985_509), registers);
+
+        let pm = [0x09, 0x45, 0x40 | 0x20 | 0x01, 0x03, 0x14, 0x10, 0x26];
+        assert_eq!(decode(&pm), Some(1_791_985_509));
+        let midnight = [0x00, 0x00, 0x40 | 0x12, 0x03, 0x14, 0x10, 0x26];
+        assert_eq!(decode(&midnight), Some(1_791_936_000));
+    }
+
+    #[test]
+    fn rejects_impossible_values() {
+        assert_eq!(decode(&[0x60, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00]), None);
+        assert_eq!(decode(&[0x00, 0x00, 0x24, 0x01, 0x01, 0x01, 0x00]), None);
+        assert_eq!(decode(&[0x0a, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00]), None);
+        assert_eq!(decode(&[0x00, 0x00, 0x00, 0x01, 0x31, 0x02, 0x26]), None);
+        assert_eq!(decode(&[0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x26]), None);
+        assert_eq!(decode(&[0xff; 7]), None);
+    }
+
+    #[test]
+    fn covers_the_chips_range() {
+        assert_eq!(decode(&encode(FIRST_UNIX)), Some(FIRST_UNIX));
+        assert_eq!(decode(&encode(END_UNIX - 1)), Some(END_UNIX - 1));
+        // 2000-01-01 was a Saturday
+        assert_eq!(encode(FIRST_UNIX)[3], 6);
+    }
+}
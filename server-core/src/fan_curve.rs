@@ -0,0 +1,125 @@
+use {
+    core::fmt,
+    heapless::Vec,
+};
+
+/// Most points of a curve, further ones are rejected
+pub const CURVE_POINTS: usize = 6;
+
+/// Fan duty over temperature: points of °C and percent, linear in between and flat beyond the first and last one.
+/// `24:0,32:100` is off below 24 °C and ramps to full speed at 32 °C.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FanCurve {
+    points: Vec<(f32, u8), CURVE_POINTS>,
+}
+
+impl FanCurve {
+    /// `celsius:percent` pairs separated by commas or spaces, the temperatures rising
+    pub fn parse(spec: &str) -> Result<Self, &'static str> {
+        let mut points = Vec::<(f32, u8), CURVE_POINTS>::new();
+        for point in spec.split([',', ' ']).filter(|point| !point.is_empty()) {
+            let (celsius, percent) = point.split_once(':').ok_or("expected celsius:percent")?;
+            let celsius: f32 = celsius.parse().ok().filter(|celsius: &f32| celsius.is_finite())
+                .ok_or("invalid temperature")?;
+            let percent: u8 = percent.parse().ok().filter(|percent| *percent <= 100).ok_or("duty from 0 to 100 %")?;
+            if points.last().is_some_and(|(last, _)| *last >= celsius) {
+                return Err("temperatures must rise from point to point");
+            }
+            points.push((celsius, percent)).map_err(|_| "too many points")?;
+        }
+        if points.is_empty() {
+            return Err("expected celsius:percent");
+        }
+        Ok(Self { points })
+    }
+
+    /// Duty in percent at a temperature, rounded to the nearest percent
+    pub fn duty(&self, celsius: f32) -> u8 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if celsius <= first.0 {
+            return first.1;
+        }
+        if celsius >= last.0 {
+            return last.1;
+        }
+
+        for pair in self.points.windows(2) {
+            let [(low_c, low_duty), (high_c, high_duty)] = [pair[0], pair[1]];
+            if celsius <= high_c {
+                let share = (celsius - low_c) / (high_c - low_c);
+                let duty = f32::from(low_duty) + share * (f32::from(high_duty) - f32::from(low_duty));
+                return libm::roundf(duty) as u8;
+            }
+        }
+        last.1
+    }
+}
+
+impl fmt::Display for FanCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (celsius, percent)) in self.points.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}:{}", celsius, percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a new target duty is worth applying: a change of at least `min_change` points, so the fan doesn't hunt
+/// around a temperature, or reaching off or full speed, which a small threshold step would otherwise never get to
+pub fn should_change(current: u8, target: u8, min_change: u8) -> bool {
+    current != target && (current.abs_diff(target) >= min_change || target == 0 || target == 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_between_points_and_flat_beyond() {
+        let curve = FanCurve::parse("24:0,32:100").unwrap();
+        assert_eq!(curve.duty(10.0), 0);
+        assert_eq!(curve.duty(24.0), 0);
+        assert_eq!(curve.duty(28.0), 50);
+        assert_eq!(curve.duty(30.0), 75);
+        assert_eq!(curve.duty(32.0), 100);
+        assert_eq!(curve.duty(45.0), 100);
+    }
+
+    #[test]
+    fn several_segments() {
+        let curve = FanCurve::parse("20:20 25:40, 30:100").unwrap();
+        assert_eq!(curve.duty(19.0), 20);
+        assert_eq!(curve.duty(22.5), 30);
+        assert_eq!(curve.duty(27.5), 70);
+        assert_eq!(FanCurve::parse("25:60").unwrap().duty(0.0), 60);
+    }
+
+    #[test]
+    fn bad_curves_are_rejected() {
+        assert!(FanCurve::parse("").is_err());
+        assert!(FanCurve::parse("24").is_err());
+        assert!(FanCurve::parse("24:101").is_err());
+        assert!(FanCurve::parse("warm:50").is_err());
+        assert!(FanCurve::parse("30:50,25:100").is_err());
+        assert!(FanCurve::parse("24:0,24:100").is_err());
+        assert!(FanCurve::parse("1:0,2:0,3:0,4:0,5:0,6:0,7:0").is_err());
+    }
+
+    #[test]
+    fn curve_round_trip() {
+        assert_eq!(FanCurve::parse("24:0, 32.5:100").unwrap().to_string(), "24:0,32.5:100");
+    }
+
+    #[test]
+    fn small_changes_are_held_back() {
+        assert!(!should_change(40, 42, 5));
+        assert!(should_change(40, 45, 5));
+        assert!(should_change(40, 35, 5));
+        assert!(should_change(3, 0, 5));
+        assert!(should_change(97, 100, 5));
+        assert!(!should_change(50, 50, 0));
+    }
+}
@@ -0,0 +1,175 @@
+//! The settings page's form: a bounded `application/x-www-form-urlencoded` parser and the checks a field's value
+//! goes through. What the fields are, what they show and what they change is a table in the firmware.
+
+use {
+    core::fmt,
+    heapless::{String, Vec},
+    crate::{device_name::{self, DeviceName}, http::percent_decode},
+};
+
+/// Fields of one submitted form, more are rejected
+pub const MAX_FIELDS: usize = 16;
+/// Longest decoded value, enough for any number, choice or device name
+pub const VALUE_LEN: usize = device_name::MAX_LEN;
+/// Longest field name
+const NAME_LEN: usize = 24;
+
+/// The decoded fields, in the order they were sent
+pub type Fields<'a> = Vec<(&'a str, String<VALUE_LEN>), MAX_FIELDS>;
+
+/// Why a body isn't taken as a form
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    TooManyFields,
+    /// A name or a decoded value longer than its limit
+    TooLong,
+    /// A broken `%XX` escape or a value that isn't UTF-8
+    BadEncoding,
+}
+
+impl Error {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Error::TooManyFields => "too many fields",
+            Error::TooLong => "a field is too long",
+            Error::BadEncoding => "a field is not properly encoded",
+        }
+    }
+}
+
+/// Split `body` at `&` and decode every value. Names are taken as they are, the form's names need no escapes.
+/// Empty pairs are skipped and a pair without `=` is a name with an empty value.
+pub fn parse(body: &str) -> Result<Fields<'_>, Error> {
+    let mut fields = Fields::new();
+
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if name.len() > NAME_LEN || value.len() > 3 * VALUE_LEN {
+            return Err(Error::TooLong);
+        }
+        // The encoded length only bounds the work, the decoded one is what has to fit
+        let value = match percent_decode::<VALUE_LEN>(value) {
+            Some(value) => value,
+            None if percent_decode::<{ 3 * VALUE_LEN }>(value).is_some() => return Err(Error::TooLong),
+            None => return Err(Error::BadEncoding),
+        };
+        fields.push((name, value)).map_err(|_| Error::TooManyFields)?;
+    }
+
+    Ok(fields)
+}
+
+/// What a field accepts
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    /// A whole number from `min` to `max`
+    Whole { min: u32, max: u32 },
+    /// A number from `min` to `max`
+    Number { min: f32, max: f32 },
+    /// Like `Number`, or empty for none
+    Optional { min: f32, max: f32 },
+    /// One of the words, as they are
+    Choice(&'static [&'static str]),
+    /// A `DeviceName`
+    Name,
+}
+
+/// A value that passed its field's check
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    Whole(u32),
+    Number(f32),
+    Optional(Option<f32>),
+    Choice(&'static str),
+    Name(DeviceName),
+}
+
+impl Kind {
+    /// `text` as this kind of value, surrounding spaces ignored. `None` when it doesn't pass, `Display` says what
+    /// was expected.
+    pub fn check(self, text: &str) -> Option<Value> {
+        let text = text.trim();
+        let number = |min: f32, max: f32| text.parse::<f32>().ok().filter(|value| (min..=max).contains(value));
+
+        match self {
+            Kind::Whole { min, max } => text.parse::<u32>().ok().filter(|value| (min..=max).contains(value)).map(Value::Whole),
+            Kind::Number { min, max } => number(min, max).map(Value::Number),
+            Kind::Optional { .. } if text.is_empty() => Some(Value::Optional(None)),
+            Kind::Optional { min, max } => number(min, max).map(|value| Value::Optional(Some(value))),
+            Kind::Choice(words) => words.iter().find(|word| **word == text).map(|word| Value::Choice(word)),
+            Kind::Name => DeviceName::parse(text).map(Value::Name),
+        }
+    }
+}
+
+/// What the kind expects, for the error banner: `a whole number from 2 to 3600`
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Whole { min, max } => write!(f, "a whole number from {} to {}", min, max),
+            Kind::Number { min, max } => write!(f, "a number from {} to {}", min, max),
+            Kind::Optional { min, max } => write!(f, "a number from {} to {} or nothing", min, max),
+            Kind::Choice(words) => {
+                for (index, word) in words.iter().enumerate() {
+                    let separator = match index {
+                        0 => "",
+                        index if index == words.len() - 1 => " or ",
+                        _ => ", ",
+                    };
+                    write!(f, "{}{}", separator, word)?;
+                }
+                Ok(())
+            },
+            Kind::Name => write!(f, "letters, digits and inner hyphens, at most {} characters", device_name::MAX_LEN),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_fields() {
+        let fields = parse("temp_high=30.5&temp_low=&&unit=c%20f&flag").unwrap();
+        let fields: std::vec::Vec<_> = fields.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        assert_eq!(fields, [("temp_high", "30.5"), ("temp_low", ""), ("unit", "c f"), ("flag", "")]);
+        assert_eq!(parse("").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn bounds_the_fields() {
+        let many = "a=1&".repeat(MAX_FIELDS + 1);
+        assert_eq!(parse(&many), Err(Error::TooManyFields));
+        assert_eq!(parse(&format!("a={}", "1".repeat(VALUE_LEN + 1))), Err(Error::TooLong));
+        assert_eq!(parse(&format!("a={}", "%41".repeat(VALUE_LEN + 1))), Err(Error::TooLong));
+        assert_eq!(parse("a=%4"), Err(Error::BadEncoding));
+        assert_eq!(parse("a=%ff"), Err(Error::BadEncoding));
+        assert_eq!(parse(&format!("a={}", "%41".repeat(VALUE_LEN))).unwrap()[0].1.as_str(), "A".repeat(VALUE_LEN));
+    }
+
+    #[test]
+    fn checks_the_ranges() {
+        let interval = Kind::Whole { min: 2, max: 3600 };
+        assert_eq!(interval.check(" 60 "), Some(Value::Whole(60)));
+        assert_eq!(interval.check("1"), None);
+        assert_eq!(interval.check("2.5"), None);
+        let limit = Kind::Optional { min: -40.0, max: 80.0 };
+        assert_eq!(limit.check(""), Some(Value::Optional(None)));
+        assert_eq!(limit.check("-12.5"), Some(Value::Optional(Some(-12.5))));
+        assert_eq!(limit.check("81"), None);
+        assert_eq!(limit.check("NaN"), None);
+        assert_eq!(Kind::Number { min: -5.0, max: 5.0 }.check(""), None);
+        assert_eq!(Kind::Choice(&["c", "f"]).check("f"), Some(Value::Choice("f")));
+        assert_eq!(Kind::Choice(&["c", "f"]).check("k"), None);
+        assert_eq!(Kind::Name.check(" attic "), DeviceName::parse("attic").map(Value::Name));
+        assert_eq!(Kind::Name.check("attic sensor"), None);
+    }
+
+    #[test]
+    fn describes_the_expectation() {
+        assert_eq!(Kind::Whole { min: 2, max: 3600 }.to_string(), "a whole number from 2 to 3600");
+        assert_eq!(Kind::Optional { min: 0.0, max: 100.0 }.to_string(), "a number from 0 to 100 or nothing");
+        assert_eq!(Kind::Choice(&["c", "f", "both"]).to_string(), "c, f or both");
+    }
+}
@@ -0,0 +1,170 @@
+use {
+    heapless::Deque,
+    crate::reading::Reading,
+};
+
+/// Compact reading stored in the history, temperature and humidity in tenths, 8 bytes per sample
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sample {
+    pub secs_since_boot: u32,
+    pub temp_dc: i16,
+    pub rh_dp: u16,
+}
+
+impl Sample {
+    /// Rounded to the nearest tenth like the live values are displayed, so the history shows what the page showed
+    pub fn new(secs_since_boot: u32, reading: &Reading) -> Self {
+        Self {
+            secs_since_boot,
+            temp_dc: libm::roundf(reading.temperature * 10.0) as i16,
+            rh_dp: libm::roundf(reading.humidity * 10.0) as u16,
+        }
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temp_dc as f32 / 10.0
+    }
+
+    pub fn humidity(&self) -> f32 {
+        self.rh_dp as f32 / 10.0
+    }
+}
+
+/// Ring buffer of the latest `N` samples, the oldest one is dropped on overflow.
+/// Every sample gets a sequence number so readers can walk the buffer without holding the lock.
+pub struct History<const N: usize> {
+    samples: Deque<Sample, N>,
+    next_seq: u32,
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new() -> Self {
+        Self { samples: Deque::new(), next_seq: 0 }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        // Cannot fail, a slot was freed above
+        let _ = self.samples.push_back(sample);
+        self.next_seq = self.next_seq.wrapping_add(1);
+    }
+
+    /// Sequence numbers of the oldest and one past the newest sample
+    pub fn seq_range(&self) -> (u32, u32) {
+        (self.next_seq.wrapping_sub(self.samples.len() as u32), self.next_seq)
+    }
+
+    /// Newest minus oldest temperature and humidity of the samples from the last `window_secs`, a sample exactly
+    /// `window_secs` old included. `None` with fewer than two and until the history reaches back over the whole
+    /// window, a part of it would make a trend from a few seconds of noise.
+    pub fn change_over(&self, window_secs: u32) -> Option<(f32, f32)> {
+        let newest = self.samples.back()?;
+        let start = newest.secs_since_boot.checked_sub(window_secs)?;
+        if self.samples.front()?.secs_since_boot > start {
+            return None;
+        }
+        let oldest = self.samples.iter().find(|sample| sample.secs_since_boot >= start)?;
+
+        if oldest.secs_since_boot == newest.secs_since_boot {
+            return None;
+        }
+
+        Some((newest.temperature() - oldest.temperature(), newest.humidity() - oldest.humidity()))
+    }
+
+    /// Sample with sequence number `seq`, if it hasn't been dropped yet
+    pub fn get(&self, seq: u32) -> Option<Sample> {
+        let (oldest, _) = self.seq_range();
+        self.samples.iter().nth(seq.wrapping_sub(oldest) as usize).copied()
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_round_to_the_nearest_tenth() {
+        let sample = |temperature, humidity| Sample::new(0, &Reading { temperature, humidity });
+        assert_eq!(sample(21.96, 45.04).temp_dc, 220);
+        assert_eq!(sample(21.96, 45.04).rh_dp, 450);
+        assert_eq!(sample(21.94, 45.05).temp_dc, 219);
+        assert_eq!(sample(21.94, 45.06).rh_dp, 451);
+        assert_eq!(sample(-0.05, 0.0).temp_dc, -1);
+        assert_eq!(sample(-0.04, 0.0).temp_dc, 0);
+        assert_eq!(sample(-12.36, 100.0).temp_dc, -124);
+        assert_eq!(sample(-12.36, 100.0).rh_dp, 1000);
+    }
+
+    fn at(secs_since_boot: u32, temperature: f32, humidity: f32) -> Sample {
+        Sample::new(secs_since_boot, &Reading { temperature, humidity })
+    }
+
+    fn close(change: Option<(f32, f32)>, expected: (f32, f32)) -> bool {
+        change.is_some_and(|(temperature, humidity)| {
+            (temperature - expected.0).abs() < 1e-4 && (humidity - expected.1).abs() < 1e-4
+        })
+    }
+
+    #[test]
+    fn changes_need_two_samples() {
+        let mut history = History::<8>::new();
+        assert_eq!(history.change_over(600), None);
+        history.push(at(100, 20.0, 50.0));
+        assert_eq!(history.change_over(600), None);
+        assert_eq!(history.change_over(0), None);
+        history.push(at(105, 20.5, 48.0));
+        assert!(close(history.change_over(5), (0.5, -2.0)));
+    }
+
+    #[test]
+    fn a_window_not_covered_yet_has_no_change() {
+        let mut history = History::<8>::new();
+        history.push(at(10, 20.0, 50.0));
+        history.push(at(15, 21.0, 51.0));
+        history.push(at(20, 22.0, 52.0));
+        assert_eq!(history.change_over(600), None);
+        assert_eq!(history.change_over(11), None);
+        assert!(close(history.change_over(10), (2.0, 2.0)));
+    }
+
+    #[test]
+    fn the_window_starts_at_its_boundary() {
+        let mut history = History::<8>::new();
+        for (secs, temperature) in [(0, 18.0), (100, 19.0), (200, 20.0), (300, 21.0)] {
+            history.push(at(secs, temperature, 50.0));
+        }
+        // A sample exactly `window_secs` old is the oldest one in it
+        assert!(close(history.change_over(200), (2.0, 0.0)));
+        assert!(close(history.change_over(199), (1.0, 0.0)));
+        assert!(close(history.change_over(300), (3.0, 0.0)));
+        assert_eq!(history.change_over(301), None);
+        // Only the newest sample in the window
+        assert_eq!(history.change_over(99), None);
+    }
+
+    #[test]
+    fn wraps_around_on_overflow() {
+        let mut history = History::<3>::new();
+        for secs in 0..5 {
+            history.push(at(secs * 5, 20.0 + secs as f32, 50.0));
+        }
+        // The oldest two are gone, their sequence numbers with them
+        assert_eq!(history.seq_range(), (2, 5));
+        assert_eq!(history.get(1), None);
+        assert_eq!(history.get(2).map(|sample| sample.secs_since_boot), Some(10));
+        assert_eq!(history.get(4).map(|sample| sample.secs_since_boot), Some(20));
+        assert_eq!(history.get(5), None);
+        // The dropped samples no longer cover the window
+        assert!(close(history.change_over(10), (2.0, 0.0)));
+        assert_eq!(history.change_over(11), None);
+    }
+}
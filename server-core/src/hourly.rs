@@ -0,0 +1,275 @@
+use {
+    heapless::Deque,
+    crate::crc::crc32,
+};
+
+/// Magic, sequence number, hour, the six values, the count, two padding bytes and the CRC
+pub const RECORD_SIZE: usize = 32;
+const RECORD_MAGIC: [u8; 4] = *b"DHTH";
+pub const HOURS_PER_DAY: u32 = 24;
+
+/// Minimum, maximum and average of the readings of an hour or a day, in tenths like the history samples
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Summary {
+    pub temp_min_dc: i16,
+    pub temp_max_dc: i16,
+    pub rh_min_dp: u16,
+    pub rh_max_dp: u16,
+    temp_sum_dc: i64,
+    rh_sum_dp: u64,
+    /// Readings it is made of
+    pub count: u32,
+}
+
+impl Summary {
+    pub fn first(temp_dc: i16, rh_dp: u16) -> Self {
+        Self {
+            temp_min_dc: temp_dc,
+            temp_max_dc: temp_dc,
+            rh_min_dp: rh_dp,
+            rh_max_dp: rh_dp,
+            temp_sum_dc: temp_dc.into(),
+            rh_sum_dp: rh_dp.into(),
+            count: 1,
+        }
+    }
+
+    pub fn add(&mut self, temp_dc: i16, rh_dp: u16) {
+        self.merge(&Self::first(temp_dc, rh_dp));
+    }
+
+    /// The averages are weighted by the readings behind each side
+    pub fn merge(&mut self, other: &Self) {
+        self.temp_min_dc = self.temp_min_dc.min(other.temp_min_dc);
+        self.temp_max_dc = self.temp_max_dc.max(other.temp_max_dc);
+        self.rh_min_dp = self.rh_min_dp.min(other.rh_min_dp);
+        self.rh_max_dp = self.rh_max_dp.max(other.rh_max_dp);
+        self.temp_sum_dc += other.temp_sum_dc;
+        self.rh_sum_dp += other.rh_sum_dp;
+        self.count = self.count.saturating_add(other.count);
+    }
+
+    pub fn temp_avg_dc(&self) -> i16 {
+        libm::round(self.temp_sum_dc as f64 / f64::from(self.count.max(1))) as i16
+    }
+
+    pub fn rh_avg_dp(&self) -> u16 {
+        libm::round(self.rh_sum_dp as f64 / f64::from(self.count.max(1))) as u16
+    }
+}
+
+/// One hour of readings, `hour` counts hours since the Unix epoch
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HourRecord {
+    pub hour: u32,
+    pub summary: Summary,
+}
+
+impl HourRecord {
+    /// The count is capped at what two bytes hold, an hour sampled every 2 s is 1800 readings
+    pub fn encode(&self, seq: u32) -> [u8; RECORD_SIZE] {
+        let summary = &self.summary;
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+        record[4..8].copy_from_slice(&seq.to_le_bytes());
+        record[8..12].copy_from_slice(&self.hour.to_le_bytes());
+        record[12..14].copy_from_slice(&summary.temp_min_dc.to_le_bytes());
+        record[14..16].copy_from_slice(&summary.temp_max_dc.to_le_bytes());
+        record[16..18].copy_from_slice(&summary.temp_avg_dc().to_le_bytes());
+        record[18..20].copy_from_slice(&summary.rh_min_dp.to_le_bytes());
+        record[20..22].copy_from_slice(&summary.rh_max_dp.to_le_bytes());
+        record[22..24].copy_from_slice(&summary.rh_avg_dp().to_le_bytes());
+        record[24..26].copy_from_slice(&(summary.count.min(u16::MAX.into()) as u16).to_le_bytes());
+
+        let crc = crc32(&record[..RECORD_SIZE - 4]);
+        record[RECORD_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// The record and its sequence number, `None` for an erased slot, a torn write or another layout
+    pub fn decode(record: &[u8; RECORD_SIZE]) -> Option<(u32, Self)> {
+        if record[..4] != RECORD_MAGIC {
+            return None;
+        }
+
+        let crc = u32::from_le_bytes(record[RECORD_SIZE - 4..].try_into().ok()?);
+        if crc != crc32(&record[..RECORD_SIZE - 4]) {
+            return None;
+        }
+
+        let word = |offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+        let half = |offset: usize| [record[offset], record[offset + 1]];
+        let count = u16::from_le_bytes(half(24));
+        let (temp_avg_dc, rh_avg_dp) = (i16::from_le_bytes(half(16)), u16::from_le_bytes(half(22)));
+        let summary = Summary {
+            temp_min_dc: i16::from_le_bytes(half(12)),
+            temp_max_dc: i16::from_le_bytes(half(14)),
+            rh_min_dp: u16::from_le_bytes(half(18)),
+            rh_max_dp: u16::from_le_bytes(half(20)),
+            // Only the averages are stored, their sums are good enough to weigh them against other hours
+            temp_sum_dc: i64::from(temp_avg_dc) * i64::from(count),
+            rh_sum_dp: u64::from(rh_avg_dp) * u64::from(count),
+            count: count.into(),
+        };
+
+        Some((word(4), Self { hour: word(8), summary }))
+    }
+
+    pub fn day(&self) -> u32 {
+        self.hour / HOURS_PER_DAY
+    }
+}
+
+/// Collects the readings of the hour in progress
+pub struct HourAccumulator {
+    current: Option<HourRecord>,
+}
+
+impl HourAccumulator {
+    pub const fn new() -> Self {
+        Self { current: None }
+    }
+
+    /// Add a reading taken in `hour`, the first one of another hour closes the current one and returns it
+    pub fn add(&mut self, hour: u32, temp_dc: i16, rh_dp: u16) -> Option<HourRecord> {
+        match &mut self.current {
+            Some(current) if current.hour == hour => {
+                current.summary.add(temp_dc, rh_dp);
+                None
+            },
+            current => current.replace(HourRecord { hour, summary: Summary::first(temp_dc, rh_dp) }),
+        }
+    }
+
+    /// The hour in progress
+    pub fn current(&self) -> Option<HourRecord> {
+        self.current
+    }
+}
+
+impl Default for HourAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One UTC day, `day` counts days since the Unix epoch
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Day {
+    pub day: u32,
+    /// Hours with readings
+    pub hours: u8,
+    pub summary: Summary,
+}
+
+/// The latest `N` days with readings, oldest first
+#[derive(Clone)]
+pub struct Days<const N: usize> {
+    days: Deque<Day, N>,
+}
+
+impl<const N: usize> Days<N> {
+    pub const fn new() -> Self {
+        Self { days: Deque::new() }
+    }
+
+    /// Records are expected oldest first, one older than all the days kept is dropped
+    pub fn add(&mut self, record: &HourRecord) {
+        let day = record.day();
+        if let Some(existing) = self.days.iter_mut().rev().find(|existing| existing.day == day) {
+            existing.hours = existing.hours.saturating_add(1);
+            existing.summary.merge(&record.summary);
+            return;
+        }
+        if self.days.back().is_some_and(|newest| newest.day > day) {
+            return;
+        }
+
+        if self.days.is_full() {
+            self.days.pop_front();
+        }
+        // Cannot fail, a slot was freed above
+        let _ = self.days.push_back(Day { day, hours: 1, summary: record.summary });
+    }
+
+    pub fn get(&self, day: u32) -> Option<Day> {
+        self.days.iter().find(|existing| existing.day == day).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Day> {
+        self.days.iter()
+    }
+}
+
+impl<const N: usize> Default for Days<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hour: u32, readings: &[(i16, u16)]) -> HourRecord {
+        let mut summary = Summary::first(readings[0].0, readings[0].1);
+        for (temp_dc, rh_dp) in &readings[1..] {
+            summary.add(*temp_dc, *rh_dp);
+        }
+        HourRecord { hour, summary }
+    }
+
+    #[test]
+    fn records_survive_encoding() {
+        let original = record(489_000, &[(-52, 401), (-48, 415), (-41, 398)]);
+        let (seq, decoded) = HourRecord::decode(&original.encode(7)).unwrap();
+        assert_eq!(seq, 7);
+        assert_eq!(decoded.hour, 489_000);
+        assert_eq!((decoded.summary.temp_min_dc, decoded.summary.temp_max_dc, decoded.summary.temp_avg_dc()), (-52, -41, -47));
+        assert_eq!((decoded.summary.rh_min_dp, decoded.summary.rh_max_dp, decoded.summary.rh_avg_dp()), (398, 415, 405));
+        assert_eq!(decoded.summary.count, 3);
+    }
+
+    #[test]
+    fn damaged_records_are_skipped() {
+        let encoded = record(1, &[(215, 500)]).encode(0);
+        assert!(HourRecord::decode(&[0xff; RECORD_SIZE]).is_none());
+
+        let mut torn = encoded;
+        torn[RECORD_SIZE - 4..].fill(0xff);
+        assert!(HourRecord::decode(&torn).is_none());
+
+        let mut flipped = encoded;
+        flipped[12] ^= 1;
+        assert!(HourRecord::decode(&flipped).is_none());
+    }
+
+    #[test]
+    fn a_new_hour_closes_the_last() {
+        let mut accumulator = HourAccumulator::new();
+        assert_eq!(accumulator.add(10, 200, 500), None);
+        assert_eq!(accumulator.add(10, 220, 520), None);
+
+        let closed = accumulator.add(11, 300, 600).unwrap();
+        assert_eq!(closed, record(10, &[(200, 500), (220, 520)]));
+        assert_eq!(closed.summary.temp_avg_dc(), 210);
+        assert_eq!(accumulator.current(), Some(record(11, &[(300, 600)])));
+    }
+
+    #[test]
+    fn days_weigh_their_hours() {
+        let mut days = Days::<2>::new();
+        days.add(&record(24, &[(100, 400)]));
+        days.add(&record(25, &[(200, 600), (200, 600), (200, 600)]));
+        let first = days.get(1).unwrap();
+        assert_eq!((first.hours, first.summary.count), (2, 4));
+        assert_eq!((first.summary.temp_min_dc, first.summary.temp_max_dc, first.summary.temp_avg_dc()), (100, 200, 175));
+        assert_eq!(first.summary.rh_avg_dp(), 550);
+
+        // The oldest day makes room, a record older than every day kept is dropped
+        days.add(&record(48, &[(0, 0)]));
+        days.add(&record(72, &[(0, 0)]));
+        days.add(&record(30, &[(0, 0)]));
+        assert_eq!(days.iter().map(|day| day.day).collect::<Vec<_>>(), [2, 3]);
+    }
+}
@@ -0,0 +1,619 @@
+use {
+    core::{fmt::Write, str::from_utf8},
+    heapless::{String, Vec},
+};
+
+/// Largest header block a response can produce
+pub const HEAD_SIZE: usize = 384;
+const MAX_HEADERS: usize = 8;
+const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+/// HTTP request methods understood by the server
+#[derive(Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Unknown,
+}
+
+impl Method {
+    pub fn parse(method: &str) -> Self {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => Method::Unknown,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Unknown => "?",
+        }
+    }
+}
+
+/// Protocol versions the server speaks, echoed in the status line
+#[derive(Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        match version {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// Response status codes used by the server
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    NoContent,
+    SeeOther,
+    NotModified,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    TooManyRequests,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::NoContent => 204,
+            Status::SeeOther => 303,
+            Status::NotModified => 304,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::PayloadTooLarge => 413,
+            Status::TooManyRequests => 429,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::NoContent => "No Content",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+
+    /// A 204 or 304 carries neither a body nor the headers describing one
+    fn has_body(self) -> bool {
+        !matches!(self, Status::NoContent | Status::NotModified)
+    }
+}
+
+/// Status line and headers of a response, the body is written separately
+pub struct Response<'a> {
+    status: Status,
+    headers: Vec<(&'a str, &'a str), MAX_HEADERS>,
+    body_len: Option<usize>,
+    overflow: bool,
+}
+
+impl<'a> Response<'a> {
+    pub fn new(status: Status) -> Self {
+        Self { status, headers: Vec::new(), body_len: None, overflow: false }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(Status::NotFound).header("Content-Type", "text/html")
+    }
+
+    /// Post/Redirect/Get: the browser follows up with a GET of `location`
+    pub fn redirect(location: &'a str) -> Self {
+        Self::new(Status::SeeOther).header("Location", location)
+    }
+
+    pub fn json() -> Self {
+        Self::new(Status::Ok).header("Content-Type", "application/json")
+    }
+
+    pub fn text(status: Status) -> Self {
+        Self::new(status).header("Content-Type", "text/plain")
+    }
+
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        self.overflow |= self.headers.push((name, value)).is_err();
+        self
+    }
+
+    /// Length of the body, without one the body runs until the connection closes
+    pub fn body_len(mut self, len: usize) -> Self {
+        self.body_len = Some(len);
+        self
+    }
+
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Whether the connection can take another request after this response: the client asked for it and can tell
+    /// where the body ends. A body without a length only ends when the connection closes.
+    pub fn keeps_alive(&self, requested: bool) -> bool {
+        requested && (self.body_len.is_some() || !self.status.has_body())
+    }
+
+    /// Complete header block including the blank line, `None` when it doesn't fit
+    pub fn head(&self, version: Version) -> Option<String<HEAD_SIZE>> {
+        if self.overflow {
+            return None;
+        }
+
+        let mut head = String::new();
+
+        write!(&mut head, "{} {} {}\r\n", version.as_str(), self.status.code(), self.status.reason()).ok()?;
+
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("content-type") && !self.status.has_body() {
+                continue;
+            }
+
+            write!(&mut head, "{}: {}\r\n", name, value).ok()?;
+        }
+
+        if let Some(len) = self.body_len.filter(|_| self.status.has_body()) {
+            write!(&mut head, "Content-Length: {}\r\n", len).ok()?;
+        }
+
+        head.push_str("\r\n").ok()?;
+
+        Some(head)
+    }
+}
+
+/// Parsed HTTP request head
+pub struct Request<'a> {
+    pub method: Method,
+    pub version: Version,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub keep_alive: bool,
+    pub authorization: Option<&'a str>,
+    pub if_none_match: Option<&'a str>,
+    pub accepts_gzip: bool,
+    /// From the `Content-Length` header, `body` holds no more than fit the request buffer
+    pub content_length: usize,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Parse the request line into method and path, the query string is stripped.
+    /// Persistence defaults to the protocol version and can be overridden by a `Connection` header.
+    pub fn parse(head: &'a str, body: &'a [u8]) -> Option<Self> {
+        let mut lines = head.lines();
+        let request_line = lines.next()?;
+        let mut parts = request_line.split_whitespace();
+        let method = Method::parse(parts.next()?);
+        let target = parts.next()?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let version = Version::parse(parts.next()?)?;
+        // HTTP/1.1 connections are persistent by default, HTTP/1.0 ones only on request
+        let mut keep_alive = version == Version::Http11;
+        let mut authorization = None;
+        let mut if_none_match = None;
+        let mut accepts_gzip = false;
+        let mut content_length = 0;
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let name = name.trim();
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("connection") {
+                if value.eq_ignore_ascii_case("close") {
+                    keep_alive = false;
+                } else if value.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value);
+            } else if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value);
+            } else if name.eq_ignore_ascii_case("accept-encoding") {
+                accepts_gzip = accepts_encoding(value, "gzip");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            }
+        }
+
+        Some(Self { method, version, path, query, keep_alive, authorization, if_none_match, accepts_gzip, content_length, body })
+    }
+
+    /// Whether the client already holds the representation identified by `etag`
+    pub fn etag_matches(&self, etag: &str) -> bool {
+        let Some(if_none_match) = self.if_none_match else {
+            return false;
+        };
+
+        if_none_match.trim() == "*" || if_none_match
+            .split(',')
+            .map(|candidate| candidate.trim())
+            .any(|candidate| candidate.strip_prefix("W/").unwrap_or(candidate) == etag)
+    }
+
+    /// Iterate over `key=value` pairs of the query string, a missing `=` gives an empty value
+    pub fn query_params(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+    }
+
+    /// Value of the first query parameter named `key`
+    pub fn query_param(&self, key: &str) -> Option<&'a str> {
+        self.query_params()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the whole body came with the request buffer. Otherwise the rest is still in the socket and the
+    /// connection can't take another request unless the handler reads it all, like a firmware upload.
+    pub fn body_complete(&self) -> bool {
+        self.body.len() >= self.content_length
+    }
+}
+
+/// Whether an `Accept-Encoding` value lists `encoding` without disabling it through `q=0`
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|item| {
+        let mut params = item.split(';').map(|param| param.trim());
+        let name = params.next().unwrap_or("");
+        let disabled = params.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+
+        name.eq_ignore_ascii_case(encoding) && !disabled
+    })
+}
+
+/// Position right after the header terminator, searching only the bytes that may contain
+/// a terminator completed by the latest read
+pub fn find_header_end(buf: &[u8], new_data_start: usize) -> Option<usize> {
+    let search_start = new_data_start.saturating_sub(HEADER_TERMINATOR.len() - 1);
+
+    buf[search_start..]
+        .windows(HEADER_TERMINATOR.len())
+        .position(|window| window == HEADER_TERMINATOR)
+        .map(|pos| search_start + pos + HEADER_TERMINATOR.len())
+}
+
+/// Value of the `Content-Length` header in a raw request head, 0 when absent or invalid
+pub fn content_length(head: &[u8]) -> usize {
+    let Ok(head) = from_utf8(head) else {
+        return 0;
+    };
+
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Raw value of field `name` in an `application/x-www-form-urlencoded` body, still percent-encoded
+pub fn form_field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Decode `+` and `%XX` escapes of a form value, `None` for a broken escape, invalid UTF-8 or more than `N` bytes
+pub fn percent_decode<const N: usize>(value: &str) -> Option<String<N>> {
+    let mut bytes = Vec::<u8, N>::new();
+    let mut input = value.bytes();
+
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                (high * 16 + low) as u8
+            },
+            byte => byte,
+        };
+        bytes.push(decoded).ok()?;
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Value of field `name` in a flat `{"name": value}` JSON body or a `name=value` form body
+pub fn body_field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    if let Some(json) = body.strip_prefix('{') {
+        return json.split(',').find_map(|pair| {
+            let (key, value) = pair.split_once(':')?;
+            (key.trim().strip_prefix('"')?.strip_suffix('"')? == name).then(|| value.trim().trim_end_matches('}').trim_end())
+        });
+    }
+
+    form_field(body, name)
+}
+
+/// Value of field `name` like `body_field`, a JSON string without its quotes. Escapes inside it and the
+/// percent-encoding of a form value are left as they are.
+pub fn body_text<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let value = body_field(body, name)?;
+    Some(value.strip_prefix('"').and_then(|text| text.strip_suffix('"')).unwrap_or(value))
+}
+
+/// Displays a string with `&`, `<`, `>` and quotes escaped for HTML text and attribute values
+pub struct HtmlStr<'a>(pub &'a str);
+
+impl core::fmt::Display for HtmlStr<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays a string with the escapes a JSON string literal needs, for values that don't come from the firmware
+pub struct JsonStr<'a>(pub &'a str);
+
+impl core::fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str) -> Request<'_> {
+        Request::parse(head, b"").expect("valid request")
+    }
+
+    #[test]
+    fn request_line_splits_path_and_query() {
+        let request = parse("GET /api/sensor?unit=f&id=1 HTTP/1.1\r\nHost: pico\r\n\r\n");
+        assert!(request.method == Method::Get);
+        assert!(request.version == Version::Http11);
+        assert_eq!(request.path, "/api/sensor");
+        assert_eq!(request.query, "unit=f&id=1");
+        assert_eq!(request.query_param("unit"), Some("f"));
+        assert_eq!(request.query_param("id"), Some("1"));
+        assert_eq!(request.query_param("missing"), None);
+    }
+
+    #[test]
+    fn query_param_without_value_is_empty_and_first_one_wins() {
+        let request = parse("GET /?flag&unit=c&unit=f HTTP/1.1\r\n\r\n");
+        assert_eq!(request.query_param("flag"), Some(""));
+        assert_eq!(request.query_param("unit"), Some("c"));
+    }
+
+    #[test]
+    fn malformed_request_lines_are_rejected() {
+        assert!(Request::parse("", b"").is_none());
+        assert!(Request::parse("GET\r\n\r\n", b"").is_none());
+        assert!(Request::parse("GET /\r\n\r\n", b"").is_none());
+        assert!(Request::parse("GET / HTTP/2\r\n\r\n", b"").is_none());
+    }
+
+    #[test]
+    fn unknown_method_still_parses() {
+        assert!(parse("BREW /pot HTTP/1.1\r\n\r\n").method == Method::Unknown);
+    }
+
+    #[test]
+    fn keep_alive_follows_version_and_connection_header() {
+        assert!(parse("GET / HTTP/1.1\r\n\r\n").keep_alive);
+        assert!(!parse("GET / HTTP/1.0\r\n\r\n").keep_alive);
+        assert!(!parse("GET / HTTP/1.1\r\nConnection: close\r\n\r\n").keep_alive);
+        assert!(parse("GET / HTTP/1.0\r\nconnection: Keep-Alive\r\n\r\n").keep_alive);
+    }
+
+    #[test]
+    fn headers_after_the_blank_line_are_ignored() {
+        let request = parse("GET / HTTP/1.1\r\n\r\nAuthorization: Basic abc\r\n");
+        assert_eq!(request.authorization, None);
+    }
+
+    #[test]
+    fn header_names_are_case_insensitive_and_values_trimmed() {
+        let request = parse("GET / HTTP/1.1\r\nAUTHORIZATION:   Bearer token  \r\nIf-None-Match: \"1\"\r\n\r\n");
+        assert_eq!(request.authorization, Some("Bearer token"));
+        assert_eq!(request.if_none_match, Some("\"1\""));
+    }
+
+    #[test]
+    fn etag_matches_lists_weak_tags_and_wildcard() {
+        let request = parse("GET / HTTP/1.1\r\nIf-None-Match: \"a\", W/\"b\"\r\n\r\n");
+        assert!(request.etag_matches("\"a\""));
+        assert!(request.etag_matches("\"b\""));
+        assert!(!request.etag_matches("\"c\""));
+        assert!(parse("GET / HTTP/1.1\r\nIf-None-Match: *\r\n\r\n").etag_matches("\"c\""));
+        assert!(!parse("GET / HTTP/1.1\r\n\r\n").etag_matches("\"a\""));
+    }
+
+    #[test]
+    fn gzip_is_accepted_unless_disabled() {
+        assert!(accepts_encoding("gzip, deflate, br", "gzip"));
+        assert!(accepts_encoding("deflate;q=0.5, GZIP;q=0.8", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0", "gzip"));
+        assert!(!accepts_encoding("gzip; q=0.000", "gzip"));
+        assert!(!accepts_encoding("br, x-gzip", "gzip"));
+        assert!(parse("GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").accepts_gzip);
+    }
+
+    #[test]
+    fn header_end_found_across_reads() {
+        let buf = b"GET / HTTP/1.1\r\nHost: pico\r\n\r\nbody";
+        assert_eq!(find_header_end(buf, 0), Some(buf.len() - 4));
+        // The terminator was completed by a read that started in its middle
+        assert_eq!(find_header_end(buf, buf.len() - 6), Some(buf.len() - 4));
+        assert_eq!(find_header_end(b"GET / HTTP/1.1\r\n\r", 0), None);
+    }
+
+    #[test]
+    fn content_length_defaults_to_zero() {
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\ncontent-length: 12\r\n\r\n"), 12);
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), 0);
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\n\r\n"), 0);
+        assert_eq!(content_length(&[0xff, 0xfe]), 0);
+        assert_eq!(parse("POST /update HTTP/1.1\r\nContent-Length: 401234\r\n\r\n").content_length, 401_234);
+        assert_eq!(parse("POST /update HTTP/1.1\r\n\r\n").content_length, 0);
+    }
+
+    #[test]
+    fn body_beyond_the_buffer_is_incomplete() {
+        let head = "POST /update HTTP/1.1\r\nContent-Length: 4\r\n\r\n";
+        assert!(Request::parse(head, b"abcd").unwrap().body_complete());
+        assert!(!Request::parse(head, b"ab").unwrap().body_complete());
+        assert!(!Request::parse(head, b"").unwrap().body_complete());
+        assert!(parse("GET / HTTP/1.1\r\n\r\n").body_complete());
+    }
+
+    #[test]
+    fn head_ends_with_blank_line() {
+        let head = Response::json().body_len(2).head(Version::Http11).unwrap();
+        assert_eq!(head.as_str(), "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n");
+    }
+
+    #[test]
+    fn bodiless_statuses_drop_content_headers() {
+        let head = Response::json().with_status(Status::NotModified).body_len(2).head(Version::Http10).unwrap();
+        assert_eq!(head.as_str(), "HTTP/1.0 304 Not Modified\r\n\r\n");
+    }
+
+    #[test]
+    fn connection_closes_after_a_body_without_length() {
+        assert!(Response::json().body_len(2).keeps_alive(true));
+        assert!(!Response::json().body_len(2).keeps_alive(false));
+        // Streamed like `/api/history` and `/history.csv`, whatever the client asked for
+        assert!(!Response::json().keeps_alive(true));
+        assert!(!Response::new(Status::Ok).header("Content-Type", "text/csv").keeps_alive(true));
+        assert!(Response::new(Status::NotModified).keeps_alive(true));
+        assert!(Response::new(Status::NoContent).keeps_alive(true));
+    }
+
+    #[test]
+    fn too_many_headers_give_no_head() {
+        let mut response = Response::new(Status::Ok);
+        for _ in 0..=MAX_HEADERS {
+            response = response.header("X-Test", "1");
+        }
+        assert!(response.head(Version::Http11).is_none());
+    }
+
+    #[test]
+    fn form_and_json_fields() {
+        assert_eq!(form_field("ssid=home&password=a%20b", "password"), Some("a%20b"));
+        assert_eq!(form_field("ssid=home", "password"), None);
+        assert_eq!(body_field("{\"sample_interval_s\": 10, \"wifi_pm\": \"none\"}", "wifi_pm"), Some("\"none\""));
+        assert_eq!(body_field("{\"temp_offset\":-0.5}", "temp_offset"), Some("-0.5"));
+        assert_eq!(body_field("temp_offset=1.5", "temp_offset"), Some("1.5"));
+    }
+
+    #[test]
+    fn unquoted_body_values() {
+        assert_eq!(body_text("{\"mode\": \"auto\", \"duty\": 40}", "mode"), Some("auto"));
+        assert_eq!(body_text("{\"mode\": \"auto\", \"duty\": 40}", "duty"), Some("40"));
+        assert_eq!(body_text("mode=auto", "mode"), Some("auto"));
+        assert_eq!(body_text("{\"mode\": \"auto}", "mode"), Some("\"auto"));
+        assert_eq!(body_text("{\"mode\" \"auto\"}", "mode"), None);
+        assert_eq!(body_text("{\"duty\": 40}", "mode"), None);
+        assert_eq!(body_text("", "mode"), None);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode::<16>("a+b%21%C3%A9").as_deref(), Some("a b!é"));
+        assert_eq!(percent_decode::<16>("%2"), None);
+        assert_eq!(percent_decode::<16>("%zz"), None);
+        assert_eq!(percent_decode::<16>("%ff"), None);
+        assert_eq!(percent_decode::<2>("abc"), None);
+    }
+
+    #[test]
+    fn escapers() {
+        assert_eq!(format!("{}", HtmlStr("<a href=\"x\">Tom & Jerry's</a>")), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(format!("{}", JsonStr("say \"hi\"\\\n")), "say \\\"hi\\\"\\\\\\u000a");
+    }
+}
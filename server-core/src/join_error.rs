@@ -0,0 +1,59 @@
+use core::fmt;
+
+/// What the CYW43 firmware's join status codes mean, indexed by the status
+const CYW43_JOIN_ERROR: [&str; 16] = [
+    "Success",
+    "Operation failed",
+    "Operation timed out",
+    "Operation no matching network found",
+    "Operation was aborted",
+    "[Protocol Failure] Packet not acknowledged",
+    "AUTH or ASSOC packet was unsolicited",
+    "Attempt to ASSOC to an auto auth configuration",
+    "Scan results are incomplete",
+    "Scan aborted by another scan",
+    "Scan aborted due to assoc in progress",
+    "802.11h quiet period started",
+    "User disabled scanning (WLC_SET_SCANSUPPRESS)",
+    "No allowable channels to scan",
+    "Scan aborted due to CCX fast roam",
+    "Abort channel select",
+];
+
+/// The description of a join status, `unknown join status 42` past the table
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JoinErrorMessage {
+    Known(&'static str),
+    Unknown(u32),
+}
+
+impl fmt::Display for JoinErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinErrorMessage::Known(message) => f.write_str(message),
+            JoinErrorMessage::Unknown(status) => write!(f, "unknown join status {}", status),
+        }
+    }
+}
+
+/// Looked up without indexing, any status the firmware comes up with gets a message
+pub fn join_error_message(status: u32) -> JoinErrorMessage {
+    match usize::try_from(status).ok().and_then(|index| CYW43_JOIN_ERROR.get(index)) {
+        Some(message) => JoinErrorMessage::Known(message),
+        None => JoinErrorMessage::Unknown(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_every_status() {
+        assert_eq!(join_error_message(0).to_string(), "Success");
+        assert_eq!(join_error_message(1).to_string(), "Operation failed");
+        assert_eq!(join_error_message(15).to_string(), "Abort channel select");
+        assert_eq!(join_error_message(16).to_string(), "unknown join status 16");
+        assert_eq!(join_error_message(u32::MAX).to_string(), "unknown join status 4294967295");
+    }
+}
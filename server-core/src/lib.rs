@@ -0,0 +1,37 @@
+//! Request parsing, routing, response building, page templates, the settings form, the bodies of the control
+//! endpoints, the throttled, filtered, calibrated and smoothed sensor reads, the derived values, the fan curve, the
+//! servo pulses, the device name, the display's text rendering, the cyw43 join statuses, the LED and buzzer
+//! patterns, the status pixel's colors, the button presses, the history samples, the hourly records and daily
+//! summaries, the DS3231 registers, the calendar, the log filter, the MAC address format, the MQTT packets, the USB
+//! shell parser, the firmware update records and the uptime format of the server.
+//! Nothing in here touches the hardware, so it builds for the host as well and its tests run with a plain
+//! `cargo test`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod button;
+pub mod calendar;
+pub mod control;
+pub mod crc;
+pub mod derived;
+pub mod device_name;
+pub mod display;
+pub mod ds3231;
+pub mod fan_curve;
+pub mod form;
+pub mod hourly;
+pub mod history;
+pub mod http;
+pub mod join_error;
+pub mod log_filter;
+pub mod mac;
+pub mod mqtt_packet;
+pub mod ota;
+pub mod pattern;
+pub mod pixel;
+pub mod reading;
+pub mod route;
+pub mod servo;
+pub mod shell;
+pub mod template;
+pub mod uptime;
@@ -0,0 +1,165 @@
+use {
+    core::{fmt, str::FromStr},
+    heapless::{String, Vec},
+    log::{Level, LevelFilter},
+};
+
+/// Most module rules at a time, further ones are rejected
+pub const MODULE_RULES: usize = 4;
+/// Longest module prefix of a rule
+pub const MODULE_LEN: usize = 32;
+
+/// Lowest level to log in general and per module prefix, e.g. `access=warn` hides the access log below warnings and
+/// `mqtt=debug` shows the MQTT debug lines while everything else stays at the general level
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogFilter {
+    pub level: LevelFilter,
+    modules: Vec<(String<MODULE_LEN>, LevelFilter), MODULE_RULES>,
+}
+
+impl LogFilter {
+    pub const fn new(level: LevelFilter) -> Self {
+        Self { level, modules: Vec::new() }
+    }
+
+    /// Replace the module rules with `prefix=level` pairs separated by commas or spaces, nothing is changed on error
+    pub fn set_modules(&mut self, spec: &str) -> Result<(), &'static str> {
+        let mut modules = Vec::new();
+        for rule in spec.split([',', ' ']).filter(|rule| !rule.is_empty()) {
+            let (prefix, level) = rule.split_once('=').ok_or("expected module=level")?;
+            let prefix = String::try_from(prefix.trim_end_matches("::")).map_err(|()| "module name too long")?;
+            if prefix.is_empty() {
+                return Err("expected module=level");
+            }
+            let level = parse_level(level).ok_or("unknown level")?;
+            modules.push((prefix, level)).map_err(|_| "too many module rules")?;
+        }
+        self.modules = modules;
+        Ok(())
+    }
+
+    /// Level for a log target, from the rule with the longest prefix of whole path segments matching it
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| match target.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// Highest level any target logs at, what `log::set_max_level` needs so the macros let those lines through
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.level, Ord::max)
+    }
+
+    /// The module rules as accepted by `set_modules`, e.g. `access=warn,mqtt=debug`
+    pub fn modules(&self) -> Modules<'_> {
+        Modules(self)
+    }
+}
+
+/// Displays the module rules of a filter, empty without any
+pub struct Modules<'a>(&'a LogFilter);
+
+impl fmt::Display for Modules<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (prefix, level)) in self.0.modules.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", prefix, as_str(*level))?;
+        }
+        Ok(())
+    }
+}
+
+/// `off`, `error`, `warn`, `info`, `debug` or `trace`, in any case
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    LevelFilter::from_str(level.trim()).ok()
+}
+
+/// Lower case name of a level, as accepted by `parse_level`
+pub fn as_str(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(level: LevelFilter, modules: &str) -> LogFilter {
+        let mut filter = LogFilter::new(level);
+        filter.set_modules(modules).unwrap();
+        filter
+    }
+
+    #[test]
+    fn general_level_without_rules() {
+        let filter = LogFilter::new(LevelFilter::Info);
+        assert!(filter.enabled("mqtt", Level::Info));
+        assert!(filter.enabled("mqtt", Level::Error));
+        assert!(!filter.enabled("mqtt", Level::Debug));
+        assert_eq!(filter.max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn a_rule_lowers_or_raises_its_modules_only() {
+        let filter = filter(LevelFilter::Info, "access=warn, mqtt=debug");
+        assert!(!filter.enabled("access", Level::Info));
+        assert!(filter.enabled("access", Level::Warn));
+        assert!(filter.enabled("mqtt::packet", Level::Debug));
+        assert!(!filter.enabled("router", Level::Debug));
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments_and_the_longest_wins() {
+        let filter = filter(LevelFilter::Info, "mqtt=off,mqtt::packet=trace");
+        assert_eq!(filter.level_for("mqtt"), LevelFilter::Off);
+        assert_eq!(filter.level_for("mqtt::packet"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("mqtt_packet"), LevelFilter::Info);
+        assert_eq!(filter.level_for("mq"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn modules_round_trip() {
+        let filter = filter(LevelFilter::Info, "access=WARN mqtt::=debug");
+        assert_eq!(filter.modules().to_string(), "access=warn,mqtt=debug");
+        assert_eq!(LogFilter::new(LevelFilter::Info).modules().to_string(), "");
+    }
+
+    #[test]
+    fn bad_rules_leave_the_filter_unchanged() {
+        let mut filter = filter(LevelFilter::Info, "access=warn");
+        let before = filter.clone();
+        assert!(filter.set_modules("access").is_err());
+        assert!(filter.set_modules("=debug").is_err());
+        assert!(filter.set_modules("mqtt=loud").is_err());
+        assert!(filter.set_modules("a=info,b=info,c=info,d=info,e=info").is_err());
+        assert_eq!(filter, before);
+        filter.set_modules("").unwrap();
+        assert_eq!(filter.max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn levels_parse_in_any_case() {
+        assert_eq!(parse_level("Debug"), Some(LevelFilter::Debug));
+        assert_eq!(parse_level(" off "), Some(LevelFilter::Off));
+        assert_eq!(parse_level("verbose"), None);
+        assert_eq!(as_str(LevelFilter::Trace), "trace");
+    }
+}
@@ -0,0 +1,64 @@
+use {
+    core::fmt::{self, Write},
+    heapless::String,
+};
+
+/// `picow_` and the twelve hex digits
+pub const DEVICE_ID_LEN: usize = 6 + 12;
+
+/// Formats as `aa:bb:cc:dd:ee:ff`, or as `aabbccddeeff` with `{:#}` for ids
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 && !f.alternate() {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl MacAddress {
+    /// `<hostname>-<mac>`, unique even when several boards share a hostname. `None` when it doesn't fit `N`.
+    pub fn client_id<const N: usize>(self, hostname: &str) -> Option<String<N>> {
+        let mut id = String::new();
+        write!(&mut id, "{}-{:#}", hostname, self).ok()?;
+        Some(id)
+    }
+
+    /// `picow_<mac>`, the Home Assistant device. Its entities' unique ids append their own suffix.
+    pub fn device_id(self) -> String<DEVICE_ID_LEN> {
+        let mut id = String::new();
+        // Always fits, the length is fixed
+        let _ = write!(&mut id, "picow_{:#}", self);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddress = MacAddress([0x28, 0xcd, 0xc1, 0x0a, 0x00, 0xff]);
+
+    #[test]
+    fn formats_with_and_without_colons() {
+        assert_eq!(MAC.to_string(), "28:cd:c1:0a:00:ff");
+        assert_eq!(format!("{:#}", MAC), "28cdc10a00ff");
+        // Leading zeros are kept in every byte
+        assert_eq!(MacAddress([0, 1, 2, 3, 4, 5]).to_string(), "00:01:02:03:04:05");
+        assert_eq!(format!("{:#}", MacAddress([0; 6])), "000000000000");
+    }
+
+    #[test]
+    fn derives_the_ids() {
+        assert_eq!(MAC.client_id::<32>("dht22-server").as_deref(), Some("dht22-server-28cdc10a00ff"));
+        assert_eq!(MAC.client_id::<24>("dht22-server"), None);
+        assert_eq!(MAC.device_id().as_str(), "picow_28cdc10a00ff");
+        assert_eq!(MacAddress([0, 0, 0, 0, 0, 1]).device_id().as_str(), "picow_000000000001");
+    }
+}
@@ -0,0 +1,250 @@
+/// MQTT 3.1.1 packet types the publisher sends or expects, it only publishes at QoS 0
+pub const CONNECT: u8 = 1;
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PINGREQ: u8 = 12;
+pub const PINGRESP: u8 = 13;
+
+/// Largest value the four byte remaining length can carry
+pub const MAX_REMAINING_LENGTH: usize = 268_435_455;
+const PROTOCOL_NAME: &[u8] = b"MQTT";
+const PROTOCOL_LEVEL: u8 = 4;
+const CLEAN_SESSION: u8 = 0x02;
+const WILL_FLAG: u8 = 0x04;
+const WILL_RETAIN: u8 = 0x20;
+const RETAIN: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The packet doesn't fit into the buffer
+    BufferTooSmall,
+    /// A remaining length longer than four bytes, or a field longer than 65535 bytes
+    Malformed,
+}
+
+/// Message the broker publishes when the client goes away without a DISCONNECT
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+/// First bytes of every packet
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FixedHeader {
+    pub packet_type: u8,
+    pub flags: u8,
+    pub remaining_length: usize,
+    /// Bytes taken by the header itself, the packet is `header_len + remaining_length` long
+    pub header_len: usize,
+}
+
+impl FixedHeader {
+    /// `Ok(None)` while `bytes` doesn't hold the complete header yet
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, Error> {
+        let Some(first) = bytes.first() else {
+            return Ok(None);
+        };
+
+        Ok(decode_remaining_length(&bytes[1..])?.map(|(remaining_length, len)| Self {
+            packet_type: first >> 4,
+            flags: first & 0x0f,
+            remaining_length,
+            header_len: 1 + len,
+        }))
+    }
+}
+
+/// Write `len` as the variable length integer, returns the bytes taken
+pub fn encode_remaining_length(mut len: usize, out: &mut [u8]) -> Result<usize, Error> {
+    if len > MAX_REMAINING_LENGTH {
+        return Err(Error::Malformed);
+    }
+
+    let mut written = 0;
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        *out.get_mut(written).ok_or(Error::BufferTooSmall)? = byte;
+        written += 1;
+        if len == 0 {
+            return Ok(written);
+        }
+    }
+}
+
+/// Value and length of the variable length integer at the start of `bytes`, `Ok(None)` while it is incomplete
+pub fn decode_remaining_length(bytes: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+    let mut value = 0;
+
+    for (index, byte) in bytes.iter().enumerate() {
+        if index == 4 {
+            return Err(Error::Malformed);
+        }
+        value |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, index + 1)));
+        }
+    }
+
+    match bytes.len() {
+        4.. => Err(Error::Malformed),
+        _ => Ok(None),
+    }
+}
+
+/// Builds one packet in a caller provided buffer
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Start a packet with the fixed header, `remaining_length` has to match what is written afterwards
+    fn new(buf: &'a mut [u8], first: u8, remaining_length: usize) -> Result<Self, Error> {
+        *buf.first_mut().ok_or(Error::BufferTooSmall)? = first;
+        let len = 1 + encode_remaining_length(remaining_length, &mut buf[1..])?;
+        Ok(Self { buf, len })
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.buf.get_mut(self.len..self.len + bytes.len()).ok_or(Error::BufferTooSmall)?.copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    /// Two byte length followed by the bytes
+    fn prefixed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let len = u16::try_from(bytes.len()).map_err(|_| Error::Malformed)?;
+        self.bytes(&len.to_be_bytes())?;
+        self.bytes(bytes)
+    }
+}
+
+/// CONNECT with a clean session, returns the packet length
+pub fn connect(buf: &mut [u8], client_id: &str, keep_alive_secs: u16, will: Option<&Will>) -> Result<usize, Error> {
+    let mut flags = CLEAN_SESSION;
+    let mut remaining_length = 2 + PROTOCOL_NAME.len() + 4 + 2 + client_id.len();
+    if let Some(will) = will {
+        flags |= WILL_FLAG | if will.retain { WILL_RETAIN } else { 0 };
+        remaining_length += 2 + will.topic.len() + 2 + will.payload.len();
+    }
+
+    let mut out = Writer::new(buf, CONNECT << 4, remaining_length)?;
+    out.prefixed(PROTOCOL_NAME)?;
+    out.bytes(&[PROTOCOL_LEVEL, flags])?;
+    out.bytes(&keep_alive_secs.to_be_bytes())?;
+    out.prefixed(client_id.as_bytes())?;
+    if let Some(will) = will {
+        out.prefixed(will.topic.as_bytes())?;
+        out.prefixed(will.payload)?;
+    }
+
+    Ok(out.len)
+}
+
+/// PUBLISH at QoS 0, which has no packet identifier, returns the packet length
+pub fn publish(buf: &mut [u8], topic: &str, payload: &[u8], retain: bool) -> Result<usize, Error> {
+    let first = PUBLISH << 4 | if retain { RETAIN } else { 0 };
+    let mut out = Writer::new(buf, first, 2 + topic.len() + payload.len())?;
+    out.prefixed(topic.as_bytes())?;
+    out.bytes(payload)?;
+
+    Ok(out.len)
+}
+
+pub fn pingreq(buf: &mut [u8]) -> Result<usize, Error> {
+    Ok(Writer::new(buf, PINGREQ << 4, 0)?.len)
+}
+
+/// Return code of a CONNACK body, 0 means accepted
+pub fn connack_return_code(body: &[u8]) -> Result<u8, Error> {
+    match body {
+        [_session_present, code] => Ok(*code),
+        _ => Err(Error::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(len: usize, encoded: &[u8]) {
+        let mut out = [0; 4];
+        assert_eq!(encode_remaining_length(len, &mut out), Ok(encoded.len()), "{}", len);
+        assert_eq!(&out[..encoded.len()], encoded, "{}", len);
+        assert_eq!(decode_remaining_length(encoded), Ok(Some((len, encoded.len()))), "{}", len);
+    }
+
+    #[test]
+    fn remaining_length_boundaries() {
+        round_trip(0, &[0x00]);
+        round_trip(127, &[0x7f]);
+        round_trip(128, &[0x80, 0x01]);
+        round_trip(16_383, &[0xff, 0x7f]);
+        round_trip(16_384, &[0x80, 0x80, 0x01]);
+        round_trip(2_097_151, &[0xff, 0xff, 0x7f]);
+        round_trip(2_097_152, &[0x80, 0x80, 0x80, 0x01]);
+        round_trip(MAX_REMAINING_LENGTH, &[0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn remaining_length_errors() {
+        let mut out = [0; 4];
+        assert_eq!(encode_remaining_length(MAX_REMAINING_LENGTH + 1, &mut out), Err(Error::Malformed));
+        assert_eq!(encode_remaining_length(128, &mut out[..1]), Err(Error::BufferTooSmall));
+
+        // Too long: a fifth byte, or four bytes that all continue
+        assert_eq!(decode_remaining_length(&[0x80, 0x80, 0x80, 0x80, 0x01]), Err(Error::Malformed));
+        assert_eq!(decode_remaining_length(&[0xff, 0xff, 0xff, 0xff]), Err(Error::Malformed));
+        // Truncated: more is still to come
+        assert_eq!(decode_remaining_length(&[]), Ok(None));
+        assert_eq!(decode_remaining_length(&[0x80]), Ok(None));
+        assert_eq!(decode_remaining_length(&[0xff, 0xff, 0xff]), Ok(None));
+        // Bytes after the integer belong to the packet
+        assert_eq!(decode_remaining_length(&[0x05, 0x80]), Ok(Some((5, 1))));
+    }
+
+    #[test]
+    fn fixed_headers() {
+        assert_eq!(FixedHeader::decode(&[]), Ok(None));
+        assert_eq!(FixedHeader::decode(&[0x20]), Ok(None));
+        assert_eq!(FixedHeader::decode(&[0x31, 0x80]), Ok(None));
+        assert_eq!(
+            FixedHeader::decode(&[0x31, 0x80, 0x01, 0x00]),
+            Ok(Some(FixedHeader { packet_type: PUBLISH, flags: 0x01, remaining_length: 128, header_len: 3 }))
+        );
+        assert_eq!(FixedHeader::decode(&[0xd0, 0xff, 0xff, 0xff, 0xff, 0x01]), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn length_prefixed_fields() {
+        let mut buf = [0; 16];
+        let len = publish(&mut buf, "a/b", b"hi", true).unwrap();
+        assert_eq!(&buf[..len], &[0x31, 7, 0, 3, b'a', b'/', b'b', b'h', b'i']);
+        assert_eq!(publish(&mut buf[..8], "a/b", b"hi", true), Err(Error::BufferTooSmall));
+        assert_eq!(pingreq(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[PINGREQ << 4, 0]);
+
+        // A topic beyond the two byte length
+        let topic = "t".repeat(usize::from(u16::MAX) + 1);
+        assert_eq!(publish(&mut buf, &topic, b"", false), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn connect_with_a_will() {
+        let mut buf = [0; 64];
+        let will = Will { topic: "s", payload: b"off", retain: true };
+        let len = connect(&mut buf, "id", 60, Some(&will)).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x10, 22, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x26, 0, 60, 0, 2, b'i', b'd', 0, 1, b's', 0, 3, b'o', b'f', b'f'][..]
+        );
+        assert_eq!(connack_return_code(&[0, 0]), Ok(0));
+        assert_eq!(connack_return_code(&[0, 5]), Ok(5));
+        assert_eq!(connack_return_code(&[0]), Err(Error::Malformed));
+    }
+}
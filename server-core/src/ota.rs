@@ -0,0 +1,182 @@
+//! Bookkeeping of the firmware updates: the state record kept in a flash sector of its own, what the boot check
+//! does with it and a look at an upload before it is taken as an image. The flash and the swap of the two slots
+//! are the firmware's.
+
+use crate::crc::crc32;
+
+/// Magic, version, state, the trial flag, a padding byte, the three words and the CRC
+pub const RECORD_SIZE: usize = 24;
+const RECORD_MAGIC: [u8; 4] = *b"DHTU";
+const RECORD_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdateState {
+    /// Nothing to do, also an erased or unreadable record
+    Idle,
+    /// A verified image of `len` bytes waits in the update slot, it is swapped in at the next boot
+    Pending { len: u32, crc: u32 },
+    /// Swapped in and not confirmed yet. `booted` once it has started, `old_len` is the previous image's length,
+    /// that image now waits in the update slot.
+    Trial { len: u32, old_len: u32, booted: bool },
+    /// The image on trial joined the network and stays
+    Confirmed,
+    /// The image on trial didn't confirm, the previous one is back
+    RolledBack,
+}
+
+/// What the boot check does before anything else runs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BootAction {
+    Run,
+    /// Check the update slot against `crc` and swap it in
+    Install { len: u32, crc: u32 },
+    /// First start of a new image, it is marked as booted and waits for the confirmation
+    StartTrial { len: u32, old_len: u32 },
+    /// The new image started before and never confirmed, the previous one is swapped back
+    Revert { len: u32, old_len: u32 },
+}
+
+impl UpdateState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UpdateState::Idle => "idle",
+            UpdateState::Pending { .. } => "pending",
+            UpdateState::Trial { .. } => "trial",
+            UpdateState::Confirmed => "confirmed",
+            UpdateState::RolledBack => "rolled_back",
+        }
+    }
+
+    pub fn boot_action(self) -> BootAction {
+        match self {
+            UpdateState::Pending { len, crc } => BootAction::Install { len, crc },
+            UpdateState::Trial { len, old_len, booted: false } => BootAction::StartTrial { len, old_len },
+            UpdateState::Trial { len, old_len, booted: true } => BootAction::Revert { len, old_len },
+            UpdateState::Idle | UpdateState::Confirmed | UpdateState::RolledBack => BootAction::Run,
+        }
+    }
+
+    pub fn encode(self) -> [u8; RECORD_SIZE] {
+        let (kind, booted, words) = match self {
+            UpdateState::Idle => (0, false, [0; 3]),
+            UpdateState::Pending { len, crc } => (1, false, [len, crc, 0]),
+            UpdateState::Trial { len, old_len, booted } => (2, booted, [len, 0, old_len]),
+            UpdateState::Confirmed => (3, false, [0; 3]),
+            UpdateState::RolledBack => (4, false, [0; 3]),
+        };
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+        record[4] = RECORD_VERSION;
+        record[5] = kind;
+        record[6] = booted.into();
+        for (index, word) in words.iter().enumerate() {
+            record[8 + 4 * index..12 + 4 * index].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let crc = crc32(&record[..RECORD_SIZE - 4]);
+        record[RECORD_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// `Idle` for an erased sector, a torn write or another layout
+    pub fn decode(record: &[u8; RECORD_SIZE]) -> Self {
+        let word = |offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+        if record[..4] != RECORD_MAGIC || record[4] != RECORD_VERSION || word(RECORD_SIZE - 4) != crc32(&record[..RECORD_SIZE - 4]) {
+            return UpdateState::Idle;
+        }
+
+        match record[5] {
+            1 => UpdateState::Pending { len: word(8), crc: word(12) },
+            2 => UpdateState::Trial { len: word(8), old_len: word(16), booted: record[6] != 0 },
+            3 => UpdateState::Confirmed,
+            4 => UpdateState::RolledBack,
+            _ => UpdateState::Idle,
+        }
+    }
+}
+
+/// Sectors of `sector_size` a swap has to exchange so that neither image is cut short
+pub fn swap_sectors(len: u32, old_len: u32, sector_size: u32) -> u32 {
+    len.max(old_len).div_ceil(sector_size)
+}
+
+/// Whether `head`, the start of an upload, begins like an image for a slot of `slot_size` bytes at `flash_base`:
+/// the vector table at `vector_offset` holds a stack pointer in RAM and a Thumb reset handler inside the slot.
+/// Catches an ELF or a UF2 file sent in place of the binary.
+pub fn looks_like_image(head: &[u8], vector_offset: usize, flash_base: u32, slot_size: u32) -> bool {
+    let Some(vectors) = head.get(vector_offset..vector_offset + 8) else {
+        return false;
+    };
+    let stack_pointer = u32::from_le_bytes([vectors[0], vectors[1], vectors[2], vectors[3]]);
+    let reset = u32::from_le_bytes([vectors[4], vectors[5], vectors[6], vectors[7]]);
+    stack_pointer & 0xf000_0000 == 0x2000_0000 && reset & 1 == 1 && (flash_base..flash_base + slot_size).contains(&(reset & !1))
+}
+
+/// A CRC-32 given as 8 hex digits, with or without `0x`
+pub fn parse_crc(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    if hex.len() != 8 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let states = [
+            UpdateState::Idle,
+            UpdateState::Pending { len: 401_234, crc: 0xdead_beef },
+            UpdateState::Trial { len: 401_234, old_len: 399_000, booted: true },
+            UpdateState::Confirmed,
+            UpdateState::RolledBack,
+        ];
+        for state in states {
+            assert_eq!(UpdateState::decode(&state.encode()), state);
+        }
+
+        let mut torn = UpdateState::Pending { len: 1, crc: 2 }.encode();
+        torn[9] ^= 1;
+        assert_eq!(UpdateState::decode(&torn), UpdateState::Idle);
+        assert_eq!(UpdateState::decode(&[0xff; RECORD_SIZE]), UpdateState::Idle);
+    }
+
+    #[test]
+    fn boots_through_the_trial() {
+        assert_eq!(UpdateState::Pending { len: 10, crc: 3 }.boot_action(), BootAction::Install { len: 10, crc: 3 });
+        let trial = |booted| UpdateState::Trial { len: 10, old_len: 20, booted };
+        assert_eq!(trial(false).boot_action(), BootAction::StartTrial { len: 10, old_len: 20 });
+        assert_eq!(trial(true).boot_action(), BootAction::Revert { len: 10, old_len: 20 });
+        assert_eq!(UpdateState::RolledBack.boot_action(), BootAction::Run);
+        assert_eq!(swap_sectors(4096, 1, 4096), 1);
+        assert_eq!(swap_sectors(4097, 8192, 4096), 2);
+        assert_eq!(swap_sectors(1, 8193, 4096), 3);
+    }
+
+    #[test]
+    fn checks_the_vector_table() {
+        let mut image = [0u8; 0x108];
+        image[0x100..0x104].copy_from_slice(&0x2004_2000u32.to_le_bytes());
+        image[0x104..0x108].copy_from_slice(&0x1000_01f7u32.to_le_bytes());
+        assert!(looks_like_image(&image, 0x100, 0x1000_0000, 0x10_0000));
+        assert!(!looks_like_image(&image, 0, 0x1000_0000, 0x10_0000));
+        assert!(!looks_like_image(&image[..0x104], 0x100, 0x1000_0000, 0x10_0000));
+        assert!(!looks_like_image(b"\x7fELF\x01\x01\x01\x00", 0, 0x1000_0000, 0x10_0000));
+        // A reset handler in ARM state or past the slot
+        image[0x104..0x108].copy_from_slice(&0x1000_01f6u32.to_le_bytes());
+        assert!(!looks_like_image(&image, 0x100, 0x1000_0000, 0x10_0000));
+        image[0x104..0x108].copy_from_slice(&0x1020_0001u32.to_le_bytes());
+        assert!(!looks_like_image(&image, 0x100, 0x1000_0000, 0x10_0000));
+    }
+
+    #[test]
+    fn parses_the_crc() {
+        assert_eq!(parse_crc("cbf43926"), Some(0xcbf4_3926));
+        assert_eq!(parse_crc("0xCBF43926"), Some(0xcbf4_3926));
+        assert_eq!(parse_crc("cbf4392"), None);
+        assert_eq!(parse_crc("+bf43926"), None);
+    }
+}
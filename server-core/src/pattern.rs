@@ -0,0 +1,95 @@
+/// One step of a pattern: whether the output is on and for how many milliseconds
+pub type Step = (bool, u32);
+
+/// A one-off blink sequence, afterwards the output goes back to what it showed before
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Blink {
+    pub on_ms: u16,
+    pub off_ms: u16,
+    pub repeats: u8,
+}
+
+impl Blink {
+    /// Five slow blinks to tell which of several devices this is
+    pub const IDENTIFY: Self = Self { on_ms: 300, off_ms: 300, repeats: 5 };
+
+    /// Phases of the whole sequence, two per repeat
+    pub fn phases(&self) -> u16 {
+        2 * u16::from(self.repeats)
+    }
+
+    /// Output state and duration of a phase, even phases are on
+    pub fn phase(&self, phase: u16) -> Step {
+        match phase % 2 {
+            0 => (true, self.on_ms.into()),
+            _ => (false, self.off_ms.into()),
+        }
+    }
+}
+
+/// An alarm that repeats until it is stopped: `count` beeps of `beep_ms`, as far apart as they are long, then quiet
+/// for the rest of `period_ms`. `3 x 200 ms every 30 s` beeps for a second and stays quiet for 29.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Beeps {
+    pub count: u8,
+    pub beep_ms: u16,
+    pub period_ms: u32,
+}
+
+impl Beeps {
+    /// Phases of one period, two per beep, the last one is the pause
+    pub fn phases(&self) -> u16 {
+        2 * u16::from(self.count.max(1))
+    }
+
+    /// Output state and duration of a phase, counted from the start of the alarm. A period too short for its beeps
+    /// still keeps a gap of `beep_ms` before the next one.
+    pub fn phase(&self, phase: u16) -> Step {
+        let phase = phase % self.phases();
+        let beep_ms = u32::from(self.beep_ms);
+        if phase.is_multiple_of(2) {
+            (true, beep_ms)
+        } else if phase + 1 < self.phases() {
+            (false, beep_ms)
+        } else {
+            let busy = beep_ms * u32::from(self.phases() - 1);
+            (false, self.period_ms.saturating_sub(busy).max(beep_ms))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blink_alternates_and_ends() {
+        let blink = Blink { on_ms: 100, off_ms: 400, repeats: 2 };
+        assert_eq!(blink.phases(), 4);
+        assert_eq!(blink.phase(0), (true, 100));
+        assert_eq!(blink.phase(1), (false, 400));
+        assert_eq!(blink.phase(2), (true, 100));
+        assert_eq!(Blink { repeats: 0, ..blink }.phases(), 0);
+    }
+
+    #[test]
+    fn beeps_fill_the_period() {
+        let beeps = Beeps { count: 3, beep_ms: 200, period_ms: 30_000 };
+        let steps: Vec<Step> = (0..beeps.phases()).map(|phase| beeps.phase(phase)).collect();
+        assert_eq!(steps, [(true, 200), (false, 200), (true, 200), (false, 200), (true, 200), (false, 29_000)]);
+        assert_eq!(steps.iter().map(|(_, ms)| ms).sum::<u32>(), 30_000);
+        // The next period starts over
+        assert_eq!(beeps.phase(6), (true, 200));
+        assert_eq!(beeps.phase(11), (false, 29_000));
+    }
+
+    #[test]
+    fn short_periods_keep_a_gap() {
+        let single = Beeps { count: 1, beep_ms: 500, period_ms: 2000 };
+        assert_eq!((single.phase(0), single.phase(1)), ((true, 500), (false, 1500)));
+
+        let crowded = Beeps { count: 3, beep_ms: 200, period_ms: 500 };
+        assert_eq!(crowded.phase(5), (false, 200));
+        assert_eq!(Beeps { count: 0, ..crowded }.phases(), 2);
+    }
+}
@@ -0,0 +1,128 @@
+use core::fmt;
+
+/// One breath of the all good indication, dimmest to brightest and back
+pub const BREATH_MS: u32 = 3000;
+/// Half a period of the sensor error blink
+pub const BLINK_MS: u32 = 500;
+/// The dimmest point of a breath, in percent, so the pixel never looks off
+const BREATH_FLOOR: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Self = Self::new(0, 0, 0);
+    pub const GREEN: Self = Self::new(0, 255, 0);
+    pub const BLUE: Self = Self::new(0, 0, 255);
+    pub const RED: Self = Self::new(255, 0, 0);
+    pub const ORANGE: Self = Self::new(255, 80, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// `rrggbb` in hex, with or without a leading `#`
+    pub fn parse(text: &str) -> Option<Self> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+        Some(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// Every channel at `percent` of its value, rounded down
+    pub fn scaled(self, percent: u8) -> Self {
+        let scale = |channel: u8| (u32::from(channel) * u32::from(percent.min(100)) / 100) as u8;
+        Self::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    /// What a WS2812 shifts in, green, red and blue from the most significant byte down with the low byte unused
+    pub fn grb_word(self) -> u32 {
+        (u32::from(self.g) << 24) | (u32::from(self.r) << 16) | (u32::from(self.b) << 8)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// What the status pixel shows, the first one that applies
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Indication {
+    /// Red blink, the primary sensor's reads fail
+    SensorError,
+    /// Orange, a threshold alert is active
+    Alert,
+    /// Blue, the Wi-Fi isn't joined
+    Joining,
+    /// Green breathing
+    Ok,
+}
+
+impl Indication {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Indication::SensorError => "sensor_error",
+            Indication::Alert => "alert",
+            Indication::Joining => "joining",
+            Indication::Ok => "ok",
+        }
+    }
+
+    /// The color `elapsed_ms` into the indication, at full brightness
+    pub fn color(self, elapsed_ms: u32) -> Rgb {
+        match self {
+            Indication::SensorError if (elapsed_ms / BLINK_MS).is_multiple_of(2) => Rgb::RED,
+            Indication::SensorError => Rgb::OFF,
+            Indication::Alert => Rgb::ORANGE,
+            Indication::Joining => Rgb::BLUE,
+            Indication::Ok => {
+                // A triangle from the floor up to full and back down
+                let phase = elapsed_ms % BREATH_MS;
+                let rising = if phase < BREATH_MS / 2 { phase } else { BREATH_MS - phase };
+                let percent = BREATH_FLOOR + (100 - BREATH_FLOOR) * rising / (BREATH_MS / 2);
+                Rgb::GREEN.scaled(percent as u8)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_hex() {
+        assert_eq!(Rgb::parse("ff8000"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::parse("#00A0ff"), Some(Rgb::new(0, 160, 255)));
+        assert_eq!(Rgb::parse("#fff"), None);
+        assert_eq!(Rgb::parse("gg0000"), None);
+        assert_eq!(Rgb::parse("+f0000"), None);
+        assert_eq!(Rgb::new(255, 128, 0).to_string(), "#ff8000");
+    }
+
+    #[test]
+    fn scales_and_orders_the_channels() {
+        assert_eq!(Rgb::new(255, 100, 10).scaled(50), Rgb::new(127, 50, 5));
+        assert_eq!(Rgb::RED.scaled(200), Rgb::RED);
+        assert_eq!(Rgb::new(0x11, 0x22, 0x33).grb_word(), 0x2211_3300);
+    }
+
+    #[test]
+    fn breathes_and_blinks() {
+        assert_eq!(Indication::Ok.color(0), Rgb::GREEN.scaled(10));
+        assert_eq!(Indication::Ok.color(BREATH_MS / 2), Rgb::GREEN);
+        assert_eq!(Indication::Ok.color(BREATH_MS / 4), Rgb::GREEN.scaled(55));
+        assert_eq!(Indication::Ok.color(BREATH_MS), Rgb::GREEN.scaled(10));
+        assert_eq!(Indication::SensorError.color(100), Rgb::RED);
+        assert_eq!(Indication::SensorError.color(BLINK_MS + 100), Rgb::OFF);
+        assert_eq!(Indication::Alert.color(12_345), Rgb::ORANGE);
+    }
+}
@@ -0,0 +1,382 @@
+//! What happens to a reading between the sensor and the handlers. Times are milliseconds since boot, passed in by
+//! the caller so nothing here depends on a real clock.
+
+/// A successful measurement of the sensor
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Reading {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+/// Offsets added to every raw reading before it is smoothed, recorded or used for derived values
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Calibration {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+impl Calibration {
+    pub const NONE: Self = Self { temperature: 0.0, humidity: 0.0 };
+
+    pub fn is_active(&self) -> bool {
+        *self != Self::NONE
+    }
+
+    /// Relative humidity stays within 0 to 100 % whatever the offset
+    pub fn apply(&self, reading: Reading) -> Reading {
+        Reading {
+            temperature: reading.temperature + self.temperature,
+            humidity: (reading.humidity + self.humidity).clamp(0.0, 100.0),
+        }
+    }
+
+    /// Calibrate a good raw reading taken at `now_ms` and feed it to `ema`. The offsets come first, so nothing
+    /// downstream ever sees an uncalibrated value.
+    pub fn condition(&self, raw: Reading, ema: &mut Ema, now_ms: u64) -> Conditioned {
+        let calibrated = self.apply(raw);
+        Conditioned { calibrated, smoothed: ema.update(calibrated, now_ms) }
+    }
+}
+
+/// What a good reading turns into. `calibrated` goes into the extremes, the history and the records, `smoothed` is
+/// its moving average, the live values, the alerts and the derived metrics are taken from it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Conditioned {
+    pub calibrated: Reading,
+    pub smoothed: Reading,
+}
+
+/// Anything that can produce a reading, implemented by the DHT driver and by mocks
+pub trait ReadSensor {
+    type Error;
+
+    fn read(&mut self) -> Result<Reading, Self::Error>;
+}
+
+/// Guard that never reads the wrapped sensor more often than `min_interval_ms`
+pub struct ThrottledSensor<T> {
+    sensor: T,
+    min_interval_ms: u64,
+    last: Option<(Reading, u64)>,
+}
+
+impl<T: ReadSensor> ThrottledSensor<T> {
+    pub const fn new(sensor: T, min_interval_ms: u64) -> Self {
+        Self { sensor, min_interval_ms, last: None }
+    }
+
+    /// Fresh reading, or the cached one when the last successful read is less than `min_interval_ms` ago.
+    /// Returns the reading together with the time it was taken.
+    pub fn read(&mut self, now_ms: u64) -> Result<(Reading, u64), T::Error> {
+        if let Some((reading, taken)) = self.last {
+            if now_ms.saturating_sub(taken) < self.min_interval_ms {
+                return Ok((reading, taken));
+            }
+        }
+
+        let reading = self.sensor.read()?;
+        self.last = Some((reading, now_ms));
+
+        Ok((reading, now_ms))
+    }
+}
+
+/// Exponential moving average of the readings, `smoothed += alpha * (raw - smoothed)`
+pub struct Ema {
+    alpha: f32,
+    reset_gap_ms: u64,
+    last: Option<(Reading, u64)>,
+}
+
+impl Ema {
+    /// `alpha` is clamped to 0 to 1, beyond that the average would overshoot or move away from the readings
+    pub const fn new(alpha: f32, reset_gap_ms: u64) -> Self {
+        Self { alpha: alpha.clamp(0.0, 1.0), reset_gap_ms, last: None }
+    }
+
+    /// Feed a raw reading taken at `now_ms` and get the smoothed value.
+    /// The first reading and the first one after a long gap are taken as they are instead of slewing towards them.
+    pub fn update(&mut self, raw: Reading, now_ms: u64) -> Reading {
+        let smoothed = match self.last {
+            Some((previous, at)) if now_ms.saturating_sub(at) <= self.reset_gap_ms => Reading {
+                temperature: previous.temperature + self.alpha * (raw.temperature - previous.temperature),
+                humidity: previous.humidity + self.alpha * (raw.humidity - previous.humidity),
+            },
+            _ => raw,
+        };
+
+        self.last = Some((smoothed, now_ms));
+        smoothed
+    }
+}
+
+/// Sensor type, the firmware is built for one of them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SensorModel {
+    Dht22,
+    Dht11,
+}
+
+impl SensorModel {
+    pub fn name(self) -> &'static str {
+        match self {
+            SensorModel::Dht22 => "DHT22",
+            SensorModel::Dht11 => "DHT11",
+        }
+    }
+
+    /// Lowercase form for machine readable fields
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SensorModel::Dht22 => "dht22",
+            SensorModel::Dht11 => "dht11",
+        }
+    }
+
+    /// Displayed decimals, the DHT11 only resolves whole degrees and percents
+    pub fn decimals(self) -> usize {
+        match self {
+            SensorModel::Dht22 => 1,
+            SensorModel::Dht11 => 0,
+        }
+    }
+
+    /// Measuring range from the datasheet, values outside of it usually mean the wrong sensor type is configured
+    pub fn in_range(self, reading: &Reading) -> bool {
+        let (temperature, humidity) = match self {
+            SensorModel::Dht22 => (-40.0..=80.0, 0.0..=100.0),
+            SensorModel::Dht11 => (0.0..=50.0, 20.0..=90.0),
+        };
+
+        temperature.contains(&reading.temperature) && humidity.contains(&reading.humidity)
+    }
+}
+
+/// Largest believable change between two consecutive good readings, in °C and percentage points
+#[derive(Clone, Copy)]
+pub struct SpikeLimits {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+/// Why the spike filter dropped a reading
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rejection {
+    /// Outside the measuring range of the sensor model
+    OutOfRange,
+    /// Too far from the previous good reading
+    Jump,
+}
+
+/// Drops single implausible readings that got past the checksum.
+/// After `accept_after` rejected jumps in a row the new level is accepted, so a genuine fast change
+/// only costs a few samples. Out of range readings are never accepted.
+pub struct SpikeFilter {
+    model: SensorModel,
+    limits: SpikeLimits,
+    accept_after: u32,
+    reset_gap_ms: u64,
+    last: Option<(Reading, u64)>,
+    rejected: u32,
+}
+
+impl SpikeFilter {
+    pub const fn new(model: SensorModel, limits: SpikeLimits, accept_after: u32, reset_gap_ms: u64) -> Self {
+        Self { model, limits, accept_after, reset_gap_ms, last: None, rejected: 0 }
+    }
+
+    /// Check a raw reading taken at `now_ms`. The first reading and the first one after a long gap are
+    /// compared with nothing, the level may have moved any amount in between.
+    pub fn check(&mut self, reading: Reading, now_ms: u64) -> Result<(), Rejection> {
+        if !self.model.in_range(&reading) {
+            return Err(Rejection::OutOfRange);
+        }
+
+        if let Some((last, at)) = self.last {
+            let jumped = (reading.temperature - last.temperature).abs() > self.limits.temperature
+                || (reading.humidity - last.humidity).abs() > self.limits.humidity;
+
+            if jumped && now_ms.saturating_sub(at) <= self.reset_gap_ms && self.rejected < self.accept_after {
+                self.rejected += 1;
+                return Err(Rejection::Jump);
+            }
+        }
+
+        self.last = Some((reading, now_ms));
+        self.rejected = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads 20.0, 21.0, ... and fails whenever `fail` is set
+    struct Counter {
+        reads: u32,
+        fail: bool,
+    }
+
+    impl ReadSensor for Counter {
+        type Error = ();
+
+        fn read(&mut self) -> Result<Reading, ()> {
+            if self.fail {
+                return Err(());
+            }
+            self.reads += 1;
+            Ok(Reading { temperature: 19.0 + self.reads as f32, humidity: 50.0 })
+        }
+    }
+
+    #[test]
+    fn serves_the_cached_reading_within_the_interval() {
+        let mut sensor = ThrottledSensor::new(Counter { reads: 0, fail: false }, 2000);
+        let (first, taken) = sensor.read(10_000).unwrap();
+        assert_eq!((first.temperature, taken), (20.0, 10_000));
+        assert_eq!(sensor.read(10_000).unwrap(), (first, 10_000));
+        assert_eq!(sensor.read(11_999).unwrap(), (first, 10_000));
+        assert_eq!(sensor.sensor.reads, 1);
+    }
+
+    #[test]
+    fn reads_the_sensor_after_the_interval() {
+        let mut sensor = ThrottledSensor::new(Counter { reads: 0, fail: false }, 2000);
+        sensor.read(10_000).unwrap();
+        let (second, taken) = sensor.read(12_000).unwrap();
+        assert_eq!((second.temperature, taken), (21.0, 12_000));
+        assert_eq!(sensor.sensor.reads, 2);
+
+        // A failed read doesn't restart the interval
+        sensor.sensor.fail = true;
+        assert_eq!(sensor.read(14_000), Err(()));
+        sensor.sensor.fail = false;
+        assert_eq!(sensor.read(14_001).unwrap().1, 14_001);
+        assert_eq!(sensor.sensor.reads, 3);
+    }
+
+    const fn reading(temperature: f32, humidity: f32) -> Reading {
+        Reading { temperature, humidity }
+    }
+
+    #[test]
+    fn the_first_sample_seeds_the_average() {
+        let mut ema = Ema::new(0.3, 60_000);
+        assert_eq!(ema.update(reading(24.0, 40.0), 0), reading(24.0, 40.0));
+        let next = ema.update(reading(34.0, 50.0), 5000);
+        assert!((next.temperature - 27.0).abs() < 1e-4);
+        assert!((next.humidity - 43.0).abs() < 1e-4);
+
+        // After a long gap it starts over at the raw value
+        assert_eq!(ema.update(reading(10.0, 80.0), 65_001), reading(10.0, 80.0));
+    }
+
+    #[test]
+    fn clamps_alpha() {
+        let mut ema = Ema::new(1.5, 60_000);
+        ema.update(reading(20.0, 50.0), 0);
+        assert_eq!(ema.update(reading(30.0, 60.0), 1000), reading(30.0, 60.0));
+
+        let mut ema = Ema::new(-0.5, 60_000);
+        ema.update(reading(20.0, 50.0), 0);
+        assert_eq!(ema.update(reading(30.0, 60.0), 1000), reading(20.0, 50.0));
+    }
+
+    #[test]
+    fn converges_on_a_steady_reading() {
+        let mut ema = Ema::new(0.3, 60_000);
+        ema.update(reading(20.0, 50.0), 0);
+        let mut previous = reading(20.0, 50.0);
+        for step in 1..=30 {
+            let smoothed = ema.update(reading(25.0, 60.0), step * 5000);
+            // Never past the target and never moving away from it
+            assert!(smoothed.temperature >= previous.temperature && smoothed.temperature <= 25.0);
+            assert!(smoothed.humidity >= previous.humidity && smoothed.humidity <= 60.0);
+            previous = smoothed;
+        }
+        assert!((previous.temperature - 25.0).abs() < 0.001);
+        assert!((previous.humidity - 60.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn calibrates_before_anything_else() {
+        let calibration = Calibration { temperature: -1.5, humidity: 4.0 };
+        let mut ema = Ema::new(0.5, 60_000);
+        let first = calibration.condition(reading(22.0, 50.0), &mut ema, 0);
+        assert_eq!(first, Conditioned { calibrated: reading(20.5, 54.0), smoothed: reading(20.5, 54.0) });
+
+        // The average is taken over calibrated values only
+        let second = calibration.condition(reading(24.0, 60.0), &mut ema, 5000);
+        assert_eq!(second.calibrated, reading(22.5, 64.0));
+        assert_eq!(second.smoothed, reading(21.5, 59.0));
+
+        assert!(!Calibration::NONE.is_active());
+        assert_eq!(Calibration::NONE.apply(reading(22.0, 50.0)), reading(22.0, 50.0));
+    }
+
+    #[test]
+    fn clamps_the_calibrated_humidity() {
+        let up = Calibration { temperature: 0.0, humidity: 8.0 };
+        assert_eq!(up.apply(reading(20.0, 95.0)), reading(20.0, 100.0));
+        let down = Calibration { temperature: 0.0, humidity: -8.0 };
+        assert_eq!(down.apply(reading(20.0, 5.0)), reading(20.0, 0.0));
+
+        // The smoothed value never leaves the range either
+        let mut ema = Ema::new(0.3, 60_000);
+        assert_eq!(up.condition(reading(20.0, 99.0), &mut ema, 0).smoothed.humidity, 100.0);
+        assert_eq!(up.condition(reading(20.0, 97.0), &mut ema, 5000).smoothed.humidity, 100.0);
+    }
+
+    const LIMITS: SpikeLimits = SpikeLimits { temperature: 5.0, humidity: 10.0 };
+
+    #[test]
+    fn rejects_a_single_spike() {
+        let mut filter = SpikeFilter::new(SensorModel::Dht22, LIMITS, 3, 60_000);
+        assert_eq!(filter.check(reading(20.0, 50.0), 0), Ok(()));
+        assert_eq!(filter.check(reading(25.0, 60.0), 5000), Ok(()));
+        assert_eq!(filter.check(reading(30.1, 60.0), 10_000), Err(Rejection::Jump));
+        assert_eq!(filter.check(reading(25.0, 70.1), 15_000), Err(Rejection::Jump));
+        // Compared with the last good reading, not with the spikes
+        assert_eq!(filter.check(reading(25.5, 59.0), 20_000), Ok(()));
+    }
+
+    #[test]
+    fn accepts_a_new_level_after_enough_outliers() {
+        let mut filter = SpikeFilter::new(SensorModel::Dht22, LIMITS, 3, 60_000);
+        filter.check(reading(20.0, 50.0), 0).unwrap();
+        for step in 1..=3 {
+            assert_eq!(filter.check(reading(30.0, 50.0), step * 5000), Err(Rejection::Jump));
+        }
+        assert_eq!(filter.check(reading(30.0, 50.0), 20_000), Ok(()));
+        // The new level is the reference from now on
+        assert_eq!(filter.check(reading(31.0, 50.0), 25_000), Ok(()));
+        assert_eq!(filter.check(reading(20.0, 50.0), 30_000), Err(Rejection::Jump));
+    }
+
+    #[test]
+    fn never_accepts_out_of_range_values() {
+        let mut filter = SpikeFilter::new(SensorModel::Dht22, LIMITS, 1, 60_000);
+        assert_eq!(filter.check(reading(80.1, 50.0), 0), Err(Rejection::OutOfRange));
+        assert_eq!(filter.check(reading(21.0, -0.1), 0), Err(Rejection::OutOfRange));
+        filter.check(reading(20.0, 50.0), 5000).unwrap();
+        for step in 2..6 {
+            assert_eq!(filter.check(reading(-40.5, 50.0), step * 5000), Err(Rejection::OutOfRange));
+        }
+        // Out of range readings don't count as outliers towards a new level
+        assert_eq!(filter.check(reading(30.0, 50.0), 30_000), Err(Rejection::Jump));
+
+        let mut dht11 = SpikeFilter::new(SensorModel::Dht11, LIMITS, 3, 60_000);
+        assert_eq!(dht11.check(reading(-1.0, 50.0), 0), Err(Rejection::OutOfRange));
+        assert_eq!(dht11.check(reading(20.0, 95.0), 0), Err(Rejection::OutOfRange));
+        assert_eq!(dht11.check(reading(20.0, 50.0), 0), Ok(()));
+    }
+
+    #[test]
+    fn starts_over_after_a_long_gap() {
+        let mut filter = SpikeFilter::new(SensorModel::Dht22, LIMITS, 3, 60_000);
+        filter.check(reading(20.0, 50.0), 0).unwrap();
+        assert_eq!(filter.check(reading(30.0, 80.0), 60_000), Err(Rejection::Jump));
+        assert_eq!(filter.check(reading(30.0, 80.0), 120_001), Ok(()));
+    }
+}
@@ -0,0 +1,294 @@
+//! Which endpoint a method and path name. Only the mapping lives here, the firmware dispatches the routes and tells
+//! `resolve` what the build serves besides the fixed endpoints through a `Site`.
+
+use crate::http::Method;
+
+/// The optional part of what the server serves
+pub struct Site {
+    /// The fan, servo, status pixel and buzzer endpoints only exist with the hardware fitted
+    pub fan: bool,
+    pub servo: bool,
+    pub pixel: bool,
+    pub buzzer: bool,
+    /// Index of the output with this name, `POST /api/gpio/<name>` switches it
+    pub output: fn(&str) -> Option<usize>,
+    /// Index of the static asset at this path
+    pub asset: fn(&str) -> Option<usize>,
+}
+
+/// Known endpoints of the server
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Route {
+    Index,
+    LedToggle,
+    LedSet,
+    ApiLed,
+    ApiSensor,
+    ApiSensorHealth,
+    ApiStatus,
+    ApiHistory,
+    ApiHistoryDaily,
+    ApiStatsReset,
+    ApiConfig,
+    ApiConfigSet,
+    ApiConfigReset,
+    ApiReboot,
+    ApiFactoryReset,
+    ApiVersion,
+    ApiDiag,
+    ApiLogLevel,
+    ApiLogLevelSet,
+    ApiGpio,
+    ApiFan,
+    ApiFanSet,
+    ApiServo,
+    ApiServoSet,
+    ApiPixel,
+    ApiPixelSet,
+    ApiAlarmAck,
+    ApiTime,
+    ApiTimeSet,
+    ApiLogging,
+    /// `POST /api/gpio/<name>`, the index `Site::output` gave for the name
+    ApiGpioSet {
+        index: usize,
+    },
+    Events,
+    Metrics,
+    HistoryCsv,
+    Settings,
+    SettingsSave,
+    /// `POST /update`, a firmware image streamed to the update slot
+    Update,
+    SetupForm,
+    SetupSave,
+    Preflight,
+    /// The index `Site::asset` gave for the path
+    Static {
+        asset: usize,
+    },
+    NotFound,
+    MethodNotAllowed {
+        allow: &'static str,
+    },
+    NotImplemented,
+}
+
+impl Route {
+    pub fn resolve(method: Method, path: &str, site: &Site) -> Self {
+        if method == Method::Unknown {
+            return Route::NotImplemented;
+        }
+
+        // HEAD is answered by the same handler as GET, only the body is dropped
+        match (method, path) {
+            (Method::Get | Method::Head, "/") => Route::Index,
+            (Method::Get | Method::Head, "/led") => Route::LedToggle,
+            (Method::Post, "/led") => Route::LedSet,
+            (Method::Get | Method::Head, "/api/led") => Route::ApiLed,
+            (Method::Get | Method::Head, "/api/sensor") => Route::ApiSensor,
+            (Method::Get | Method::Head, "/api/sensor/health") => Route::ApiSensorHealth,
+            (Method::Get | Method::Head, "/api/status") => Route::ApiStatus,
+            (Method::Get | Method::Head, "/api/history") => Route::ApiHistory,
+            (Method::Get | Method::Head, "/api/history/daily") => Route::ApiHistoryDaily,
+            (Method::Post, "/api/stats/reset") => Route::ApiStatsReset,
+            (Method::Get | Method::Head, "/api/config") => Route::ApiConfig,
+            (Method::Post, "/api/config") => Route::ApiConfigSet,
+            (Method::Post, "/api/config/reset") => Route::ApiConfigReset,
+            (Method::Post, "/api/reboot") => Route::ApiReboot,
+            (Method::Post, "/api/factory-reset") => Route::ApiFactoryReset,
+            (Method::Get | Method::Head, "/api/version") => Route::ApiVersion,
+            (Method::Get | Method::Head, "/api/diag") => Route::ApiDiag,
+            (Method::Get | Method::Head, "/api/log-level") => Route::ApiLogLevel,
+            (Method::Post, "/api/log-level") => Route::ApiLogLevelSet,
+            (Method::Get | Method::Head, "/api/gpio") => Route::ApiGpio,
+            (Method::Get | Method::Head, "/api/fan") if site.fan => Route::ApiFan,
+            (Method::Post, "/api/fan") if site.fan => Route::ApiFanSet,
+            (Method::Get | Method::Head, "/api/servo") if site.servo => Route::ApiServo,
+            (Method::Post, "/api/servo") if site.servo => Route::ApiServoSet,
+            (Method::Get | Method::Head, "/api/pixel") if site.pixel => Route::ApiPixel,
+            (Method::Post, "/api/pixel") if site.pixel => Route::ApiPixelSet,
+            (Method::Post, "/api/alarm/ack") if site.buzzer => Route::ApiAlarmAck,
+            (Method::Get | Method::Head, "/api/time") => Route::ApiTime,
+            (Method::Post, "/api/time") => Route::ApiTimeSet,
+            (Method::Get | Method::Head, "/api/logging") => Route::ApiLogging,
+            (Method::Get, "/events") => Route::Events,
+            (Method::Get | Method::Head, "/metrics") => Route::Metrics,
+            (Method::Get | Method::Head, "/history.csv") => Route::HistoryCsv,
+            (Method::Get | Method::Head, "/settings") => Route::Settings,
+            (Method::Post, "/settings") => Route::SettingsSave,
+            (Method::Post, "/update") => Route::Update,
+            (Method::Options, path) if path.starts_with("/api/") && allowed_methods(path, site).is_some() => Route::Preflight,
+            (Method::Get | Method::Head, path) => Route::asset(path, site).unwrap_or_else(|| Route::unmatched(path, site)),
+            (Method::Post, path) => match gpio_index(path, site) {
+                Some(index) => Route::ApiGpioSet { index },
+                None => Route::unmatched(path, site),
+            },
+            (_, path) => Route::unmatched(path, site),
+        }
+    }
+
+    /// Routes while the setup access point is up: the Wi-Fi form at every page path, so phones show it when they join
+    pub fn resolve_setup(method: Method, path: &str, site: &Site) -> Self {
+        match (method, path) {
+            (Method::Unknown, _) => Route::NotImplemented,
+            (Method::Post, "/setup") => Route::SetupSave,
+            (Method::Get | Method::Head, path) => Route::asset(path, site).unwrap_or(Route::SetupForm),
+            (_, "/setup") => Route::MethodNotAllowed { allow: "GET, HEAD, POST" },
+            _ => Route::NotFound,
+        }
+    }
+
+    fn asset(path: &str, site: &Site) -> Option<Self> {
+        (site.asset)(path).map(|asset| Route::Static { asset })
+    }
+
+    /// Routes that change device state, and the settings page they are changed from, protected by Basic Auth when
+    /// it is configured
+    pub fn requires_auth(&self) -> bool {
+        matches!(self, Route::LedToggle | Route::LedSet | Route::Settings | Route::SettingsSave | Route::Update | Route::ApiStatsReset | Route::ApiConfigSet | Route::ApiConfigReset | Route::ApiReboot | Route::ApiFactoryReset | Route::ApiLogLevelSet | Route::ApiGpioSet { .. } | Route::ApiFanSet | Route::ApiServoSet | Route::ApiPixelSet | Route::ApiAlarmAck | Route::ApiTimeSet | Route::SetupSave)
+    }
+
+    /// JSON API routes that need the API token when it is configured, preflights never carry credentials
+    pub fn requires_token(&self) -> bool {
+        matches!(self, Route::ApiLed | Route::ApiSensor | Route::ApiSensorHealth | Route::ApiStatus | Route::ApiHistory | Route::ApiHistoryDaily | Route::ApiStatsReset | Route::ApiConfig | Route::ApiConfigSet | Route::ApiConfigReset | Route::ApiReboot | Route::ApiFactoryReset | Route::ApiVersion | Route::ApiDiag | Route::ApiLogLevel | Route::ApiLogLevelSet | Route::ApiGpio | Route::ApiGpioSet { .. } | Route::ApiFan | Route::ApiFanSet | Route::ApiServo | Route::ApiServoSet | Route::ApiPixel | Route::ApiPixelSet | Route::ApiAlarmAck | Route::ApiTime | Route::ApiTimeSet | Route::ApiLogging)
+    }
+
+    /// Route for a request no handler accepted: either the method or the whole path is unknown
+    fn unmatched(path: &str, site: &Site) -> Self {
+        match allowed_methods(path, site) {
+            Some(allow) => Route::MethodNotAllowed { allow },
+            None => Route::NotFound,
+        }
+    }
+}
+
+/// Index of the output a `/api/gpio/<name>` path names
+fn gpio_index(path: &str, site: &Site) -> Option<usize> {
+    (site.output)(path.strip_prefix("/api/gpio/")?)
+}
+
+/// Methods supported by a known path, used for the `Allow` header of a 405
+fn allowed_methods(path: &str, site: &Site) -> Option<&'static str> {
+    match path {
+        "/led" | "/settings" | "/api/config" | "/api/log-level" | "/api/time" => Some("GET, HEAD, POST"),
+        "/api/fan" if site.fan => Some("GET, HEAD, POST"),
+        "/api/servo" if site.servo => Some("GET, HEAD, POST"),
+        "/api/pixel" if site.pixel => Some("GET, HEAD, POST"),
+        "/" | "/api/led" | "/api/sensor" | "/api/sensor/health" | "/api/status" | "/api/history" | "/api/history/daily" | "/api/version" | "/api/diag" | "/api/gpio" | "/api/logging" | "/history.csv" | "/metrics" => Some("GET, HEAD"),
+        "/events" => Some("GET"),
+        "/api/stats/reset" | "/api/config/reset" | "/api/reboot" | "/api/factory-reset" | "/update" => Some("POST"),
+        "/api/alarm/ack" if site.buzzer => Some("POST"),
+        path if gpio_index(path, site).is_some() => Some("POST"),
+        path if (site.asset)(path).is_some() => Some("GET, HEAD"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSETS: [&str; 3] = ["/favicon.ico", "/style.css", "/app.js"];
+
+    /// A fan fitted, nothing else, and one output named `humidifier`
+    const SITE: Site = Site {
+        fan: true,
+        servo: false,
+        pixel: false,
+        buzzer: false,
+        output: |name| (name == "humidifier").then_some(0),
+        asset: |path| ASSETS.iter().position(|asset| *asset == path),
+    };
+
+    fn resolve(method: &str, path: &str) -> Route {
+        Route::resolve(Method::parse(method), path, &SITE)
+    }
+
+    #[test]
+    fn exact_paths_only() {
+        assert_eq!(resolve("GET", "/led"), Route::LedToggle);
+        assert_eq!(resolve("POST", "/led"), Route::LedSet);
+        // A longer path sharing the prefix is another path
+        assert_eq!(resolve("GET", "/ledstrip"), Route::NotFound);
+        assert_eq!(resolve("POST", "/ledstrip"), Route::NotFound);
+        assert_eq!(resolve("GET", "/led/"), Route::NotFound);
+        assert_eq!(resolve("GET", "/api/sensors"), Route::NotFound);
+        assert_eq!(resolve("GET", "/api/history/daily"), Route::ApiHistoryDaily);
+        assert_eq!(resolve("GET", "/api/history/dail"), Route::NotFound);
+        assert_eq!(resolve("GET", "/LED"), Route::NotFound);
+    }
+
+    #[test]
+    fn static_assets() {
+        assert_eq!(resolve("GET", "/favicon.ico"), Route::Static { asset: 0 });
+        assert_eq!(resolve("HEAD", "/style.css"), Route::Static { asset: 1 });
+        assert_eq!(resolve("GET", "/app.js"), Route::Static { asset: 2 });
+        // Not an asset, and not `/led` either
+        assert_eq!(resolve("GET", "/led.css"), Route::NotFound);
+        assert_eq!(resolve("GET", "/style.css.gz"), Route::NotFound);
+        assert_eq!(resolve("POST", "/style.css"), Route::MethodNotAllowed { allow: "GET, HEAD" });
+    }
+
+    #[test]
+    fn wrong_method_on_a_known_path_is_not_allowed() {
+        assert_eq!(resolve("POST", "/"), Route::MethodNotAllowed { allow: "GET, HEAD" });
+        assert_eq!(resolve("DELETE", "/led"), Route::MethodNotAllowed { allow: "GET, HEAD, POST" });
+        assert_eq!(resolve("HEAD", "/events"), Route::MethodNotAllowed { allow: "GET" });
+        assert_eq!(resolve("GET", "/update"), Route::MethodNotAllowed { allow: "POST" });
+        assert_eq!(resolve("PUT", "/api/fan"), Route::MethodNotAllowed { allow: "GET, HEAD, POST" });
+        assert_eq!(resolve("GET", "/api/gpio/humidifier"), Route::MethodNotAllowed { allow: "POST" });
+        // Unknown paths are not found whatever the method
+        assert_eq!(resolve("GET", "/nope"), Route::NotFound);
+        assert_eq!(resolve("DELETE", "/nope"), Route::NotFound);
+    }
+
+    #[test]
+    fn unsupported_methods_are_not_implemented() {
+        assert_eq!(resolve("BREW", "/"), Route::NotImplemented);
+        assert_eq!(resolve("get", "/"), Route::NotImplemented);
+        assert_eq!(resolve("TRACE", "/nope"), Route::NotImplemented);
+        assert_eq!(Route::resolve_setup(Method::parse("BREW"), "/setup", &SITE), Route::NotImplemented);
+    }
+
+    #[test]
+    fn missing_hardware_has_no_endpoint() {
+        assert_eq!(resolve("GET", "/api/fan"), Route::ApiFan);
+        assert_eq!(resolve("POST", "/api/fan"), Route::ApiFanSet);
+        assert_eq!(resolve("GET", "/api/servo"), Route::NotFound);
+        assert_eq!(resolve("POST", "/api/alarm/ack"), Route::NotFound);
+        assert_eq!(resolve("OPTIONS", "/api/fan"), Route::Preflight);
+        assert_eq!(resolve("OPTIONS", "/api/pixel"), Route::NotFound);
+    }
+
+    #[test]
+    fn outputs_by_name() {
+        assert_eq!(resolve("POST", "/api/gpio/humidifier"), Route::ApiGpioSet { index: 0 });
+        assert_eq!(resolve("POST", "/api/gpio/heater"), Route::NotFound);
+        assert_eq!(resolve("POST", "/api/gpio/"), Route::NotFound);
+        assert_eq!(resolve("GET", "/api/gpio"), Route::ApiGpio);
+    }
+
+    #[test]
+    fn setup_mode_serves_the_form_everywhere() {
+        let resolve_setup = |method, path| Route::resolve_setup(Method::parse(method), path, &SITE);
+        assert_eq!(resolve_setup("GET", "/"), Route::SetupForm);
+        assert_eq!(resolve_setup("GET", "/generate_204"), Route::SetupForm);
+        assert_eq!(resolve_setup("GET", "/style.css"), Route::Static { asset: 1 });
+        assert_eq!(resolve_setup("POST", "/setup"), Route::SetupSave);
+        assert_eq!(resolve_setup("PUT", "/setup"), Route::MethodNotAllowed { allow: "GET, HEAD, POST" });
+        assert_eq!(resolve_setup("POST", "/led"), Route::NotFound);
+    }
+
+    #[test]
+    fn state_changing_routes_need_auth() {
+        assert!(resolve("POST", "/led").requires_auth());
+        assert!(resolve("POST", "/api/stats/reset").requires_auth());
+        assert!(resolve("POST", "/api/gpio/humidifier").requires_auth());
+        assert!(resolve("POST", "/update").requires_auth());
+        assert!(!resolve("GET", "/api/sensor").requires_auth());
+        assert!(resolve("GET", "/api/sensor").requires_token());
+        assert!(!resolve("OPTIONS", "/api/sensor").requires_token());
+        assert!(!resolve("GET", "/style.css").requires_token());
+    }
+}
@@ -0,0 +1,62 @@
+/// Widest travel of a hobby servo, angles beyond are clamped to it
+pub const MAX_ANGLE: u8 = 180;
+/// One frame of the servo signal, 50 Hz
+pub const PERIOD_US: u16 = 20_000;
+
+/// Pulse width in µs for an angle, linear from `min_us` at 0° to `max_us` at 180°. A `max_us` below `min_us`
+/// turns a servo mounted the other way round.
+pub fn pulse_us(angle: u8, min_us: u16, max_us: u16) -> u16 {
+    let angle = i32::from(angle.min(MAX_ANGLE));
+    let (min_us, max_us) = (i32::from(min_us), i32::from(max_us));
+    (min_us + (max_us - min_us) * angle / i32::from(MAX_ANGLE)) as u16
+}
+
+/// The proportional rule of the auto mode: closed at 0° up to the humidity setpoint, opened `gain` degrees per %RH
+/// above it and fully open at 180°
+pub fn auto_angle(humidity: f32, setpoint: f32, gain: f32) -> u8 {
+    let angle = (humidity - setpoint) * gain;
+    // A NaN converts to 0, closed
+    libm::roundf(angle.clamp(0.0, f32::from(MAX_ANGLE))) as u8
+}
+
+/// Whether the auto mode moves to a new angle: the first one, a change of at least `min_change` degrees, so the
+/// flap doesn't chase every reading, or reaching closed or fully open
+pub fn should_move(current: Option<u8>, target: u8, min_change: u8) -> bool {
+    match current {
+        None => true,
+        Some(current) => current != target && (current.abs_diff(target) >= min_change || target == 0 || target == MAX_ANGLE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angles_map_to_pulses() {
+        assert_eq!(pulse_us(0, 1000, 2000), 1000);
+        assert_eq!(pulse_us(90, 1000, 2000), 1500);
+        assert_eq!(pulse_us(180, 1000, 2000), 2000);
+        assert_eq!(pulse_us(255, 1000, 2000), 2000);
+        assert_eq!(pulse_us(45, 2000, 1000), 1750);
+    }
+
+    #[test]
+    fn opens_above_the_setpoint() {
+        assert_eq!(auto_angle(70.0, 80.0, 10.0), 0);
+        assert_eq!(auto_angle(80.0, 80.0, 10.0), 0);
+        assert_eq!(auto_angle(84.5, 80.0, 10.0), 45);
+        assert_eq!(auto_angle(99.0, 80.0, 10.0), 180);
+        assert_eq!(auto_angle(f32::NAN, 80.0, 10.0), 0);
+    }
+
+    #[test]
+    fn small_moves_are_held_back() {
+        assert!(should_move(None, 0, 5));
+        assert!(!should_move(Some(40), 42, 5));
+        assert!(should_move(Some(40), 45, 5));
+        assert!(should_move(Some(3), 0, 5));
+        assert!(should_move(Some(178), 180, 5));
+        assert!(!should_move(Some(90), 90, 0));
+    }
+}
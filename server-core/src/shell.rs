@@ -0,0 +1,297 @@
+use {
+    core::str::from_utf8,
+    heapless::Vec,
+};
+
+/// Longest command line, further characters are ignored
+pub const LINE_LEN: usize = 128;
+/// Most samples `history` prints, the USB log buffer doesn't hold many more lines
+pub const MAX_HISTORY: usize = 20;
+const DEFAULT_HISTORY: usize = 10;
+/// More words than any command takes
+const MAX_TOKENS: usize = 4;
+
+pub const HELP: &str = "Commands:\r\n\
+    \x20 status                      address, signal and uptime\r\n\
+    \x20 read                        sample the sensors now and print the readings\r\n\
+    \x20 led on|off                  switch the LED\r\n\
+    \x20 wifi rejoin                 leave and join the network again\r\n\
+    \x20 wifi show                   the stored and the built in networks\r\n\
+    \x20 wifi set <ssid> [password]  store the network to join from the next boot on, \"quote\" names with spaces\r\n\
+    \x20 wifi clear                  forget the stored network\r\n\
+    \x20 history [count]             the latest samples, 10 by default, at most 20\r\n\
+    \x20 reboot                      restart the device\r\n\
+    \x20 help                        this listing";
+const WIFI_USAGE: &str = "wifi rejoin|show|clear or wifi set <ssid> [password]";
+
+/// What a key did to the line, the caller echoes it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Key {
+    /// A printable character was appended
+    Insert(u8),
+    /// The last character was removed
+    Erase,
+    /// The line is complete, `LineEditor::line` has it until `clear`
+    Enter,
+    /// Nothing changed: control characters, escape sequences, a full line or nothing left to erase
+    Ignore,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    /// After ESC
+    Start,
+    /// Inside `ESC [`, up to the final byte
+    Sequence,
+}
+
+/// Collects typed bytes into a line of printable ASCII. Backspace and DEL erase, CR, LF or CR LF end the line and
+/// escape sequences such as the arrow keys are dropped.
+pub struct LineEditor {
+    line: Vec<u8, LINE_LEN>,
+    escape: Escape,
+    after_cr: bool,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self { line: Vec::new(), escape: Escape::None, after_cr: false }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Key {
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match (self.escape, byte) {
+            (Escape::Start, b'[') => {
+                self.escape = Escape::Sequence;
+                return Key::Ignore;
+            },
+            (Escape::Start, _) => self.escape = Escape::None,
+            // Parameters and intermediates until the final byte
+            (Escape::Sequence, 0x20..=0x3f) => return Key::Ignore,
+            (Escape::Sequence, _) => {
+                self.escape = Escape::None;
+                return Key::Ignore;
+            },
+            (Escape::None, _) => {},
+        }
+
+        match byte {
+            0x1b => {
+                self.escape = Escape::Start;
+                Key::Ignore
+            },
+            b'\r' => {
+                self.after_cr = true;
+                Key::Enter
+            },
+            b'\n' if after_cr => Key::Ignore,
+            b'\n' => Key::Enter,
+            0x08 | 0x7f => match self.line.pop() {
+                Some(_) => Key::Erase,
+                None => Key::Ignore,
+            },
+            b' '..=b'~' => match self.line.push(byte) {
+                Ok(()) => Key::Insert(byte),
+                Err(_) => Key::Ignore,
+            },
+            _ => Key::Ignore,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        // Only printable ASCII gets in
+        from_utf8(&self.line).unwrap_or("")
+    }
+
+    pub fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command<'a> {
+    Help,
+    Status,
+    Read,
+    Led(bool),
+    WifiRejoin,
+    WifiShow,
+    /// Checked by the caller, an empty password is an open network
+    WifiSet { ssid: &'a str, password: &'a str },
+    WifiClear,
+    Reboot,
+    /// The latest samples, 1 to `MAX_HISTORY`
+    History(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParseError {
+    /// Not a command, answered with the help listing
+    Unknown,
+    /// A known command with wrong arguments, the usage to show
+    Usage(&'static str),
+}
+
+/// Words of a line separated by any whitespace, a word in double quotes keeps its whitespace. `Usage` with more
+/// than any command takes or a quote left open.
+pub fn tokenize(line: &str) -> Result<Vec<&str, MAX_TOKENS>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (token, after) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').ok_or(ParseError::Usage("a quote is never closed"))?,
+            None => rest.split_once(|char: char| char.is_ascii_whitespace()).unwrap_or((rest, "")),
+        };
+        tokens.push(token).map_err(|_| ParseError::Usage("too many arguments"))?;
+        rest = after.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The command of a line, `None` for an empty one. Command names are case insensitive.
+pub fn parse(line: &str) -> Result<Option<Command<'_>>, ParseError> {
+    let tokens = tokenize(line)?;
+    let Some((name, args)) = tokens.split_first() else {
+        return Ok(None);
+    };
+    let is = |expected: &str| name.eq_ignore_ascii_case(expected);
+
+    let command = if is("help") || *name == "?" {
+        no_args(args, Command::Help, "help")?
+    } else if is("status") {
+        no_args(args, Command::Status, "status")?
+    } else if is("read") {
+        no_args(args, Command::Read, "read")?
+    } else if is("reboot") {
+        no_args(args, Command::Reboot, "reboot")?
+    } else if is("led") {
+        match args {
+            [state] if state.eq_ignore_ascii_case("on") => Command::Led(true),
+            [state] if state.eq_ignore_ascii_case("off") => Command::Led(false),
+            _ => return Err(ParseError::Usage("led on|off")),
+        }
+    } else if is("wifi") {
+        match args {
+            [action] if action.eq_ignore_ascii_case("rejoin") => Command::WifiRejoin,
+            [action] if action.eq_ignore_ascii_case("show") => Command::WifiShow,
+            [action] if action.eq_ignore_ascii_case("clear") => Command::WifiClear,
+            [action, ssid] if action.eq_ignore_ascii_case("set") => Command::WifiSet { ssid, password: "" },
+            [action, ssid, password] if action.eq_ignore_ascii_case("set") => Command::WifiSet { ssid, password },
+            _ => return Err(ParseError::Usage(WIFI_USAGE)),
+        }
+    } else if is("history") {
+        let count = match args {
+            [] => DEFAULT_HISTORY,
+            [count] => count.parse().ok().filter(|count| (1..=MAX_HISTORY).contains(count))
+                .ok_or(ParseError::Usage("history [count], count from 1 to 20"))?,
+            _ => return Err(ParseError::Usage("history [count], count from 1 to 20")),
+        };
+        Command::History(count)
+    } else {
+        return Err(ParseError::Unknown);
+    };
+
+    Ok(Some(command))
+}
+
+fn no_args<'a>(args: &[&str], command: Command<'a>, usage: &'static str) -> Result<Command<'a>, ParseError> {
+    match args {
+        [] => Ok(command),
+        _ => Err(ParseError::Usage(usage)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_in(editor: &mut LineEditor, bytes: &[u8]) -> std::vec::Vec<Key> {
+        bytes.iter().map(|byte| editor.feed(*byte)).collect()
+    }
+
+    #[test]
+    fn backspace_and_delete_erase() {
+        let mut editor = LineEditor::new();
+        let keys = type_in(&mut editor, b"lef\x08d\x7f\x7fed");
+        assert_eq!(editor.line(), "led");
+        assert_eq!(keys[3], Key::Erase);
+        assert_eq!(type_in(&mut editor, b"\x08\x08\x08\x08"), [Key::Erase, Key::Erase, Key::Erase, Key::Ignore]);
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn cr_lf_ends_a_line_once() {
+        let mut editor = LineEditor::new();
+        assert_eq!(type_in(&mut editor, b"read\r\n"), [Key::Insert(b'r'), Key::Insert(b'e'), Key::Insert(b'a'), Key::Insert(b'd'), Key::Enter, Key::Ignore]);
+        assert_eq!(editor.line(), "read");
+        editor.clear();
+        assert_eq!(type_in(&mut editor, b"\n\r"), [Key::Enter, Key::Enter]);
+    }
+
+    #[test]
+    fn escape_sequences_and_control_characters_are_dropped() {
+        let mut editor = LineEditor::new();
+        type_in(&mut editor, b"st\x1b[Aat\x1b[1;5Cus\x01\t");
+        assert_eq!(editor.line(), "status");
+    }
+
+    #[test]
+    fn a_full_line_ignores_more() {
+        let mut editor = LineEditor::new();
+        type_in(&mut editor, &[b'x'; LINE_LEN]);
+        assert_eq!(editor.feed(b'y'), Key::Ignore);
+        assert_eq!(editor.line().len(), LINE_LEN);
+        assert_eq!(editor.feed(0x08), Key::Erase);
+    }
+
+    #[test]
+    fn tokens_split_on_any_whitespace() {
+        assert_eq!(tokenize("  led \t on  ").unwrap(), ["led", "on"]);
+        assert!(tokenize("   ").unwrap().is_empty());
+        assert_eq!(tokenize("a b c d e"), Err(ParseError::Usage("too many arguments")));
+    }
+
+    #[test]
+    fn quotes_keep_whitespace() {
+        assert_eq!(tokenize("wifi set \"My Network\" pass word").unwrap_err(), ParseError::Usage("too many arguments"));
+        assert_eq!(tokenize("wifi set \"My  Network\" \"pass word\"").unwrap(), ["wifi", "set", "My  Network", "pass word"]);
+        assert_eq!(tokenize("set \"\"").unwrap(), ["set", ""]);
+        assert_eq!(tokenize("set \"open"), Err(ParseError::Usage("a quote is never closed")));
+    }
+
+    #[test]
+    fn commands_and_their_arguments() {
+        assert_eq!(parse(""), Ok(None));
+        assert_eq!(parse("STATUS"), Ok(Some(Command::Status)));
+        assert_eq!(parse("led On"), Ok(Some(Command::Led(true))));
+        assert_eq!(parse("led off"), Ok(Some(Command::Led(false))));
+        assert_eq!(parse("wifi rejoin"), Ok(Some(Command::WifiRejoin)));
+        assert_eq!(parse("wifi Show"), Ok(Some(Command::WifiShow)));
+        assert_eq!(parse("wifi clear"), Ok(Some(Command::WifiClear)));
+        assert_eq!(parse("wifi set home secret123"), Ok(Some(Command::WifiSet { ssid: "home", password: "secret123" })));
+        assert_eq!(parse("wifi set \"Guest WiFi\""), Ok(Some(Command::WifiSet { ssid: "Guest WiFi", password: "" })));
+        assert_eq!(parse("history"), Ok(Some(Command::History(10))));
+        assert_eq!(parse("history 20"), Ok(Some(Command::History(20))));
+        assert_eq!(parse("?"), Ok(Some(Command::Help)));
+    }
+
+    #[test]
+    fn wrong_arguments_show_the_usage() {
+        assert_eq!(parse("led"), Err(ParseError::Usage("led on|off")));
+        assert_eq!(parse("led blink"), Err(ParseError::Usage("led on|off")));
+        assert_eq!(parse("wifi"), Err(ParseError::Usage(WIFI_USAGE)));
+        assert_eq!(parse("wifi set"), Err(ParseError::Usage(WIFI_USAGE)));
+        assert!(matches!(parse("history 0"), Err(ParseError::Usage(_))));
+        assert!(matches!(parse("history 21"), Err(ParseError::Usage(_))));
+        assert!(matches!(parse("history ten"), Err(ParseError::Usage(_))));
+        assert_eq!(parse("reboot now"), Err(ParseError::Usage("reboot")));
+        assert_eq!(parse("format"), Err(ParseError::Unknown));
+    }
+}
@@ -0,0 +1,502 @@
+//! Page templates with `{{name}}` placeholders, HTML-escaped, and `{{{name}}}` ones inserted as they are.
+//! `{{#if name}}...{{/if}}` keeps its content only when `name` has a non-empty value. The older `<!--#NAME-->`
+//! tags still work as aliases of `{{NAME}}` for one more release. A template is split into segments once with
+//! `compile`, every request then only walks them, `render` streams straight to the writer without buffering.
+
+use {
+    embedded_io_async::Write,
+    heapless::Vec,
+};
+
+/// Deepest nesting of `{{#if}}` blocks, a template going deeper is rejected
+pub const MAX_DEPTH: usize = 8;
+/// Literals and placeholders of the index template, with room to spare over what it takes with every optional block
+pub const INDEX_SEGMENTS: usize = 160;
+/// Literals and placeholders of the settings template
+pub const SETTINGS_SEGMENTS: usize = 48;
+const LEGACY_OPEN: &str = "<!--#";
+const LEGACY_CLOSE: &str = "-->";
+
+// Placeholder names the index page is rendered with
+pub const TEMP_TAG: &str = "TEMP";
+pub const TEMP_UNIT_TAG: &str = "TEMPUNIT";
+pub const UNIT_MODE_TAG: &str = "UNITMODE";
+pub const DECIMALS_TAG: &str = "DECIMALS";
+pub const HUMID_TAG: &str = "HUMID";
+pub const DEW_POINT_TAG: &str = "DEWPOINT";
+pub const HEAT_INDEX_TAG: &str = "HEATINDEX";
+pub const ABS_HUMID_TAG: &str = "ABSHUM";
+pub const CALIBRATION_TAG: &str = "CALIBRATION";
+pub const CHIP_TEMP_TAG: &str = "CHIPTEMP";
+pub const INTERVAL_TAG: &str = "INTERVAL";
+pub const TEMP_TREND_TAG: &str = "TTREND";
+pub const HUMID_TREND_TAG: &str = "HTREND";
+pub const ALERT_TAG: &str = "ALERT";
+pub const TMIN_TAG: &str = "TMIN";
+pub const TMAX_TAG: &str = "TMAX";
+pub const HMIN_TAG: &str = "HMIN";
+pub const HMAX_TAG: &str = "HMAX";
+pub const STALE_TAG: &str = "STALE";
+pub const AGE_TAG: &str = "AGE";
+pub const TIME_TAG: &str = "TIME";
+pub const RSSI_TAG: &str = "RSSI";
+/// `good`, `ok`, `poor` or `unknown`, used as CSS class suffix
+pub const SIGNAL_TAG: &str = "SIGNAL";
+pub const HOSTNAME_TAG: &str = "HOSTNAME";
+/// The device name, in the title and the header
+pub const NAME_TAG: &str = "NAME";
+pub const MAC_TAG: &str = "MAC";
+pub const IP_TAG: &str = "IP";
+pub const SSID_TAG: &str = "SSID";
+/// Like `3d 4h 12m`
+pub const UPTIME_TAG: &str = "UPTIME";
+pub const LED_TAG: &str = "LED";
+/// Like `40 % (auto, 1200 RPM)`, empty without a fan
+pub const FAN_TAG: &str = "FAN";
+/// Like `45° (auto)`, empty without a servo
+pub const SERVO_TAG: &str = "SERVO";
+/// `sounding` or `silenced until the alert clears` while an alert is active, empty without a buzzer
+pub const ALARM_TAG: &str = "ALARM";
+/// Non-empty while the buzzer sounds, for the button that silences it
+pub const ALARM_SOUNDING_TAG: &str = "ALARMON";
+/// Firmware version and commit, like `0.1.0 (1a2b3c4)`
+pub const VERSION_TAG: &str = "VERSION";
+/// Only used indexed, `{{LABEL0}}` is the label of the first sensor
+pub const LABEL_TAG: &str = "LABEL";
+/// Only used indexed, `{{GPIONAME0}}` is the name of the first output and empty when there is none
+pub const GPIO_NAME_TAG: &str = "GPIONAME";
+/// Only used indexed, `{{GPIO0}}` is `ON` or `OFF` for the first output
+pub const GPIO_TAG: &str = "GPIO";
+
+// The settings page has `HOSTNAME`, these two and a placeholder named like each form field
+/// Why the submitted form was rejected, empty otherwise
+pub const ERROR_TAG: &str = "ERROR";
+/// Non-empty right after a save
+pub const SAVED_TAG: &str = "SAVED";
+
+/// Piece of a compiled template, the names borrow from the template text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment<'a> {
+    Literal(&'a str),
+    /// `{{name}}`, HTML-escaped
+    Escaped(&'a str),
+    /// `{{{name}}}`, as it is
+    Raw(&'a str),
+    /// `<!--#NAME-->`, escaped like `{{NAME}}` but written back unchanged when `NAME` is unknown
+    Legacy(&'a str),
+    /// `{{#if name}}`, a false condition continues at segment `end`, right after the block
+    If { name: &'a str, end: usize },
+}
+
+impl Segment<'_> {
+    pub fn is_literal(&self) -> bool {
+        matches!(self, Segment::Literal(_))
+    }
+}
+
+/// Why a template could not be compiled
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A `{{` without its closing braces
+    Unclosed,
+    /// A `{{/if}}` without its `{{#if}}` or a block still open at the end
+    Unbalanced,
+    /// `{{#if}}` blocks nested deeper than `MAX_DEPTH`
+    TooDeep,
+    /// More segments than the list holds
+    TooLong,
+}
+
+fn lookup<'a>(context: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    context.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+/// Split `template` into at most `N` segments. `{{/if}}` leaves no segment of its own, its `{{#if}}` points past it.
+pub fn compile<const N: usize>(template: &str) -> Result<Vec<Segment<'_>, N>, Error> {
+    let mut segments = Vec::<Segment, N>::new();
+    // Indices of the `If` segments whose `{{/if}}` is still to come
+    let mut open = Vec::<usize, MAX_DEPTH>::new();
+    let mut rest = template;
+
+    loop {
+        let pos = match (rest.find("{{"), rest.find(LEGACY_OPEN)) {
+            (Some(placeholder), Some(legacy)) => placeholder.min(legacy),
+            (Some(pos), None) | (None, Some(pos)) => pos,
+            (None, None) => rest.len(),
+        };
+        if pos > 0 {
+            segments.push(Segment::Literal(&rest[..pos])).map_err(|_| Error::TooLong)?;
+        }
+        rest = &rest[pos..];
+        if rest.is_empty() {
+            break;
+        }
+
+        if let Some(tag) = rest.strip_prefix(LEGACY_OPEN) {
+            // Not a well formed tag, kept as text up to the next candidate
+            let segment = match tag.split_once(LEGACY_CLOSE) {
+                Some((name, after)) if is_name(name) => {
+                    rest = after;
+                    Segment::Legacy(name)
+                },
+                _ => {
+                    rest = tag;
+                    Segment::Literal(LEGACY_OPEN)
+                },
+            };
+            segments.push(segment).map_err(|_| Error::TooLong)?;
+            continue;
+        }
+
+        let (raw, open_braces, close_braces) = match rest.starts_with("{{{") {
+            true => (true, "{{{", "}}}"),
+            false => (false, "{{", "}}"),
+        };
+        let (inner, after) = rest[open_braces.len()..].split_once(close_braces).ok_or(Error::Unclosed)?;
+        let inner = inner.trim();
+        rest = after;
+
+        let segment = if let Some(name) = inner.strip_prefix("#if ").filter(|_| !raw) {
+            open.push(segments.len()).map_err(|_| Error::TooDeep)?;
+            Segment::If { name: name.trim(), end: 0 }
+        } else if inner == "/if" && !raw {
+            let start = open.pop().ok_or(Error::Unbalanced)?;
+            let end = segments.len();
+            if let Segment::If { end: block_end, .. } = &mut segments[start] {
+                *block_end = end;
+            }
+            continue;
+        } else if raw {
+            Segment::Raw(inner)
+        } else {
+            Segment::Escaped(inner)
+        };
+        segments.push(segment).map_err(|_| Error::TooLong)?;
+    }
+
+    match open.is_empty() {
+        true => Ok(segments),
+        false => Err(Error::Unbalanced),
+    }
+}
+
+/// Legacy tags are single upper case words, anything else after `<!--#` is a comment
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+/// Index of the segment after `index`, skipping a block whose condition is false
+fn next(segments: &[Segment], context: &[(&str, &str)], index: usize) -> usize {
+    match segments[index] {
+        Segment::If { name, end } if lookup(context, name).is_none_or(str::is_empty) => end,
+        _ => index + 1,
+    }
+}
+
+fn escaped_len(value: &str) -> usize {
+    value.bytes().map(|byte| match byte {
+        b'&' => "&amp;".len(),
+        b'<' | b'>' => "&lt;".len(),
+        b'"' => "&quot;".len(),
+        b'\'' => "&#39;".len(),
+        _ => 1,
+    }).sum()
+}
+
+/// Value with `&`, `<`, `>` and quotes escaped for HTML text and attribute values
+async fn write_escaped<W: Write>(out: &mut W, value: &str) -> Result<(), W::Error> {
+    let mut rest = value;
+
+    while let Some(pos) = rest.find(['&', '<', '>', '"', '\'']) {
+        out.write_all(&rest.as_bytes()[..pos]).await?;
+        let entity = match rest.as_bytes()[pos] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+            _ => "&#39;",
+        };
+        out.write_all(entity.as_bytes()).await?;
+        rest = &rest[pos + 1..];
+    }
+
+    out.write_all(rest.as_bytes()).await
+}
+
+/// Bytes `render` writes for the same segments and context, for the Content-Length
+pub fn rendered_len(segments: &[Segment], context: &[(&str, &str)]) -> usize {
+    let mut len = 0;
+    let mut index = 0;
+
+    while index < segments.len() {
+        len += match segments[index] {
+            Segment::Literal(text) => text.len(),
+            Segment::Escaped(name) => lookup(context, name).map_or(0, escaped_len),
+            Segment::Raw(name) => lookup(context, name).map_or(0, str::len),
+            Segment::Legacy(name) => match lookup(context, name) {
+                Some(value) => escaped_len(value),
+                None => LEGACY_OPEN.len() + name.len() + LEGACY_CLOSE.len(),
+            },
+            Segment::If { .. } => 0,
+        };
+        index = next(segments, context, index);
+    }
+
+    len
+}
+
+/// Write the compiled template to `out`, resolving placeholders from `context`. The text around them is copied
+/// byte for byte, line endings included. An unknown name is empty, an unknown `<!--#NAME-->` is written back since
+/// it may be a plain comment. A writer that runs out of room ends the render with its error.
+pub async fn render<W: Write>(segments: &[Segment<'_>], context: &[(&str, &str)], out: &mut W) -> Result<(), W::Error> {
+    let mut index = 0;
+
+    while index < segments.len() {
+        match segments[index] {
+            Segment::Literal(text) => out.write_all(text.as_bytes()).await?,
+            Segment::Escaped(name) => write_escaped(out, lookup(context, name).unwrap_or("")).await?,
+            Segment::Raw(name) => out.write_all(lookup(context, name).unwrap_or("").as_bytes()).await?,
+            Segment::Legacy(name) => match lookup(context, name) {
+                Some(value) => write_escaped(out, value).await?,
+                None => {
+                    out.write_all(LEGACY_OPEN.as_bytes()).await?;
+                    out.write_all(name.as_bytes()).await?;
+                    out.write_all(LEGACY_CLOSE.as_bytes()).await?;
+                },
+            },
+            Segment::If { .. } => {},
+        }
+        index = next(segments, context, index);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        core::convert::Infallible,
+        embassy_futures::block_on,
+        embedded_io_async::ErrorType,
+    };
+
+    /// Enough for the 8 KiB template below
+    const SEGMENTS: usize = 2048;
+
+    struct Page(std::vec::Vec<u8>);
+
+    impl ErrorType for Page {
+        type Error = Infallible;
+    }
+
+    impl Write for Page {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn try_render(template: &str, context: &[(&str, &str)]) -> Result<std::string::String, Error> {
+        let segments = compile::<SEGMENTS>(template)?;
+        let mut page = Page(std::vec::Vec::new());
+        let Ok(()) = block_on(super::render(&segments, context, &mut page));
+        assert_eq!(page.0.len(), rendered_len(&segments, context));
+        Ok(std::string::String::from_utf8(page.0).unwrap())
+    }
+
+    fn render(template: &str, context: &[(&str, &str)]) -> std::string::String {
+        try_render(template, context).unwrap()
+    }
+
+    #[test]
+    fn placeholders_at_start_and_end_of_line() {
+        assert_eq!(render("{{TEMP}} °C", &[(TEMP_TAG, "21.5")]), "21.5 °C");
+        assert_eq!(render("Temp: {{ TEMP }}", &[(TEMP_TAG, "21.5")]), "Temp: 21.5");
+    }
+
+    #[test]
+    fn several_placeholders_on_one_line() {
+        let context = [(TEMP_TAG, "21.5"), (HUMID_TAG, "40")];
+        assert_eq!(render("{{HUMID}} % / {{TEMP}} / {{HUMID}}", &context), "40 % / 21.5 / 40");
+    }
+
+    #[test]
+    fn unknown_name_is_empty() {
+        assert_eq!(render("<p>{{OTHER}}</p>", &[(TEMP_TAG, "21.5")]), "<p></p>");
+    }
+
+    #[test]
+    fn values_are_escaped_unless_raw() {
+        let context = [("SSID", "<script>alert('x') & \"y\"</script>")];
+        assert_eq!(render("{{SSID}}", &context), "&lt;script&gt;alert(&#39;x&#39;) &amp; &quot;y&quot;&lt;/script&gt;");
+        assert_eq!(render("<!--#SSID-->", &context), "&lt;script&gt;alert(&#39;x&#39;) &amp; &quot;y&quot;&lt;/script&gt;");
+        assert_eq!(render("{{{SSID}}}", &context), "<script>alert('x') & \"y\"</script>");
+    }
+
+    #[test]
+    fn value_looking_like_a_placeholder_is_not_substituted() {
+        let context = [(TEMP_TAG, "{{HUMID}}<!--#HUMID-->"), (HUMID_TAG, "40")];
+        assert_eq!(render("{{{TEMP}}}", &context), "{{HUMID}}<!--#HUMID-->");
+    }
+
+    #[test]
+    fn conditional_blocks() {
+        let context = [(ALERT_TAG, "Alert: too hot"), (CALIBRATION_TAG, "")];
+        assert_eq!(render("{{#if ALERT}}<p>{{ALERT}}</p>{{/if}}", &context), "<p>Alert: too hot</p>");
+        assert_eq!(render("a{{#if CALIBRATION}}<p>{{CALIBRATION}}</p>{{/if}}b", &context), "ab");
+        assert_eq!(render("a{{#if MISSING}}x{{/if}}b", &context), "ab");
+    }
+
+    #[test]
+    fn nested_blocks_inside_a_false_one_are_skipped() {
+        let context = [("ON", "1"), ("OFF", "")];
+        assert_eq!(render("{{#if OFF}}a{{#if ON}}b{{/if}}c{{/if}}d", &context), "d");
+        assert_eq!(render("{{#if ON}}a{{#if OFF}}b{{/if}}c{{/if}}d", &context), "acd");
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        assert_eq!(try_render("{{TEMP", &[]), Err(Error::Unclosed));
+        assert_eq!(try_render("{{/if}}", &[]), Err(Error::Unbalanced));
+        assert_eq!(try_render("{{#if TEMP}}x", &[]), Err(Error::Unbalanced));
+
+        let mut deep = std::string::String::new();
+        for _ in 0..=MAX_DEPTH {
+            deep.push_str("{{#if TEMP}}");
+        }
+        for _ in 0..=MAX_DEPTH {
+            deep.push_str("{{/if}}");
+        }
+        assert_eq!(try_render(&deep, &[]), Err(Error::TooDeep));
+    }
+
+    #[test]
+    fn legacy_tags_are_aliases() {
+        let context = [(TEMP_TAG, "21.5"), (TEMP_UNIT_TAG, "°C")];
+        assert_eq!(render("<!--#TEMP--> {{TEMP}}", &context), "21.5 21.5");
+        // `<!--#TEMP-->` must not be taken for the start of `<!--#TEMPUNIT-->`
+        assert_eq!(render("<!--#TEMP--><!--#TEMPUNIT-->", &context), "21.5°C");
+    }
+
+    #[test]
+    fn unknown_legacy_tag_and_comments_are_kept() {
+        assert_eq!(render("<!--#OTHER--> <!-- note -->", &[(TEMP_TAG, "21.5")]), "<!--#OTHER--> <!-- note -->");
+    }
+
+    #[test]
+    fn unknown_tags_leave_the_rest_alone() {
+        let context = [(TEMP_TAG, "21.5")];
+        assert_eq!(render("{{OTHER}}{{TEMP}}{{{OTHER}}}", &context), "21.5");
+        assert_eq!(render("{{#if OTHER}}x{{/if}}{{TEMP}}", &context), "21.5");
+        // Names are case sensitive
+        assert_eq!(render("{{temp}}<!--#temp-->", &context), "<!--#temp-->");
+    }
+
+    #[test]
+    fn tags_cut_off_at_the_end() {
+        let context = [(TEMP_TAG, "21.5")];
+        assert_eq!(try_render("a {{", &context), Err(Error::Unclosed));
+        assert_eq!(try_render("a {{TEMP}", &context), Err(Error::Unclosed));
+        assert_eq!(try_render("a {{{TEMP}}", &context), Err(Error::Unclosed));
+        assert_eq!(try_render("a {{#if TEMP", &context), Err(Error::Unclosed));
+        // An unfinished legacy tag is only text, like any other comment
+        assert_eq!(render("a <!--#TEMP", &context), "a <!--#TEMP");
+        assert_eq!(render("a <!--#TEMP--", &context), "a <!--#TEMP--");
+        assert_eq!(render("a <!--#", &context), "a <!--#");
+    }
+
+    #[test]
+    fn adjacent_and_nested_tags() {
+        let context = [(TEMP_TAG, "21.5"), (HUMID_TAG, "40")];
+        assert_eq!(render("{{TEMP}}{{HUMID}}<!--#TEMP-->{{{HUMID}}}<!--#HUMID-->", &context), "21.54021.54040");
+        assert_eq!(render("{{#if TEMP}}{{/if}}{{#if HUMID}}{{HUMID}}{{/if}}", &context), "40");
+        // A tag inside another one is not a tag, the outer one ends at the first closing braces
+        assert_eq!(render("{{ {{TEMP}} }}", &context), " }}");
+        assert_eq!(render("<!--#<!--#TEMP-->-->", &context), "<!--#21.5-->");
+    }
+
+    #[test]
+    fn line_endings_are_kept() {
+        let context = [(TEMP_TAG, "21.5")];
+        assert_eq!(render("a\n{{TEMP}}\nb\n", &context), "a\n21.5\nb\n");
+        assert_eq!(render("a\r\n{{TEMP}}\r\nb\r\n", &context), "a\r\n21.5\r\nb\r\n");
+        assert_eq!(render("a\n<!--#TEMP-->", &context), "a\n21.5");
+        assert_eq!(render("", &context), "");
+    }
+
+    #[test]
+    fn large_template_is_not_cut() {
+        let mut template = std::string::String::new();
+        while template.len() <= 8192 {
+            template.push_str("<p>{{TEMP}}</p>\n");
+        }
+        assert_eq!(render(&template, &[(TEMP_TAG, "21.5")]), template.replace("{{TEMP}}", "21.5"));
+    }
+
+    /// Takes at most `N` bytes and then fails like a full socket buffer
+    struct Bounded<const N: usize>(usize);
+
+    #[derive(Debug, PartialEq)]
+    struct Full;
+
+    impl embedded_io_async::Error for Full {
+        fn kind(&self) -> embedded_io_async::ErrorKind {
+            embedded_io_async::ErrorKind::OutOfMemory
+        }
+    }
+
+    impl<const N: usize> ErrorType for Bounded<N> {
+        type Error = Full;
+    }
+
+    impl<const N: usize> Write for Bounded<N> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            if self.0 + buf.len() > N {
+                return Err(Full);
+            }
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn full_writer_is_an_error() {
+        let segments = compile::<8>("<p>{{TEMP}}</p>").unwrap();
+        let mut out = Bounded::<8>(0);
+        assert_eq!(block_on(super::render(&segments, &[(TEMP_TAG, "21.5")], &mut out)), Err(Full));
+    }
+
+    #[test]
+    fn rendered_len_counts_escapes_and_skipped_blocks() {
+        let template = "<title>{{HOSTNAME}}</title>\n{{#if TEMP}}<!--#TEMP-->{{/if}}{{#if STALE}}stale{{/if}}<!--#OTHER-->";
+        let context = [(HOSTNAME_TAG, "pico & co"), (TEMP_TAG, "21.5")];
+        let segments = compile::<16>(template).unwrap();
+        assert_eq!(rendered_len(&segments, &context), "<title>pico &amp; co</title>\n21.5<!--#OTHER-->".len());
+    }
+
+    #[test]
+    fn compiled_segments() {
+        let segments = compile::<8>("a{{#if ON}}{{X}}{{/if}}{{{Y}}}").unwrap();
+        assert_eq!(segments.as_slice(), [
+            Segment::Literal("a"),
+            Segment::If { name: "ON", end: 3 },
+            Segment::Escaped("X"),
+            Segment::Raw("Y"),
+        ]);
+        assert_eq!(compile::<2>("a{{X}}b"), Err(Error::TooLong));
+    }
+
+    #[test]
+    fn pages_fit_the_firmware_segment_lists() {
+        assert!(compile::<INDEX_SEGMENTS>(include_str!("../../server/src/html/index.html")).is_ok());
+        assert!(compile::<SETTINGS_SEGMENTS>(include_str!("../../server/src/html/settings.html")).is_ok());
+    }
+
+    #[test]
+    fn index_page_renders_every_placeholder() {
+        let page = render(include_str!("../../server/src/html/index.html"), &[(TEMP_TAG, "21.5")]);
+        assert!(!page.contains("{{"));
+        assert!(!page.contains("<!--#"));
+    }
+}
@@ -0,0 +1,46 @@
+use core::fmt;
+
+/// Seconds since boot as `3d 4h 12m`, the larger units only once they are reached and the seconds left out
+pub struct Uptime(pub u64);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (days, hours, minutes) = (self.0 / 86_400, self.0 / 3600 % 24, self.0 / 60 % 60);
+        match (days, hours) {
+            (0, 0) => write!(f, "{}m", minutes),
+            (0, _) => write!(f, "{}h {}m", hours, minutes),
+            _ => write!(f, "{}d {}h {}m", days, hours, minutes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(secs: u64) -> String {
+        Uptime(secs).to_string()
+    }
+
+    #[test]
+    fn minutes_only_below_an_hour() {
+        assert_eq!(format(0), "0m");
+        assert_eq!(format(59), "0m");
+        assert_eq!(format(60), "1m");
+        assert_eq!(format(3599), "59m");
+    }
+
+    #[test]
+    fn hours_roll_over_into_days() {
+        assert_eq!(format(3600), "1h 0m");
+        assert_eq!(format(86_399), "23h 59m");
+        assert_eq!(format(86_400), "1d 0h 0m");
+        assert_eq!(format(86_400 + 59), "1d 0h 0m");
+    }
+
+    #[test]
+    fn days_hours_and_minutes() {
+        assert_eq!(format(3 * 86_400 + 4 * 3600 + 12 * 60 + 30), "3d 4h 12m");
+        assert_eq!(format(400 * 86_400 + 23 * 3600 + 59 * 60), "400d 23h 59m");
+    }
+}
@@ -1,36 +1,758 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! This build script copies the memory layout of the board, `memory-pico-w.x`
+//! or `memory-pico2-w.x`, from the crate root into a directory where the
+//! linker finds it as `memory.x` at build time. There is deliberately no
+//! `memory.x` in the crate root: the linker searches the project root
+//! directory -- wherever `Cargo.toml` is -- before the search path, so one
+//! there would win over the board's. Additionally, by requesting that
+//! Cargo re-run the build script whenever a layout is changed, updating
+//! one ensures a rebuild of the application with the new memory settings.
+//!
+//! It also gzips every file under `src/html/` into `OUT_DIR`, so static
+//! assets can be served precompressed to clients that accept it, and generates
+//! `config.rs` there: every setting as a typed constant, taken from
+//! `server-config.toml` when the file sets it, then from the environment
+//! variable of the same name in capitals, then from its default. A value that
+//! doesn't parse stops the build with the name and where it came from.
+//!
+//! The git short hash and the build time are handed to the firmware as the
+//! `GIT_HASH` and `BUILD_TIMESTAMP` variables, see `capture_build_info`.
 
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+const HTML_DIR: &str = "src/html";
+/// GPIOs taken by the CYW43 driver on the Pico W
+const RESERVED_PINS: [u8; 4] = [23, 24, 25, 29];
+/// SDA and SCL of I2C0, taken with the `oled` or the `rtc` feature
+const I2C_PINS: [u8; 2] = [4, 5];
+/// The GPIOs SPI0's clock, TX and RX can be routed to, the same on both chips
+const SPI0_SCK_PINS: [u8; 4] = [2, 6, 18, 22];
+const SPI0_MOSI_PINS: [u8; 3] = [3, 7, 19];
+const SPI0_MISO_PINS: [u8; 4] = [0, 4, 16, 20];
+/// Built in networks, the one saved through the setup page comes on top
+const MAX_WIFI_NETWORKS: usize = 4;
+/// Optional settings file next to `Cargo.toml`, kept out of git since it holds passwords
+const CONFIG_FILE: &str = "server-config.toml";
+/// Longest hostname the DHCP client sends
+const HOSTNAME_LEN: usize = 32;
+/// Named outputs at most, the index page has a button for each of the first four
+const MAX_GPIO_OUTPUTS: usize = 4;
+/// Longest output name, the last path segment of `/api/gpio/<name>`
+const GPIO_NAME_LEN: usize = 16;
+/// Memory layouts by board feature, linked as `memory.x`
+const MEMORY_LAYOUTS: [(&str, &str, &[u8]); 2] = [
+    ("CARGO_FEATURE_PICO_W", "memory-pico-w.x", include_bytes!("memory-pico-w.x")),
+    ("CARGO_FEATURE_PICO2_W", "memory-pico2-w.x", include_bytes!("memory-pico2-w.x")),
+];
+/// Every setting, the environment variable name and the key of `server-config.toml` in lower case
+const SETTINGS: &[&str] = &[
+    "WIFI_NETWORK", "WIFI_PASSWORD", "WIFI_NETWORKS", "WIFI_PM", "HOSTNAME", "DEVICE_NAME", "SERVER_PORT", "SETUP_AP_PASSWORD",
+    "HTTP_AUTH_USER", "HTTP_AUTH_PASS", "API_TOKEN", "CORS_ORIGIN", "NTP_SERVER", "MQTT_BROKER", "MQTT_PORT",
+    "MQTT_TOPIC_PREFIX", "HA_DISCOVERY", "DISCOVERY_PORT", "DISCOVERY_BEACON", "SYSLOG_SERVER", "SYSLOG_PORT",
+    "TEMP_UNIT", "TEMP_OFFSET", "HUMID_OFFSET", "STATIC_IP", "STATIC_NETMASK", "STATIC_GATEWAY", "STATIC_DNS",
+    "TEMP_HIGH", "TEMP_LOW", "HUMID_HIGH", "HUMID_LOW", "SAMPLE_INTERVAL_S", "SPIKE_TEMP_LIMIT", "SPIKE_HUMID_LIMIT",
+    "DHT_PINS", "DHT_LABELS", "GPIO_OUTPUTS", "FAN_PIN", "FAN_TACH_PIN", "FAN_CURVE", "FAN_MIN_CHANGE",
+    "FAN_FAILSAFE_DUTY", "BUZZER_PIN", "BUZZER_TYPE", "BUZZER_TONE_HZ", "BUZZER_BEEPS", "BUZZER_BEEP_MS",
+    "BUZZER_PERIOD_S", "SERVO_PIN", "SERVO_MIN_PULSE_US", "SERVO_MAX_PULSE_US", "SERVO_HOLD_MS", "SERVO_SETPOINT",
+    "SERVO_GAIN", "SERVO_MIN_CHANGE", "BUTTON_PIN", "PIXEL_PIN", "PIXEL_COUNT", "PIXEL_BRIGHTNESS", "SD_CS_PIN", "SD_SCK_PIN", "SD_MOSI_PIN", "SD_MISO_PIN", "SD_FLUSH_EVERY",
+    "LOG_LEVEL", "LOG_MODULES",
+];
+
+fn gzip_html_files(out: &Path) {
+    for entry in fs::read_dir(HTML_DIR).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let mut file_name = path.file_name().unwrap().to_os_string();
+        file_name.push(".gz");
+
+        let mut encoder = GzEncoder::new(File::create(out.join(file_name)).unwrap(), Compression::best());
+        encoder.write_all(&fs::read(&path).unwrap()).unwrap();
+        encoder.finish().unwrap();
+    }
+}
+
+/// Where each value comes from: `server-config.toml` first, then the environment
+struct Settings {
+    file: HashMap<String, String>,
+}
+
+impl Settings {
+    fn load() -> Self {
+        let file = match fs::read_to_string(CONFIG_FILE) {
+            Ok(text) => parse_config_file(&text),
+            Err(_) => HashMap::new(),
+        };
+        Self { file }
+    }
+
+    fn in_file(&self, name: &str) -> Option<String> {
+        self.file.get(&name.to_lowercase()).cloned()
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.in_file(name).or_else(|| env::var(name).ok())
+    }
+
+    fn source(&self, name: &str) -> String {
+        match self.file.contains_key(&name.to_lowercase()) {
+            true => format!("{} in {}", name.to_lowercase(), CONFIG_FILE),
+            false => name.to_string(),
+        }
+    }
+
+    /// Stops the build naming the setting, its value and what it should have been
+    fn invalid(&self, name: &str, value: &str, expected: &str) -> ! {
+        panic!("{} = {:?} is invalid, expected {}", self.source(name), value, expected)
+    }
+
+    /// Surrounding whitespace is dropped, except in passwords and tokens which are taken as written
+    fn text(&self, name: &str, default: &str) -> String {
+        match self.get(name) {
+            Some(value) if name.ends_with("_PASS") || name.ends_with("_PASSWORD") || name.ends_with("_TOKEN") => value,
+            Some(value) => value.trim().to_string(),
+            None => default.to_string(),
+        }
+    }
+
+    fn port(&self, name: &str, default: u16) -> u16 {
+        let value = self.text(name, &default.to_string());
+        match value.parse::<u16>() {
+            Ok(port) if port != 0 => port,
+            _ => self.invalid(name, &value, "a port from 1 to 65535"),
+        }
+    }
+
+    /// `1`/`true` or `0`/`false`, an empty value is off
+    fn flag(&self, name: &str) -> bool {
+        let value = self.text(name, "0");
+        match value.as_str() {
+            "1" | "true" => true,
+            "0" | "false" | "" => false,
+            _ => self.invalid(name, &value, "1 or 0"),
+        }
+    }
+
+    fn whole(&self, name: &str, default: u32, range: RangeInclusive<u32>) -> u32 {
+        let value = self.text(name, &default.to_string());
+        match value.parse::<u32>() {
+            Ok(number) if range.contains(&number) => number,
+            _ => self.invalid(name, &value, &format!("a whole number from {} to {}", range.start(), range.end())),
+        }
+    }
+
+    fn number(&self, name: &str, default: f32) -> f32 {
+        let value = self.text(name, &format!("{:?}", default));
+        self.parse_number(name, &value)
+    }
+
+    /// An empty value disables the limit
+    fn limit(&self, name: &str, default: &str) -> Option<f32> {
+        let value = self.text(name, default);
+        (!value.is_empty()).then(|| self.parse_number(name, &value))
+    }
+
+    fn parse_number(&self, name: &str, value: &str) -> f32 {
+        match value.parse::<f32>() {
+            Ok(number) if number.is_finite() => number,
+            _ => self.invalid(name, value, "a number"),
+        }
+    }
+}
+
+/// The subset of TOML the settings need: `key = value` lines with a string, number, boolean or single line array of
+/// them, and `#` comments. Booleans become `1`/`0` and arrays the comma separated list the variables take, `;`
+/// separated for `wifi_networks`. Unknown keys stop the build so a typo doesn't silently fall back to the default.
+fn parse_config_file(text: &str) -> HashMap<String, String> {
+    let mut settings = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let fail = |message: &str| -> ! { panic!("{}:{}: {}", CONFIG_FILE, number + 1, message) };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            fail("tables are not supported, every key goes at the top level");
+        }
+
+        let Some((key, value)) = line.split_once('=') else { fail("expected key = value") };
+        let key = key.trim();
+        if !SETTINGS.iter().any(|name| name.to_lowercase() == key) {
+            fail(&format!("unknown key {:?}", key));
+        }
+
+        let separator = if key == "wifi_networks" { ";" } else { "," };
+        let value = match value.trim().strip_prefix('[') {
+            Some(items) => {
+                let (items, rest) = items.split_once(']').unwrap_or_else(|| fail("arrays must end on the same line"));
+                end_of_value(rest).unwrap_or_else(|| fail("unexpected text after the array"));
+                let mut list = Vec::new();
+                let mut items = items.trim();
+                while !items.is_empty() {
+                    let (item, rest) = parse_scalar(items).unwrap_or_else(|| fail("unterminated string"));
+                    list.push(item);
+                    items = rest.trim_start().strip_prefix(',').unwrap_or(rest).trim_start();
+                }
+                list.join(separator)
+            },
+            None => {
+                let (scalar, rest) = parse_scalar(value.trim()).unwrap_or_else(|| fail("unterminated string"));
+                end_of_value(rest).unwrap_or_else(|| fail("unexpected text after the value"));
+                scalar
+            },
+        };
+        if settings.insert(key.to_string(), value).is_some() {
+            fail(&format!("{} is set twice", key));
+        }
+    }
+    settings
+}
+
+/// The first value of `text` and what follows it, a basic string takes the `\"`, `\\`, `\n` and `\t` escapes
+fn parse_scalar(text: &str) -> Option<(String, &str)> {
+    let Some(string) = text.strip_prefix('"') else {
+        let end = text.find([',', ']', '#']).unwrap_or(text.len());
+        let bare = text[..end].trim();
+        let value = match bare {
+            "true" => "1",
+            "false" => "0",
+            bare => bare,
+        };
+        return Some((value.to_string(), &text[end..]));
+    };
+
+    let mut value = String::new();
+    let mut chars = string.char_indices();
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '"' => return Some((value, &string[index + 1..])),
+            '\\' => value.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                escaped => escaped,
+            }),
+            char => value.push(char),
+        }
+    }
+    None
+}
+
+/// Only whitespace or a comment may follow a value
+fn end_of_value(rest: &str) -> Option<()> {
+    let rest = rest.trim();
+    (rest.is_empty() || rest.starts_with('#')).then_some(())
+}
+
+/// A single DNS label short enough for DHCP: letters, digits and hyphens, no hyphen at either end
+fn is_valid_hostname(name: &str) -> bool {
+    (1..=HOSTNAME_LEN).contains(&name.len())
+        && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Stops the build when a pin setting names a pin the firmware uses itself
+fn check_unreserved(name: &str, pin: u8) {
+    assert!(!RESERVED_PINS.contains(&pin), "{}: GPIO{} is used by the CYW43", name, pin);
+    let i2c = env::var_os("CARGO_FEATURE_OLED").is_some() || env::var_os("CARGO_FEATURE_RTC").is_some();
+    assert!(!(i2c && I2C_PINS.contains(&pin)), "{}: GPIO{} is used by the I2C bus of the display and the RTC", name, pin);
+}
+
+/// `DHT_PINS="2,3"` and optional `DHT_LABELS="indoor,outdoor"` become `DHT_PINS` and `DHT_LABELS` arrays, the pins
+/// are returned for the checks of the other pin settings
+fn generate_dht_pins(settings: &Settings, generated: &mut impl Write) -> Vec<u8> {
+    let pins_env = settings.get("DHT_PINS").unwrap_or_else(|| "2".into());
+    let pins: Vec<u8> = pins_env
+        .split(',')
+        .map(|pin| pin.trim().parse().unwrap_or_else(|_| panic!("DHT_PINS: invalid pin {:?}", pin)))
+        .collect();
+
+    for (index, pin) in pins.iter().enumerate() {
+        assert!(*pin <= 28, "DHT_PINS: GPIO{} does not exist", pin);
+        check_unreserved("DHT_PINS", *pin);
+        assert!(!pins[..index].contains(pin), "DHT_PINS: GPIO{} is listed twice", pin);
+    }
+
+    let labels_env = settings.get("DHT_LABELS").unwrap_or_default();
+    let mut labels: Vec<String> = labels_env
+        .split(',')
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect();
+    assert!(labels.len() <= pins.len(), "DHT_LABELS: more labels than DHT_PINS");
+    for index in labels.len()..pins.len() {
+        labels.push(format!("sensor{}", index));
+    }
+
+    writeln!(generated, "pub const DHT_PINS: [u8; {}] = {:?};", pins.len(), pins).unwrap();
+    writeln!(generated, "pub const DHT_LABELS: [&str; {}] = {:?};", labels.len(), labels).unwrap();
+    pins
+}
+
+/// `GPIO_OUTPUTS="fan:14,humidifier:15"` becomes the `GPIO_OUTPUTS` array of (name, pin) pairs, empty by default.
+/// Names are lower case letters, digits, `-` and `_`, the pins must be free of the CYW43 and the sensors. The pins
+/// are returned for the checks of the fan pins.
+fn generate_gpio_outputs(settings: &Settings, dht_pins: &[u8], generated: &mut impl Write) -> Vec<u8> {
+    let value = settings.text("GPIO_OUTPUTS", "");
+    let expected = "name:pin pairs like fan:14,humidifier:15";
+    let mut outputs: Vec<(String, u8)> = Vec::new();
+
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let Some((name, pin)) = entry.split_once(':') else {
+            settings.invalid("GPIO_OUTPUTS", &value, expected);
+        };
+        let (name, pin) = (name.trim(), pin.trim());
+        let valid_name = (1..=GPIO_NAME_LEN).contains(&name.len())
+            && name.bytes().all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_'));
+        if !valid_name {
+            settings.invalid("GPIO_OUTPUTS", &value,
+                &format!("names of up to {} lower case letters, digits, - and _", GPIO_NAME_LEN));
+        }
+        let pin: u8 = pin.parse().unwrap_or_else(|_| settings.invalid("GPIO_OUTPUTS", &value, expected));
+
+        assert!(pin <= 28, "GPIO_OUTPUTS: GPIO{} does not exist", pin);
+        check_unreserved("GPIO_OUTPUTS", pin);
+        assert!(!dht_pins.contains(&pin), "GPIO_OUTPUTS: GPIO{} is one of the DHT_PINS", pin);
+        assert!(outputs.iter().all(|(_, other)| *other != pin), "GPIO_OUTPUTS: GPIO{} is listed twice", pin);
+        assert!(outputs.iter().all(|(other, _)| other != name), "GPIO_OUTPUTS: the name {} is listed twice", name);
+        outputs.push((name.to_string(), pin));
+    }
+    assert!(outputs.len() <= MAX_GPIO_OUTPUTS, "GPIO_OUTPUTS: more than {} outputs", MAX_GPIO_OUTPUTS);
+
+    writeln!(generated, "pub const GPIO_OUTPUTS: [(&str, u8); {}] = {:?};", outputs.len(), outputs).unwrap();
+    outputs.into_iter().map(|(_, pin)| pin).collect()
+}
+
+/// An optional pin setting, empty by default, that must not be taken by anything else
+fn free_pin(settings: &Settings, name: &str, taken: &[u8]) -> Option<u8> {
+    let value = settings.text(name, "");
+    if value.is_empty() {
+        return None;
+    }
+    let pin: u8 = value.parse().unwrap_or_else(|_| settings.invalid(name, &value, "a GPIO number"));
+    assert!(pin <= 28, "{}: GPIO{} does not exist", name, pin);
+    check_unreserved(name, pin);
+    assert!(!taken.contains(&pin), "{}: GPIO{} is already taken by another pin setting", name, pin);
+    Some(pin)
+}
+
+/// A percentage setting from 0 to 100
+fn percent(settings: &Settings, name: &str, default: u8) -> u8 {
+    let value = settings.text(name, &default.to_string());
+    match value.parse::<u8>() {
+        Ok(percent) if percent <= 100 => percent,
+        _ => settings.invalid(name, &value, "a whole percentage from 0 to 100"),
+    }
+}
+
+/// The `FAN_*` settings as constants, the fan and tach pins are returned. The curve is checked at startup.
+fn generate_fan(settings: &Settings, taken: &[u8], generated: &mut impl Write) -> (Option<u8>, Option<u8>) {
+    let fan_pin = free_pin(settings, "FAN_PIN", taken);
+    let taken = [taken, fan_pin.as_slice()].concat();
+    let tach_pin = free_pin(settings, "FAN_TACH_PIN", &taken);
+    assert!(fan_pin.is_some() || tach_pin.is_none(), "FAN_TACH_PIN needs FAN_PIN");
+
+    writeln!(generated, "pub const FAN_PIN: Option<u8> = {:?};", fan_pin).unwrap();
+    writeln!(generated, "pub const FAN_TACH_PIN: Option<u8> = {:?};", tach_pin).unwrap();
+    writeln!(generated, "pub const FAN_CURVE: &str = {:?};", settings.text("FAN_CURVE", "24:0,32:100")).unwrap();
+    writeln!(generated, "pub const FAN_MIN_CHANGE: u8 = {};", percent(settings, "FAN_MIN_CHANGE", 5)).unwrap();
+    writeln!(generated, "pub const FAN_FAILSAFE_DUTY: u8 = {};", percent(settings, "FAN_FAILSAFE_DUTY", 100)).unwrap();
+    (fan_pin, tach_pin)
+}
+
+/// The `BUZZER_*` settings as constants, the pin is returned and whether it needs a PWM slice, for a passive buzzer
+fn generate_buzzer(settings: &Settings, taken: &[u8], fan_pin: Option<u8>, generated: &mut impl Write) -> (Option<u8>, bool) {
+    let pin = free_pin(settings, "BUZZER_PIN", taken);
+    let kind = settings.text("BUZZER_TYPE", "active").to_lowercase();
+    let passive = match kind.as_str() {
+        "active" => false,
+        "passive" => true,
+        _ => settings.invalid("BUZZER_TYPE", &kind, "active, for a buzzer with its own oscillator, or passive"),
+    };
+    let slice = |pin: u8| pin / 2 % 8;
+    if let (true, Some(pin), Some(fan_pin)) = (passive, pin, fan_pin) {
+        assert!(slice(pin) != slice(fan_pin),
+            "BUZZER_PIN: GPIO{} shares PWM slice {} with FAN_PIN, which runs at another frequency", pin, slice(pin));
+    }
+    let beeps = settings.whole("BUZZER_BEEPS", 3, 1..=10);
+    let beep_ms = settings.whole("BUZZER_BEEP_MS", 200, 10..=2000);
+    let period_s = settings.whole("BUZZER_PERIOD_S", 30, 1..=3600);
+    assert!(period_s * 1000 >= 2 * beeps * beep_ms, "BUZZER_PERIOD_S: {} s is shorter than the beeps", period_s);
+
+    writeln!(generated, "pub const BUZZER_PIN: Option<u8> = {:?};", pin).unwrap();
+    writeln!(generated, "pub const BUZZER_PASSIVE: bool = {};", passive).unwrap();
+    writeln!(generated, "pub const BUZZER_TONE_HZ: u32 = {};", settings.whole("BUZZER_TONE_HZ", 2700, 500..=10_000)).unwrap();
+    writeln!(generated, "pub const BUZZER_BEEPS: u8 = {};", beeps).unwrap();
+    writeln!(generated, "pub const BUZZER_BEEP_MS: u16 = {};", beep_ms).unwrap();
+    writeln!(generated, "pub const BUZZER_PERIOD_S: u32 = {};", period_s).unwrap();
+    (pin, passive)
+}
+
+/// The `SERVO_*` settings as constants, the pin is returned. Its PWM slice runs at 50 Hz and can't be shared with the
+/// fan or a passive buzzer.
+fn generate_servo(settings: &Settings, taken: &[u8], pwm_pins: &[(&str, Option<u8>)], generated: &mut impl Write) -> Option<u8> {
+    let pin = free_pin(settings, "SERVO_PIN", taken);
+    let slice = |pin: u8| pin / 2 % 8;
+    for (name, other) in pwm_pins {
+        if let (Some(pin), Some(other)) = (pin, other) {
+            assert!(slice(pin) != slice(*other),
+                "SERVO_PIN: GPIO{} shares PWM slice {} with {}, which runs at another frequency", pin, slice(pin), name);
+        }
+    }
+    let min_us = settings.whole("SERVO_MIN_PULSE_US", 1000, 500..=2500);
+    let max_us = settings.whole("SERVO_MAX_PULSE_US", 2000, 500..=2500);
+    assert!(min_us != max_us, "SERVO_MAX_PULSE_US: {} µs is the same as SERVO_MIN_PULSE_US", max_us);
+    let gain = settings.number("SERVO_GAIN", 10.0);
+    if gain <= 0.0 {
+        settings.invalid("SERVO_GAIN", &gain.to_string(), "degrees per %RH above 0");
+    }
+
+    writeln!(generated, "pub const SERVO_PIN: Option<u8> = {:?};", pin).unwrap();
+    writeln!(generated, "pub const SERVO_MIN_PULSE_US: u16 = {};", min_us).unwrap();
+    writeln!(generated, "pub const SERVO_MAX_PULSE_US: u16 = {};", max_us).unwrap();
+    writeln!(generated, "pub const SERVO_HOLD_MS: u32 = {};", settings.whole("SERVO_HOLD_MS", 1000, 100..=60_000)).unwrap();
+    writeln!(generated, "pub const SERVO_SETPOINT: Option<f32> = {:?};", settings.limit("SERVO_SETPOINT", "")).unwrap();
+    writeln!(generated, "pub const SERVO_GAIN: f32 = {:?};", gain).unwrap();
+    writeln!(generated, "pub const SERVO_MIN_CHANGE: u8 = {};", settings.whole("SERVO_MIN_CHANGE", 5, 1..=90)).unwrap();
+    pin
+}
+
+/// One of the SPI0 pin settings, only read when the SD card is configured
+fn spi_pin(settings: &Settings, name: &str, default: u8, allowed: &[u8], taken: &[u8]) -> u8 {
+    let value = settings.text(name, &default.to_string());
+    let pin: u8 = value.parse().unwrap_or_else(|_| settings.invalid(name, &value, "a GPIO number"));
+    if !allowed.contains(&pin) {
+        let allowed: Vec<String> = allowed.iter().map(u8::to_string).collect();
+        settings.invalid(name, &value, &format!("one of the GPIOs SPI0 reaches, {}", allowed.join(", ")));
+    }
+    check_unreserved(name, pin);
+    assert!(!taken.contains(&pin), "{}: GPIO{} is already taken by another pin setting", name, pin);
+    pin
+}
+
+/// The `SD_*` settings as constants and the function for `board.rs` taking SPI0 and the card's pins. SPI0 and its
+/// pins are only taken with `SD_CS_PIN` set.
+fn generate_sd_card(settings: &Settings, taken: &[u8], generated: &mut impl Write) -> String {
+    let cs = free_pin(settings, "SD_CS_PIN", taken);
+    writeln!(generated, "pub const SD_CS_PIN: Option<u8> = {:?};", cs).unwrap();
+    writeln!(generated, "pub const SD_FLUSH_EVERY: usize = {};", settings.whole("SD_FLUSH_EVERY", 12, 1..=120)).unwrap();
+
+    let body = match cs {
+        Some(cs) => {
+            let mut pins = vec![cs];
+            for (name, default, allowed) in [
+                ("SD_SCK_PIN", 18, SPI0_SCK_PINS.as_slice()),
+                ("SD_MOSI_PIN", 19, SPI0_MOSI_PINS.as_slice()),
+                ("SD_MISO_PIN", 16, SPI0_MISO_PINS.as_slice()),
+            ] {
+                let pin = spi_pin(settings, name, default, allowed, &[taken, pins.as_slice()].concat());
+                pins.push(pin);
+            }
+            let (sck, mosi, miso) = (pins[1], pins[2], pins[3]);
+            format!(
+                "    let spi = Spi::new_blocking(\n        embassy_rp::peripherals::SPI0::steal(),\n        \
+                 embassy_rp::peripherals::PIN_{sck}::steal(),\n        embassy_rp::peripherals::PIN_{mosi}::steal(),\n        \
+                 embassy_rp::peripherals::PIN_{miso}::steal(),\n        config,\n    );\n    Some((spi, AnyPin::steal({cs})))\n"
+            )
+        },
+        None => "    let _ = config;\n    None\n".to_string(),
+    };
+    format!(
+        "/// SPI0 on `SD_SCK_PIN`, `SD_MOSI_PIN` and `SD_MISO_PIN` and the card's select line on `SD_CS_PIN`, generated\n\
+         /// by build.rs\n\
+         ///\n\
+         /// # Safety\n\
+         ///\n\
+         /// Steals SPI0 and the pins, call once and only while nothing else uses them\n\
+         unsafe fn sd_spi(config: spi::Config) -> Option<(SdSpi, AnyPin)> {{\n{body}}}\n"
+    )
+}
+
+/// The `PIXEL_*` settings as constants and the function for `pixel.rs` taking the data pin for PIO1, the pin is
+/// returned
+fn generate_pixel(settings: &Settings, taken: &[u8], generated: &mut impl Write) -> (Option<u8>, String) {
+    let pin = free_pin(settings, "PIXEL_PIN", taken);
+    writeln!(generated, "pub const PIXEL_PIN: Option<u8> = {:?};", pin).unwrap();
+    writeln!(generated, "pub const PIXEL_COUNT: usize = {};", settings.whole("PIXEL_COUNT", 1, 1..=16)).unwrap();
+    writeln!(generated, "pub const PIXEL_BRIGHTNESS: u8 = {};", percent(settings, "PIXEL_BRIGHTNESS", 20)).unwrap();
+
+    let body = match pin {
+        Some(pin) => format!("    Some(common.make_pio_pin(embassy_rp::peripherals::PIN_{pin}::steal()))\n"),
+        None => "    let _ = common;\n    None\n".to_string(),
+    };
+    let function = format!(
+        "/// The WS2812 data pin on `PIXEL_PIN` as a pin of PIO1, generated by build.rs\n\
+         ///\n\
+         /// # Safety\n\
+         ///\n\
+         /// Steals the pin, call once and only while nothing else uses it\n\
+         pub unsafe fn pixel_pin(common: &mut pio::Common<'static, PIO1>) -> Option<pio::Pin<'static, PIO1>> {{\n{body}}}\n"
+    );
+    (pin, function)
+}
+
+/// A function for `board.rs` taking the PWM output on `pin`: the slice and channel follow from the pin, so it is
+/// generated for the pin at hand
+fn pwm_function(name: &str, setting: &str, pin: Option<u8>) -> String {
+    let body = match pin {
+        Some(pin) => {
+            let (constructor, channel) = if pin % 2 == 0 { ("new_output_a", "A") } else { ("new_output_b", "B") };
+            let slice = pin / 2 % 8;
+            format!(
+                "    // GPIO{pin} is channel {channel} of slice {slice}\n    \
+                 Some(Pwm::{constructor}(\n        embassy_rp::peripherals::PWM_SLICE{slice}::steal(),\n        embassy_rp::peripherals::PIN_{pin}::steal(),\n        config,\n    ))\n"
+            )
+        },
+        None => "    let _ = config;\n    None\n".to_string(),
+    };
+    format!(
+        "/// The PWM output on `{setting}`, generated by build.rs\n\
+         ///\n\
+         /// # Safety\n\
+         ///\n\
+         /// Steals the slice and the pin, call once and only while nothing else uses either\n\
+         unsafe fn {name}(config: pwm::Config) -> Option<Pwm<'static>> {{\n{body}}}\n"
+    )
+}
+
+/// `WIFI_NETWORKS="home:pass1;workshop:pass2"` becomes the `WIFI_NETWORKS` array of (SSID, password) pairs, the
+/// single `WIFI_NETWORK`/`WIFI_PASSWORD` pair is used while it is unset. Each pair is split at its first `:`, so a
+/// password may contain `:` but neither value can contain `;`. The values are checked at startup. Networks in
+/// `server-config.toml` replace those of the environment as a whole, the two are never mixed.
+fn generate_wifi_networks(settings: &Settings, generated: &mut impl Write) {
+    let in_file = settings.in_file("WIFI_NETWORKS").is_some() || settings.in_file("WIFI_NETWORK").is_some();
+    let get = |name: &str| match in_file {
+        true => settings.in_file(name),
+        false => env::var(name).ok(),
+    };
+
+    let networks: Vec<(String, String)> = match get("WIFI_NETWORKS") {
+        Some(list) => list
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (ssid, password) = entry
+                    .split_once(':')
+                    .unwrap_or_else(|| panic!("WIFI_NETWORKS: {:?} is not ssid:password", entry));
+                (ssid.trim().to_string(), password.to_string())
+            })
+            .collect(),
+        // Both left empty builds an image without a network, provisioned over USB or the setup page
+        None => match get("WIFI_NETWORK").filter(|ssid| !ssid.trim().is_empty()) {
+            Some(ssid) => vec![(ssid, get("WIFI_PASSWORD").unwrap_or_default())],
+            None if get("WIFI_PASSWORD").is_some_and(|password| !password.is_empty()) => panic!("WIFI_PASSWORD is set but WIFI_NETWORK is missing"),
+            None => Vec::new(),
+        },
+    };
+    assert!(networks.len() <= MAX_WIFI_NETWORKS, "WIFI_NETWORKS: at most {} networks", MAX_WIFI_NETWORKS);
+    if networks.is_empty() {
+        println!("cargo:warning=No WiFi network configured, the device starts its setup access point until one is stored");
+    }
+
+    writeln!(generated, "pub const WIFI_NETWORKS: [(&str, &str); {}] = {:?};", networks.len(), networks).unwrap();
+}
+
+/// Every other setting, `DHT_*` and `WIFI_NETWORKS` aside, as a constant of the type the firmware uses
+fn generate_config(out: &Path) {
+    let settings = Settings::load();
+    let mut generated = File::create(out.join("config.rs")).unwrap();
+    let generated = &mut generated;
+    let mut text = |name: &str, value: String| writeln!(generated, "pub const {}: &str = {:?};", name, value).unwrap();
+
+    let hostname = settings.text("HOSTNAME", "Pico-W");
+    if !is_valid_hostname(&hostname) {
+        settings.invalid("HOSTNAME", &hostname, "letters, digits and inner hyphens, at most 32 characters");
+    }
+    let device_name = settings.text("DEVICE_NAME", &hostname);
+    if !is_valid_hostname(&device_name) {
+        settings.invalid("DEVICE_NAME", &device_name, "letters, digits and inner hyphens, at most 32 characters");
+    }
+    text("HOSTNAME", hostname);
+    text("DEVICE_NAME", device_name);
+
+    let setup_ap_password = settings.text("SETUP_AP_PASSWORD", "pico-setup");
+    if !(8..=64).contains(&setup_ap_password.len()) {
+        settings.invalid("SETUP_AP_PASSWORD", &setup_ap_password, "8 to 64 characters");
+    }
+    text("SETUP_AP_PASSWORD", setup_ap_password);
+
+    let auth_user = settings.text("HTTP_AUTH_USER", "");
+    let auth_pass = settings.text("HTTP_AUTH_PASS", "");
+    if auth_user.is_empty() != auth_pass.is_empty() {
+        panic!("HTTP_AUTH_USER and HTTP_AUTH_PASS go together, only one of them is set");
+    }
+    text("HTTP_AUTH_USER", auth_user);
+    text("HTTP_AUTH_PASS", auth_pass);
+
+    for (name, default) in [
+        ("API_TOKEN", ""),
+        ("CORS_ORIGIN", "*"),
+        ("NTP_SERVER", "pool.ntp.org"),
+        ("MQTT_BROKER", ""),
+        ("MQTT_TOPIC_PREFIX", "home/{name}"),
+        ("SYSLOG_SERVER", ""),
+        ("WIFI_PM", "powersave"),
+        ("TEMP_UNIT", "C"),
+        ("STATIC_IP", ""),
+        ("STATIC_NETMASK", "24"),
+        ("STATIC_GATEWAY", ""),
+        ("STATIC_DNS", ""),
+        ("LOG_MODULES", ""),
+    ] {
+        text(name, settings.text(name, default));
+    }
+
+    for (name, default) in [("SERVER_PORT", 80), ("MQTT_PORT", 1883), ("DISCOVERY_PORT", 47822), ("SYSLOG_PORT", 514)] {
+        writeln!(generated, "pub const {}: u16 = {};", name, settings.port(name, default)).unwrap();
+    }
+    for name in ["HA_DISCOVERY", "DISCOVERY_BEACON"] {
+        writeln!(generated, "pub const {}: bool = {};", name, settings.flag(name)).unwrap();
+    }
+    for name in ["TEMP_OFFSET", "HUMID_OFFSET"] {
+        writeln!(generated, "pub const {}: f32 = {:?};", name, settings.number(name, 0.0)).unwrap();
+    }
+    for (name, default) in [("TEMP_HIGH", "30.0"), ("TEMP_LOW", ""), ("HUMID_HIGH", "70.0"), ("HUMID_LOW", "")] {
+        writeln!(generated, "pub const {}: Option<f32> = {:?};", name, settings.limit(name, default)).unwrap();
+    }
+    for (name, default) in [("SPIKE_TEMP_LIMIT", 5.0), ("SPIKE_HUMID_LIMIT", 15.0)] {
+        let limit = settings.number(name, default);
+        if limit < 0.0 {
+            settings.invalid(name, &limit.to_string(), "a limit of at least 0");
+        }
+        writeln!(generated, "pub const {}: f32 = {:?};", name, limit).unwrap();
+    }
+
+    let interval = settings.text("SAMPLE_INTERVAL_S", "5");
+    match interval.parse::<u64>() {
+        Ok(secs) if secs > 0 => writeln!(generated, "pub const SAMPLE_INTERVAL_S: u64 = {};", secs).unwrap(),
+        _ => settings.invalid("SAMPLE_INTERVAL_S", &interval, "a whole number of seconds"),
+    }
+
+    // The module rules are checked at startup, by the same parser as `POST /api/log-level`
+    let level = settings.text("LOG_LEVEL", "info").to_lowercase();
+    let variant = match level.as_str() {
+        "off" => "Off",
+        "error" => "Error",
+        "warn" => "Warn",
+        "info" => "Info",
+        "debug" => "Debug",
+        "trace" => "Trace",
+        _ => settings.invalid("LOG_LEVEL", &level, "off, error, warn, info, debug or trace"),
+    };
+    writeln!(generated, "pub const LOG_LEVEL: log::LevelFilter = log::LevelFilter::{};", variant).unwrap();
+
+    let dht_pins = generate_dht_pins(&settings, generated);
+    let gpio_pins = generate_gpio_outputs(&settings, &dht_pins, generated);
+    let taken = [dht_pins, gpio_pins].concat();
+    let (fan_pin, tach_pin) = generate_fan(&settings, &taken, generated);
+    let taken = [taken.as_slice(), fan_pin.as_slice(), tach_pin.as_slice()].concat();
+    let (buzzer_pin, passive) = generate_buzzer(&settings, &taken, fan_pin, generated);
+    let buzzer_pwm_pin = buzzer_pin.filter(|_| passive);
+    let taken = [taken.as_slice(), buzzer_pin.as_slice()].concat();
+    let servo_pin = generate_servo(&settings, &taken, &[("FAN_PIN", fan_pin), ("BUZZER_PIN", buzzer_pwm_pin)], generated);
+    let functions = [
+        pwm_function("fan_pwm", "FAN_PIN", fan_pin),
+        pwm_function("buzzer_pwm", "BUZZER_PIN", buzzer_pwm_pin),
+        pwm_function("servo_pwm", "SERVO_PIN", servo_pin),
+    ];
+    fs::write(out.join("pwm_outputs.rs"), functions.join("\n")).unwrap();
+    let taken = [taken.as_slice(), servo_pin.as_slice()].concat();
+    let button_pin = free_pin(&settings, "BUTTON_PIN", &taken);
+    writeln!(generated, "pub const BUTTON_PIN: Option<u8> = {:?};", button_pin).unwrap();
+    let taken = [taken.as_slice(), button_pin.as_slice()].concat();
+    let (pixel_pin, pixel_function) = generate_pixel(&settings, &taken, generated);
+    fs::write(out.join("pixel_pin.rs"), pixel_function).unwrap();
+    let taken = [taken.as_slice(), pixel_pin.as_slice()].concat();
+    fs::write(out.join("sd_spi.rs"), generate_sd_card(&settings, &taken, generated)).unwrap();
+    generate_wifi_networks(&settings, generated);
+}
+
+/// Output of a git command, `None` without git or outside a repository, e.g. when building from a tarball
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !text.trim().is_empty()).then(|| text.trim().to_string())
+}
+
+/// `2024-05-01T12:34:56Z` from seconds since the Unix epoch, Howard Hinnant's `civil_from_days`
+fn iso8601(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let time = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+/// `GIT_HASH` is the short commit hash, `unknown` without git, and `BUILD_TIMESTAMP` the UTC time this script ran,
+/// or `SOURCE_DATE_EPOCH` when it is set so reproducible builds stay reproducible
+fn capture_build_info() {
+    let hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+    // A commit or checkout moves HEAD or the branch it points to, both are logged here
+    if let Some(path) = git(&["rev-parse", "--git-path", "logs/HEAD"]) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
+    let secs = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.trim().parse().unwrap_or_else(|_| panic!("SOURCE_DATE_EPOCH = {:?} is not a number", epoch)),
+        Err(_) => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    };
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", iso8601(secs));
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
 
 fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // Put the board's layout in our output directory as `memory.x` and
+    // ensure it's on the linker search path. Without a board feature
+    // there is nothing to link, `board.rs` stops the build with an error.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(include_bytes!("memory.x"))
-        .unwrap();
+    if let Some((_, _, layout)) = MEMORY_LAYOUTS.iter().find(|(feature, _, _)| env::var_os(feature).is_some()) {
+        File::create(out.join("memory.x")).unwrap().write_all(layout).unwrap();
+    }
     println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
+    // any file in the project changes. By specifying the layouts
     // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // one of them is changed.
+    for (_, file, _) in MEMORY_LAYOUTS {
+        println!("cargo:rerun-if-changed={}", file);
+    }
+
+    // Precompressed copies of the web assets
+    gzip_html_files(out);
+    println!("cargo:rerun-if-changed={}", HTML_DIR);
+
+    generate_config(out);
+    capture_build_info();
+    println!("cargo:rerun-if-changed={}", CONFIG_FILE);
+    for name in SETTINGS {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    // The RP2040's second stage bootloader, the RP2350 boots from the image definition embassy-rp emits
+    if env::var_os("CARGO_FEATURE_PICO_W").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    }
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
@@ -1,6 +1,9 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The running firmware's slot, boot stage 2 included. An update is written to the slot of the same size right
+       after it and swapped in at boot, see ota.rs. The last five 4K sectors hold the update state, the hourly
+       records, the settings and the Wi-Fi credentials, see storage.rs. */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 1012K - 0x100
 
     /* Pick one of the two options for RAM layout     */
 
@@ -15,3 +18,8 @@ MEMORY {
     /* SCRATCH_A: ORIGIN = 0x20040000, LENGTH = 4K    */
     /* SCRATCH_B: ORIGIN = 0x20041000, LENGTH = 4K    */
 }
+
+/* First byte past the firmware's slot, storage.rs checks the update slot starts there */
+__storage_start = ORIGIN(FLASH) + LENGTH(FLASH);
+/* First byte past the image, only the sectors up to it are swapped */
+__image_end = LOADADDR(.data) + SIZEOF(.data);
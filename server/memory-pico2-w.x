@@ -0,0 +1,54 @@
+MEMORY {
+    /* The RP2350 boots from the start of flash, the boot ROM finds the image through the start block */
+    /* The running firmware's slot. An update is written to the slot of the same size right after it and swapped
+       in at boot, see ota.rs. The last five 4K sectors hold the update state, the hourly records, the settings and
+       the Wi-Fi credentials, see storage.rs. */
+    FLASH : ORIGIN = 0x10000000, LENGTH = 2036K
+
+    /* SRAM0 to SRAM7 as one striped block, SRAM8 and SRAM9 are left alone */
+    RAM   : ORIGIN = 0x20000000, LENGTH = 512K
+    SRAM8 : ORIGIN = 0x20080000, LENGTH = 4K
+    SRAM9 : ORIGIN = 0x20081000, LENGTH = 4K
+}
+
+/* First byte past the firmware's slot, storage.rs checks the update slot starts there */
+__storage_start = ORIGIN(FLASH) + LENGTH(FLASH);
+
+SECTIONS {
+    /* The IMAGE_DEF block embassy-rp emits, the boot ROM only looks at the first 4K for it */
+    .start_block : ALIGN(4)
+    {
+        __start_block_addr = .;
+        KEEP(*(.start_block));
+        KEEP(*(.boot_info));
+    } > FLASH
+} INSERT AFTER .vector_table;
+
+/* Move .text past the start block */
+_stext = ADDR(.start_block) + SIZEOF(.start_block);
+
+SECTIONS {
+    /* Picotool 'Binary Info' entries */
+    .bi_entries : ALIGN(4)
+    {
+        __bi_entries_start = .;
+        KEEP(*(.bi_entries));
+        . = ALIGN(4);
+        __bi_entries_end = .;
+    } > FLASH
+} INSERT AFTER .text;
+
+SECTIONS {
+    /* Closes the block loop the start block opens */
+    .end_block : ALIGN(4)
+    {
+        __end_block_addr = .;
+        KEEP(*(.end_block));
+    } > FLASH
+} INSERT AFTER .uninit;
+
+PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
+
+/* First byte past the image, the block loop's end included, only the sectors up to it are swapped */
+__image_end = __end_block_addr + SIZEOF(.end_block);
+PROVIDE(end_to_start = __start_block_addr - __end_block_addr);
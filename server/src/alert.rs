@@ -0,0 +1,199 @@
+use {
+    core::cell::Cell,
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    crate::reading::Reading,
+};
+
+/// An alert clears only once the value is this far back inside its threshold, in °C and percentage points
+pub const TEMP_HYSTERESIS: f32 = 2.0;
+pub const HUMID_HYSTERESIS: f32 = 5.0;
+
+/// Limits the primary sensor's readings are checked against, `None` disables a limit
+#[derive(Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub temperature_high: Option<f32>,
+    pub temperature_low: Option<f32>,
+    pub humidity_high: Option<f32>,
+    pub humidity_low: Option<f32>,
+}
+
+impl Thresholds {
+    pub const NONE: Self = Self { temperature_high: None, temperature_low: None, humidity_high: None, humidity_low: None };
+
+    /// The four limits in the order of `Alert::ALL`
+    pub fn limits(&self) -> [Option<f32>; 4] {
+        [self.temperature_high, self.temperature_low, self.humidity_high, self.humidity_low]
+    }
+
+    pub fn from_limits([temperature_high, temperature_low, humidity_high, humidity_low]: [Option<f32>; 4]) -> Self {
+        Self { temperature_high, temperature_low, humidity_high, humidity_low }
+    }
+}
+
+/// A threshold that is currently exceeded
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Alert {
+    TemperatureHigh,
+    TemperatureLow,
+    HumidityHigh,
+    HumidityLow,
+}
+
+impl Alert {
+    pub const ALL: [Alert; 4] = [Alert::TemperatureHigh, Alert::TemperatureLow, Alert::HumidityHigh, Alert::HumidityLow];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Alert::TemperatureHigh => "temperature_high",
+            Alert::TemperatureLow => "temperature_low",
+            Alert::HumidityHigh => "humidity_high",
+            Alert::HumidityLow => "humidity_low",
+        }
+    }
+
+    /// Text for the banner on the page
+    pub fn describe(self) -> &'static str {
+        match self {
+            Alert::TemperatureHigh => "temperature too high",
+            Alert::TemperatureLow => "temperature too low",
+            Alert::HumidityHigh => "humidity too high",
+            Alert::HumidityLow => "humidity too low",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Set of active alerts
+#[derive(Clone, Copy, PartialEq)]
+pub struct Alerts(u8);
+
+impl Alerts {
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, alert: Alert) -> bool {
+        self.0 & alert.bit() != 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Alert> {
+        Alert::ALL.into_iter().filter(move |alert| self.contains(*alert))
+    }
+
+    fn set(&mut self, alert: Alert, active: bool) {
+        if active {
+            self.0 |= alert.bit();
+        } else {
+            self.0 &= !alert.bit();
+        }
+    }
+
+    /// Alerts after `reading`, each one starts past its threshold and clears only `hysteresis` back inside it
+    pub fn evaluate(self, thresholds: &Thresholds, reading: &Reading) -> Self {
+        let high = |alert, limit: Option<f32>, value: f32, hysteresis: f32| match limit {
+            Some(limit) if self.contains(alert) => value > limit - hysteresis,
+            Some(limit) => value > limit,
+            None => false,
+        };
+        let low = |alert, limit: Option<f32>, value: f32, hysteresis: f32| match limit {
+            Some(limit) if self.contains(alert) => value < limit + hysteresis,
+            Some(limit) => value < limit,
+            None => false,
+        };
+
+        let mut next = self;
+        next.set(Alert::TemperatureHigh,
+            high(Alert::TemperatureHigh, thresholds.temperature_high, reading.temperature, TEMP_HYSTERESIS));
+        next.set(Alert::TemperatureLow,
+            low(Alert::TemperatureLow, thresholds.temperature_low, reading.temperature, TEMP_HYSTERESIS));
+        next.set(Alert::HumidityHigh,
+            high(Alert::HumidityHigh, thresholds.humidity_high, reading.humidity, HUMID_HYSTERESIS));
+        next.set(Alert::HumidityLow,
+            low(Alert::HumidityLow, thresholds.humidity_low, reading.humidity, HUMID_HYSTERESIS));
+        next
+    }
+}
+
+static THRESHOLDS: Mutex<CriticalSectionRawMutex, Cell<Thresholds>> = Mutex::new(Cell::new(Thresholds::NONE));
+static ALERTS: Mutex<CriticalSectionRawMutex, Cell<Alerts>> = Mutex::new(Cell::new(Alerts(0)));
+/// Raised whenever the set of active alerts changes, wakes the LED task
+pub static ALERTS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Alerts silenced through `POST /api/alarm/ack`, each one only until it clears
+static ACKNOWLEDGED: Mutex<CriticalSectionRawMutex, Cell<Alerts>> = Mutex::new(Cell::new(Alerts(0)));
+/// Raised whenever the alarm starts, stops or is acknowledged, wakes the buzzer task
+pub static ALARM_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What the buzzer makes of the alerts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Alarm {
+    /// At least one alert is active
+    pub active: bool,
+    /// Every active alert has been acknowledged, a new one sounds the alarm again
+    pub acknowledged: bool,
+}
+
+impl Alarm {
+    pub fn sounding(self) -> bool {
+        self.active && !self.acknowledged
+    }
+}
+
+pub fn thresholds() -> Thresholds {
+    THRESHOLDS.lock(Cell::get)
+}
+
+/// Takes effect from the next reading on
+pub fn set_thresholds(thresholds: Thresholds) {
+    THRESHOLDS.lock(|current| current.set(thresholds));
+}
+
+pub fn alerts() -> Alerts {
+    ALERTS.lock(Cell::get)
+}
+
+/// Check a new reading of the primary sensor, called by the sensor task
+pub fn update(reading: &Reading) {
+    let thresholds = thresholds();
+    let changed = ALERTS.lock(|alerts| {
+        let current = alerts.get();
+        let next = current.evaluate(&thresholds, reading);
+        alerts.set(next);
+        (next != current).then_some(next)
+    });
+
+    if let Some(next) = changed {
+        for alert in next.iter() {
+            log::warn!("Alert active: {}", alert.describe());
+        }
+        if next.is_empty() {
+            log::info!("Alerts cleared");
+        }
+        // A cleared alert has to be acknowledged again the next time it triggers
+        ACKNOWLEDGED.lock(|acknowledged| acknowledged.set(Alerts(acknowledged.get().0 & next.0)));
+        ALERTS_CHANGED.signal(());
+        ALARM_CHANGED.signal(());
+    }
+}
+
+pub fn alarm() -> Alarm {
+    let active = alerts();
+    let acknowledged = ACKNOWLEDGED.lock(Cell::get);
+    Alarm { active: !active.is_empty(), acknowledged: !active.is_empty() && active.0 & !acknowledged.0 == 0 }
+}
+
+/// Silences the alarm for the alerts active now, until each of them clears
+pub fn acknowledge() -> Alarm {
+    let active = alerts();
+    ACKNOWLEDGED.lock(|acknowledged| acknowledged.set(active));
+    if !active.is_empty() {
+        log::info!("Alarm acknowledged");
+        ALARM_CHANGED.signal(());
+    }
+    alarm()
+}
@@ -0,0 +1,108 @@
+use crate::config::{API_TOKEN, HTTP_AUTH_USER, HTTP_AUTH_PASS};
+
+const MAX_CREDENTIALS_LEN: usize = 128;
+
+/// Basic Auth is only enforced when credentials were provided at build time
+pub fn basic_auth_enabled() -> bool {
+    !HTTP_AUTH_USER.is_empty()
+}
+
+/// Check an `Authorization` header value against the built-in credentials
+pub fn check_basic_auth(authorization: Option<&str>) -> bool {
+    if !basic_auth_enabled() {
+        return true;
+    }
+
+    let Some((scheme, encoded)) = authorization.and_then(|value| value.trim().split_once(' ')) else {
+        return false;
+    };
+
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return false;
+    }
+
+    let mut decoded = [0u8; MAX_CREDENTIALS_LEN];
+    let Some(len) = base64_decode(encoded.trim().as_bytes(), &mut decoded) else {
+        return false;
+    };
+
+    let credentials = &decoded[..len];
+    let Some(split) = credentials.iter().position(|&b| b == b':') else {
+        return false;
+    };
+
+    // Evaluate both comparisons so a wrong user takes as long as a wrong password
+    let user_ok = constant_time_eq(&credentials[..split], HTTP_AUTH_USER.as_bytes());
+    let pass_ok = constant_time_eq(&credentials[split + 1..], HTTP_AUTH_PASS.as_bytes());
+    user_ok & pass_ok
+}
+
+/// Token auth for the JSON API is only enforced when a token was provided at build time
+pub fn api_token_enabled() -> bool {
+    !API_TOKEN.is_empty()
+}
+
+/// Accept either an `Authorization: Bearer <token>` header or a `?token=` query parameter
+pub fn check_api_token(authorization: Option<&str>, query_token: Option<&str>) -> bool {
+    if !api_token_enabled() {
+        return true;
+    }
+
+    let header_token = authorization
+        .and_then(|value| value.trim().split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim());
+
+    match header_token.or(query_token) {
+        Some(token) => constant_time_eq(token.as_bytes(), API_TOKEN.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compare without an early exit so the time taken doesn't reveal how many leading bytes match
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+
+    for (i, &byte) in a.iter().enumerate() {
+        diff |= (byte ^ b.get(i).copied().unwrap_or(0)) as usize;
+    }
+
+    diff == 0
+}
+
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode standard base64 into `out`, returning the decoded length.
+/// Fails on invalid characters or when `out` is too small.
+pub fn base64_decode(input: &[u8], out: &mut [u8]) -> Option<usize> {
+    let input = match input.iter().position(|&c| c == b'=') {
+        Some(padding) => &input[..padding],
+        None => input,
+    };
+
+    let mut len = 0;
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for &c in input {
+        acc = (acc << 6) | base64_value(c)? as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            *out.get_mut(len)? = (acc >> bits) as u8;
+            len += 1;
+        }
+    }
+
+    Some(len)
+}
@@ -0,0 +1,177 @@
+//! Everything that differs between the boards, selected with the `pico-w` or `pico2-w` feature. Both carry the
+//! CYW43439 on the same pins and bring out the same GPIOs, what changes is the chip: the RP2040 or the RP2350A, its
+//! flash size, its reset registers and the memory layout build.rs links.
+
+use {
+    embassy_rp::{
+        gpio::AnyPin,
+        pac,
+        peripherals::{ADC, ADC_TEMP_SENSOR, DMA_CH0, FLASH, PIN_23, PIN_24, PIN_25, PIN_29, PIO0, PIO1, SPI0, USB, WATCHDOG},
+        pio,
+        pwm::{self, Pwm},
+        spi::{self, Spi},
+        Peripherals,
+    },
+    crate::{
+        buzzer::BuzzerOutput,
+        config::{BUTTON_PIN, BUZZER_PASSIVE, BUZZER_PIN, DHT_PINS, FAN_TACH_PIN, GPIO_OUTPUTS, PIXEL_PIN},
+        gpio::GPIO_COUNT,
+        sensor::SENSOR_COUNT,
+    },
+};
+#[cfg(any(feature = "oled", feature = "rtc"))]
+use {
+    embassy_rp::{
+        i2c::{Async, I2c},
+        peripherals::{I2C0, PIN_4, PIN_5},
+    },
+    embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex},
+};
+
+#[cfg(all(feature = "pico-w", feature = "pico2-w"))]
+compile_error!("Select one board, `pico-w` or `pico2-w`, `--no-default-features` drops the default `pico-w`");
+#[cfg(not(any(feature = "pico-w", feature = "pico2-w")))]
+compile_error!("Select a board with the `pico-w` or `pico2-w` feature");
+
+#[cfg(feature = "pico-w")]
+pub const NAME: &str = "Pico W";
+#[cfg(feature = "pico2-w")]
+pub const NAME: &str = "Pico 2 W";
+
+/// Size of the flash chip, its last five sectors hold the update state and the stored hours, settings and
+/// credentials
+#[cfg(feature = "pico-w")]
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+#[cfg(feature = "pico2-w")]
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+/// The running firmware's slot at the start of the flash and the update slot after it, as the memory layout has
+/// them. Half of what the storage sectors leave, rounded down to whole sectors.
+#[cfg(feature = "pico-w")]
+pub const SLOT_SIZE: usize = 1012 * 1024;
+#[cfg(feature = "pico2-w")]
+pub const SLOT_SIZE: usize = 2036 * 1024;
+
+/// Where an image keeps its vector table, the RP2040's boot stage 2 takes the first 256 bytes
+#[cfg(feature = "pico-w")]
+pub const VECTOR_TABLE_OFFSET: usize = 0x100;
+#[cfg(feature = "pico2-w")]
+pub const VECTOR_TABLE_OFFSET: usize = 0;
+
+/// The state machine and DMA channel driving the CYW43's SPI bus
+pub type WifiPio = PIO0;
+pub type WifiDma = DMA_CH0;
+
+/// The CYW43's power, chip select, data and clock lines
+pub struct WifiPins {
+    pub pwr: PIN_23,
+    pub cs: PIN_25,
+    pub dio: PIN_24,
+    pub clk: PIN_29,
+    pub pio: WifiPio,
+    pub dma: WifiDma,
+}
+
+/// I2C0 and its pins, SDA on GPIO 4 and SCL on GPIO 5, taken with the `oled` or the `rtc` feature. build.rs keeps
+/// the other pin settings off them.
+#[cfg(any(feature = "oled", feature = "rtc"))]
+pub struct I2cPins {
+    pub i2c: I2C0,
+    pub sda: PIN_4,
+    pub scl: PIN_5,
+}
+
+/// Both the display and the RTC answer at 400 kHz
+#[cfg(any(feature = "oled", feature = "rtc"))]
+pub const I2C_FREQUENCY_HZ: u32 = 400_000;
+
+/// I2C0 shared by the display and the RTC, held for one transfer at a time
+#[cfg(any(feature = "oled", feature = "rtc"))]
+pub type I2cBus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C0, Async>>;
+
+/// SPI0 of the SD card, driven in blocking mode by the FAT code
+pub type SdSpi = Spi<'static, SPI0, spi::Blocking>;
+
+/// The peripherals the firmware uses, by what they are for
+pub struct Board {
+    /// One per entry of `DHT_PINS`
+    pub dht_pins: [AnyPin; SENSOR_COUNT],
+    /// One per entry of `GPIO_OUTPUTS`
+    pub gpio_pins: [AnyPin; GPIO_COUNT],
+    /// On `FAN_PIN`, at the default configuration until `fan::init` sets the frequency
+    pub fan: Option<Pwm<'static>>,
+    pub fan_tach: Option<AnyPin>,
+    /// On `SERVO_PIN`, at the default configuration until `servo_task` sets 50 Hz
+    pub servo: Option<Pwm<'static>>,
+    /// On `BUZZER_PIN`, a PWM output for a passive buzzer and a plain one for an active buzzer
+    pub buzzer: Option<BuzzerOutput>,
+    pub button: Option<AnyPin>,
+    /// PIO1 for the WS2812 pixels on `PIXEL_PIN`, `pixel_pin` hands it the data pin once it is set up
+    pub pixel: Option<PIO1>,
+    /// SPI0 and the select line of the SD card on `SD_CS_PIN`
+    pub sd_card: Option<(SdSpi, AnyPin)>,
+    #[cfg(any(feature = "oled", feature = "rtc"))]
+    pub i2c: I2cPins,
+    pub wifi: WifiPins,
+    pub usb: USB,
+    pub flash: FLASH,
+    pub watchdog: WATCHDOG,
+    pub adc: ADC,
+    pub temp_sensor: ADC_TEMP_SENSOR,
+}
+
+impl Board {
+    pub fn init(p: Peripherals) -> Self {
+        Self {
+            // Safety: build.rs rejects duplicates, pins in more than one setting and the pins of the CYW43, nothing
+            // else takes a GPIO, a PWM slice, SPI0 or PIO1 by number
+            dht_pins: DHT_PINS.map(|pin| unsafe { AnyPin::steal(pin) }),
+            gpio_pins: GPIO_OUTPUTS.map(|(_, pin)| unsafe { AnyPin::steal(pin) }),
+            fan: unsafe { fan_pwm(pwm::Config::default()) },
+            fan_tach: FAN_TACH_PIN.map(|pin| unsafe { AnyPin::steal(pin) }),
+            servo: unsafe { servo_pwm(pwm::Config::default()) },
+            buzzer: match BUZZER_PIN {
+                Some(_) if BUZZER_PASSIVE => unsafe { buzzer_pwm(pwm::Config::default()) }.map(BuzzerOutput::Pwm),
+                pin => pin.map(|pin| BuzzerOutput::Gpio(unsafe { AnyPin::steal(pin) })),
+            },
+            button: BUTTON_PIN.map(|pin| unsafe { AnyPin::steal(pin) }),
+            pixel: PIXEL_PIN.map(|_| p.PIO1),
+            sd_card: unsafe { sd_spi(spi::Config::default()) },
+            #[cfg(any(feature = "oled", feature = "rtc"))]
+            i2c: I2cPins { i2c: p.I2C0, sda: p.PIN_4, scl: p.PIN_5 },
+            wifi: WifiPins { pwr: p.PIN_23, cs: p.PIN_25, dio: p.PIN_24, clk: p.PIN_29, pio: p.PIO0, dma: p.DMA_CH0 },
+            usb: p.USB,
+            flash: p.FLASH,
+            watchdog: p.WATCHDOG,
+            adc: p.ADC,
+            temp_sensor: p.ADC_TEMP_SENSOR,
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/pwm_outputs.rs"));
+include!(concat!(env!("OUT_DIR"), "/sd_spi.rs"));
+include!(concat!(env!("OUT_DIR"), "/pixel_pin.rs"));
+
+/// Causes of the last chip level reset the chip recorded, more than one can be set
+pub struct ChipReset {
+    pub power_on: bool,
+    pub run_pin: bool,
+    pub debugger: bool,
+}
+
+#[cfg(feature = "pico-w")]
+pub fn chip_reset() -> ChipReset {
+    let flags = pac::VREG_AND_CHIP_RESET.chip_reset().read();
+    ChipReset { power_on: flags.had_por(), run_pin: flags.had_run(), debugger: flags.had_psm_restart() }
+}
+
+#[cfg(feature = "pico2-w")]
+pub fn chip_reset() -> ChipReset {
+    let flags = pac::POWMAN.chip_reset().read();
+    ChipReset {
+        power_on: flags.had_por() || flags.had_bor(),
+        run_pin: flags.had_run_low(),
+        debugger: flags.had_dp_reset_req(),
+    }
+}
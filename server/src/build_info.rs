@@ -0,0 +1,8 @@
+/// Crate version of the firmware
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the commit it was built from, `unknown` when built outside a git checkout
+pub const GIT_HASH: &str = env!("GIT_HASH");
+/// UTC time of the build, ISO 8601
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+/// Version and commit as shown on the page, `0.1.0 (1a2b3c4)`
+pub const SUMMARY: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
@@ -0,0 +1,75 @@
+use {
+    core::sync::atomic::Ordering,
+    embassy_rp::gpio::{AnyPin, Input, Pull},
+    embassy_time::{Duration, Instant, Timer},
+    server_core::button::{Press, DEBOUNCE_MS},
+    crate::{
+        config::BUTTON_PIN,
+        control::LedCommand,
+        led,
+        router::Context,
+        watchdog, wifi,
+    },
+};
+
+const DEBOUNCE: Duration = Duration::from_millis(DEBOUNCE_MS);
+/// Time for the log line to get out before the reset
+const REBOOT_DELAY: Duration = Duration::from_millis(200);
+
+/// Reads a button between the pin and ground and hands each press to the task that owns what it does. A press is
+/// classified when the button is released, so one stuck low only keeps this task waiting on the pin.
+#[embassy_executor::task]
+pub async fn button_task(pin: AnyPin, ctx: &'static Context) -> ! {
+    let mut button = Input::new(pin, Pull::Up);
+    log::info!("Button on GPIO{}", BUTTON_PIN.unwrap_or(0));
+
+    // A button held through the reset, e.g. the very long press that caused it, counts once it was let go
+    if button.is_low() {
+        log::warn!("Button held at boot, ignored until it is released");
+        release(&mut button).await;
+    }
+
+    loop {
+        button.wait_for_falling_edge().await;
+        let pressed = Instant::now();
+        let released = release(&mut button).await;
+
+        match Press::classify((released - pressed).as_millis()) {
+            Some(press) => {
+                log::info!("Button: {} press", press.as_str());
+                act(press, ctx).await;
+            },
+            None => log::trace!("Button: bounce"),
+        }
+    }
+}
+
+/// Waits until the button has been up for `DEBOUNCE` and returns when it went up
+async fn release(button: &mut Input<'static>) -> Instant {
+    loop {
+        button.wait_for_high().await;
+        let released = Instant::now();
+        Timer::after(DEBOUNCE).await;
+        if button.is_high() {
+            return released;
+        }
+    }
+}
+
+async fn act(press: Press, ctx: &Context) {
+    match press {
+        // What `/led` does
+        Press::Short => led::command(LedCommand::Toggle).await,
+        Press::Long if ctx.setup_mode.load(Ordering::Relaxed) => log::info!("The setup access point is up, nothing to rejoin"),
+        Press::Long => wifi::request_rejoin(),
+        Press::VeryLong => {
+            if let Err(e) = ctx.storage.lock().await.clear_credentials() {
+                log::error!("Unable to clear the stored Wi-Fi credentials: {:?}", e);
+                return;
+            }
+            log::warn!("Cleared the stored Wi-Fi credentials");
+            Timer::after(REBOOT_DELAY).await;
+            watchdog::reboot_into_setup();
+        },
+    }
+}
@@ -0,0 +1,87 @@
+use {
+    embassy_futures::select::{select, Either},
+    embassy_rp::{
+        clocks::clk_sys_freq,
+        gpio::{AnyPin, Level, Output},
+        pwm::{self, Pwm},
+    },
+    embassy_time::{Duration, Timer},
+    crate::{
+        alert::{self, ALARM_CHANGED},
+        config::{BUZZER_BEEPS, BUZZER_BEEP_MS, BUZZER_PERIOD_S, BUZZER_PIN, BUZZER_TONE_HZ},
+        pattern::Beeps,
+    },
+};
+
+/// Set by `BUZZER_PIN`, without it `POST /api/alarm/ack` answers 404 and the alerts stay silent
+pub const PRESENT: bool = BUZZER_PIN.is_some();
+const PATTERN: Beeps = Beeps { count: BUZZER_BEEPS, beep_ms: BUZZER_BEEP_MS, period_ms: BUZZER_PERIOD_S * 1000 };
+/// Brings the tone's period into the 16-bit counter down to 500 Hz, on either chip
+const PWM_DIVIDER: u8 = 8;
+
+/// How the buzzer is wired, `BUZZER_TYPE` picks one
+pub enum BuzzerOutput {
+    /// An active buzzer has its own oscillator and sounds while the pin is high
+    Gpio(AnyPin),
+    /// A passive piezo needs the tone itself, a square wave at `BUZZER_TONE_HZ`
+    Pwm(Pwm<'static>),
+}
+
+enum Driver {
+    Gpio(Output<'static>),
+    Pwm(Pwm<'static>, pwm::Config),
+}
+
+impl Driver {
+    fn new(output: BuzzerOutput) -> Self {
+        match output {
+            BuzzerOutput::Gpio(pin) => Driver::Gpio(Output::new(pin, Level::Low)),
+            BuzzerOutput::Pwm(mut pwm) => {
+                let mut config = pwm::Config::default();
+                config.divider = PWM_DIVIDER.into();
+                config.top = (clk_sys_freq() / u32::from(PWM_DIVIDER) / BUZZER_TONE_HZ - 1) as u16;
+                pwm.set_config(&config);
+                Driver::Pwm(pwm, config)
+            },
+        }
+    }
+
+    fn set(&mut self, on: bool) {
+        match self {
+            Driver::Gpio(output) => output.set_level(Level::from(on)),
+            Driver::Pwm(pwm, config) => {
+                // Half the period high is the loudest a piezo gets, a compare of 0 keeps the output low
+                let compare = if on { (config.top / 2) + 1 } else { 0 };
+                config.compare_a = compare;
+                config.compare_b = compare;
+                pwm.set_config(config);
+            },
+        }
+    }
+}
+
+/// Beeps the `BUZZER_*` pattern while an alert is active and not acknowledged, from the start of the pattern each
+/// time the alarm sounds
+#[embassy_executor::task]
+pub async fn buzzer_task(output: BuzzerOutput) -> ! {
+    let mut driver = Driver::new(output);
+    log::info!("Buzzer on GPIO{}", BUZZER_PIN.unwrap_or(0));
+
+    loop {
+        driver.set(false);
+        while !alert::alarm().sounding() {
+            ALARM_CHANGED.wait().await;
+        }
+
+        let mut phase = 0;
+        loop {
+            let (on, millis) = PATTERN.phase(phase);
+            driver.set(on);
+            match select(Timer::after(Duration::from_millis(millis.into())), ALARM_CHANGED.wait()).await {
+                Either::First(()) => phase = (phase + 1) % PATTERN.phases(),
+                Either::Second(()) if alert::alarm().sounding() => {},
+                Either::Second(()) => break,
+            }
+        }
+    }
+}
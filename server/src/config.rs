@@ -0,0 +1,98 @@
+use {
+    core::str::FromStr,
+    embassy_net::{Ipv4Address, Ipv4Cidr, StaticConfigV4},
+    crate::{mqtt::MqttConfig, wifi::PowerMode},
+};
+
+// Every build time setting, generated by build.rs from `server-config.toml`, the environment and the defaults. The
+// numbers, ports and flags are checked there, a bad one stops the build, the rest are checked at startup.
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+/// How the device names itself and where it listens, both checked by build.rs.
+/// The hostname is the DHCP hostname, `<hostname>.local`, the discovery answer and the MQTT client id. What people
+/// see, the page title, the service instance, the MQTT topics and the syslog hostname, goes by the device name
+/// (`DEVICE_NAME`, changeable at runtime, see `settings::device_name`).
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub hostname: &'static str,
+    pub server_port: u16,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self { hostname: HOSTNAME, server_port: SERVER_PORT }
+    }
+}
+
+/// Static configuration from the `STATIC_*` variables, `None` when `STATIC_IP` is unset or any value is malformed.
+/// Every malformed value is logged, the caller falls back to DHCP.
+pub fn static_config() -> Option<StaticConfigV4> {
+    if STATIC_IP.trim().is_empty() {
+        return None;
+    }
+
+    let parse_address = |name: &str, value: &str| match Ipv4Address::from_str(value.trim()) {
+        Ok(address) => Some(address),
+        Err(_) => {
+            log::error!("Invalid {} {:?}, falling back to DHCP", name, value);
+            None
+        },
+    };
+
+    let address = parse_address("STATIC_IP", STATIC_IP)?;
+    let Some(prefix) = parse_prefix(STATIC_NETMASK) else {
+        log::error!("Invalid STATIC_NETMASK {:?}, falling back to DHCP", STATIC_NETMASK);
+        return None;
+    };
+    let gateway = match STATIC_GATEWAY.trim() {
+        "" => None,
+        gateway => Some(parse_address("STATIC_GATEWAY", gateway)?),
+    };
+
+    let mut dns_servers = heapless::Vec::new();
+    for server in STATIC_DNS.split(',').map(str::trim).filter(|server| !server.is_empty()) {
+        if dns_servers.push(parse_address("STATIC_DNS", server)?).is_err() {
+            log::warn!("STATIC_DNS: only the first {} servers are used", dns_servers.len());
+            break;
+        }
+    }
+
+    Some(StaticConfigV4 { address: Ipv4Cidr::new(address, prefix), gateway, dns_servers })
+}
+
+/// Publisher settings from the `MQTT_*` variables, `None` while `MQTT_BROKER` is unset or the prefix is unusable
+pub fn mqtt_config(hostname: &'static str, mac: [u8; 6]) -> Option<MqttConfig> {
+    if MQTT_BROKER.is_empty() {
+        return None;
+    }
+
+    let config = MqttConfig::new(MQTT_BROKER, MQTT_PORT, MQTT_TOPIC_PREFIX, hostname, mac, HA_DISCOVERY);
+    if config.is_none() {
+        log::error!("MQTT_TOPIC_PREFIX {:?} is too long, MQTT is disabled", MQTT_TOPIC_PREFIX);
+    }
+    config
+}
+
+/// Collector and port from the `SYSLOG_*` variables, `None` while `SYSLOG_SERVER` is unset
+pub fn syslog_config() -> Option<(&'static str, u16)> {
+    (!SYSLOG_SERVER.is_empty()).then_some((SYSLOG_SERVER, SYSLOG_PORT))
+}
+
+/// Prefix length of `24` or `255.255.255.0`, a netmask must be contiguous
+pub fn parse_prefix(netmask: &str) -> Option<u8> {
+    let netmask = netmask.trim();
+    if let Ok(prefix) = netmask.parse::<u8>() {
+        return (prefix <= 32).then_some(prefix);
+    }
+
+    let mask = u32::from(Ipv4Address::from_str(netmask).ok()?);
+    (mask.leading_ones() + mask.trailing_zeros() == 32).then_some(mask.leading_ones() as u8)
+}
+
+/// Radio power management from `WIFI_PM`, an invalid value is logged and replaced by power saving
+pub fn wifi_power_mode() -> PowerMode {
+    PowerMode::parse(WIFI_PM).unwrap_or_else(|| {
+        log::warn!("Ignoring invalid WIFI_PM {:?}", WIFI_PM);
+        PowerMode::PowerSave
+    })
+}
@@ -0,0 +1,96 @@
+use {
+    core::cell::RefCell,
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    server_core::history::Sample,
+    crate::{
+        history,
+        hourly::{Days, HourAccumulator, HourRecord, HOURS_PER_DAY},
+        router::Context,
+        sensor::{self, Extremes, PRIMARY_SENSOR},
+        storage::Storage,
+    },
+};
+
+/// Days served by `/api/history/daily`, the flash ring holds at least 5 of them after a reboot
+pub const DAILY_DAYS: usize = 7;
+const SECS_PER_HOUR: u64 = 3600;
+
+struct Daily {
+    hour: HourAccumulator,
+    days: Days<DAILY_DAYS>,
+    /// Whether today's stored hours went into the primary sensor's extremes yet
+    seeded: bool,
+}
+
+static DAILY: Mutex<CriticalSectionRawMutex, RefCell<Daily>> =
+    Mutex::new(RefCell::new(Daily { hour: HourAccumulator::new(), days: Days::new(), seeded: false }));
+/// The hour just closed, waiting to be written by `record_task`
+static CLOSED: Signal<CriticalSectionRawMutex, HourRecord> = Signal::new();
+
+/// Rebuilds the days from the hours stored in flash, at boot before the clock is synced
+pub fn load(storage: &mut Storage) {
+    let count = DAILY.lock(|daily| storage.load_hours(|record| daily.borrow_mut().days.add(record)));
+    log::info!("Loaded {} stored hours", count);
+}
+
+/// Adds a sample of the primary sensor. Until the clock is synced the samples have no hour and only count towards
+/// the extremes since boot.
+pub fn add(sample: &Sample) {
+    let Some(timestamp) = history::timestamp(sample) else {
+        return;
+    };
+    let hour = (timestamp / SECS_PER_HOUR) as u32;
+
+    let (closed, today) = DAILY.lock(|daily| {
+        let mut daily = daily.borrow_mut();
+        let closed = daily.hour.add(hour, sample.temp_dc, sample.rh_dp);
+        if let Some(closed) = &closed {
+            daily.days.add(closed);
+        }
+        // Once the date is known the min/max on the page go back to the start of the day, not the reboot
+        let today = if daily.seeded { None } else { daily.days.get(hour / HOURS_PER_DAY) };
+        daily.seeded = true;
+        (closed, today)
+    });
+
+    if let Some(today) = today {
+        let summary = today.summary;
+        sensor::merge_extremes(PRIMARY_SENSOR, Extremes {
+            temperature_min: f32::from(summary.temp_min_dc) / 10.0,
+            temperature_max: f32::from(summary.temp_max_dc) / 10.0,
+            humidity_min: f32::from(summary.rh_min_dp) / 10.0,
+            humidity_max: f32::from(summary.rh_max_dp) / 10.0,
+            count: summary.count,
+        });
+    }
+    if let Some(closed) = closed {
+        CLOSED.signal(closed);
+    }
+}
+
+/// The stored days with the hour in progress folded in, oldest first
+pub fn days() -> Days<DAILY_DAYS> {
+    DAILY.lock(|daily| {
+        let daily = daily.borrow();
+        let mut days = daily.days.clone();
+        if let Some(current) = daily.hour.current() {
+            days.add(&current);
+        }
+        days
+    })
+}
+
+/// Appends every closed hour to the flash ring, 24 small writes a day
+#[embassy_executor::task]
+pub async fn record_task(ctx: &'static Context) -> ! {
+    loop {
+        let record = CLOSED.wait().await;
+        match ctx.storage.lock().await.append_hour(&record) {
+            Ok(()) => log::debug!("Stored hour {}", record.hour),
+            Err(e) => log::error!("Unable to store hour {}: {:?}", record.hour, e),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use {
+    embassy_net::{
+        udp::{PacketMetadata, UdpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    defmt::unwrap,
+    crate::diag,
+};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+/// Clients get `.100` upwards in the access point's subnet
+const POOL_START: u8 = 100;
+const POOL_SIZE: usize = 4;
+/// Long enough for the setup page, the device reboots once it is submitted
+const LEASE_SECS: u32 = 3600;
+const PREFIX_LEN: u8 = 24;
+/// Fixed BOOTP header in front of the magic cookie
+const HEADER_LEN: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = HEADER_LEN + MAGIC_COOKIE.len();
+/// Smallest datagram every DHCP client accepts
+const PACKET_SIZE: usize = 576;
+
+const BOOTREPLY: u8 = 2;
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+
+/// Hardware addresses of the clients holding an address, index `i` has `.100 + i`
+struct Leases {
+    clients: [Option<[u8; 6]>; POOL_SIZE],
+}
+
+impl Leases {
+    const fn new() -> Self {
+        Self { clients: [None; POOL_SIZE] }
+    }
+
+    /// Address of `client`, a new one from the pool when it has none yet, `None` when the pool is used up
+    fn assign(&mut self, client: [u8; 6], server: Ipv4Address) -> Option<Ipv4Address> {
+        let index = match self.clients.iter().position(|lease| *lease == Some(client)) {
+            Some(index) => index,
+            None => {
+                let index = self.clients.iter().position(Option::is_none)?;
+                self.clients[index] = Some(client);
+                index
+            },
+        };
+
+        let [a, b, c, _] = server.octets();
+        Some(Ipv4Address::new(a, b, c, POOL_START + index as u8))
+    }
+
+    fn release(&mut self, client: [u8; 6]) {
+        for lease in self.clients.iter_mut().filter(|lease| **lease == Some(client)) {
+            *lease = None;
+        }
+    }
+}
+
+/// Hands out addresses to the phones and laptops joining the setup access point, the device itself is `server`
+#[embassy_executor::task]
+pub async fn dhcp_server_task(stack: Stack<'static>, server: Ipv4Address) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let _open = diag::OpenSocket::new();
+    unwrap!(socket.bind(SERVER_PORT));
+
+    let mut leases = Leases::new();
+    let mut packet = [0; PACKET_SIZE];
+
+    loop {
+        let len = match socket.recv_from(&mut packet).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                log::warn!("DHCP receive error: {:?}", e);
+                continue;
+            },
+        };
+
+        let Some(reply_len) = reply(&mut packet, len, server, &mut leases) else {
+            continue;
+        };
+
+        // The client has no address yet, so the reply goes to everyone
+        let destination = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), CLIENT_PORT);
+        if let Err(e) = socket.send_to(&packet[..reply_len], destination).await {
+            log::warn!("DHCP send error: {:?}", e);
+        }
+    }
+}
+
+/// Turn the request in `packet[..len]` into the reply in place, `None` when there is nothing to answer
+fn reply(packet: &mut [u8; PACKET_SIZE], len: usize, server: Ipv4Address, leases: &mut Leases) -> Option<usize> {
+    if len < OPTIONS_OFFSET || packet[HEADER_LEN..OPTIONS_OFFSET] != MAGIC_COOKIE {
+        return None;
+    }
+
+    let options = &packet[OPTIONS_OFFSET..len];
+    let message_type = *find_option(options, OPTION_MESSAGE_TYPE)?.first()?;
+    let requested = find_option(options, OPTION_REQUESTED_IP).and_then(|value| <[u8; 4]>::try_from(value).ok());
+    let server_id = find_option(options, OPTION_SERVER_ID).and_then(|value| <[u8; 4]>::try_from(value).ok());
+    let client: [u8; 6] = packet[28..34].try_into().ok()?;
+    let client_address: [u8; 4] = packet[12..16].try_into().ok()?;
+
+    let (reply_type, address) = match message_type {
+        DISCOVER => (OFFER, leases.assign(client, server)?),
+        // A client answering another server's offer
+        REQUEST if server_id.is_some_and(|id| id != server.octets()) => return None,
+        REQUEST => {
+            let address = leases.assign(client, server)?;
+            let wanted = requested.unwrap_or(client_address);
+            if wanted == address.octets() || wanted == [0; 4] {
+                (ACK, address)
+            } else {
+                (NAK, Ipv4Address::UNSPECIFIED)
+            }
+        },
+        RELEASE => {
+            leases.release(client);
+            return None;
+        },
+        _ => return None,
+    };
+
+    if reply_type == ACK {
+        log::info!("DHCP: leased {} to {:02x?}", address, client);
+    }
+
+    // Transaction id, flags, relay address and hardware address stay as the client sent them
+    packet[0] = BOOTREPLY;
+    packet[3] = 0;
+    packet[8..10].fill(0);
+    packet[12..16].fill(0);
+    packet[16..20].copy_from_slice(&address.octets());
+    packet[20..24].copy_from_slice(&server.octets());
+    packet[44..HEADER_LEN].fill(0);
+
+    let mut options = OptionWriter { packet, len: OPTIONS_OFFSET };
+    options.push(OPTION_MESSAGE_TYPE, &[reply_type]);
+    options.push(OPTION_SERVER_ID, &server.octets());
+    if reply_type != NAK {
+        let mask = u32::MAX << (32 - PREFIX_LEN);
+        options.push(OPTION_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+        options.push(OPTION_SUBNET_MASK, &mask.to_be_bytes());
+        options.push(OPTION_ROUTER, &server.octets());
+        options.push(OPTION_DNS, &server.octets());
+    }
+    Some(options.end())
+}
+
+/// Value of option `code`, `None` when it is missing or the options are cut short
+fn find_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match *options.first()? {
+            OPTION_END => return None,
+            OPTION_PAD => options = &options[1..],
+            current => {
+                let len = *options.get(1)? as usize;
+                let value = options.get(2..2 + len)?;
+                if current == code {
+                    return Some(value);
+                }
+                options = &options[2 + len..];
+            },
+        }
+    }
+}
+
+/// Appends options after the magic cookie, every reply is far smaller than the packet
+struct OptionWriter<'a> {
+    packet: &'a mut [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl OptionWriter<'_> {
+    fn push(&mut self, code: u8, value: &[u8]) {
+        self.packet[self.len] = code;
+        self.packet[self.len + 1] = value.len() as u8;
+        self.packet[self.len + 2..self.len + 2 + value.len()].copy_from_slice(value);
+        self.len += 2 + value.len();
+    }
+
+    /// Terminate the options, returns the length of the reply
+    fn end(self) -> usize {
+        self.packet[self.len] = OPTION_END;
+        self.len + 1
+    }
+}
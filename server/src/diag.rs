@@ -0,0 +1,95 @@
+use {
+    core::{cell::Cell, hint::black_box, ptr::addr_of},
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+};
+
+/// Written over the unused stack at boot, a word still holding it was never reached
+const PAINT: u32 = 0xA5A5_A5A5;
+/// Left unpainted below the stack pointer of `paint_stack`, covers its own frame and the few calls it makes
+const PAINT_MARGIN: usize = 512;
+
+extern "C" {
+    // Both from the cortex-m-rt linker script: the stack grows down from `_stack_start`, the initial stack
+    // pointer at the end of RAM, to `_stack_end` right after `.bss` and `.uninit`
+    static _stack_start: u32;
+    static _stack_end: u32;
+}
+
+static OPEN_SOCKETS: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+static ACCEPTED: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Lowest and one past the highest address of the stack
+fn stack_bounds() -> (usize, usize) {
+    // Only the addresses are taken, the symbols are never read
+    (addr_of!(_stack_end) as usize, addr_of!(_stack_start) as usize)
+}
+
+/// Fills the stack below the current frame with `PAINT`, so `stack_headroom` can later tell how deep it got.
+///
+/// # Safety
+///
+/// Call once, first thing in `main` before any other task is spawned. It relies on:
+/// - every task and interrupt running on the one main stack, between `_stack_end` and `_stack_start`, with no heap
+///   or anything else placed in that range,
+/// - nothing living below the current stack pointer minus `PAINT_MARGIN`, true before any other task ran,
+/// - no interrupt pushing a frame meanwhile, the critical section keeps them off.
+pub unsafe fn paint_stack() {
+    critical_section::with(|_| {
+        let (bottom, _) = stack_bounds();
+        let marker = 0u8;
+        let top = (black_box(addr_of!(marker)) as usize - PAINT_MARGIN) & !3;
+
+        let mut word = bottom as *mut u32;
+        while (word as usize) < top {
+            word.write_volatile(PAINT);
+            word = word.add(1);
+        }
+    });
+}
+
+/// Size of the stack region in bytes
+pub fn stack_size() -> usize {
+    let (bottom, top) = stack_bounds();
+    top - bottom
+}
+
+/// Bytes at the bottom of the stack never used since boot, the margin left before it runs into `.bss`
+pub fn stack_headroom() -> usize {
+    let (bottom, top) = stack_bounds();
+    let mut word = bottom as *const u32;
+    // Safety: aligned reads inside the stack region, the painted words are not part of any live frame
+    while (word as usize) < top && unsafe { word.read_volatile() } == PAINT {
+        word = word.wrapping_add(1);
+    }
+    word as usize - bottom
+}
+
+/// Held next to a socket for as long as it exists, so the open sockets can be counted against `SOCKET_COUNT`
+pub struct OpenSocket(());
+
+impl OpenSocket {
+    pub fn new() -> Self {
+        OPEN_SOCKETS.lock(|open| open.set(open.get() + 1));
+        Self(())
+    }
+}
+
+impl Drop for OpenSocket {
+    fn drop(&mut self) {
+        OPEN_SOCKETS.lock(|open| open.set(open.get().saturating_sub(1)));
+    }
+}
+
+/// Sockets of the firmware's own tasks that currently exist, the ones embassy-net opens itself not included
+pub fn open_sockets() -> u32 {
+    OPEN_SOCKETS.lock(Cell::get)
+}
+
+pub fn count_accepted() {
+    ACCEPTED.lock(|accepted| accepted.set(accepted.get().wrapping_add(1)));
+}
+
+/// Connections accepted by the HTTP tasks since boot
+pub fn accepted() -> u32 {
+    ACCEPTED.lock(Cell::get)
+}
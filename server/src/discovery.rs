@@ -0,0 +1,109 @@
+//! Zero-config discovery for tooling without mDNS: a `DHT22-DISCOVER` datagram to the discovery port is
+//! answered with a JSON description of the node, and with `DISCOVERY_BEACON=1` the same JSON is broadcast
+//! every minute. To list every node on the LAN:
+//!
+//! ```text
+//! echo -n DHT22-DISCOVER | socat - UDP-DATAGRAM:255.255.255.255:47822,broadcast
+//! python3 -c "import socket; s = socket.socket(socket.AF_INET, socket.SOCK_DGRAM); s.setsockopt(socket.SOL_SOCKET, socket.SO_BROADCAST, 1); s.settimeout(2); s.sendto(b'DHT22-DISCOVER', ('255.255.255.255', 47822)); print(s.recvfrom(512))"
+//! ```
+
+use {
+    core::fmt::Write as CoreWrite,
+    embassy_futures::select::{select, Either},
+    embassy_net::{
+        udp::{PacketMetadata, UdpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    embassy_time::{Duration, Instant, Timer},
+    heapless::String,
+    defmt::unwrap,
+    crate::{
+        diag,
+        rate_limit::RateLimiter,
+        sensor::{self, SENSOR_MODEL},
+    },
+};
+
+const PROBE: &[u8] = b"DHT22-DISCOVER";
+const BEACON_INTERVAL: Duration = Duration::from_secs(60);
+/// Answers across all clients are spaced at least this far apart, on top of the per client limit
+const MIN_REPLY_GAP: Duration = Duration::from_millis(100);
+const PACKET_SIZE: usize = 256;
+
+/// JSON describing the node, the readings are null while the primary sensor has none
+fn write_announcement<W: CoreWrite>(out: &mut W, hostname: &str, address: Ipv4Address) -> core::fmt::Result {
+    write!(out, "{{\"hostname\": \"{}\", \"ip\": \"{}\", \"version\": \"{}\", \"sensor_model\": \"{}\", ",
+        hostname, address, env!("CARGO_PKG_VERSION"), SENSOR_MODEL.as_str())?;
+    match sensor::status(sensor::PRIMARY_SENSOR) {
+        sensor::Status::Ready { reading, .. } => write!(out, "\"temperature_c\": {:.1}, \"humidity_pct\": {:.1}}}",
+            reading.temperature, reading.humidity),
+        _ => out.write_str("\"temperature_c\": null, \"humidity_pct\": null}"),
+    }
+}
+
+/// Answers probes on `port` and broadcasts the beacon when `beacon` is set
+#[embassy_executor::task]
+pub async fn discovery_task(stack: Stack<'static>, hostname: &'static str, port: u16, beacon: bool) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let _open = diag::OpenSocket::new();
+    unwrap!(socket.bind(port));
+
+    let mut limiter = RateLimiter::new();
+    let mut last_reply: Option<Instant> = None;
+    let mut next_beacon = Instant::now();
+    let mut probe = [0; PACKET_SIZE];
+
+    loop {
+        let beacon_due = async {
+            match beacon {
+                true => Timer::at(next_beacon).await,
+                false => core::future::pending().await,
+            }
+        };
+
+        let event = select(socket.recv_from(&mut probe), beacon_due).await;
+        let destination = match event {
+            Either::First(Ok((len, meta))) => {
+                if &probe[..len] != PROBE {
+                    continue;
+                }
+
+                let now = Instant::now();
+                let too_soon = last_reply.is_some_and(|last| now - last < MIN_REPLY_GAP);
+                if too_soon || limiter.check(meta.endpoint.addr, now).is_err() {
+                    log::debug!("Discovery probe from {:?} dropped by the rate limit", meta.endpoint);
+                    continue;
+                }
+                last_reply = Some(now);
+                meta.endpoint
+            },
+            Either::First(Err(e)) => {
+                log::warn!("Discovery receive error: {:?}", e);
+                continue;
+            },
+            Either::Second(()) => {
+                next_beacon = Instant::now() + BEACON_INTERVAL;
+                IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), port)
+            },
+        };
+
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        let mut announcement = String::<PACKET_SIZE>::new();
+        if write_announcement(&mut announcement, hostname, config.address.address()).is_err() {
+            log::warn!("Discovery announcement doesn't fit into a packet");
+            continue;
+        }
+        if let Err(e) = socket.send_to(announcement.as_bytes(), destination).await {
+            log::warn!("Discovery send error: {:?}", e);
+        }
+    }
+}
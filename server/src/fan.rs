@@ -0,0 +1,191 @@
+use {
+    core::cell::{Cell, RefCell},
+    embassy_futures::select::{select, Either},
+    embassy_rp::{
+        clocks::clk_sys_freq,
+        gpio::{AnyPin, Input, Pull},
+        pwm::{self, Pwm},
+    },
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    embassy_time::{Duration, Instant, Timer},
+    defmt::unwrap,
+    crate::{
+        config::{FAN_CURVE, FAN_FAILSAFE_DUTY, FAN_MIN_CHANGE, FAN_PIN, FAN_TACH_PIN},
+        fan_curve::{self, FanCurve},
+        reading::Reading,
+    },
+};
+
+/// Set by `FAN_PIN`, without it the fan routes answer 404 and nothing is driven
+pub const PRESENT: bool = FAN_PIN.is_some();
+/// What the Intel spec for 4-pin fans asks for, above hearing
+const PWM_FREQUENCY_HZ: u32 = 25_000;
+/// Pulses per revolution on the tach line of a PC fan
+const TACH_PULSES_PER_REV: u32 = 2;
+/// Edges are counted over this window, the RPM is updated once per window
+const TACH_WINDOW: Duration = Duration::from_secs(1);
+/// Used when `FAN_CURVE` doesn't parse
+const DEFAULT_CURVE: &str = "24:0,32:100";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// The duty follows the curve, from the primary sensor's readings
+    Auto,
+    /// The duty set through `POST /api/fan`
+    Manual,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct State {
+    pub mode: Mode,
+    /// The duty being driven, in percent
+    pub duty: u8,
+    /// Auto mode without a usable reading, `FAN_FAILSAFE_DUTY` is driven
+    pub failsafe: bool,
+    /// From the tach pin, `None` without one or before the first window
+    pub rpm: Option<u32>,
+}
+
+// Starts at the failsafe duty, the first good reading brings it down to the curve
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> =
+    Mutex::new(Cell::new(State { mode: Mode::Auto, duty: FAN_FAILSAFE_DUTY, failsafe: true, rpm: None }));
+static PWM: Mutex<CriticalSectionRawMutex, RefCell<Option<(Pwm<'static>, pwm::Config)>>> =
+    Mutex::new(RefCell::new(None));
+static CURVE: Mutex<CriticalSectionRawMutex, RefCell<Option<FanCurve>>> = Mutex::new(RefCell::new(None));
+
+/// Sets the PWM to 25 kHz at the failsafe duty and checks `FAN_CURVE`, errors are logged
+pub fn init(pwm: Option<Pwm<'static>>) {
+    let curve = FanCurve::parse(FAN_CURVE).unwrap_or_else(|e| {
+        log::error!("Ignoring invalid FAN_CURVE {:?}: {}, using {}", FAN_CURVE, e, DEFAULT_CURVE);
+        unwrap!(FanCurve::parse(DEFAULT_CURVE))
+    });
+    CURVE.lock(|current| *current.borrow_mut() = Some(curve));
+
+    let Some(mut pwm) = pwm else {
+        return;
+    };
+    let mut config = pwm::Config::default();
+    // 125 MHz on the RP2040 and 150 MHz on the RP2350 both fit the 16-bit counter without a divider
+    config.top = (clk_sys_freq() / PWM_FREQUENCY_HZ - 1) as u16;
+    set_compare(&mut config, FAN_FAILSAFE_DUTY);
+    pwm.set_config(&config);
+    PWM.lock(|current| *current.borrow_mut() = Some((pwm, config)));
+    log::info!("Fan on GPIO{}, {} % until the first reading", FAN_PIN.unwrap_or(0), FAN_FAILSAFE_DUTY);
+}
+
+fn set_compare(config: &mut pwm::Config, duty: u8) {
+    // A compare of top + 1 keeps the output high for the whole period
+    let compare = (u32::from(config.top) + 1) * u32::from(duty) / 100;
+    config.compare_a = compare as u16;
+    config.compare_b = compare as u16;
+}
+
+fn drive(duty: u8) {
+    PWM.lock(|current| {
+        if let Some((pwm, config)) = current.borrow_mut().as_mut() {
+            set_compare(config, duty);
+            pwm.set_config(config);
+        }
+    });
+}
+
+pub fn state() -> State {
+    STATE.lock(Cell::get)
+}
+
+pub fn curve() -> Option<FanCurve> {
+    CURVE.lock(|curve| curve.borrow().clone())
+}
+
+fn update_state(f: impl FnOnce(&mut State)) -> State {
+    STATE.lock(|state| {
+        let mut current = state.get();
+        f(&mut current);
+        state.set(current);
+        current
+    })
+}
+
+/// Fixed duty until `set_auto`
+pub fn set_manual(duty: u8) {
+    let duty = duty.min(100);
+    update_state(|state| {
+        state.mode = Mode::Manual;
+        state.duty = duty;
+        state.failsafe = false;
+    });
+    drive(duty);
+    log::info!("Fan set to {} %", duty);
+}
+
+/// Back to the curve from the latest reading, the failsafe duty without one
+pub fn set_auto(latest: Option<&Reading>) {
+    update_state(|state| {
+        state.mode = Mode::Auto;
+        state.failsafe = true;
+    });
+    log::info!("Fan following the curve");
+    update(latest);
+}
+
+/// Called by the sensor task for the primary sensor on every round, `None` when it failed. In auto mode the duty
+/// follows the curve, only in steps of `FAN_MIN_CHANGE`, and falls back to `FAN_FAILSAFE_DUTY` without a reading.
+pub fn update(reading: Option<&Reading>) {
+    if !PRESENT {
+        return;
+    }
+    let target = match reading {
+        Some(reading) => CURVE.lock(|curve| curve.borrow().as_ref().map(|curve| curve.duty(reading.temperature))),
+        None => None,
+    };
+
+    let before = state();
+    if before.mode != Mode::Auto {
+        return;
+    }
+    let (duty, failsafe) = match target {
+        Some(target) if before.failsafe || fan_curve::should_change(before.duty, target, FAN_MIN_CHANGE) => (target, false),
+        Some(_) => return,
+        None => (FAN_FAILSAFE_DUTY, true),
+    };
+    update_state(|state| {
+        state.duty = duty;
+        state.failsafe = failsafe;
+    });
+    drive(duty);
+    if failsafe && !before.failsafe {
+        log::warn!("No reading for the fan curve, fan at {} %", duty);
+    } else {
+        log::debug!("Fan at {} %", duty);
+    }
+}
+
+/// Counts the falling edges of the tach line, which the fan pulls low twice per revolution
+#[embassy_executor::task]
+pub async fn tach_task(pin: AnyPin) -> ! {
+    // The tach output is open collector
+    let mut tach = Input::new(pin, Pull::Up);
+    log::info!("Fan tach on GPIO{}", FAN_TACH_PIN.unwrap_or(0));
+
+    loop {
+        let start = Instant::now();
+        let mut edges = 0;
+        let mut window = Timer::after(TACH_WINDOW);
+        while let Either::First(()) = select(tach.wait_for_falling_edge(), &mut window).await {
+            edges += 1;
+        }
+
+        let elapsed_ms = start.elapsed().as_millis().max(1) as u32;
+        let rpm = edges * 60_000 / TACH_PULSES_PER_REV / elapsed_ms;
+        update_state(|state| state.rpm = Some(rpm));
+    }
+}
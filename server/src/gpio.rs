@@ -0,0 +1,57 @@
+use {
+    core::cell::RefCell,
+    embassy_rp::gpio::{AnyPin, Level, Output},
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    heapless::Vec,
+    crate::{config::GPIO_OUTPUTS, control::Switch},
+};
+
+/// One output per entry of `GPIO_OUTPUTS`, indexed by position
+pub const GPIO_COUNT: usize = GPIO_OUTPUTS.len();
+
+/// The outputs themselves, their output latch is the state: nothing else keeps a copy that could drift
+static OUTPUTS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Output<'static>, GPIO_COUNT>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Drive every output low, which is off, before anything can switch one
+pub fn init(pins: [AnyPin; GPIO_COUNT]) {
+    OUTPUTS.lock(|outputs| {
+        let mut outputs = outputs.borrow_mut();
+        for pin in pins {
+            let _ = outputs.push(Output::new(pin, Level::Low));
+        }
+    });
+}
+
+/// Index of the output with this name
+pub fn find(name: &str) -> Option<usize> {
+    GPIO_OUTPUTS.iter().position(|(output, _)| *output == name)
+}
+
+/// What the pin is driving, false before `init`
+pub fn is_on(index: usize) -> bool {
+    OUTPUTS.lock(|outputs| outputs.borrow().get(index).is_some_and(|output| output.is_set_high()))
+}
+
+/// Switches an output and returns what it drives now
+pub fn switch(index: usize, switch: Switch) -> bool {
+    let on = OUTPUTS.lock(|outputs| {
+        let mut outputs = outputs.borrow_mut();
+        let output = outputs.get_mut(index)?;
+        match switch {
+            Switch::On => output.set_high(),
+            Switch::Off => output.set_low(),
+            Switch::Toggle => output.toggle(),
+        }
+        Some(output.is_set_high())
+    });
+
+    match on {
+        Some(on) => {
+            let (name, pin) = GPIO_OUTPUTS[index];
+            log::info!("Output {} (GPIO{}) {}", name, pin, if on { "on" } else { "off" });
+            on
+        },
+        None => false,
+    }
+}
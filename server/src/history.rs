@@ -0,0 +1,17 @@
+use {
+    core::cell::RefCell,
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    server_core::history::{History, Sample},
+    crate::sntp,
+};
+
+/// Number of samples kept, one per sensor sample interval: one hour at 5 s.
+/// The buffer is statically allocated, 720 samples of 8 bytes take 5760 bytes of RAM.
+pub const HISTORY_CAPACITY: usize = 720;
+
+pub static HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History<HISTORY_CAPACITY>>> = Mutex::new(RefCell::new(History::new()));
+
+/// Unix time `sample` was taken, `None` until the clock is synced
+pub fn timestamp(sample: &Sample) -> Option<u64> {
+    sntp::unix_at(sample.secs_since_boot)
+}
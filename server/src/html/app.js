@@ -0,0 +1,51 @@
+// The server renders the unit it picked from TEMP_UNIT or ?unit= and the precision of the sensor model
+const unit = document.body.dataset.unit;
+const decimals = Number(document.body.dataset.decimals);
+
+const formatTemperature = (celsius) => {
+    const fahrenheit = celsius * 9 / 5 + 32;
+    if (unit === 'f') {
+        return fahrenheit.toFixed(decimals);
+    }
+    if (unit === 'both') {
+        return `${celsius.toFixed(decimals)} °C / ${fahrenheit.toFixed(decimals)} °F`;
+    }
+    return celsius.toFixed(decimals);
+};
+
+// Output buttons switch through the API and show the state the device reports back
+for (const button of document.querySelectorAll('button.gpio')) {
+    button.addEventListener('click', async () => {
+        const response = await fetch(`/api/gpio/${button.dataset.gpio}`, { method: 'POST', body: 'toggle' });
+        if (response.ok) {
+            const output = await response.json();
+            button.querySelector('span').textContent = output.on ? 'ON' : 'OFF';
+        }
+    });
+}
+
+// Silencing the buzzer only holds until the alert clears, the server says what it did
+const alarmAck = document.getElementById('alarm-ack');
+if (alarmAck) {
+    alarmAck.addEventListener('click', async () => {
+        const response = await fetch('/api/alarm/ack', { method: 'POST' });
+        if (response.ok) {
+            const { alarm } = await response.json();
+            if (!alarm.sounding) {
+                document.getElementById('alarm').textContent = 'silenced until the alert clears';
+                alarmAck.remove();
+            }
+        }
+    });
+}
+
+// Update the readings in place as the server pushes them
+const events = new EventSource('/events');
+
+events.onmessage = (event) => {
+    const data = JSON.parse(event.data);
+    document.getElementById('temperature').textContent = formatTemperature(data.temperature);
+    document.getElementById('humidity').textContent = data.humidity.toFixed(decimals);
+    document.getElementById('age').textContent = '0';
+    document.getElementById('stale').textContent = '';
+};
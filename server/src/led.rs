@@ -0,0 +1,160 @@
+use {
+    core::{
+        cell::Cell,
+        future::pending,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    embassy_futures::select::{select, select3, Either3},
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        channel::Channel,
+        signal::Signal,
+    },
+    embassy_time::{Duration, Timer},
+    crate::{
+        alert::{self, ALERTS_CHANGED},
+        control::LedCommand,
+        pattern::Blink,
+        router::Context,
+    },
+};
+
+/// State of the Wi-Fi connection as far as the LED shows it
+#[derive(Clone, Copy, PartialEq)]
+pub enum LinkStatus {
+    Joining,
+    /// The last join was rejected, usually a wrong password
+    AuthFailed,
+    /// The last join found no network with the configured SSID
+    NoNetwork,
+    /// The last join failed for another reason
+    JoinFailed,
+    Connected,
+    /// Joined, but the network configuration is gone until DHCP hands out an address again
+    WaitingForNetwork,
+    /// The setup access point is up
+    Setup,
+}
+
+/// One step of a blink pattern: LED state and how long it is held
+type Step = (bool, Duration);
+
+const fn step(on: bool, millis: u64) -> Step {
+    (on, Duration::from_millis(millis))
+}
+
+const JOINING: &[Step] = &[step(true, 500)];
+/// Three fast blinks, then a pause
+const AUTH_FAILED: &[Step] = &[
+    step(true, 100), step(false, 100),
+    step(true, 100), step(false, 100),
+    step(true, 100), step(false, 1000),
+];
+const NO_NETWORK: &[Step] = &[step(true, 1000), step(false, 1000)];
+/// Two fast blinks, then a pause
+const JOIN_FAILED: &[Step] = &[step(true, 100), step(false, 100), step(true, 100), step(false, 1000)];
+/// Short flash every two seconds
+const SETUP: &[Step] = &[step(true, 100), step(false, 1900)];
+/// Long on, short off
+const WAITING_FOR_NETWORK: &[Step] = &[step(true, 1500), step(false, 500)];
+/// 2 Hz
+const ALERT: &[Step] = &[step(true, 250), step(false, 250)];
+
+static LINK_STATUS: Mutex<CriticalSectionRawMutex, Cell<LinkStatus>> = Mutex::new(Cell::new(LinkStatus::Joining));
+static LINK_STATUS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn set_link_status(status: LinkStatus) {
+    LINK_STATUS.lock(|current| current.set(status));
+    LINK_STATUS_CHANGED.signal(());
+}
+
+pub fn link_status() -> LinkStatus {
+    LINK_STATUS.lock(Cell::get)
+}
+
+/// Pattern the LED task shows instead of anything commanded: Wi-Fi problems first, then alerts
+fn pattern() -> Option<&'static [Step]> {
+    match LINK_STATUS.lock(Cell::get) {
+        LinkStatus::Joining => Some(JOINING),
+        LinkStatus::AuthFailed => Some(AUTH_FAILED),
+        LinkStatus::NoNetwork => Some(NO_NETWORK),
+        LinkStatus::JoinFailed => Some(JOIN_FAILED),
+        LinkStatus::WaitingForNetwork => Some(WAITING_FOR_NETWORK),
+        LinkStatus::Setup => Some(SETUP),
+        LinkStatus::Connected if !alert::alerts().is_empty() => Some(ALERT),
+        LinkStatus::Connected => None,
+    }
+}
+
+static COMMANDS: Channel<CriticalSectionRawMutex, LedCommand, 4> = Channel::new();
+/// Only written by the LED task, which takes the commands in order
+static MANUAL: AtomicBool = AtomicBool::new(false);
+
+/// Queue a command, only waits while the queue is full, never for the Wi-Fi chip
+pub async fn command(command: LedCommand) {
+    COMMANDS.send(command).await;
+}
+
+/// The manual state, updated as soon as the LED task takes a command even if a pattern hides it
+pub fn manual_state() -> bool {
+    MANUAL.load(Ordering::Relaxed)
+}
+
+/// Owns the LED: plays the Wi-Fi and alert patterns, queued blinks and otherwise the manual state, and only writes
+/// the GPIO when the state actually changes
+#[embassy_executor::task]
+pub async fn led_task(ctx: &'static Context) -> ! {
+    let mut shown = None;
+    let mut current_pattern: Option<&'static [Step]> = None;
+    let mut pattern_step = 0;
+    // The blink being played and its next phase
+    let mut blink: Option<(Blink, u16)> = None;
+
+    loop {
+        let status_pattern = pattern();
+        if status_pattern.map(<[Step]>::as_ptr) != current_pattern.map(<[Step]>::as_ptr) {
+            current_pattern = status_pattern;
+            pattern_step = 0;
+        }
+
+        let (on, hold) = match (status_pattern, blink) {
+            (Some(steps), _) => {
+                // Whoever asked for the blink can't see it behind a Wi-Fi or alert pattern
+                blink = None;
+                let (on, duration) = steps[pattern_step];
+                (on, Some(duration))
+            },
+            (None, Some((blink, phase))) => {
+                let (on, millis) = blink.phase(phase);
+                (on, Some(Duration::from_millis(millis.into())))
+            },
+            (None, None) => (manual_state(), None),
+        };
+
+        if shown != Some(on) {
+            ctx.control.lock().await.gpio_set(0, on).await;
+            shown = Some(on);
+        }
+
+        let hold = async {
+            match hold {
+                Some(duration) => Timer::after(duration).await,
+                None => pending().await,
+            }
+        };
+        match select3(hold, COMMANDS.receive(), select(ALERTS_CHANGED.wait(), LINK_STATUS_CHANGED.wait())).await {
+            Either3::First(()) => match (status_pattern, blink) {
+                (Some(steps), _) => pattern_step = (pattern_step + 1) % steps.len(),
+                (None, Some((current, phase))) => {
+                    blink = (phase + 1 < current.phases()).then_some((current, phase + 1));
+                },
+                (None, None) => {},
+            },
+            Either3::Second(LedCommand::On) => MANUAL.store(true, Ordering::Relaxed),
+            Either3::Second(LedCommand::Off) => MANUAL.store(false, Ordering::Relaxed),
+            Either3::Second(LedCommand::Toggle) => MANUAL.store(!manual_state(), Ordering::Relaxed),
+            Either3::Second(LedCommand::Pattern(new)) => blink = (new.repeats > 0).then_some((new, 0)),
+            Either3::Third(_) => {},
+        }
+    }
+}
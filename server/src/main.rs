@@ -1,101 +1,144 @@
 #![no_std]
 #![no_main]
 
+mod alert;
+mod auth;
+mod board;
+mod build_info;
+mod button;
+mod buzzer;
+mod config;
+mod daily;
+mod diag;
+mod dhcp_server;
+mod discovery;
+mod fan;
+mod gpio;
+mod history;
+mod led;
+mod mdns;
+mod mqtt;
+mod pixel;
+#[cfg(feature = "oled")]
+mod oled;
+mod ota;
+mod rate_limit;
+mod router;
+mod rtc;
+mod sd_log;
+mod sensor;
+mod servo;
+mod settings;
+mod shell;
+mod sntp;
+mod storage;
+mod syslog;
+mod watchdog;
+mod wifi;
+
 use {
-    cyw43::JoinOptions,
     cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER},
     
     embassy_executor::Spawner,
-    embassy_time::{Duration, Timer},
+    embassy_futures::select::{select, Either},
+    embassy_time::{Duration, Instant},
     embassy_net::{
         tcp::TcpSocket,
         Config,
         DhcpConfig, 
+        Stack,
         StackResources,
     },
+    embassy_sync::mutex::Mutex,
     embassy_rp::{
         bind_interrupts,
+        adc::{self, Adc, InterruptHandler as AdcInterruptHandler},
         pio::InterruptHandler as PioInterruptHandler,
         usb::InterruptHandler as UsbInterruptHandler,     
         clocks::RoscRng,
-        gpio::{Level, Output, Flex, AnyPin},
-        peripherals::{DMA_CH0, PIO0, USB},
+        gpio::{Level, Output},
+        peripherals::USB,
         pio::Pio,
         usb::Driver,
     },
 
-    embedded_io_async::Write,
-    core::str::{from_utf8, FromStr},
+    core::{
+        str::{from_utf8, FromStr},
+        sync::atomic::{AtomicBool, Ordering},
+    },
     rand::RngCore,
-    static_cell::StaticCell,
-    defmt::{unwrap, info},
-    heapless::String,
-    core::fmt::Write as CoreWrite,
-    embassy_dht_sensor::DHTSensor,
+    static_cell::{ConstStaticCell, StaticCell},
+    defmt::unwrap,
+    rate_limit::RateLimiter,
+    server_core::{calendar, control, crc, derived, device_name, ds3231, fan_curve, form, hourly, http::{self, Request}, join_error, log_filter::{self, LogFilter}, mac, mqtt_packet, pattern, reading, route, template, uptime},
+    router::{Context, ReadError, Shutdown, SocketPolicy},
+    mdns::ServiceInfo,
+    reading::SpikeLimits,
+    sensor::ChipSensor,
+    storage::{Credentials, RuntimeSettings, Storage},
+    watchdog::Subsystem,
+    mac::MacAddress,
+    wifi::Networks,
     {defmt_rtt as _, panic_probe as _},
 };
 
-const WIFI_NETWORK: &str = env!("WIFI_NETWORK");
-const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
-const CLIENT_NAME: &str = "Pico-W";
-const TCP_PORT: u16 = 80;
-const BUFF_SIZE: usize = 8192;
-const HTML_BYTES: &[u8] = include_bytes!("html/index.html");
-const SSI_TEMP_TAG: &str = "<!--#TEMP-->";
-const SSI_HUMID_TAG: &str = "<!--#HUMID-->";
-
-const CYW43_JOIN_ERROR: [&str; 16] = [
-    "Success", 
-    "Operation failed", 
-    "Operation timed out",
-    "Operation no matching network found",
-    "Operation was aborted",
-    "[Protocol Failure] Packet not acknowledged",
-    "AUTH or ASSOC packet was unsolicited",
-    "Attempt to ASSOC to an auto auth configuration",
-    "Scan results are incomplete",
-    "Scan aborted by another scan",
-    "Scan aborted due to assoc in progress",
-    "802.11h quiet period started",
-    "User disabled scanning (WLC_SET_SCANSUPPRESS)",
-    "No allowable channels to scat",
-    "Scan aborted due to CCX fast roam",
-    "Abort channel select"
-];
-
-bind_interrupts!(pub struct Irqs {
-    PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
-    USBCTRL_IRQ => UsbInterruptHandler<USB>;
-});
+/// Wait for the first bytes of a request on a new and on a kept alive connection
+const FIRST_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const HTTP_TASKS: usize = 3;
+// One socket per HTTP task plus the DHCP, DNS, setup DHCP server, mDNS, SNTP, MQTT, discovery and syslog sockets
+const SOCKET_COUNT: usize = HTTP_TASKS + 8;
+pub const BUFF_SIZE: usize = 8192;
+
+/// Socket and request buffers owned by one connection handler
+struct ConnectionBuffers {
+    rx: [u8; BUFF_SIZE],
+    tx: [u8; BUFF_SIZE],
+    request: [u8; BUFF_SIZE],
+}
 
-fn process_ssi(html_file: &str, ssi_tag: &str, value: &str) -> String<BUFF_SIZE>{
-    let mut processed_html = String::<BUFF_SIZE>::new();
-    
-    for line in html_file.lines() {
-        // Replace SSI tag with actual value
-
-        if let Some(pos) = line.find(ssi_tag) {
-            // Split line into parts before and after the tag
-            let before = &line[..pos];
-            let after = &line[pos + ssi_tag.len()..];
-            
-            // Write the reconstructed line
-            write!(&mut processed_html, "{}{}{}\r\n", before, value, after).unwrap();
-        } else {
-            write!(&mut processed_html, "{}\r\n", line).unwrap();
+impl ConnectionBuffers {
+    const fn new() -> Self {
+        Self {
+            rx: [0; BUFF_SIZE],
+            tx: [0; BUFF_SIZE],
+            request: [0; BUFF_SIZE],
         }
     }
-
-    return processed_html;
 }
 
+// Kept in static memory instead of the task arena
+static CONNECTION_BUFFERS: [ConstStaticCell<ConnectionBuffers>; HTTP_TASKS] =
+    [const { ConstStaticCell::new(ConnectionBuffers::new()) }; HTTP_TASKS];
+
+bind_interrupts!(pub struct Irqs {
+    PIO0_IRQ_0 => PioInterruptHandler<board::WifiPio>;
+    PIO1_IRQ_0 => PioInterruptHandler<embassy_rp::peripherals::PIO1>;
+    USBCTRL_IRQ => UsbInterruptHandler<USB>;
+    ADC_IRQ_FIFO => AdcInterruptHandler;
+    #[cfg(any(feature = "oled", feature = "rtc"))]
+    I2C0_IRQ => embassy_rp::i2c::InterruptHandler<embassy_rp::peripherals::I2C0>;
+});
+
 #[embassy_executor::task]
 async fn usb_logger_task(driver: Driver<'static, USB>) {
-    embassy_usb_logger::run!(1024, log::LevelFilter::Info, driver);
+    let mut filter = LogFilter::new(config::LOG_LEVEL);
+    let modules = filter.set_modules(config::LOG_MODULES);
+    syslog::set_filter(filter);
+    static LOGGER: StaticCell<syslog::Logger> = StaticCell::new();
+    let logger = LOGGER.init(syslog::Logger::new());
+    // Safety: the first task to run, nothing else sets the logger
+    unsafe {
+        let _ = log::set_logger_racy(logger);
+    }
+    if let Err(e) = modules {
+        log::error!("Ignoring invalid LOG_MODULES {:?}: {}", config::LOG_MODULES, e);
+    }
+    logger.usb.run(&mut embassy_usb_logger::LoggerState::new(), driver).await;
 }
 
 #[embassy_executor::task]
-async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, board::WifiPio, 0, board::WifiDma>>) -> ! {
     runner.run().await
 }
 
@@ -104,34 +147,237 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
     runner.run().await
 }
 
+
+#[embassy_executor::task(pool_size = HTTP_TASKS)]
+async fn http_task(id: usize, stack: Stack<'static>, ctx: &'static Context, port: u16, buffers: &'static mut ConnectionBuffers) -> ! {
+    let ConnectionBuffers { rx, tx, request: buf } = buffers;
+
+    loop {
+        let mut socket = TcpSocket::new(stack, rx, tx);
+        let _open = diag::OpenSocket::new();
+        SocketPolicy::REQUEST.apply(&mut socket);
+
+        // Nothing can reach a device without an address, the listener waits until it has one again
+        let setup_mode = ctx.setup_mode.load(Ordering::Relaxed);
+        if !setup_mode && !stack.is_config_up() {
+            watchdog::idle(Subsystem::Http(id), stack.wait_config_up()).await;
+        }
+
+        let result = match watchdog::idle(Subsystem::Http(id), select(socket.accept(port), stack.wait_config_down())).await {
+            Either::First(result) => result,
+            // The static setup address never goes down
+            Either::Second(()) => {
+                log::info!("[{}] Network configuration lost, pausing the listener", id);
+                socket.abort();
+                continue;
+            },
+        };
+        if let Err(e) = result {
+            log::warn!("[{}] Accept Error: {:?}", id, e);
+            continue;
+        }
+
+        log::info!("[{}] Received Connection from {:?}", id, socket.remote_endpoint());
+        diag::count_accepted();
+        let accepted = Instant::now();
+        let mut first_request = true;
+
+        // Accepted just as the link dropped, nothing can be sent on it. The access point never reports a link.
+        if !stack.is_link_up() && !setup_mode {
+            router::finish_connection(&mut socket, Shutdown::Abort).await;
+            continue;
+        }
+
+        let mut idle_timeout = FIRST_REQUEST_TIMEOUT;
+
+        let shutdown = loop {
+            watchdog::check_in(Subsystem::Http(id));
+            // A keep-alive connection from before a link loss is dead, the client has to reconnect
+            if !stack.is_link_up() && !setup_mode {
+                log::warn!("[{}] Link down, dropping the connection", id);
+                break Shutdown::Abort;
+            }
+
+            match router::read_request(&mut socket, buf, idle_timeout).await {
+                Err(ReadError::Closed) => {
+                    log::info!("Connection closed by client");
+                    break Shutdown::Graceful;
+                }
+                Err(ReadError::Idle) => {
+                    log::info!("Closing idle connection");
+                    break Shutdown::Graceful;
+                }
+                Err(ReadError::Timeout) => {
+                    log::warn!("Request headers not received in time");
+                    if router::request_timeout(&mut socket).await.is_err() {
+                        log::warn!("Unable to send the timeout response");
+                    }
+                    break Shutdown::Graceful;
+                }
+                Err(ReadError::HeadTooLarge) => {
+                    log::warn!("Request head exceeds the size limit");
+                    if router::header_fields_too_large(&mut socket).await.is_err() {
+                        log::warn!("Unable to send the header too large response");
+                    }
+                    break Shutdown::Graceful;
+                }
+                Ok(received) => {
+                    let body = &buf[received.head_len..received.len];
+                    let request = from_utf8(&buf[..received.head_len])
+                        .ok()
+                        .and_then(|head| Request::parse(head, body));
+
+                    let Some(request) = request else {
+                        log::warn!("Malformed request from {:?}", socket.remote_endpoint());
+                        if router::bad_request(&mut socket).await.is_err() {
+                            log::warn!("Unable to send the bad request response");
+                        }
+                        break Shutdown::Graceful;
+                    };
+
+                    let started = Instant::now();
+                    let limited = match socket.remote_endpoint() {
+                        Some(endpoint) => ctx.rate_limiter.lock().await.check(endpoint.addr, Instant::now()).err(),
+                        None => None,
+                    };
+
+                    let result = match limited {
+                        // Answered without dispatching so no sensor read happens
+                        Some(retry_after) => {
+                            log::warn!("Rate limited {:?}", socket.remote_endpoint());
+                            router::too_many_requests(&mut socket, &request, retry_after).await
+                        },
+                        None => router::dispatch(&request, ctx, &mut socket, Subsystem::Http(id)).await,
+                    };
+
+                    router::log_access(socket.remote_endpoint(), &request, &result, started.elapsed());
+                    if first_request && result.is_ok() {
+                        router::record_latency(accepted.elapsed());
+                    }
+                    first_request = false;
+
+                    match result {
+                        // The response decides, a streamed body may have ended the connection whatever was asked
+                        Ok(sent) if !sent.close => idle_timeout = KEEP_ALIVE_TIMEOUT,
+                        Ok(_) => break Shutdown::Graceful,
+                        Err(router::Error::Write(e)) => {
+                            log::warn!("Write Error: {:?}", e);
+                            break Shutdown::Abort;
+                        },
+                        Err(router::Error::Overflow) => {
+                            log::error!("Response buffer overflow: Buffer is too small");
+                            if router::internal_error(&mut socket).await.is_err() {
+                                log::warn!("Unable to send the internal error response");
+                            }
+                            break Shutdown::Graceful;
+                        },
+                        Err(router::Error::Template) => {
+                            log::error!("Page template is malformed");
+                            if router::internal_error(&mut socket).await.is_err() {
+                                log::warn!("Unable to send the internal error response");
+                            }
+                            break Shutdown::Graceful;
+                        },
+                        Err(router::Error::Truncated) => {
+                            log::error!("Streamed response truncated: Buffer is too small");
+                            break Shutdown::Abort;
+                        },
+                    }
+                }
+                Err(ReadError::Socket(e)) => {
+                    log::warn!("Read Error: {:?}", e);
+                    break Shutdown::Abort;
+                }
+            };
+        };
+
+        router::finish_connection(&mut socket, shutdown).await;
+    }
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    let p = embassy_rp::init(Default::default());
-    let usb_driver = Driver::new(p.USB, Irqs);
-    let dht_pin = Flex::new(AnyPin::from(p.PIN_2));
-    let mut dht_sensor = DHTSensor::new(dht_pin);
-    let mut led_toggle_status = true;
+    // Safety: nothing else ran yet, see its documentation
+    unsafe { diag::paint_stack() };
+    let board = board::Board::init(embassy_rp::init(Default::default()));
+    let usb_driver = Driver::new(board.usb, Irqs);
+    let mut storage = Storage::new(board.flash);
+    // Before anything else uses the flash, it may swap the images and reset
+    ota::boot_check(&mut storage);
+    let stored_settings = storage.load_settings();
+    match stored_settings {
+        Some(_) => log::info!("Using the stored settings"),
+        None => log::info!("No stored settings, using the build time ones"),
+    }
+    daily::load(&mut storage);
+    let dht_sensors = sensor::new_sensors(board.dht_pins);
+    gpio::init(board.gpio_pins);
+    fan::init(board.fan);
+    let chip_sensor = ChipSensor::new(
+        Adc::new(board.adc, Irqs, adc::Config::default()),
+        adc::Channel::new_temp_sensor(board.temp_sensor),
+    );
 
     unwrap!(spawner.spawn(usb_logger_task(usb_driver)));
-
-    log::info!("Preparing the Server!");
+    unwrap!(spawner.spawn(watchdog::watchdog_task(board.watchdog)));
+    #[cfg(any(feature = "oled", feature = "rtc"))]
+    let i2c_bus: &'static board::I2cBus = {
+        static BUS: StaticCell<board::I2cBus> = StaticCell::new();
+        let pins = board.i2c;
+        let mut i2c_config = embassy_rp::i2c::Config::default();
+        i2c_config.frequency = board::I2C_FREQUENCY_HZ;
+        BUS.init(Mutex::new(embassy_rp::i2c::I2c::new_async(pins.i2c, pins.scl, pins.sda, Irqs, i2c_config)))
+    };
+    // Early, so the first samples already get the RTC's time
+    #[cfg(feature = "rtc")]
+    unwrap!(spawner.spawn(rtc::rtc_task(i2c_bus)));
+
+    log::info!("Preparing the Server! Firmware {}, built {}", build_info::SUMMARY, build_info::BUILD_TIMESTAMP);
+    log::info!("Running on a {}", board::NAME);
+    let config = config::Config::from_env();
+    log::info!("Hostname {}, serving on port {}", config.hostname, config.server_port);
+    if sensor::SIMULATED {
+        log::warn!("Simulated sensor mode: the readings are made up, nothing is read from the DHT pins");
+    }
+    ota::log_boot_check();
+
+    // Credentials from the setup page or `wifi set` win over the ones built in
+    static NETWORKS: StaticCell<Networks> = StaticCell::new();
+    let networks = NETWORKS.init(Networks::new());
+    if let Some(credentials) = storage.load_credentials() {
+        log::info!("Using the stored credentials for {}", credentials.ssid);
+        let _ = networks.push(credentials);
+    }
+    for (ssid, password) in config::WIFI_NETWORKS {
+        if networks.iter().any(|network| network.ssid == ssid) {
+            continue;
+        }
+        let Some(credentials) = Credentials::new(ssid, password) else {
+            log::error!("Skipping network {:?}, the password needs 8 to 64 characters or none", ssid);
+            continue;
+        };
+        // build.rs allows one network less than fit, the stored one always has room
+        let _ = networks.push(credentials);
+    }
+    let networks = &*networks;
 
     let mut rng = RoscRng;
     let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
 
-    let pwr = Output::new(p.PIN_23, Level::Low);
-    let cs = Output::new(p.PIN_25, Level::High);
-    let mut pio = Pio::new(p.PIO0, Irqs);
+    let wifi = board.wifi;
+    let pwr = Output::new(wifi.pwr, Level::Low);
+    let cs = Output::new(wifi.cs, Level::High);
+    let mut pio = Pio::new(wifi.pio, Irqs);
     let spi = PioSpi::new(
         &mut pio.common, 
         pio.sm0, 
         DEFAULT_CLOCK_DIVIDER,
         pio.irq0, 
         cs, 
-        p.PIN_24, 
-        p.PIN_29, 
-        p.DMA_CH0
+        wifi.dio, 
+        wifi.clk, 
+        wifi.dma
     );
 
     static STATE: StaticCell<cyw43::State> = StaticCell::new();
@@ -140,140 +386,116 @@ async fn main(spawner: Spawner) {
     unwrap!(spawner.spawn(cyw43_task(runner)));
 
     control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
 
     log::info!("CYW43 has been set!");    
-    control.gpio_set(0, true).await;
-
-    // Using DHCP config for the ipv4 address
-    let mut dhcp_config = DhcpConfig::default();
-    dhcp_config.hostname = Some(heapless::String::from_str(CLIENT_NAME).unwrap());
-    let config = Config::dhcpv4(dhcp_config);
+    let mac = control.address().await;
+    log::info!("MAC address {}", MacAddress(mac));
+
+    // DHCP unless a valid static address is configured
+    let static_config = config::static_config();
+    let use_dhcp = static_config.is_none();
+    let net_config = match static_config {
+        Some(static_config) => {
+            log::info!("Using static address {} via {:?}", static_config.address, static_config.gateway);
+            Config::ipv4_static(static_config)
+        },
+        None => {
+            let mut dhcp_config = DhcpConfig::default();
+            // Config limits the hostname to what fits
+            dhcp_config.hostname = heapless::String::from_str(config.hostname).ok();
+            Config::dhcpv4(dhcp_config)
+        },
+    };
 
     // Generate random seed
     let seed = rng.next_u64();
 
     // Init network stack
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
-    let (stack, runner) = embassy_net::new(net_device, config, RESOURCES.init(StackResources::new()), seed);
+    static RESOURCES: StaticCell<StackResources<SOCKET_COUNT>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(net_device, net_config, RESOURCES.init(StackResources::new()), seed);
 
     unwrap!(spawner.spawn(net_task(runner)));
 
-    // Connecting to the Network
-    loop {
-        match control.join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes())).await {
-            Ok(_) => {
-                Timer::after_millis(100).await;
-                break
-            },
-            Err(err) => {
-                if err.status<16 {
-                    let error_code = err.status as usize;
-                    control.gpio_set(0, led_toggle_status).await;
-                    led_toggle_status = !led_toggle_status;
-                    log::info!("Join failed with error = {}", CYW43_JOIN_ERROR[error_code]);
-                }
-            }
-        }
+    static CONTEXT: StaticCell<Context> = StaticCell::new();
+    let ctx = CONTEXT.init(Context {
+        control: Mutex::new(control),
+        rate_limiter: Mutex::new(RateLimiter::new()),
+        index: router::compile_index(),
+        settings: router::compile_settings(),
+        hostname: config.hostname,
+        mac,
+        stack,
+        dhcp: use_dhcp,
+        setup_mode: AtomicBool::new(false),
+        storage: Mutex::new(storage),
+    });
+    unwrap!(spawner.spawn(led::led_task(ctx)));
+    unwrap!(spawner.spawn(shell::shell_task(ctx)));
+    if let Some(pin) = board.button {
+        unwrap!(spawner.spawn(button::button_task(pin, ctx)));
     }
-
-    // Wait for DHCP, not necessary when using static IP
-    info!("Waiting for DHCP...");
-    while !stack.is_config_up() {
-        Timer::after_millis(100).await;
+    #[cfg(feature = "oled")]
+    unwrap!(spawner.spawn(oled::display_task(i2c_bus, ctx)));
+
+    stored_settings.unwrap_or_else(RuntimeSettings::defaults).apply(ctx).await;
+    unwrap!(spawner.spawn(settings::save_task(ctx, stored_settings)));
+    unwrap!(spawner.spawn(daily::record_task(ctx)));
+    if ota::on_trial() {
+        unwrap!(spawner.spawn(ota::trial_task(ctx)));
     }
-    log::info!("DHCP is Now Up!");
-    control.gpio_set(0, false).await;
 
-    match stack.config_v4(){
-        Some(value) => {
-            log::info!("Server Address: {:?}", value.address.address());
-            Timer::after_millis(100).await;
+    // The watchdog task has read the request by now, it ran while the radio came up
+    let joined = match networks.is_empty() {
+        _ if watchdog::setup_requested() => {
+            log::warn!("Setup access point requested before the reset, not joining");
+            false
+        },
+        false => wifi::connect_wifi(ctx, stack, networks, use_dhcp, Some(wifi::JOIN_ATTEMPTS_BEFORE_SETUP)).await,
+        true => {
+            log::error!("No usable Wi-Fi network is configured");
+            false
+        },
+    };
+
+    match joined {
+        true => {
+            ota::confirm();
+            unwrap!(spawner.spawn(wifi::wifi_task(stack, ctx, networks, use_dhcp)));
+            unwrap!(spawner.spawn(wifi::link_info_task(ctx)));
+            let info = ServiceInfo { model: sensor::SENSOR_MODEL.as_str(), version: env!("CARGO_PKG_VERSION") };
+            unwrap!(spawner.spawn(mdns::mdns_task(stack, ctx, config.hostname, mac, config.server_port, info)));
+            unwrap!(spawner.spawn(sntp::sntp_task(stack, config::NTP_SERVER)));
+            unwrap!(spawner.spawn(discovery::discovery_task(stack, config.hostname, config::DISCOVERY_PORT, config::DISCOVERY_BEACON)));
+            if let Some(mqtt_config) = config::mqtt_config(config.hostname, mac) {
+                unwrap!(spawner.spawn(mqtt::mqtt_task(stack, mqtt_config)));
+            }
+            if let Some((server, port)) = config::syslog_config() {
+                log::info!("Forwarding log lines to {}:{}", server, port);
+                unwrap!(spawner.spawn(syslog::syslog_task(stack, server, port)));
+            }
         },
-        None => log::warn!("Unable to Get the Adrress")
+        false => wifi::start_setup(spawner, ctx, stack).await,
+    }
+    let spike_limits = SpikeLimits { temperature: config::SPIKE_TEMP_LIMIT, humidity: config::SPIKE_HUMID_LIMIT };
+    unwrap!(spawner.spawn(sensor::sensor_task(dht_sensors, chip_sensor, spike_limits)));
+    if let Some(tach) = board.fan_tach {
+        unwrap!(spawner.spawn(fan::tach_task(tach)));
+    }
+    if let Some(pio) = board.pixel {
+        unwrap!(spawner.spawn(pixel::pixel_task(pio)));
+    }
+    if let Some(pwm) = board.servo {
+        unwrap!(spawner.spawn(servo::servo_task(pwm)));
+    }
+    if let Some(output) = board.buzzer {
+        unwrap!(spawner.spawn(buzzer::buzzer_task(output)));
+    }
+    if let Some((spi, cs)) = board.sd_card {
+        unwrap!(spawner.spawn(sd_log::sd_task(spi, cs)));
     }
 
-    let mut rx_buffer = [0; BUFF_SIZE];
-    let mut tx_buffer = [0; BUFF_SIZE];
-    let mut buf = [0; BUFF_SIZE];
-    let html_str = from_utf8(HTML_BYTES).unwrap();
-    
-    led_toggle_status = false;
-
-    loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(Duration::from_secs(10)));
-
-        if let Err(e) = socket.accept(TCP_PORT).await {
-            log::warn!("Accept Error: {:?}", e);
-            continue;
-        }
-
-        log::info!("Received Connection from {:?}", socket.remote_endpoint());
-        
-        // Currently only accept 1 connection at a time
-        loop {
-            match socket.read(&mut buf).await {
-                Ok(0) => {
-                    log::info!("Connection closed by client");
-                    break;
-                }
-                Ok(n) => {
-                    let request = from_utf8(&buf[..n]).unwrap();
-                    let mut processed_html = String::<BUFF_SIZE>::new();
-                    write!(&mut processed_html, "{}", html_str).unwrap();
-
-                    // Handle button request
-                    if request.starts_with("GET /led") {
-                        led_toggle_status = !led_toggle_status;
-                        control.gpio_set(0, led_toggle_status).await;
-                    } 
-
-                    let mut temp_str = String::<32>::new();
-                    let mut humidity_str = String::<32>::new();
-
-                    match dht_sensor.read() {
-                        Ok(data) => {
-                            write!(&mut temp_str, "{:.1}", data.temperature).unwrap();
-                            write!(&mut humidity_str, "{:.1}", data.humidity).unwrap();
-                        },
-                        Err(e) => {
-                            write!(&mut temp_str, "{:?}", e).unwrap();
-                            write!(&mut humidity_str, "{:?}", e).unwrap();
-                        }
-                    }
-
-                    // Process SSI template
-                    processed_html = process_ssi(processed_html.as_str(), SSI_TEMP_TAG, temp_str.as_str());
-                    processed_html = process_ssi(processed_html.as_str(), SSI_HUMID_TAG, humidity_str.as_str());
-
-                    // Build HTTP response
-                    let mut response = String::<BUFF_SIZE>::new();
-                    
-                    match write!(&mut response,
-                                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
-                                processed_html.len(),
-                                processed_html) 
-                    {
-                        Ok(_) => {
-                            if let Err(e) = socket.write_all(response.as_bytes()).await {
-                                log::warn!("Write Error: {:?}", e);
-                                break;
-                            }
-                        }
-                        Err(_) => {
-                            log::error!("Response buffer overflow: Buffer is too small");
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    log::warn!("Read Error: {:?}", e);
-                    break;
-                }
-            };
-        }
+    // Every handler listens on the same port, embassy-net hands each connection to one idle socket
+    for (id, buffers) in CONNECTION_BUFFERS.iter().enumerate() {
+        unwrap!(spawner.spawn(http_task(id, stack, ctx, config.server_port, buffers.take())));
     }
 }
@@ -0,0 +1,425 @@
+use {
+    core::{fmt::Write as CoreWrite, str::from_utf8},
+    embassy_futures::select::{select3, Either3},
+    embassy_net::{
+        udp::{PacketMetadata, UdpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    embassy_time::{Duration, Timer},
+    heapless::String,
+    defmt::unwrap,
+    crate::{
+        device_name::DeviceName,
+        diag,
+        router::Context,
+        settings::{self, NAME_CHANGES},
+        mac::MacAddress,
+        wifi,
+    },
+};
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+/// Ethernet address of the group, the CYW43 drops multicast frames it wasn't told about
+const MDNS_MAC: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+const PACKET_SIZE: usize = 512;
+const NAME_LEN: usize = 96;
+/// Records naming the host expire sooner than the others, as RFC 6762 recommends
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+/// Unsolicited responses sent once the responder starts
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+const SERVICE_TYPE: &str = "_http._tcp.local";
+/// Lists the service types of the host for browsers enumerating everything
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+
+const FLAGS_RESPONSE: u16 = 0x8000;
+const FLAGS_AUTHORITATIVE_RESPONSE: u16 = 0x8400;
+const CLASS_IN: u16 = 1;
+/// Set in the class of unique records, tells caches to drop what they had for the name
+const CACHE_FLUSH: u16 = 0x8000;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+/// Contents of the service's TXT record
+pub struct ServiceInfo {
+    pub model: &'static str,
+    pub version: &'static str,
+}
+
+impl ServiceInfo {
+    /// `key=value` entries in the order they are written
+    fn entries(&self) -> [(&'static str, &'static str); 2] {
+        [("model", self.model), ("version", self.version)]
+    }
+}
+
+/// A record the responder owns
+#[derive(Clone, Copy)]
+enum Record {
+    /// `<host>.local` A
+    Address,
+    /// `_services._dns-sd._udp.local` PTR `_http._tcp.local`
+    ServiceType,
+    /// `_http._tcp.local` PTR `<instance>._http._tcp.local`
+    ServicePointer,
+    /// `<instance>._http._tcp.local` SRV, pointing at the host and port
+    Service,
+    Text,
+}
+
+impl Record {
+    const ALL: [Record; 5] = [Record::Address, Record::ServiceType, Record::ServicePointer, Record::Service, Record::Text];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Shared records may be answered by many hosts, only the others get the cache flush bit
+    fn unique(self) -> bool {
+        !matches!(self, Record::ServiceType | Record::ServicePointer)
+    }
+}
+
+/// Set of records going into one section of a response
+#[derive(Clone, Copy, Default)]
+struct Records(u8);
+
+impl Records {
+    fn with(self, record: Record) -> Self {
+        Self(self.0 | record.bit())
+    }
+
+    fn without(self, other: Records) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    fn iter(self) -> impl Iterator<Item = Record> {
+        Record::ALL.into_iter().filter(move |record| self.0 & record.bit() != 0)
+    }
+}
+
+struct Responder {
+    host: String<NAME_LEN>,
+    instance: String<NAME_LEN>,
+    port: u16,
+    info: ServiceInfo,
+}
+
+impl Responder {
+    /// Answers and additional records for the questions of `query`, `None` for anything that isn't a query
+    fn answer(&self, query: &[u8]) -> Option<(Records, Records)> {
+        let flags = u16::from_be_bytes(query.get(2..4)?.try_into().ok()?);
+        if flags & FLAGS_RESPONSE != 0 {
+            return None;
+        }
+
+        let questions = u16::from_be_bytes(query.get(4..6)?.try_into().ok()?);
+        let mut offset = 12;
+        let mut answers = Records::default();
+        let mut additional = Records::default();
+
+        for _ in 0..questions {
+            let (name, end) = read_name(query, offset)?;
+            let kind = u16::from_be_bytes(query.get(end..end + 2)?.try_into().ok()?);
+            offset = end + 4;
+            let wants = |record_kind| kind == record_kind || kind == TYPE_ANY;
+
+            if name.eq_ignore_ascii_case(&self.host) && wants(TYPE_A) {
+                answers = answers.with(Record::Address);
+            } else if name.eq_ignore_ascii_case(SERVICE_ENUMERATION) && wants(TYPE_PTR) {
+                answers = answers.with(Record::ServiceType);
+            } else if name.eq_ignore_ascii_case(SERVICE_TYPE) && wants(TYPE_PTR) {
+                // Everything a browser needs to resolve the instance, saves it the follow-up queries
+                answers = answers.with(Record::ServicePointer);
+                additional = additional.with(Record::Service).with(Record::Text).with(Record::Address);
+            } else if name.eq_ignore_ascii_case(&self.instance) {
+                if wants(TYPE_SRV) {
+                    answers = answers.with(Record::Service);
+                    additional = additional.with(Record::Address);
+                }
+                if wants(TYPE_TXT) {
+                    answers = answers.with(Record::Text);
+                }
+            }
+        }
+
+        Some((answers, additional.without(answers)))
+    }
+
+    /// Response carrying `answers` and `additional`, `None` when it doesn't fit into one packet
+    fn response(&self, answers: Records, additional: Records, address: Ipv4Address, out: &mut Writer) -> Option<()> {
+        out.u16(0)?;
+        out.u16(FLAGS_AUTHORITATIVE_RESPONSE)?;
+        out.u16(0)?;
+        out.u16(answers.iter().count() as u16)?;
+        out.u16(0)?;
+        out.u16(additional.iter().count() as u16)?;
+
+        for record in answers.iter().chain(additional.iter()) {
+            self.record(record, address, out)?;
+        }
+
+        Some(())
+    }
+
+    fn record(&self, record: Record, address: Ipv4Address, out: &mut Writer) -> Option<()> {
+        let (name, kind, ttl) = match record {
+            Record::Address => (self.host.as_str(), TYPE_A, HOST_TTL),
+            Record::ServiceType => (SERVICE_ENUMERATION, TYPE_PTR, SERVICE_TTL),
+            Record::ServicePointer => (SERVICE_TYPE, TYPE_PTR, SERVICE_TTL),
+            Record::Service => (self.instance.as_str(), TYPE_SRV, HOST_TTL),
+            Record::Text => (self.instance.as_str(), TYPE_TXT, SERVICE_TTL),
+        };
+
+        out.name(name)?;
+        out.u16(kind)?;
+        out.u16(if record.unique() { CLASS_IN | CACHE_FLUSH } else { CLASS_IN })?;
+        out.u32(ttl)?;
+
+        // Length placeholder, patched once the data is written
+        let length_at = out.len;
+        out.u16(0)?;
+
+        match record {
+            Record::Address => out.bytes(&address.octets())?,
+            Record::ServiceType => out.name(SERVICE_TYPE)?,
+            Record::ServicePointer => out.name(&self.instance)?,
+            Record::Service => {
+                // Priority and weight, there is only one target
+                out.u16(0)?;
+                out.u16(0)?;
+                out.u16(self.port)?;
+                out.name(&self.host)?;
+            },
+            Record::Text => {
+                for (key, value) in self.info.entries() {
+                    let len = key.len() + 1 + value.len();
+                    out.bytes(&[u8::try_from(len).ok()?])?;
+                    out.bytes(key.as_bytes())?;
+                    out.bytes(b"=")?;
+                    out.bytes(value.as_bytes())?;
+                }
+            },
+        }
+
+        let length = (out.len - length_at - 2) as u16;
+        out.buf[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+        Some(())
+    }
+
+    /// The service pointer with a TTL of 0, browsers drop the instance right away instead of at its expiry
+    fn goodbye(&self, out: &mut Writer) -> Option<()> {
+        out.u16(0)?;
+        out.u16(FLAGS_AUTHORITATIVE_RESPONSE)?;
+        out.u16(0)?;
+        out.u16(1)?;
+        out.u16(0)?;
+        out.u16(0)?;
+
+        out.name(SERVICE_TYPE)?;
+        out.u16(TYPE_PTR)?;
+        out.u16(CLASS_IN)?;
+        out.u32(0)?;
+        let length_at = out.len;
+        out.u16(0)?;
+        out.name(&self.instance)?;
+        let length = (out.len - length_at - 2) as u16;
+        out.buf[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+        Some(())
+    }
+}
+
+/// `<name> (<mac>)._http._tcp.local`
+fn instance_name(name: DeviceName, mac: [u8; 6]) -> String<NAME_LEN> {
+    let mut instance = String::new();
+    // A device name and a MAC always fit
+    unwrap!(write!(&mut instance, "{} ({}).{}", name, MacAddress(mac), SERVICE_TYPE).ok());
+    instance
+}
+
+/// Dotted name at `offset` and the offset right after it, compression pointers are followed
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String<NAME_LEN>, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // Bounds a pointer loop in a malicious packet
+    let mut jumps = 0;
+
+    loop {
+        let len = *packet.get(offset)? as usize;
+        match len {
+            0 => return Some((name, end.unwrap_or(offset + 1))),
+            len if len & 0xc0 == 0xc0 => {
+                end.get_or_insert(offset + 2);
+                jumps += 1;
+                if jumps > 8 {
+                    return None;
+                }
+                offset = (len & 0x3f) << 8 | *packet.get(offset + 1)? as usize;
+            },
+            len if len & 0xc0 != 0 => return None,
+            len => {
+                let label = from_utf8(packet.get(offset + 1..offset + 1 + len)?).ok()?;
+                if !name.is_empty() {
+                    name.push('.').ok()?;
+                }
+                name.push_str(label).ok()?;
+                offset += 1 + len;
+            },
+        }
+    }
+}
+
+/// Builds a packet, every method returns `None` once it is full
+struct Writer {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Writer {
+    const fn new() -> Self {
+        Self { buf: [0; PACKET_SIZE], len: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf.get_mut(self.len..self.len + bytes.len())?.copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Uncompressed, the few records fit easily without
+    fn name(&mut self, name: &str) -> Option<()> {
+        for label in name.split('.') {
+            self.bytes(&[u8::try_from(label.len()).ok().filter(|len| *len < 64)?])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+}
+
+/// Announce every record so browsers that are already open pick the device up, or its new address
+async fn announce(responder: &Responder, stack: Stack<'static>, socket: &mut UdpSocket<'_>, out: &mut Writer) {
+    let group = IpEndpoint::new(IpAddress::Ipv4(MDNS_GROUP), MDNS_PORT);
+    let everything = Record::ALL.into_iter().fold(Records::default(), Records::with);
+
+    for _ in 0..ANNOUNCEMENTS {
+        if let Some(config) = stack.config_v4() {
+            out.len = 0;
+            if responder.response(everything, Records::default(), config.address.address(), out).is_some() {
+                if let Err(e) = socket.send_to(&out.buf[..out.len], group).await {
+                    log::warn!("mDNS announcement failed: {:?}", e);
+                }
+            }
+        }
+        Timer::after(ANNOUNCE_INTERVAL).await;
+    }
+}
+
+/// Answers `<hostname>.local` and advertises the web server as `<device name> (<mac>)._http._tcp.local` for service
+/// browsers, the MAC keeps the instances of boards sharing a name apart. A new device name retires the old instance
+/// and announces the new one.
+/// Only multicast queries are answered, one-shot resolvers sending from another port than 5353 get nothing.
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>, ctx: &'static Context, hostname: &'static str, mac: [u8; 6], port: u16, info: ServiceInfo) -> ! {
+    let mut host = String::new();
+    unwrap!(write!(&mut host, "{}.local", hostname).ok());
+    let mut responder = Responder { host, instance: instance_name(settings::device_name(), mac), port, info };
+
+    if let Err(e) = ctx.control.lock().await.add_multicast_address(MDNS_MAC).await {
+        log::warn!("Unable to receive mDNS multicast: {:?}", e);
+    }
+    if let Err(e) = stack.join_multicast_group(MDNS_GROUP) {
+        log::warn!("Unable to join the mDNS group: {:?}", e);
+    }
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2 * PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let _open = diag::OpenSocket::new();
+    unwrap!(socket.bind(MDNS_PORT));
+
+    let group = IpEndpoint::new(IpAddress::Ipv4(MDNS_GROUP), MDNS_PORT);
+    let mut query = [0; PACKET_SIZE];
+    let mut out = Writer::new();
+
+    // Cannot fail, one receiver of each is reserved for this task
+    let mut address_changes = unwrap!(wifi::ADDRESS_CHANGES.receiver());
+    let mut name_changes = unwrap!(NAME_CHANGES.receiver());
+    // The name applied at boot is already in the instance
+    let _ = name_changes.try_changed();
+
+    announce(&responder, stack, &mut socket, &mut out).await;
+    log::info!("Advertising http://{}:{}/", responder.host, port);
+
+    loop {
+        let event = select3(socket.recv_from(&mut query), address_changes.changed(), name_changes.changed()).await;
+        let (len, meta) = match event {
+            Either3::First(Ok(received)) => received,
+            Either3::First(Err(e)) => {
+                log::warn!("mDNS receive error: {:?}", e);
+                continue;
+            },
+            // Caches still hold the old address, the flush bit replaces it
+            Either3::Second(Some(_)) => {
+                announce(&responder, stack, &mut socket, &mut out).await;
+                continue;
+            },
+            Either3::Second(None) => continue,
+            Either3::Third(name) => {
+                out.len = 0;
+                if responder.goodbye(&mut out).is_some() && stack.config_v4().is_some() {
+                    if let Err(e) = socket.send_to(&out.buf[..out.len], group).await {
+                        log::warn!("mDNS goodbye failed: {:?}", e);
+                    }
+                }
+                responder.instance = instance_name(name, mac);
+                announce(&responder, stack, &mut socket, &mut out).await;
+                continue;
+            },
+        };
+
+        if meta.endpoint.port != MDNS_PORT {
+            continue;
+        }
+        let Some((answers, additional)) = responder.answer(&query[..len]) else {
+            continue;
+        };
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        if answers.is_empty() {
+            continue;
+        }
+
+        out.len = 0;
+        if responder.response(answers, additional, config.address.address(), &mut out).is_none() {
+            log::warn!("mDNS response doesn't fit into a packet");
+            continue;
+        }
+        if let Err(e) = socket.send_to(&out.buf[..out.len], group).await {
+            log::warn!("mDNS send error: {:?}", e);
+        }
+    }
+}
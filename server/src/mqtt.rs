@@ -0,0 +1,371 @@
+use {
+    core::{
+        fmt::Write as CoreWrite,
+        str::{from_utf8, FromStr},
+    },
+    embassy_futures::select::{select, select4, Either, Either4},
+    embassy_net::{
+        dns::DnsQueryType,
+        tcp::{self, TcpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Receiver},
+    embassy_time::{with_timeout, Duration, Instant, Timer},
+    embedded_io_async::Write,
+    heapless::String,
+    crate::{
+        device_name::{self, DeviceName},
+        diag,
+        mac::MacAddress,
+        mqtt_packet::{self, FixedHeader, Will},
+        reading::Reading,
+        sensor::{READINGS, READING_RECEIVERS, SENSOR_MODEL},
+        settings::{self, NAME_CHANGES, NAME_RECEIVERS},
+        wifi::{self, ADDRESS_RECEIVERS},
+    },
+};
+
+/// The broker drops the client after one and a half times this without a packet
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Ping a bit before the keep alive runs out
+const PING_INTERVAL: Duration = Duration::from_secs(45);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait after the first failed connection, doubled after every further one
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+const SOCKET_BUFFER_SIZE: usize = 512;
+/// Fits a discovery config, readings and pings need far less
+const PACKET_SIZE: usize = 768;
+const TOPIC_LEN: usize = 96;
+/// Longest hostname, a dash and the MAC without separators
+const CLIENT_ID_LEN: usize = 32 + 1 + 12;
+const DISCOVERY_PAYLOAD_SIZE: usize = 640;
+const ONLINE: &[u8] = b"online";
+const OFFLINE: &[u8] = b"offline";
+
+/// Broker and topics from the `MQTT_*` variables
+pub struct MqttConfig {
+    /// Hostname or IPv4 address
+    broker: &'static str,
+    port: u16,
+    /// `{name}` in it stands for the device name, the topics are built for each connection
+    topic_prefix: &'static str,
+    /// `<hostname>-<mac>`, unique even when several boards share a hostname
+    client_id: String<CLIENT_ID_LEN>,
+    /// The Home Assistant unique ids derive from it
+    mac: [u8; 6],
+    /// Announce the entities through Home Assistant discovery
+    discovery: bool,
+}
+
+impl MqttConfig {
+    /// `None` when the topics don't fit with `topic_prefix` and the longest device name
+    pub fn new(broker: &'static str, port: u16, topic_prefix: &'static str, hostname: &'static str, mac: [u8; 6], discovery: bool) -> Option<Self> {
+        let longest = [b'x'; device_name::MAX_LEN];
+        Topics::new(topic_prefix, from_utf8(&longest).ok()?)?;
+        let client_id = MacAddress(mac).client_id(hostname)?;
+        Some(Self { broker, port, topic_prefix, client_id, mac, discovery })
+    }
+}
+
+/// One Home Assistant entity announced through discovery
+struct Entity {
+    config_topic: &'static str,
+    name: &'static str,
+    /// Appended to the device id to form the entity's unique id
+    suffix: &'static str,
+    unit: &'static str,
+    device_class: &'static str,
+}
+
+const ENTITIES: [Entity; 2] = [
+    Entity {
+        config_topic: "homeassistant/sensor/picow_temp/config",
+        name: "Temperature",
+        suffix: "temp",
+        unit: "°C",
+        device_class: "temperature",
+    },
+    Entity {
+        config_topic: "homeassistant/sensor/picow_hum/config",
+        name: "Humidity",
+        suffix: "hum",
+        unit: "%",
+        device_class: "humidity",
+    },
+];
+
+struct Topics {
+    temperature: String<TOPIC_LEN>,
+    humidity: String<TOPIC_LEN>,
+    availability: String<TOPIC_LEN>,
+}
+
+impl Topics {
+    fn new(prefix: &str, device_name: &str) -> Option<Self> {
+        let topic = |name: &str| {
+            let mut topic = String::<TOPIC_LEN>::new();
+            for (index, part) in prefix.trim_end_matches('/').split("{name}").enumerate() {
+                if index > 0 {
+                    topic.push_str(device_name).ok()?;
+                }
+                topic.push_str(part).ok()?;
+            }
+            write!(&mut topic, "/{}", name).ok()?;
+            Some(topic)
+        };
+
+        Some(Self { temperature: topic("temperature")?, humidity: topic("humidity")?, availability: topic("availability")? })
+    }
+}
+
+enum Error {
+    Resolve,
+    Connect(tcp::ConnectError),
+    Socket(tcp::Error),
+    Closed,
+    Timeout,
+    /// CONNACK with a non-zero return code, e.g. 5 for not authorized
+    Refused(u8),
+    Packet(mqtt_packet::Error),
+    UnexpectedPacket(u8),
+    /// The connection is bound to the old address
+    AddressChanged,
+    /// The topics and the Home Assistant device go by the old name
+    Renamed,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Error::Resolve => f.write_str("broker name not resolved"),
+            Error::Connect(e) => write!(f, "connect error {:?}", e),
+            Error::Socket(e) => write!(f, "socket error {:?}", e),
+            Error::Closed => f.write_str("closed by the broker"),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Refused(code) => write!(f, "refused with return code {}", code),
+            Error::Packet(e) => write!(f, "packet error {:?}", e),
+            Error::UnexpectedPacket(packet_type) => write!(f, "unexpected packet type {}", packet_type),
+            Error::AddressChanged => f.write_str("network address changed"),
+            Error::Renamed => f.write_str("device name changed"),
+        }
+    }
+}
+
+impl From<mqtt_packet::Error> for Error {
+    fn from(e: mqtt_packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+/// Publishes every reading of the primary sensor to `<prefix>/temperature` and `<prefix>/humidity`,
+/// `<prefix>/availability` is a retained `online` while connected and `offline` as last will. A new device name
+/// takes the old topics offline and reconnects with the new ones, the discovery then renames the device.
+#[embassy_executor::task]
+pub async fn mqtt_task(stack: Stack<'static>, config: MqttConfig) -> ! {
+    // Cannot fail, one receiver is reserved for this task
+    let mut readings = READINGS.receiver().unwrap();
+    let mut address_changes = wifi::ADDRESS_CHANGES.receiver().unwrap();
+    let mut name_changes = NAME_CHANGES.receiver().unwrap();
+    let mut rx = [0; SOCKET_BUFFER_SIZE];
+    let mut tx = [0; SOCKET_BUFFER_SIZE];
+    let mut backoff = RECONNECT_MIN;
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx, &mut tx);
+        let _open = diag::OpenSocket::new();
+        let name = settings::device_name();
+        // Cannot fail, `MqttConfig::new` tried the longest name
+        let topics = Topics::new(config.topic_prefix, name.as_str()).unwrap();
+
+        match connect(stack, &mut socket, &config, &topics, name).await {
+            Ok(()) => {
+                log::info!("MQTT connected to {}:{}", config.broker, config.port);
+                backoff = RECONNECT_MIN;
+                // A change from before this connection is already taken into account
+                let _ = address_changes.try_changed();
+                let _ = name_changes.try_changed();
+                let e = run(&mut socket, &topics, &mut readings, &mut address_changes, &mut name_changes).await;
+                match e {
+                    Error::Renamed => log::info!("MQTT reconnecting as {}", settings::device_name()),
+                    e => log::warn!("MQTT connection lost: {}", e),
+                }
+            },
+            Err(e) => log::warn!("MQTT connection to {}:{} failed: {}", config.broker, config.port, e),
+        }
+
+        socket.abort();
+        let _ = socket.flush().await;
+        log::info!("MQTT reconnecting in {} s", backoff.as_secs());
+        Timer::after(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// Open the connection and the session, then mark the client online
+async fn connect(stack: Stack<'static>, socket: &mut TcpSocket<'_>, config: &MqttConfig, topics: &Topics, name: DeviceName) -> Result<(), Error> {
+    let address = match Ipv4Address::from_str(config.broker) {
+        Ok(address) => IpAddress::Ipv4(address),
+        Err(_) => *stack.dns_query(config.broker, DnsQueryType::A).await.map_err(|_| Error::Resolve)?
+            .first()
+            .ok_or(Error::Resolve)?,
+    };
+
+    socket.set_timeout(Some(KEEP_ALIVE * 2));
+    with_timeout(CONNECT_TIMEOUT, socket.connect(IpEndpoint::new(address, config.port))).await
+        .map_err(|_| Error::Timeout)?
+        .map_err(Error::Connect)?;
+
+    let will = Will { topic: &topics.availability, payload: OFFLINE, retain: true };
+    let mut packet = [0; PACKET_SIZE];
+    let len = mqtt_packet::connect(&mut packet, &config.client_id, KEEP_ALIVE.as_secs() as u16, Some(&will))?;
+    socket.write_all(&packet[..len]).await.map_err(Error::Socket)?;
+
+    let mut connack = [0; 4];
+    with_timeout(CONNECT_TIMEOUT, read_exact(socket, &mut connack)).await.map_err(|_| Error::Timeout)??;
+    let header = FixedHeader::decode(&connack)?.ok_or(Error::Packet(mqtt_packet::Error::Malformed))?;
+    if header.packet_type != mqtt_packet::CONNACK {
+        return Err(Error::UnexpectedPacket(header.packet_type));
+    }
+    match mqtt_packet::connack_return_code(&connack[header.header_len..])? {
+        0 => {},
+        code => return Err(Error::Refused(code)),
+    }
+
+    let len = mqtt_packet::publish(&mut packet, &topics.availability, ONLINE, true)?;
+    socket.write_all(&packet[..len]).await.map_err(Error::Socket)?;
+
+    // Retained, but sent on every connect since the broker may have lost them or Home Assistant was reset
+    if config.discovery {
+        for (entity, state_topic) in ENTITIES.iter().zip([&topics.temperature, &topics.humidity]) {
+            let mut payload = String::<DISCOVERY_PAYLOAD_SIZE>::new();
+            write_discovery(&mut payload, entity, state_topic, config, topics, name).map_err(|_| Error::Packet(mqtt_packet::Error::BufferTooSmall))?;
+            let len = mqtt_packet::publish(&mut packet, entity.config_topic, payload.as_bytes(), true)?;
+            socket.write_all(&packet[..len]).await.map_err(Error::Socket)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Discovery config of `entity`, both entities share the device block so they show up as one device
+fn write_discovery<W: CoreWrite>(out: &mut W, entity: &Entity, state_topic: &str, config: &MqttConfig, topics: &Topics, name: DeviceName) -> core::fmt::Result {
+    let device_id = MacAddress(config.mac).device_id();
+
+    write!(out, "{{\"name\": \"{}\", \"state_topic\": \"{}\", \"availability_topic\": \"{}\", ",
+        entity.name, state_topic, topics.availability)?;
+    write!(out, "\"unit_of_measurement\": \"{}\", \"device_class\": \"{}\", \"state_class\": \"measurement\", ",
+        entity.unit, entity.device_class)?;
+    write!(out, "\"unique_id\": \"{}_{}\", ", device_id, entity.suffix)?;
+    write!(out, "\"device\": {{\"identifiers\": [\"{}\"], \"name\": \"{}\", \"model\": \"{}\", \"manufacturer\": \"Raspberry Pi\", \"sw_version\": \"{}\"}}}}",
+        device_id, name, SENSOR_MODEL.name(), env!("CARGO_PKG_VERSION"))
+}
+
+/// Publish readings and keep the session alive until the connection fails
+async fn run(
+    socket: &mut TcpSocket<'_>,
+    topics: &Topics,
+    readings: &mut Receiver<'static, CriticalSectionRawMutex, Reading, READING_RECEIVERS>,
+    address_changes: &mut Receiver<'static, CriticalSectionRawMutex, Option<Ipv4Address>, ADDRESS_RECEIVERS>,
+    name_changes: &mut Receiver<'static, CriticalSectionRawMutex, DeviceName, NAME_RECEIVERS>,
+) -> Error {
+    let mut packet = [0; PACKET_SIZE];
+    let mut received = [0; 16];
+    let mut received_len = 0;
+    let mut last_sent = Instant::now();
+    let mut ping_outstanding = false;
+
+    loop {
+        let event = select4(
+            readings.changed(),
+            Timer::at(last_sent + PING_INTERVAL),
+            socket.read(&mut received[received_len..]),
+            select(address_changes.changed(), name_changes.changed()),
+        ).await;
+        match event {
+            Either4::First(reading) => {
+                if let Err(e) = publish_reading(socket, topics, &reading, &mut packet).await {
+                    return e;
+                }
+                last_sent = Instant::now();
+            },
+            Either4::Second(_) => {
+                // The previous ping is still unanswered after a whole interval
+                if ping_outstanding {
+                    return Error::Timeout;
+                }
+                let len = match mqtt_packet::pingreq(&mut packet) {
+                    Ok(len) => len,
+                    Err(e) => return e.into(),
+                };
+                if let Err(e) = socket.write_all(&packet[..len]).await {
+                    return Error::Socket(e);
+                }
+                ping_outstanding = true;
+                last_sent = Instant::now();
+            },
+            Either4::Third(Ok(0)) => return Error::Closed,
+            Either4::Third(Ok(n)) => {
+                received_len += n;
+                // Nothing is subscribed, the broker only ever sends tiny packets
+                while let Some(header) = match FixedHeader::decode(&received[..received_len]) {
+                    Ok(header) => header,
+                    Err(e) => return e.into(),
+                } {
+                    let len = header.header_len + header.remaining_length;
+                    if len > received.len() {
+                        return Error::UnexpectedPacket(header.packet_type);
+                    }
+                    if len > received_len {
+                        break;
+                    }
+                    match header.packet_type {
+                        mqtt_packet::PINGRESP => ping_outstanding = false,
+                        other => return Error::UnexpectedPacket(other),
+                    }
+                    received.copy_within(len..received_len, 0);
+                    received_len -= len;
+                }
+            },
+            Either4::Third(Err(e)) => return Error::Socket(e),
+            Either4::Fourth(Either::First(_)) => return Error::AddressChanged,
+            // The will only covers a lost connection, nothing publishes to the old topics anymore
+            Either4::Fourth(Either::Second(_)) => {
+                if let Ok(len) = mqtt_packet::publish(&mut packet, &topics.availability, OFFLINE, true) {
+                    let _ = socket.write_all(&packet[..len]).await;
+                    let _ = socket.flush().await;
+                }
+                return Error::Renamed;
+            },
+        }
+    }
+}
+
+async fn publish_reading(socket: &mut TcpSocket<'_>, topics: &Topics, reading: &Reading, packet: &mut [u8]) -> Result<(), Error> {
+    let mut payload = String::<16>::new();
+    for (topic, value) in [(&topics.temperature, reading.temperature), (&topics.humidity, reading.humidity)] {
+        payload.clear();
+        // Cannot fail, a reading takes a handful of characters
+        let _ = write!(&mut payload, "{:.1}", value);
+        let len = mqtt_packet::publish(packet, topic, payload.as_bytes(), false)?;
+        socket.write_all(&packet[..len]).await.map_err(Error::Socket)?;
+    }
+
+    Ok(())
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Result<(), Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match socket.read(&mut buf[filled..]).await {
+            Ok(0) => return Err(Error::Closed),
+            Ok(n) => filled += n,
+            Err(e) => return Err(Error::Socket(e)),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use {
+    core::{fmt::Write, iter, sync::atomic::Ordering},
+    embassy_futures::select::select,
+    embassy_rp::i2c,
+    embassy_time::{Duration, Timer},
+    heapless::String,
+    server_core::display::{Framebuffer, PAGES, WIDTH},
+    crate::{
+        board::I2cBus,
+        derived::celsius_to_fahrenheit,
+        router::{Context, TempUnit},
+        sensor::{self, READINGS, SENSOR_MODEL},
+        wifi::{self, SETUP_AP_SSID},
+    },
+};
+
+/// The SSD1306's address with its SA0 pin low, 0x3D with it high
+const ADDRESS: u8 = 0x3c;
+/// 0 to 255, the reset value is 0x7f
+const CONTRAST: u8 = 0x8f;
+/// For a display mounted upside down
+const ROTATE_180: bool = false;
+/// Redraw at least this often, the address and the Wi-Fi state change without a new reading
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// First byte of a transfer, what the bytes after it are
+const COMMANDS: u8 = 0x00;
+const DATA: u8 = 0x40;
+
+/// The whole setup after power up, the display is blank until the first frame
+const INIT: [u8; 25] = [
+    0xae, // display off
+    0xd5, 0x80, // clock divider and oscillator frequency, the reset values
+    0xa8, (PAGES * 8 - 1) as u8, // multiplex ratio, one per row
+    0xd3, 0x00, // no display offset
+    0x40, // start line 0
+    0x8d, 0x14, // charge pump on, the module has no external supply for the panel
+    0x20, 0x00, // horizontal addressing, a frame is written in one go
+    if ROTATE_180 { 0xa0 } else { 0xa1 }, // segment remap, column 127 on the left
+    if ROTATE_180 { 0xc0 } else { 0xc8 }, // COM scan direction, page 0 on top
+    0xda, 0x12, // alternative COM pin configuration of the 64 row panels
+    0x81, CONTRAST,
+    0xd9, 0xf1, // precharge periods for the charge pump
+    0xdb, 0x40, // VCOMH deselect level
+    0xa4, // show the RAM content
+    0xa6, // not inverted
+    0xaf, // display on
+];
+/// Address range of a whole frame, sent before each one
+const FRAME_WINDOW: [u8; 6] = [0x21, 0x00, (WIDTH - 1) as u8, 0x22, 0x00, (PAGES - 1) as u8];
+
+async fn send(bus: &I2cBus, control: u8, bytes: &[u8]) -> Result<(), i2c::Error> {
+    bus.lock().await.write_async(ADDRESS, iter::once(control).chain(bytes.iter().copied())).await
+}
+
+/// Shows the primary sensor's reading, the address and the Wi-Fi state, redrawn on every new reading. Without a
+/// display answering, or once it stops answering, the task logs it and ends, the bus is left alone after that.
+#[embassy_executor::task]
+pub async fn display_task(bus: &'static I2cBus, ctx: &'static Context) {
+    if let Err(e) = send(bus, COMMANDS, &INIT).await {
+        log::warn!("No display answering at {:#04x} on I2C0 ({:?}), display updates are off", ADDRESS, e);
+        return;
+    }
+    log::info!("Display found at {:#04x} on I2C0", ADDRESS);
+
+    let Some(mut readings) = READINGS.receiver() else {
+        log::error!("No reading receiver left for the display");
+        return;
+    };
+    let mut frame = Framebuffer::new();
+
+    loop {
+        render(&mut frame, ctx);
+        let sent = match send(bus, COMMANDS, &FRAME_WINDOW).await {
+            Ok(()) => send(bus, DATA, frame.as_bytes()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            log::warn!("The display stopped answering ({:?}), display updates are off", e);
+            return;
+        }
+
+        select(readings.changed(), Timer::after(REFRESH_INTERVAL)).await;
+    }
+}
+
+fn render(frame: &mut Framebuffer, ctx: &Context) {
+    frame.clear();
+    let mut line = String::<32>::new();
+
+    match sensor::status(sensor::PRIMARY_SENSOR) {
+        sensor::Status::Ready { reading, stale, .. } => {
+            let decimals = SENSOR_MODEL.decimals();
+            let _ = if TempUnit::configured() == TempUnit::Fahrenheit {
+                write!(line, "{:.*}°F", decimals, celsius_to_fahrenheit(reading.temperature))
+            } else {
+                write!(line, "{:.*}°C", decimals, reading.temperature)
+            };
+            frame.text(0, 0, &line, 2);
+            line.clear();
+            let _ = write!(line, "{:.*} %", decimals, reading.humidity);
+            frame.text(0, 2, &line, 2);
+            if stale {
+                frame.text(0, 4, "last read failed", 1);
+            }
+        },
+        sensor::Status::NotReady => {
+            frame.text(0, 0, "--", 2);
+            frame.text(0, 2, "--", 2);
+            frame.text(0, 4, "no reading yet", 1);
+        },
+    }
+
+    line.clear();
+    let _ = match ctx.stack.config_v4() {
+        Some(config) => write!(line, "IP {}", config.address.address()),
+        None => write!(line, "IP --"),
+    };
+    frame.text(0, 5, &line, 1);
+
+    line.clear();
+    if ctx.setup_mode.load(Ordering::Relaxed) {
+        frame.text(0, 6, "Setup AP", 1);
+        frame.text(0, 7, SETUP_AP_SSID, 1);
+        return;
+    }
+    match wifi::joined_ssid() {
+        Some(ssid) => {
+            frame.text(0, 6, &ssid, 1);
+            if let Some(info) = wifi::link_info() {
+                let _ = write!(line, "{} dBm, {}", info.rssi_dbm, info.quality().as_str());
+                frame.text(0, 7, &line, 1);
+            }
+        },
+        None => {
+            frame.text(0, 6, "Wi-Fi not joined", 1);
+        },
+    }
+}
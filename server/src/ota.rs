@@ -0,0 +1,328 @@
+use {
+    core::{cell::Cell, ptr},
+    cortex_m::peripheral::SCB,
+    embassy_futures::select::{select, Either},
+    embassy_rp::{
+        flash::{self, ERASE_SIZE},
+        rom_data,
+    },
+    embassy_sync::{
+        blocking_mutex::{self, raw::CriticalSectionRawMutex},
+        mutex::{Mutex, MutexGuard},
+        signal::Signal,
+    },
+    embassy_time::{Duration, Timer},
+    server_core::ota::{looks_like_image, swap_sectors, BootAction, UpdateState},
+    crate::{
+        board::{SLOT_SIZE, VECTOR_TABLE_OFFSET},
+        crc::crc32_continue,
+        router::Context,
+        storage::{Storage, UPDATE_SLOT_OFFSET, XIP_BASE},
+    },
+};
+
+/// A new image has this long after its first start to join the network, otherwise the previous one comes back
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(300);
+const SECTOR_SIZE: u32 = ERASE_SIZE as u32;
+const SECTOR_WORDS: usize = ERASE_SIZE / 4;
+/// Appended to an upload that doesn't give `?crc=`, little endian
+pub const TRAILER_SIZE: usize = 4;
+
+extern "C" {
+    /// Set by the memory layout right past the running image
+    static __image_end: u8;
+}
+
+/// What the boot check left, updated by the confirmation
+static STATE: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<UpdateState>> =
+    blocking_mutex::Mutex::new(Cell::new(UpdateState::Idle));
+/// Set when the boot check dropped an upload that didn't read back as it was verified
+static DISCARDED: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<bool>> = blocking_mutex::Mutex::new(Cell::new(false));
+static CONFIRMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static UPLOAD: Mutex<CriticalSectionRawMutex, Upload> = Mutex::new(Upload::new());
+
+pub fn state() -> UpdateState {
+    STATE.lock(Cell::get)
+}
+
+fn set_state(state: UpdateState) {
+    STATE.lock(|current| current.set(state));
+}
+
+pub fn on_trial() -> bool {
+    matches!(state(), UpdateState::Trial { .. })
+}
+
+fn running_image_len() -> u32 {
+    (ptr::addr_of!(__image_end) as usize - XIP_BASE) as u32
+}
+
+/// Runs first thing at boot, before anything else touches the flash: swaps in an upload that still reads back as
+/// it was verified, starts the trial of an image swapped in by the last boot or swaps the previous image back when
+/// that trial ended in a reset. The swaps take a few seconds and end in a reset. A power cut during one leaves half
+/// of each image, the device then needs a USB reflash in BOOTSEL mode.
+pub fn boot_check(storage: &mut Storage) {
+    let state = storage.load_update_state();
+    match state.boot_action() {
+        BootAction::Run => set_state(state),
+        BootAction::Install { len, crc } => {
+            if storage.update_crc(len).ok() == Some(crc) {
+                let old_len = running_image_len();
+                if storage.save_update_state(UpdateState::Trial { len, old_len, booted: false }).is_ok() {
+                    // Safety: nothing else runs yet
+                    unsafe { swap_and_reset(swap_sectors(len, old_len, SECTOR_SIZE)) }
+                }
+            }
+            // Tried again at the next boot when only the state couldn't be stored
+            if storage.save_update_state(UpdateState::Idle).is_ok() {
+                DISCARDED.lock(|discarded| discarded.set(true));
+            }
+        },
+        BootAction::StartTrial { len, old_len } => {
+            let trial = UpdateState::Trial { len, old_len, booted: true };
+            // Without the flag stored, a reset before the confirmation keeps this image instead of rolling it back
+            let _ = storage.save_update_state(trial);
+            set_state(trial);
+        },
+        BootAction::Revert { len, old_len } => {
+            if storage.save_update_state(UpdateState::RolledBack).is_ok() {
+                // Safety: as above
+                unsafe { swap_and_reset(swap_sectors(len, old_len, SECTOR_SIZE)) }
+            }
+            set_state(state);
+        },
+    }
+}
+
+/// What the boot check did, it runs before the logger is up
+pub fn log_boot_check() {
+    if DISCARDED.lock(Cell::get) {
+        log::error!("The uploaded firmware no longer matched its CRC, it was not installed");
+    }
+    match state() {
+        UpdateState::Trial { .. } => {
+            log::warn!("Running a new firmware on trial, it is kept once it joins the Wi-Fi within {} s", TRIAL_TIMEOUT.as_secs());
+        },
+        UpdateState::RolledBack => log::warn!("The last firmware update never joined the Wi-Fi, the previous firmware is back"),
+        _ => {},
+    }
+}
+
+/// The network is joined, a new image on trial stays
+pub fn confirm() {
+    CONFIRMED.signal(());
+}
+
+/// Waits for the confirmation of a new image. Without it in `TRIAL_TIMEOUT` the device resets and the boot check
+/// swaps the previous image back, the same as after a crash or a watchdog reset before it.
+#[embassy_executor::task]
+pub async fn trial_task(ctx: &'static Context) {
+    if let Either::Second(()) = select(CONFIRMED.wait(), Timer::after(TRIAL_TIMEOUT)).await {
+        log::error!("The new firmware didn't join the Wi-Fi within {} s, rolling back", TRIAL_TIMEOUT.as_secs());
+        SCB::sys_reset();
+    }
+    match ctx.storage.lock().await.save_update_state(UpdateState::Confirmed) {
+        Ok(()) => {
+            set_state(UpdateState::Confirmed);
+            log::info!("The new firmware joined the Wi-Fi, it stays");
+        },
+        Err(e) => log::error!("Unable to confirm the new firmware ({:?}), the next reset rolls it back", e),
+    }
+}
+
+/// The boot ROM's flash routines, looked up while the flash can still be read
+#[repr(C)]
+struct RomFlash {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+}
+
+/// Exchanges the first `sectors` sectors of the two slots and resets into what the running slot holds then.
+///
+/// # Safety
+///
+/// Only from the boot check: no other task, interrupt handler, core or DMA channel may be using the flash.
+unsafe fn swap_and_reset(sectors: u32) -> ! {
+    let rom = RomFlash {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        flash_enter_cmd_xip: rom_data::flash_enter_cmd_xip::ptr(),
+    };
+    let mut buffers = [[0u32; SECTOR_WORDS]; 2];
+    // The watchdog of the last boot survives the reset, a swap of a full slot takes longer than its timeout
+    embassy_rp::pac::WATCHDOG.ctrl().modify(|w| w.set_enable(false));
+    cortex_m::interrupt::disable();
+    swap_slots(&rom, sectors, &mut buffers)
+}
+
+/// Runs from RAM and only calls into the boot ROM, the code it was called from is overwritten along the way.
+/// Nothing in here may panic or call a function in the flash: the arithmetic wraps and the copies go through
+/// pointers. Each sector of both slots is read while the flash is still mapped, then both are erased and written
+/// with the other's content.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn swap_slots(rom: &RomFlash, sectors: u32, buffers: &mut [[u32; SECTOR_WORDS]; 2]) -> ! {
+    let running = buffers.as_mut_ptr() as *mut u32;
+    let update = running.wrapping_add(SECTOR_WORDS);
+
+    let mut sector = 0;
+    while sector < sectors {
+        let running_offset = sector.wrapping_mul(SECTOR_SIZE);
+        let update_offset = UPDATE_SLOT_OFFSET.wrapping_add(running_offset);
+        let running_source = (XIP_BASE as u32).wrapping_add(running_offset) as *const u32;
+        let update_source = (XIP_BASE as u32).wrapping_add(update_offset) as *const u32;
+        let mut word = 0;
+        while word < SECTOR_WORDS {
+            running.wrapping_add(word).write_volatile(running_source.wrapping_add(word).read_volatile());
+            update.wrapping_add(word).write_volatile(update_source.wrapping_add(word).read_volatile());
+            word = word.wrapping_add(1);
+        }
+
+        (rom.connect_internal_flash)();
+        (rom.flash_exit_xip)();
+        // A block size that never matches keeps the ROM to 4K sector erases
+        (rom.flash_range_erase)(running_offset, ERASE_SIZE, 1 << 31, 0);
+        (rom.flash_range_program)(running_offset, update as *const u8, ERASE_SIZE);
+        (rom.flash_range_erase)(update_offset, ERASE_SIZE, 1 << 31, 0);
+        (rom.flash_range_program)(update_offset, running as *const u8, ERASE_SIZE);
+        (rom.flash_flush_cache)();
+        (rom.flash_enter_cmd_xip)();
+        sector = sector.wrapping_add(1);
+    }
+
+    // SYSRESETREQ, `SCB::sys_reset` is in the flash
+    (0xe000_ed0c as *mut u32).write_volatile(0x05fa_0004);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Why an upload was refused or stopped
+#[derive(Debug)]
+pub enum UploadError {
+    /// Another upload holds the update slot
+    InProgress,
+    /// The update slot holds the previous image until the running one confirms
+    OnTrial,
+    /// Empty or larger than the slot
+    BadLength,
+    /// The start of the upload isn't a vector table for this board
+    NotAnImage,
+    /// The CRC of what was received isn't the expected one
+    CrcMismatch { expected: u32, received: u32 },
+    /// The body stopped short of its Content-Length
+    Incomplete,
+    /// Writing the update slot or the state failed
+    Flash(flash::Error),
+}
+
+impl UploadError {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            UploadError::InProgress => "another update is being received",
+            UploadError::OnTrial => "the running firmware is on trial until it joins the Wi-Fi",
+            UploadError::BadLength => "the image is empty or larger than the update slot",
+            UploadError::NotAnImage => "not a firmware image for this board, expected the raw binary",
+            UploadError::CrcMismatch { .. } => "the CRC doesn't match the image",
+            UploadError::Incomplete => "the upload stopped early",
+            UploadError::Flash(_) => "writing the flash failed",
+        }
+    }
+}
+
+/// An upload being written to the update slot a sector at a time
+pub struct Upload {
+    /// Bytes of the image, a CRC trailer not included
+    len: u32,
+    /// Bytes of the body so far, the trailer included
+    received: u32,
+    crc: u32,
+    trailer: [u8; TRAILER_SIZE],
+    sector: [u8; ERASE_SIZE],
+}
+
+impl Upload {
+    const fn new() -> Self {
+        Self { len: 0, received: 0, crc: 0, trailer: [0; TRAILER_SIZE], sector: [0; ERASE_SIZE] }
+    }
+
+    /// Takes the update slot for an image of `len` bytes. A pending upload is dropped, it would be overwritten.
+    pub async fn begin(ctx: &Context, len: usize) -> Result<MutexGuard<'static, CriticalSectionRawMutex, Upload>, UploadError> {
+        if len == 0 || len > SLOT_SIZE {
+            return Err(UploadError::BadLength);
+        }
+        if on_trial() {
+            return Err(UploadError::OnTrial);
+        }
+        let mut upload = UPLOAD.try_lock().map_err(|_| UploadError::InProgress)?;
+        if let UpdateState::Pending { .. } = ctx.storage.lock().await.load_update_state() {
+            ctx.storage.lock().await.save_update_state(UpdateState::Idle).map_err(UploadError::Flash)?;
+        }
+        *upload = Upload { len: len as u32, ..Upload::new() };
+        Ok(upload)
+    }
+
+    /// The next bytes of the body, a sector is written each time one fills up
+    pub async fn write(&mut self, ctx: &Context, mut bytes: &[u8]) -> Result<(), UploadError> {
+        while !bytes.is_empty() {
+            if self.received >= self.len {
+                // The trailer, anything past it is ignored
+                for byte in bytes {
+                    if let Some(slot) = self.trailer.get_mut((self.received - self.len) as usize) {
+                        *slot = *byte;
+                    }
+                    self.received += 1;
+                }
+                return Ok(());
+            }
+
+            let at = (self.received % SECTOR_SIZE) as usize;
+            let take = bytes.len().min(ERASE_SIZE - at).min((self.len - self.received) as usize);
+            self.sector[at..at + take].copy_from_slice(&bytes[..take]);
+            self.crc = crc32_continue(self.crc, &bytes[..take]);
+            self.received += take as u32;
+            bytes = &bytes[take..];
+
+            if at + take == ERASE_SIZE || self.received == self.len {
+                self.flush(ctx).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the sector in progress, the rest of a last partial one erased
+    async fn flush(&mut self, ctx: &Context) -> Result<(), UploadError> {
+        let index = (self.received - 1) / SECTOR_SIZE;
+        if index == 0 && !looks_like_image(&self.sector, VECTOR_TABLE_OFFSET, XIP_BASE as u32, SLOT_SIZE as u32) {
+            return Err(UploadError::NotAnImage);
+        }
+        let filled = (self.received - index * SECTOR_SIZE) as usize;
+        self.sector[filled..].fill(0xff);
+        ctx.storage.lock().await.write_update_sector(index, &self.sector).map_err(UploadError::Flash)
+    }
+
+    /// Checks the image against `expected`, or the trailer without it, and how it reads back from the flash. Once
+    /// both match it is stored as pending, the next boot installs it.
+    pub async fn finish(&mut self, ctx: &Context, expected: Option<u32>) -> Result<(), UploadError> {
+        let expected = expected.unwrap_or(u32::from_le_bytes(self.trailer));
+        if self.crc != expected {
+            return Err(UploadError::CrcMismatch { expected, received: self.crc });
+        }
+        let mut storage = ctx.storage.lock().await;
+        let written = storage.update_crc(self.len).map_err(UploadError::Flash)?;
+        if written != expected {
+            return Err(UploadError::CrcMismatch { expected, received: written });
+        }
+        storage.save_update_state(UpdateState::Pending { len: self.len, crc: expected }).map_err(UploadError::Flash)?;
+        set_state(UpdateState::Pending { len: self.len, crc: expected });
+        log::info!("Received a firmware image of {} bytes, CRC {:08x}", self.len, expected);
+        Ok(())
+    }
+}
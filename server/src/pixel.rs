@@ -0,0 +1,127 @@
+use {
+    core::cell::Cell,
+    embassy_rp::{
+        clocks::clk_sys_freq,
+        peripherals::PIO1,
+        pio::{
+            program::{Assembler, JmpCondition, OutDestination, SetDestination, SideSet},
+            Config, FifoJoin, Pio, ShiftConfig, ShiftDirection,
+        },
+    },
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    embassy_time::{Duration, Instant, Timer},
+    fixed::types::U24F8,
+    server_core::pixel::{Indication, Rgb},
+    crate::{
+        alert,
+        board,
+        config::{PIXEL_BRIGHTNESS, PIXEL_COUNT, PIXEL_PIN},
+        led::{self, LinkStatus},
+        sensor::{self, PRIMARY_SENSOR},
+        Irqs,
+    },
+};
+
+/// Set by `PIXEL_PIN`, without it `/api/pixel` answers 404
+pub const PRESENT: bool = PIXEL_PIN.is_some();
+/// Smooth enough for the breathing
+const FRAME: Duration = Duration::from_millis(40);
+/// A WS2812 bit is 1.25 µs
+const BIT_RATE_KHZ: u32 = 800;
+/// PIO cycles of one bit: the high start, the data and the low stop
+const START_CYCLES: u8 = 2;
+const DATA_CYCLES: u8 = 5;
+const STOP_CYCLES: u8 = 3;
+
+/// The color set through `POST /api/pixel`, shown instead of the status while set
+static MANUAL: Mutex<CriticalSectionRawMutex, Cell<Option<Rgb>>> = Mutex::new(Cell::new(None));
+
+pub fn manual() -> Option<Rgb> {
+    MANUAL.lock(Cell::get)
+}
+
+/// A fixed color until `set_auto`, black turns the pixels off
+pub fn set_manual(color: Rgb) {
+    MANUAL.lock(|manual| manual.set(Some(color)));
+    log::info!("Status pixel set to {}", color);
+}
+
+/// Back to showing the status
+pub fn set_auto() {
+    MANUAL.lock(|manual| manual.set(None));
+    log::info!("Status pixel showing the status");
+}
+
+/// From the state the LED and the alerts go by. A primary sensor that never answered since boot isn't an error yet.
+pub fn indication() -> Indication {
+    let sensor_error = match sensor::status(PRIMARY_SENSOR) {
+        sensor::Status::Ready { stale, .. } => stale,
+        sensor::Status::NotReady => sensor::last_error(PRIMARY_SENSOR).is_some(),
+    };
+    if sensor_error {
+        Indication::SensorError
+    } else if !alert::alerts().is_empty() {
+        Indication::Alert
+    } else if led::link_status() != LinkStatus::Connected {
+        Indication::Joining
+    } else {
+        Indication::Ok
+    }
+}
+
+/// Drives `PIXEL_COUNT` WS2812 pixels from state machine 0 of PIO1, all in one color: the status or the manual
+/// color, at `PIXEL_BRIGHTNESS`. A frame goes out every 40 ms, a few pixels take well under a millisecond.
+#[embassy_executor::task]
+pub async fn pixel_task(pio: PIO1) {
+    let Pio { mut common, mut sm0, .. } = Pio::new(pio, Irqs);
+    // Safety: build.rs gave the pin to nothing else, this is the only call
+    let Some(pin) = (unsafe { board::pixel_pin(&mut common) }) else {
+        return;
+    };
+
+    // Each bit starts high for 2 cycles, stays high for a one or goes low for a zero for 5 and ends low for 3
+    let mut assembler = Assembler::<32>::new_with_side_set(SideSet::new(false, 1, false));
+    let mut wrap_target = assembler.label();
+    let mut wrap_source = assembler.label();
+    let mut zero = assembler.label();
+    assembler.set_with_side_set(SetDestination::PINDIRS, 1, 0);
+    assembler.bind(&mut wrap_target);
+    assembler.out_with_delay_and_side_set(OutDestination::X, 1, STOP_CYCLES - 1, 0);
+    assembler.jmp_with_delay_and_side_set(JmpCondition::XIsZero, &mut zero, START_CYCLES - 1, 1);
+    assembler.jmp_with_delay_and_side_set(JmpCondition::Always, &mut wrap_target, DATA_CYCLES - 1, 1);
+    assembler.bind(&mut zero);
+    assembler.nop_with_delay_and_side_set(DATA_CYCLES - 1, 0);
+    assembler.bind(&mut wrap_source);
+    let program = common.load_program(&assembler.assemble_with_wrap(wrap_source, wrap_target));
+
+    let mut config = Config::default();
+    config.set_out_pins(&[&pin]);
+    config.set_set_pins(&[&pin]);
+    config.use_program(&program, &[&pin]);
+    // In kHz, so the division fits the fixed point type
+    let cycles_per_bit = u32::from(START_CYCLES + DATA_CYCLES + STOP_CYCLES);
+    config.clock_divider = U24F8::from_num(clk_sys_freq() / 1000) / U24F8::from_num(BIT_RATE_KHZ * cycles_per_bit);
+    // 24 bits a pixel, pulled from the joined FIFO as they run out
+    config.fifo_join = FifoJoin::TxOnly;
+    config.shift_out = ShiftConfig { auto_fill: true, threshold: 24, direction: ShiftDirection::Left };
+    sm0.set_config(&config);
+    sm0.set_enable(true);
+    log::info!("{} status pixels on GPIO{}", PIXEL_COUNT, PIXEL_PIN.unwrap_or(0));
+
+    let mut shown = indication();
+    let mut since = Instant::now();
+    loop {
+        let current = indication();
+        if current != shown {
+            shown = current;
+            since = Instant::now();
+        }
+        let color = manual().unwrap_or_else(|| current.color(since.elapsed().as_millis() as u32));
+        let word = color.scaled(PIXEL_BRIGHTNESS).grb_word();
+        for _ in 0..PIXEL_COUNT {
+            sm0.tx().wait_push(word).await;
+        }
+        // A low line for longer than 50 µs latches the colors, the next frame is much further off
+        Timer::after(FRAME).await;
+    }
+}
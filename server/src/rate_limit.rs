@@ -0,0 +1,76 @@
+use {
+    embassy_net::IpAddress,
+    embassy_time::Instant,
+    heapless::Vec,
+};
+
+/// Number of distinct clients tracked, the least recently seen one is evicted when full
+const MAX_CLIENTS: usize = 8;
+/// Burst size a client may send before being limited
+const BUCKET_CAPACITY: u64 = 5;
+/// Sustained requests per second allowed per client
+const REFILL_PER_SECOND: u64 = 5;
+
+/// Tokens are kept in thousandths so refill works with millisecond resolution
+const MILLI: u64 = 1000;
+
+struct Bucket {
+    client: IpAddress,
+    milli_tokens: u64,
+    last_seen: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed_ms = now.saturating_duration_since(self.last_seen).as_millis();
+        self.milli_tokens = (self.milli_tokens + elapsed_ms * REFILL_PER_SECOND).min(BUCKET_CAPACITY * MILLI);
+        self.last_seen = now;
+    }
+}
+
+/// Token bucket rate limiter keyed on the remote address
+pub struct RateLimiter {
+    buckets: Vec<Bucket, MAX_CLIENTS>,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        Self { buckets: Vec::new() }
+    }
+
+    /// Take a token for `client`, or return the number of seconds until one is available
+    pub fn check(&mut self, client: IpAddress, now: Instant) -> Result<(), u64> {
+        let bucket = match self.buckets.iter().position(|bucket| bucket.client == client) {
+            Some(index) => &mut self.buckets[index],
+            None => self.insert(client, now),
+        };
+
+        bucket.refill(now);
+
+        if bucket.milli_tokens >= MILLI {
+            bucket.milli_tokens -= MILLI;
+            Ok(())
+        } else {
+            let missing = MILLI - bucket.milli_tokens;
+            Err(missing.div_ceil(REFILL_PER_SECOND * MILLI).max(1))
+        }
+    }
+
+    fn insert(&mut self, client: IpAddress, now: Instant) -> &mut Bucket {
+        if self.buckets.is_full() {
+            if let Some(oldest) = self.buckets
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, bucket)| bucket.last_seen)
+                .map(|(index, _)| index)
+            {
+                self.buckets.swap_remove(oldest);
+            }
+        }
+
+        let bucket = Bucket { client, milli_tokens: BUCKET_CAPACITY * MILLI, last_seen: now };
+        // Cannot fail, a slot was freed above
+        let _ = self.buckets.push(bucket);
+        self.buckets.last_mut().unwrap()
+    }
+}
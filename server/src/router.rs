@@ -0,0 +1,1924 @@
+use {
+    cyw43::Control,
+    embassy_sync::{blocking_mutex::{self, raw::CriticalSectionRawMutex}, mutex::Mutex},
+    embassy_time::{with_timeout, Duration, Instant, Timer},
+    embassy_futures::select::{select, Either},
+    embassy_net::{tcp::{self, TcpSocket}, IpEndpoint, Stack, StackResources},
+    log::Level,
+    embedded_io_async::Write,
+    heapless::{String, Vec},
+    core::{
+        cell::Cell,
+        fmt::Write as CoreWrite,
+        str::from_utf8,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    cortex_m::peripheral::SCB,
+    static_cell::StaticCell,
+    server_core::ota::parse_crc,
+    crate::{alert::{self, Thresholds}, auth, board, buzzer, daily, diag, ds3231, derived::{self, celsius_to_fahrenheit}, device_name::DeviceName, fan, gpio::{self, GPIO_COUNT}, control::{FanRequest, LedCommand, PixelRequest, ServoRequest, Switch}, led, log_filter::{self, LogFilter}, ota::{self, Upload, UploadError, TRAILER_SIZE}, history::{self, HISTORY}, rate_limit::RateLimiter, form, http::{self, JsonStr, Method, Request, Response, Status, Version}, pixel, reading::Calibration, route::{Route, Site}, sensor::{self, READINGS, SENSOR_COUNT, SENSOR_MODEL}, servo, settings::{self, FORM_FIELDS}, rtc, sd_log::{self, FILE_NAME}, sntp::{self, Iso8601, IsoDate, TimeSource}, watchdog::{self, Subsystem}, storage::{Credentials, Storage, PASSWORD_LEN, SSID_LEN}, syslog, wifi, mac::MacAddress, config::{CORS_ORIGIN, DHT_LABELS, GPIO_OUTPUTS, PIXEL_BRIGHTNESS, PIXEL_COUNT, SD_FLUSH_EVERY}, template::{self, Segment, INDEX_SEGMENTS, SETTINGS_SEGMENTS, TEMP_TAG, TEMP_UNIT_TAG, UNIT_MODE_TAG, DECIMALS_TAG, HUMID_TAG, LABEL_TAG, GPIO_TAG, GPIO_NAME_TAG, DEW_POINT_TAG, HEAT_INDEX_TAG, ABS_HUMID_TAG, CALIBRATION_TAG, CHIP_TEMP_TAG, INTERVAL_TAG, TEMP_TREND_TAG, HUMID_TREND_TAG, ALERT_TAG, ALARM_TAG, ALARM_SOUNDING_TAG, TMIN_TAG, TMAX_TAG, HMIN_TAG, HMAX_TAG, STALE_TAG, AGE_TAG, TIME_TAG, RSSI_TAG, SIGNAL_TAG, HOSTNAME_TAG, NAME_TAG, MAC_TAG, IP_TAG, SSID_TAG, UPTIME_TAG, LED_TAG, FAN_TAG, SERVO_TAG, VERSION_TAG, ERROR_TAG, SAVED_TAG}, uptime::Uptime, build_info},
+};
+
+const CHUNK_SIZE: usize = 1024;
+/// Largest request head that is interpreted, anything bigger is answered with 431
+const MAX_HEAD_SIZE: usize = 4096;
+/// Deadline for the rest of a request once its first bytes arrived
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Largest `/api/sensor` body, a full primary object with every alert active takes about 700 bytes
+const SENSOR_JSON_SIZE: usize = 896 * SENSOR_COUNT + 2;
+/// Prometheus text exposition of every sensor
+const METRICS_SIZE: usize = 1792 + 160 * SENSOR_COUNT;
+const HEALTH_JSON_SIZE: usize = 256 + 192 * SENSOR_COUNT;
+const STATUS_JSON_SIZE: usize = 1360;
+const DIAG_JSON_SIZE: usize = 768;
+const GPIO_JSON_SIZE: usize = 16 + 64 * GPIO_COUNT;
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+/// Plain placeholders filled in by the index handler, the indexed per sensor ones come on top
+const INDEX_TAG_COUNT: usize = 35;
+/// Requests taking longer than this are logged at info level like errors
+const SLOW_REQUEST: Duration = Duration::from_millis(500);
+const STATIC_CACHE_CONTROL: &str = "max-age=86400";
+const NO_STORE: &str = "no-store";
+/// The first reading is taken right after boot, matching `sensor::DEFAULT_SAMPLE_INTERVAL`
+const SENSOR_RETRY_AFTER: &str = "5";
+const BASIC_AUTH_CHALLENGE: &str = "Basic realm=\"pico\"";
+const CORS_ALLOW_METHODS: &str = "GET, POST";
+const CORS_ALLOW_HEADERS: &str = "Content-Type, Authorization";
+/// Time for the browser to receive the confirmation before the device reboots
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
+/// Same for an API client, the acknowledgment is small
+const API_REBOOT_DELAY: Duration = Duration::from_millis(250);
+/// Longest `?delay=` of `/api/reboot` and `/api/factory-reset`, in seconds
+const MAX_REBOOT_DELAY_S: u64 = 60;
+
+/// The index template, checked at compile time so the firmware can't start with a broken one
+const HTML: &str = match from_utf8(include_bytes!("html/index.html")) {
+    Ok(html) => html,
+    Err(_) => panic!("index.html is not valid UTF-8"),
+};
+/// The settings form, pre-filled with the values in effect or the ones just submitted
+const SETTINGS_HTML: &str = match from_utf8(include_bytes!("html/settings.html")) {
+    Ok(html) => html,
+    Err(_) => panic!("settings.html is not valid UTF-8"),
+};
+/// Placeholders of the settings page besides the fields
+const SETTINGS_TAG_COUNT: usize = 3;
+pub const NOT_FOUND_HTML_BYTES: &[u8] = include_bytes!("html/404.html");
+pub const SETUP_HTML_BYTES: &[u8] = include_bytes!("html/setup.html");
+pub const SETUP_SAVED_HTML_BYTES: &[u8] = include_bytes!("html/setup_saved.html");
+
+/// Embedded files served as-is: (path, bytes, gzipped bytes from build.rs, content type)
+pub const STATIC_ASSETS: [(&str, &[u8], &[u8], &str); 3] = [
+    ("/favicon.ico", include_bytes!("html/favicon.ico"), include_bytes!(concat!(env!("OUT_DIR"), "/favicon.ico.gz")), "image/x-icon"),
+    ("/style.css", include_bytes!("html/style.css"), include_bytes!(concat!(env!("OUT_DIR"), "/style.css.gz")), "text/css"),
+    ("/app.js", include_bytes!("html/app.js"), include_bytes!(concat!(env!("OUT_DIR"), "/app.js.gz")), "application/javascript"),
+];
+
+/// ETag of every static asset, same order as `STATIC_ASSETS`
+pub static STATIC_ASSET_ETAGS: [u32; STATIC_ASSETS.len()] = asset_etags();
+
+/// FNV-1a hash, assets never change between flashes so this is evaluated at compile time
+const fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+
+    hash
+}
+
+const fn asset_etags() -> [u32; STATIC_ASSETS.len()] {
+    let mut etags = [0; STATIC_ASSETS.len()];
+    let mut i = 0;
+
+    while i < STATIC_ASSETS.len() {
+        etags[i] = fnv1a(STATIC_ASSETS[i].1);
+        i += 1;
+    }
+
+    etags
+}
+
+/// Temperature display unit, configured with `TEMP_UNIT` or `POST /api/config` and overridable per request with
+/// `?unit=`
+#[derive(Clone, Copy, PartialEq)]
+pub enum TempUnit {
+    Celsius,
+    Fahrenheit,
+    Both,
+}
+
+static CONFIGURED_UNIT: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<TempUnit>> =
+    blocking_mutex::Mutex::new(Cell::new(TempUnit::Celsius));
+
+impl TempUnit {
+    pub const ALL: [TempUnit; 3] = [TempUnit::Celsius, TempUnit::Fahrenheit, TempUnit::Both];
+
+    pub fn parse(unit: &str) -> Option<Self> {
+        if unit.eq_ignore_ascii_case("c") {
+            Some(TempUnit::Celsius)
+        } else if unit.eq_ignore_ascii_case("f") {
+            Some(TempUnit::Fahrenheit)
+        } else if unit.eq_ignore_ascii_case("both") {
+            Some(TempUnit::Both)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_request(request: &Request) -> Self {
+        request.query_param("unit").and_then(Self::parse).unwrap_or_else(Self::configured)
+    }
+
+    /// The unit of pages without `?unit=` and of the display
+    pub fn configured() -> Self {
+        CONFIGURED_UNIT.lock(Cell::get)
+    }
+
+    pub fn set_configured(unit: Self) {
+        CONFIGURED_UNIT.lock(|current| current.set(unit));
+    }
+
+    /// Write a Celsius value in this unit with the sensor model's decimals, `Both` carries its own unit suffixes
+    pub fn format<W: CoreWrite>(self, celsius: f32, out: &mut W) -> core::fmt::Result {
+        let decimals = SENSOR_MODEL.decimals();
+
+        match self {
+            TempUnit::Celsius => write!(out, "{:.*}", decimals, celsius),
+            TempUnit::Fahrenheit => write!(out, "{:.*}", decimals, celsius_to_fahrenheit(celsius)),
+            TempUnit::Both => write!(out, "{:.*} °C / {:.*} °F", decimals, celsius, decimals, celsius_to_fahrenheit(celsius)),
+        }
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TempUnit::Celsius => "°C",
+            TempUnit::Fahrenheit => "°F",
+            TempUnit::Both => "",
+        }
+    }
+
+    /// Value of the `data-unit` attribute the page script formats live updates with
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TempUnit::Celsius => "c",
+            TempUnit::Fahrenheit => "f",
+            TempUnit::Both => "both",
+        }
+    }
+}
+
+/// The optional endpoints, outputs and assets of this build
+const SITE: Site = Site {
+    fan: fan::PRESENT,
+    servo: servo::PRESENT,
+    pixel: pixel::PRESENT,
+    buzzer: buzzer::PRESENT,
+    output: gpio::find,
+    asset: find_asset,
+};
+
+fn find_asset(path: &str) -> Option<usize> {
+    STATIC_ASSETS.iter().position(|(asset_path, _, _, _)| *asset_path == path)
+}
+
+/// Credentials from the form of the setup page
+fn parse_credentials(body: &[u8]) -> Option<Credentials> {
+    let body = from_utf8(body).ok()?.trim();
+    let ssid = http::percent_decode::<SSID_LEN>(http::form_field(body, "ssid")?)?;
+    let password = http::percent_decode::<PASSWORD_LEN>(http::form_field(body, "password").unwrap_or(""))?;
+
+    Credentials::new(&ssid, &password)
+}
+
+/// Store the submitted credentials and reboot into station mode with them, the response is the last thing sent
+async fn save_credentials(ctx: &Context, socket: &mut TcpSocket<'_>, request: &Request<'_>, credentials: &Credentials) -> Result<Sent, Error> {
+    if let Err(e) = ctx.storage.lock().await.save_credentials(credentials) {
+        log::error!("Unable to store the Wi-Fi credentials: {:?}", e);
+        let response = Response::text(Status::InternalServerError);
+        return send(socket, Framing::of(request), response, b"Unable to store the credentials").await;
+    }
+
+    log::info!("Stored the credentials for {}, rebooting", credentials.ssid);
+    let response = Response::new(Status::Ok).header("Content-Type", "text/html");
+    send(socket, Framing::CLOSE, response, SETUP_SAVED_HTML_BYTES).await?;
+    socket.flush().await.map_err(Error::Write)?;
+    Timer::after(REBOOT_DELAY).await;
+    SCB::sys_reset()
+}
+
+/// Stream the body of `POST /update` to the update slot and reboot to install it once its CRC checks out, from
+/// `?crc=` or the last 4 bytes of the body. The body is the raw binary, `objcopy -O binary` of the ELF. The
+/// connection is closed after the answer, a refused upload leaves the rest of its body unread.
+async fn receive_update(ctx: &Context, socket: &mut TcpSocket<'_>, request: &Request<'_>, task: Subsystem) -> Result<Sent, Error> {
+    let expected = match request.query_param("crc") {
+        Some(text) => match parse_crc(text) {
+            Some(crc) => Some(crc),
+            None => return send(socket, Framing::CLOSE, Response::text(Status::BadRequest), b"crc must be 8 hex digits").await,
+        },
+        None => None,
+    };
+    let len = match expected {
+        Some(_) => request.content_length,
+        None => request.content_length.saturating_sub(TRAILER_SIZE),
+    };
+
+    let result = async {
+        let mut upload = Upload::begin(ctx, len).await?;
+        upload.write(ctx, request.body).await?;
+        let mut received = request.body.len();
+        let mut chunk = [0; CHUNK_SIZE];
+        while received < request.content_length {
+            watchdog::check_in(task);
+            let wanted = chunk.len().min(request.content_length - received);
+            match with_timeout(REQUEST_TIMEOUT, socket.read(&mut chunk[..wanted])).await {
+                Ok(Ok(n)) if n > 0 => {
+                    upload.write(ctx, &chunk[..n]).await?;
+                    received += n;
+                },
+                _ => return Err(UploadError::Incomplete),
+            }
+        }
+        upload.finish(ctx, expected).await
+    }.await;
+
+    let error = match result {
+        Ok(()) => {
+            send(socket, Framing::CLOSE, Response::text(Status::Ok), b"Verified, rebooting to install").await?;
+            socket.flush().await.map_err(Error::Write)?;
+            Timer::after(REBOOT_DELAY).await;
+            SCB::sys_reset()
+        },
+        Err(error) => error,
+    };
+    let status = match error {
+        UploadError::InProgress | UploadError::OnTrial => Status::ServiceUnavailable,
+        UploadError::BadLength if len > 0 => Status::PayloadTooLarge,
+        UploadError::Flash(_) => Status::InternalServerError,
+        UploadError::BadLength | UploadError::NotAnImage | UploadError::CrcMismatch { .. } | UploadError::Incomplete => Status::BadRequest,
+    };
+    match error {
+        UploadError::CrcMismatch { expected, received } => {
+            log::warn!("Firmware update refused: CRC {:08x} expected, {:08x} received", expected, received);
+        },
+        UploadError::Flash(e) => log::error!("Firmware update failed: {:?}", e),
+        _ => log::warn!("Firmware update refused: {}", error.describe()),
+    }
+    send(socket, Framing::CLOSE, Response::text(status), error.describe().as_bytes()).await
+}
+
+/// `POST /api/reboot` and `POST /api/factory-reset`: acknowledge, then reset after `?delay=` seconds or right after
+/// the response. The factory reset erases the stored settings and Wi-Fi credentials first, the device comes back
+/// with the build time ones.
+async fn reboot(ctx: &Context, socket: &mut TcpSocket<'_>, request: &Request<'_>, factory: bool) -> Result<Sent, Error> {
+    let delay = match request.query_param("delay").map(|delay| delay.parse::<u64>().ok().filter(|secs| *secs <= MAX_REBOOT_DELAY_S)) {
+        None => API_REBOOT_DELAY,
+        Some(Some(secs)) => Duration::from_secs(secs).max(API_REBOOT_DELAY),
+        Some(None) => {
+            let response = Response::json().with_status(Status::BadRequest);
+            return send(socket, Framing::of(request), response, b"{\"ok\": false, \"error\": \"delay must be 0 to 60 s\"}").await;
+        },
+    };
+
+    let remote = socket.remote_endpoint().map(|endpoint| endpoint.addr);
+    if factory {
+        let erased = match settings::reset(ctx).await {
+            Ok(()) => ctx.storage.lock().await.clear_credentials(),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = erased {
+            log::error!("Unable to erase the stored configuration: {:?}", e);
+            let response = Response::json().with_status(Status::InternalServerError);
+            return send(socket, Framing::of(request), response, b"{\"ok\": false, \"error\": \"unable to erase the stored configuration\"}").await;
+        }
+        log::warn!("Factory reset requested by {:?}, rebooting in {} ms", remote, delay.as_millis());
+    } else {
+        log::warn!("Reboot requested by {:?}, rebooting in {} ms", remote, delay.as_millis());
+    }
+
+    let mut body = String::<64>::new();
+    write!(&mut body, "{{\"ok\": true, \"factory_reset\": {}, \"reboot_in_ms\": {}}}", factory, delay.as_millis()).map_err(|_| Error::Overflow)?;
+    let sent = match send(socket, Framing::CLOSE, Response::json(), body.as_bytes()).await {
+        Ok(sent) => socket.flush().await.map(|()| sent).map_err(Error::Write),
+        Err(e) => Err(e),
+    };
+    // Also when the client went away meanwhile
+    watchdog::reboot_after(delay);
+    sent
+}
+
+/// Runtime settings changed by `POST /api/config`, fields that are missing stay as they are
+struct ConfigUpdate {
+    sample_interval: Option<Duration>,
+    temp_offset: Option<f32>,
+    humid_offset: Option<f32>,
+    power_mode: Option<wifi::PowerMode>,
+    temp_unit: Option<TempUnit>,
+    /// In the order of `Thresholds::limits`, `Some(None)` disables a limit
+    limits: [Option<Option<f32>>; 4],
+    device_name: Option<DeviceName>,
+}
+
+/// Keys of the thresholds in `POST /api/config`, in the order of `Thresholds::limits`
+const LIMIT_FIELDS: [&str; 4] = ["temp_high", "temp_low", "humid_high", "humid_low"];
+
+fn parse_config_update(body: &[u8]) -> Result<ConfigUpdate, &'static str> {
+    let body = from_utf8(body).map_err(|_| "body is not UTF-8")?.trim();
+    let offset = |name, error| match http::body_field(body, name) {
+        Some(value) => value.parse::<f32>().ok().filter(|offset| offset.is_finite()).map(Some).ok_or(error),
+        None => Ok(None),
+    };
+
+    // `null` or an empty form value disables a threshold
+    let limit = |name| match http::body_field(body, name).map(|value| value.trim_matches('"')) {
+        Some("" | "null") => Ok(Some(None)),
+        Some(value) => value.parse::<f32>().ok().filter(|limit| limit.is_finite()).map(|limit| Some(Some(limit)))
+            .ok_or("invalid threshold, expected a number or null"),
+        None => Ok(None),
+    };
+    let mut limits = [None; 4];
+    for (limit_value, name) in limits.iter_mut().zip(LIMIT_FIELDS) {
+        *limit_value = limit(name)?;
+    }
+
+    let update = ConfigUpdate {
+        sample_interval: match http::body_field(body, "sample_interval_s") {
+            Some(value) => Some(Duration::from_secs(value.parse::<u64>().map_err(|_| "invalid sample_interval_s")?)),
+            None => None,
+        },
+        temp_offset: offset("temp_offset", "invalid temp_offset")?,
+        humid_offset: offset("humid_offset", "invalid humid_offset")?,
+        power_mode: match http::body_field(body, "wifi_pm") {
+            Some(value) => Some(wifi::PowerMode::parse(value.trim_matches('"')).ok_or("invalid wifi_pm")?),
+            None => None,
+        },
+        temp_unit: match http::body_field(body, "temp_unit") {
+            Some(value) => Some(TempUnit::parse(value.trim_matches('"')).ok_or("invalid temp_unit, expected c, f or both")?),
+            None => None,
+        },
+        limits,
+        device_name: match http::body_field(body, "device_name") {
+            Some(value) => Some(DeviceName::parse(value.trim_matches('"'))
+                .ok_or("invalid device_name, expected letters, digits and inner hyphens, at most 32 characters")?),
+            None => None,
+        },
+    };
+
+    let empty = update.sample_interval.is_none() && update.temp_offset.is_none() && update.humid_offset.is_none()
+        && update.power_mode.is_none() && update.temp_unit.is_none() && update.limits.iter().all(Option::is_none)
+        && update.device_name.is_none();
+    if empty {
+        return Err("expected sample_interval_s, temp_offset, humid_offset, wifi_pm, temp_unit, a threshold or device_name");
+    }
+
+    Ok(update)
+}
+
+/// Why a response could not be completed. None of them takes the device down, the connection loop logs the error
+/// and goes on accepting.
+#[derive(Debug, defmt::Format)]
+pub enum Error {
+    /// The socket failed or the client went away, nothing more can be sent
+    Write(tcp::Error),
+    /// The response didn't fit its buffer before anything was sent, a 500 can still go out
+    Overflow,
+    /// A chunk of a streamed body didn't fit after the head went out, the connection can only be dropped
+    Truncated,
+    /// The page template didn't compile at boot, nothing was sent so a 500 can still go out
+    Template,
+}
+
+/// What was sent for a request, reported in the access log
+#[derive(Clone, Copy)]
+pub struct Sent {
+    pub status: u16,
+    pub bytes: usize,
+    /// The response said `Connection: close`, a body without Content-Length only ends when the connection does
+    pub close: bool,
+}
+
+pub enum ReadError {
+    Closed,
+    Idle,
+    Timeout,
+    HeadTooLarge,
+    Socket(tcp::Error),
+}
+
+/// Sizes of a request received into the read buffer
+pub struct Received {
+    pub head_len: usize,
+    pub len: usize,
+}
+
+/// Accumulate the request head into `buf` until the header terminator is seen, at most `MAX_HEAD_SIZE` bytes,
+/// followed by as much of the announced body as fits.
+/// The request deadline only starts after the first bytes arrive, waiting for them is bounded by `idle_timeout`.
+pub async fn read_request(socket: &mut TcpSocket<'_>, buf: &mut [u8], idle_timeout: Duration) -> Result<Received, ReadError> {
+    let head_limit = buf.len().min(MAX_HEAD_SIZE);
+
+    let mut len = match with_timeout(idle_timeout, socket.read(&mut buf[..head_limit])).await {
+        Err(_) => return Err(ReadError::Idle),
+        Ok(Ok(0)) => return Err(ReadError::Closed),
+        Ok(Ok(n)) => n,
+        Ok(Err(e)) => return Err(ReadError::Socket(e)),
+    };
+
+    let remaining = async {
+        let mut start = 0;
+
+        let head_len = loop {
+            if let Some(end) = http::find_header_end(&buf[..len], start) {
+                break end;
+            }
+
+            if len == head_limit {
+                return Err(ReadError::HeadTooLarge);
+            }
+
+            start = len;
+            match socket.read(&mut buf[len..head_limit]).await {
+                Ok(0) => return Err(ReadError::Closed),
+                Ok(n) => len += n,
+                Err(e) => return Err(ReadError::Socket(e)),
+            }
+        };
+
+        let body_end = (head_len + http::content_length(&buf[..head_len])).min(buf.len());
+
+        while len < body_end {
+            match socket.read(&mut buf[len..body_end]).await {
+                Ok(0) => return Err(ReadError::Closed),
+                Ok(n) => len += n,
+                Err(e) => return Err(ReadError::Socket(e)),
+            }
+        }
+
+        Ok(Received { head_len, len: len.min(body_end) })
+    };
+
+    match with_timeout(REQUEST_TIMEOUT, remaining).await {
+        Ok(result) => result,
+        Err(_) => Err(ReadError::Timeout),
+    }
+}
+
+pub async fn request_timeout(socket: &mut TcpSocket<'_>) -> Result<Sent, Error> {
+    send(socket, Framing::CLOSE, Response::text(Status::RequestTimeout), b"Request Timeout").await
+}
+
+/// Answer a request whose head could not be parsed, the connection is closed afterwards
+pub async fn bad_request(socket: &mut TcpSocket<'_>) -> Result<Sent, Error> {
+    send(socket, Framing::CLOSE, Response::text(Status::BadRequest), b"Bad Request").await
+}
+
+pub async fn header_fields_too_large(socket: &mut TcpSocket<'_>) -> Result<Sent, Error> {
+    send(socket, Framing::CLOSE, Response::text(Status::HeaderFieldsTooLarge), b"Request Header Fields Too Large").await
+}
+
+/// Answer a request whose handler failed before sending anything, the connection is closed afterwards
+pub async fn internal_error(socket: &mut TcpSocket<'_>) -> Result<Sent, Error> {
+    send(socket, Framing::CLOSE, Response::text(Status::InternalServerError), b"Internal Server Error").await
+}
+
+pub async fn too_many_requests(socket: &mut TcpSocket<'_>, request: &Request<'_>, retry_after_secs: u64) -> Result<Sent, Error> {
+    let mut retry_after = String::<20>::new();
+    write!(&mut retry_after, "{}", retry_after_secs).map_err(|_| Error::Overflow)?;
+    let response = Response::text(Status::TooManyRequests).header("Retry-After", retry_after.as_str());
+    send(socket, Framing::of(request), response, b"Too Many Requests").await
+}
+
+/// TCP level timeouts of a connection, chosen per route
+#[derive(Clone, Copy)]
+pub struct SocketPolicy {
+    /// Sent data not acknowledged for this long, or unanswered keep-alive probes, abort the connection
+    ack_timeout: Duration,
+    /// Probe interval while the connection carries nothing, `None` sends no probes
+    keep_alive: Option<Duration>,
+}
+
+impl SocketPolicy {
+    /// Request and response exchanges, also in effect while a request is read
+    pub const REQUEST: Self = Self { ack_timeout: Duration::from_secs(10), keep_alive: None };
+    /// Streams stay open for hours, the probes find a client that vanished without waiting for the next write
+    const STREAM: Self = Self { ack_timeout: Duration::from_secs(30), keep_alive: Some(Duration::from_secs(15)) };
+
+    /// Socket timeouts while the route's handler has the connection
+    fn of(route: &Route) -> Self {
+        match route {
+            Route::Events => SocketPolicy::STREAM,
+            _ => SocketPolicy::REQUEST,
+        }
+    }
+
+    pub fn apply(self, socket: &mut TcpSocket<'_>) {
+        socket.set_timeout(Some(self.ack_timeout));
+        socket.set_keep_alive(self.keep_alive);
+    }
+}
+
+/// Time to answer the first request of a connection, from the accepted connection to the response handed to
+/// TCP. It takes the handshake's round trip and the request's arrival, which is where radio power saving shows.
+#[derive(Clone, Copy)]
+struct Latency {
+    samples: u32,
+    total_micros: u64,
+}
+
+static LATENCY: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Latency>> =
+    blocking_mutex::Mutex::new(Cell::new(Latency { samples: 0, total_micros: 0 }));
+
+pub fn record_latency(elapsed: Duration) {
+    LATENCY.lock(|latency| {
+        let current = latency.get();
+        latency.set(Latency {
+            samples: current.samples.saturating_add(1),
+            total_micros: current.total_micros.saturating_add(elapsed.as_micros()),
+        });
+    });
+}
+
+/// Start measuring from scratch, done whenever the power mode changes
+pub fn reset_latency() {
+    LATENCY.lock(|latency| latency.set(Latency { samples: 0, total_micros: 0 }));
+}
+
+/// One access log line per answered request, normal requests only show up at debug level. The `access` target lets
+/// `LOG_MODULES` quiet them separately from the rest.
+pub fn log_access(remote: Option<IpEndpoint>, request: &Request<'_>, result: &Result<Sent, Error>, elapsed: Duration) {
+    let remote = remote.map(|endpoint| endpoint.addr);
+
+    match result {
+        Ok(sent) => {
+            let level = if sent.status >= 400 || elapsed >= SLOW_REQUEST { Level::Info } else { Level::Debug };
+            log::log!(target: "access", level, "{:?} {} {} {} {}B {}ms",
+                remote, request.method.as_str(), request.path, sent.status, sent.bytes, elapsed.as_millis());
+        },
+        Err(_) => {
+            log::info!(target: "access", "{:?} {} {} aborted {}ms", remote, request.method.as_str(), request.path, elapsed.as_millis());
+        },
+    }
+}
+
+/// How a connection ends
+pub enum Shutdown {
+    /// The last response must reach the client, flush and close before dropping the socket
+    Graceful,
+    /// The connection is broken, reset it right away
+    Abort,
+}
+
+/// Single exit path of a connection.
+/// A graceful shutdown flushes pending data, sends FIN and drains the peer until it closes so the last
+/// response isn't cut off by a RST, a peer that doesn't close within `DRAIN_TIMEOUT` gets aborted.
+pub async fn finish_connection(socket: &mut TcpSocket<'_>, shutdown: Shutdown) {
+    if let Shutdown::Abort = shutdown {
+        abort(socket).await;
+        return;
+    }
+
+    if let Err(e) = socket.flush().await {
+        log::warn!("Flush Error: {:?}", e);
+        abort(socket).await;
+        return;
+    }
+    socket.close();
+
+    let mut drain = [0; 64];
+    let drained = with_timeout(DRAIN_TIMEOUT, async {
+        while let Ok(n) = socket.read(&mut drain).await {
+            if n == 0 {
+                break;
+            }
+        }
+    }).await;
+
+    if drained.is_err() {
+        abort(socket).await;
+    }
+}
+
+/// Reset the connection, the RST is only sent once the socket is polled so wait for the flush
+async fn abort(socket: &mut TcpSocket<'_>) {
+    socket.abort();
+    let _ = with_timeout(DRAIN_TIMEOUT, socket.flush()).await;
+}
+
+/// Split a page template once, requests then only walk the segments. A broken template is logged and leaves
+/// the list empty, the page then answers with a 500.
+fn compile_page<const N: usize>(name: &str, html: &'static str, cell: &'static StaticCell<Vec<Segment<'static>, N>>) -> &'static [Segment<'static>] {
+    let segments = template::compile(html).unwrap_or_else(|e| {
+        log::error!("{} template doesn't compile: {:?}", name, e);
+        Vec::new()
+    });
+    let placeholders = segments.iter().filter(|segment| !segment.is_literal()).count();
+    log::info!("{} template: {} segments, {} placeholders", name, segments.len(), placeholders);
+
+    cell.init(segments)
+}
+
+pub fn compile_index() -> &'static [Segment<'static>] {
+    static INDEX: StaticCell<Vec<Segment<'static>, INDEX_SEGMENTS>> = StaticCell::new();
+    compile_page("Index", HTML, &INDEX)
+}
+
+pub fn compile_settings() -> &'static [Segment<'static>] {
+    static SETTINGS: StaticCell<Vec<Segment<'static>, SETTINGS_SEGMENTS>> = StaticCell::new();
+    compile_page("Settings", SETTINGS_HTML, &SETTINGS)
+}
+
+/// State shared by all connection handlers
+pub struct Context {
+    pub control: Mutex<CriticalSectionRawMutex, Control<'static>>,
+    pub rate_limiter: Mutex<CriticalSectionRawMutex, RateLimiter>,
+    /// The index template split into segments at boot, empty when it didn't compile
+    pub index: &'static [Segment<'static>],
+    /// Same for the settings page
+    pub settings: &'static [Segment<'static>],
+    /// Device name from `Config`, shown in the page title
+    pub hostname: &'static str,
+    pub mac: [u8; 6],
+    pub stack: Stack<'static>,
+    /// Whether the address comes from DHCP instead of the `STATIC_*` settings
+    pub dhcp: bool,
+    /// Set once joining failed and the setup access point is up, only the Wi-Fi form is served then
+    pub setup_mode: AtomicBool,
+    pub storage: Mutex<CriticalSectionRawMutex, Storage>,
+}
+
+pub async fn dispatch(request: &Request<'_>, ctx: &Context, socket: &mut TcpSocket<'_>, task: Subsystem) -> Result<Sent, Error> {
+    let route = match ctx.setup_mode.load(Ordering::Relaxed) {
+        true => Route::resolve_setup(request.method, request.path, &SITE),
+        false => Route::resolve(request.method, request.path, &SITE),
+    };
+    SocketPolicy::of(&route).apply(socket);
+
+    if route.requires_auth() && !auth::check_basic_auth(request.authorization) {
+        let response = Response::text(Status::Unauthorized).header("WWW-Authenticate", BASIC_AUTH_CHALLENGE);
+        return send(socket, Framing::of(request), response, b"Unauthorized").await;
+    }
+
+    if route.requires_token() && !auth::check_api_token(request.authorization, request.query_param("token")) {
+        let response = Response::json().with_status(Status::Forbidden);
+        return send(socket, Framing::of(request), response, b"{\"ok\": false, \"error\": \"invalid or missing API token\"}").await;
+    }
+
+    match route {
+        Route::Index => serve_index(ctx, socket, request).await,
+        Route::LedToggle => {
+            if request.method == Method::Get {
+                led::command(LedCommand::Toggle).await;
+            }
+            // Post/Redirect/Get: refreshing the page reloads the index instead of toggling again
+            send(socket, Framing::of(request), Response::redirect("/"), b"").await
+        },
+        Route::LedSet => match LedCommand::from_body(request.body) {
+            Some(command) => {
+                led::command(command).await;
+                // The LED task may not have taken the command yet, the answer is the state it leads to
+                let on = match command {
+                    LedCommand::On => true,
+                    LedCommand::Off => false,
+                    LedCommand::Toggle => !led::manual_state(),
+                    LedCommand::Pattern(_) => led::manual_state(),
+                };
+                serve_led_json(socket, request, on).await
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected state=on|off|toggle|blink or {\"on\": true|false}").await
+            },
+        },
+        Route::ApiLed => serve_led_json(socket, request, led::manual_state()).await,
+        Route::Events => serve_events(socket, request, task).await,
+        Route::HistoryCsv => serve_history_csv(socket, request).await,
+        Route::Metrics => serve_metrics(socket, request).await,
+        Route::Settings => serve_settings(ctx, socket, request, None, None).await,
+        Route::SettingsSave => match from_utf8(request.body).map_err(|_| form::Error::BadEncoding).and_then(|body| form::parse(body.trim())) {
+            Ok(fields) => match settings::apply_form(&fields) {
+                // Post/Redirect/Get: refreshing the page doesn't submit the form again
+                Ok(()) => send(socket, Framing::of(request), Response::redirect("/settings?saved"), b"").await,
+                Err(field) => {
+                    let mut banner = String::<128>::new();
+                    write!(&mut banner, "{}: expected {}", field.label, field.kind).map_err(|_| Error::Overflow)?;
+                    serve_settings(ctx, socket, request, Some(&fields), Some(&banner)).await
+                },
+            },
+            Err(e) => {
+                let mut banner = String::<64>::new();
+                write!(&mut banner, "Unable to read the form: {}", e.as_str()).map_err(|_| Error::Overflow)?;
+                serve_settings(ctx, socket, request, None, Some(&banner)).await
+            },
+        },
+        Route::Update => receive_update(ctx, socket, request, task).await,
+        Route::SetupForm => {
+            let response = Response::new(Status::Ok).header("Content-Type", "text/html").header("Cache-Control", NO_STORE);
+            send(socket, Framing::of(request), response, SETUP_HTML_BYTES).await
+        },
+        Route::SetupSave => match parse_credentials(request.body) {
+            Some(credentials) => save_credentials(ctx, socket, request, &credentials).await,
+            None => {
+                let body = b"Expected ssid and password, the password empty or 8 to 64 characters";
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), body).await
+            },
+        },
+        Route::Preflight => {
+            let response = Response::new(Status::NoContent)
+                .header("Access-Control-Allow-Methods", CORS_ALLOW_METHODS)
+                .header("Access-Control-Allow-Headers", CORS_ALLOW_HEADERS);
+            send(socket, Framing::of(request), response, b"").await
+        },
+        Route::ApiSensor => serve_sensor_json(socket, request).await,
+        Route::ApiSensorHealth => serve_sensor_health(socket, request).await,
+        Route::ApiStatus => serve_status(socket, request, ctx).await,
+        Route::ApiHistory => serve_history_json(socket, request).await,
+        Route::ApiHistoryDaily => serve_daily_json(socket, request).await,
+        Route::ApiStatsReset => {
+            sensor::reset_extremes();
+            send(socket, Framing::of(request), Response::json(), b"{\"ok\": true}").await
+        },
+        Route::ApiConfig => serve_config_json(socket, request).await,
+        Route::ApiConfigReset => match settings::reset(ctx).await {
+            Ok(()) => serve_config_json(socket, request).await,
+            Err(e) => {
+                log::error!("Unable to clear the stored settings: {:?}", e);
+                let response = Response::json().with_status(Status::InternalServerError);
+                send(socket, Framing::of(request), response, b"{\"ok\": false, \"error\": \"unable to clear the stored settings\"}").await
+            },
+        },
+        Route::ApiReboot => reboot(ctx, socket, request, false).await,
+        Route::ApiFactoryReset => reboot(ctx, socket, request, true).await,
+        Route::ApiVersion => serve_version(socket, request).await,
+        Route::ApiDiag => serve_diag(socket, request).await,
+        Route::ApiLogLevel => serve_log_level(socket, request).await,
+        Route::ApiGpio => serve_gpio(socket, request).await,
+        Route::ApiFan => serve_fan(socket, request).await,
+        Route::ApiFanSet => match FanRequest::from_body(request.body) {
+            Some(FanRequest::Duty(duty)) => {
+                fan::set_manual(duty);
+                serve_fan(socket, request).await
+            },
+            Some(FanRequest::Auto) => {
+                let latest = match sensor::status(sensor::PRIMARY_SENSOR) {
+                    sensor::Status::Ready { reading, stale: false, .. } => Some(reading),
+                    _ => None,
+                };
+                fan::set_auto(latest.as_ref());
+                serve_fan(socket, request).await
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected duty=<0-100>, mode=auto, {\"duty\": <0-100>} or {\"mode\": \"auto\"}").await
+            },
+        },
+        Route::ApiServo => serve_servo(socket, request).await,
+        Route::ApiServoSet => match ServoRequest::from_body(request.body) {
+            Some(ServoRequest::Angle(angle)) => {
+                servo::set_manual(angle);
+                serve_servo(socket, request).await
+            },
+            Some(ServoRequest::Auto) => {
+                let latest = match sensor::status(sensor::PRIMARY_SENSOR) {
+                    sensor::Status::Ready { reading, stale: false, .. } => Some(reading),
+                    _ => None,
+                };
+                match servo::set_auto(latest.as_ref()) {
+                    true => serve_servo(socket, request).await,
+                    false => send(socket, Framing::of(request), Response::text(Status::BadRequest), b"The auto mode needs SERVO_SETPOINT").await,
+                }
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected angle=<0-180>, mode=auto, {\"angle\": <0-180>} or {\"mode\": \"auto\"}").await
+            },
+        },
+        Route::ApiPixel => serve_pixel(socket, request).await,
+        Route::ApiPixelSet => match PixelRequest::from_body(request.body) {
+            Some(PixelRequest::Color(color)) => {
+                pixel::set_manual(color);
+                serve_pixel(socket, request).await
+            },
+            Some(PixelRequest::Auto) => {
+                pixel::set_auto();
+                serve_pixel(socket, request).await
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected color=<rrggbb>, mode=auto, {\"color\": \"#rrggbb\"} or {\"mode\": \"auto\"}").await
+            },
+        },
+        Route::ApiAlarmAck => {
+            alert::acknowledge();
+            serve_alarm(socket, request).await
+        },
+        Route::ApiTime => serve_time(socket, request).await,
+        Route::ApiLogging => serve_logging(socket, request).await,
+        Route::ApiTimeSet => match parse_time(request.body) {
+            Some(unix) => {
+                sntp::set_clock(unix, TimeSource::Manual);
+                serve_time(socket, request).await
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected unix=<seconds> or {\"unix\": <seconds>}, 2000 to 2099").await
+            },
+        },
+        Route::ApiGpioSet { index } => match Switch::from_body(request.body) {
+            Some(switch) => {
+                let on = gpio::switch(index, switch);
+                serve_gpio_output(socket, request, index, on).await
+            },
+            None => {
+                send(socket, Framing::of(request), Response::text(Status::BadRequest), b"Expected on, off or toggle, state=<one of them> or {\"on\": true|false}").await
+            },
+        },
+        Route::ApiLogLevelSet => match parse_log_filter(request.body) {
+            Ok(filter) => {
+                log::info!("Log level {}, modules \"{}\"", log_filter::as_str(filter.level), filter.modules());
+                syslog::set_filter(filter);
+                serve_log_level(socket, request).await
+            },
+            Err(error) => {
+                let mut body = String::<96>::new();
+                write!(&mut body, "{{\"ok\": false, \"error\": \"{}\"}}", error).map_err(|_| Error::Overflow)?;
+                send(socket, Framing::of(request), Response::json().with_status(Status::BadRequest), body.as_bytes()).await
+            },
+        },
+        Route::ApiConfigSet => match parse_config_update(request.body) {
+            Ok(update) => {
+                if let Some(interval) = update.sample_interval {
+                    sensor::set_sample_interval(interval);
+                }
+                if update.temp_offset.is_some() || update.humid_offset.is_some() {
+                    let current = sensor::calibration();
+                    sensor::set_calibration(Calibration {
+                        temperature: update.temp_offset.unwrap_or(current.temperature),
+                        humidity: update.humid_offset.unwrap_or(current.humidity),
+                    });
+                }
+                if let Some(mode) = update.power_mode {
+                    wifi::set_power_mode(ctx, mode).await;
+                }
+                if let Some(unit) = update.temp_unit {
+                    TempUnit::set_configured(unit);
+                }
+                if update.limits.iter().any(Option::is_some) {
+                    let mut limits = alert::thresholds().limits();
+                    for (limit, new) in limits.iter_mut().zip(update.limits) {
+                        *limit = new.unwrap_or(*limit);
+                    }
+                    alert::set_thresholds(Thresholds::from_limits(limits));
+                }
+                if let Some(name) = update.device_name {
+                    settings::set_device_name(name);
+                }
+                settings::request_save();
+                serve_config_json(socket, request).await
+            },
+            Err(error) => {
+                let mut body = String::<96>::new();
+                write!(&mut body, "{{\"ok\": false, \"error\": \"{}\"}}", error).map_err(|_| Error::Overflow)?;
+                send(socket, Framing::of(request), Response::json().with_status(Status::BadRequest), body.as_bytes()).await
+            },
+        },
+        // Static responses, no template pass and no sensor read
+        Route::Static { asset } => {
+            let (_, bytes, gzip_bytes, content_type) = STATIC_ASSETS[asset];
+            let etag = STATIC_ASSET_ETAGS[asset];
+            // Each encoding is a separate representation with its own ETag
+            let (body, encoding, etag_suffix) = if request.accepts_gzip {
+                (gzip_bytes, Some("gzip"), "-gz")
+            } else {
+                (bytes, None, "")
+            };
+
+            let mut etag_value = String::<16>::new();
+            write!(&mut etag_value, "\"{:08x}{}\"", etag, etag_suffix).map_err(|_| Error::Overflow)?;
+
+            let not_modified = request.etag_matches(etag_value.as_str());
+            let mut response = Response::new(if not_modified { Status::NotModified } else { Status::Ok })
+                .header("Content-Type", content_type)
+                .header("ETag", etag_value.as_str())
+                .header("Cache-Control", STATIC_CACHE_CONTROL)
+                .header("Vary", "Accept-Encoding");
+
+            if let Some(encoding) = encoding {
+                response = response.header("Content-Encoding", encoding);
+            }
+
+            send(socket, Framing::of(request), response, if not_modified { b"" } else { body }).await
+        },
+        Route::NotFound => {
+            send(socket, Framing::of(request), Response::not_found(), NOT_FOUND_HTML_BYTES).await
+        },
+        Route::MethodNotAllowed { allow } => {
+            let response = Response::text(Status::MethodNotAllowed).header("Allow", allow);
+            send(socket, Framing::of(request), response, b"Method Not Allowed").await
+        },
+        Route::NotImplemented => {
+            send(socket, Framing::of(request), Response::text(Status::NotImplemented), b"Not Implemented").await
+        },
+    }
+}
+
+async fn serve_led_json(socket: &mut TcpSocket<'_>, request: &Request<'_>, on: bool) -> Result<Sent, Error> {
+    let body: &[u8] = if on { b"{\"on\": true}" } else { b"{\"on\": false}" };
+    send(socket, Framing::of(request), Response::json(), body).await
+}
+
+/// The settings form pre-filled with the values in effect, or with the submitted ones over them when `error` says
+/// why they weren't taken. A rejected form is answered with a 400.
+async fn serve_settings(ctx: &Context, socket: &mut TcpSocket<'_>, request: &Request<'_>, submitted: Option<&form::Fields<'_>>, error: Option<&str>) -> Result<Sent, Error> {
+    if ctx.settings.is_empty() {
+        return Err(Error::Template);
+    }
+
+    let mut values = settings::form_values();
+    for (field, value) in FORM_FIELDS.iter().zip(values.iter_mut()) {
+        if let Some((_, text)) = submitted.into_iter().flatten().find(|(name, _)| *name == field.name) {
+            value.clone_from(text);
+        }
+    }
+
+    let saved = error.is_none() && request.query_param("saved").is_some();
+    let name = settings::device_name();
+    let mut tags = Vec::<(&str, &str), { SETTINGS_TAG_COUNT + FORM_FIELDS.len() }>::new();
+    tags.extend_from_slice(&[
+        (NAME_TAG, name.as_str()),
+        (ERROR_TAG, error.unwrap_or("")),
+        (SAVED_TAG, if saved { "1" } else { "" }),
+    ]).map_err(|_| Error::Overflow)?;
+    for (field, value) in FORM_FIELDS.iter().zip(&values) {
+        tags.push((field.name, value.as_str())).map_err(|_| Error::Overflow)?;
+    }
+    let body_len = template::rendered_len(ctx.settings, &tags);
+
+    let status = if error.is_some() { Status::BadRequest } else { Status::Ok };
+    let response = Response::new(status)
+        .header("Content-Type", "text/html")
+        .header("Cache-Control", NO_STORE)
+        .body_len(body_len);
+    let mut sent = send_head(socket, Framing::of(request), response).await?;
+
+    if request.method == Method::Head {
+        return Ok(sent);
+    }
+
+    template::render(ctx.settings, &tags, socket).await.map_err(Error::Write)?;
+    sent.bytes += body_len;
+
+    Ok(sent)
+}
+
+async fn serve_index(ctx: &Context, socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut temp_str = String::<32>::new();
+    let mut humidity_str = String::<32>::new();
+    let mut dew_point_str = String::<32>::new();
+    let mut heat_index_str = String::<32>::new();
+    let mut abs_humidity_str = String::<16>::new();
+    let mut calibration_str = String::<64>::new();
+    let mut age_str = String::<16>::new();
+    let mut time_str = String::<24>::new();
+    let mut rssi_str = String::<8>::new();
+    let mut mac_str = String::<17>::new();
+    write!(&mut mac_str, "{}", MacAddress(ctx.mac)).map_err(|_| Error::Overflow)?;
+    let mut ip_str = String::<16>::new();
+    match ctx.stack.config_v4() {
+        Some(config) => write!(&mut ip_str, "{}", config.address.address()).map_err(|_| Error::Overflow)?,
+        None => ip_str.push_str("--").map_err(|_| Error::Overflow)?,
+    }
+    let ssid = wifi::joined_ssid();
+    let mut uptime_str = String::<24>::new();
+    write!(&mut uptime_str, "{}", Uptime(Instant::now().as_secs()))
+        .map_err(|_| Error::Overflow)?;
+    let mut decimals_str = String::<4>::new();
+    let mut chip_temp_str = String::<32>::new();
+    let mut interval_str = String::<16>::new();
+    let mut stale_str = String::<96>::new();
+    let mut alert_str = String::<96>::new();
+    let mut temp_min_str = String::<32>::new();
+    let mut temp_max_str = String::<32>::new();
+    let mut humidity_min_str = String::<32>::new();
+    let mut humidity_max_str = String::<32>::new();
+    let mut fan_str = String::<48>::new();
+    let mut servo_str = String::<24>::new();
+    let mut alarm_str = String::<32>::new();
+    let unit = TempUnit::from_request(request);
+    let trends = sensor::trends();
+
+    let last_error = sensor::last_error(sensor::PRIMARY_SENSOR);
+    let sensor::Status::Ready { reading, age, stale, .. } = sensor::status(sensor::PRIMARY_SENSOR) else {
+        let mut body = String::<128>::new();
+        match last_error {
+            Some((error, _)) => write!(&mut body, "Sensor not ready: {}", error.describe()),
+            None => write!(&mut body, "Sensor not ready, try again in a few seconds"),
+        }.map_err(|_| Error::Overflow)?;
+
+        let response = Response::text(Status::ServiceUnavailable).header("Retry-After", SENSOR_RETRY_AFTER);
+        return send(socket, Framing::of(request), response, body.as_bytes()).await;
+    };
+
+    unit.format(reading.temperature, &mut temp_str).map_err(|_| Error::Overflow)?;
+    write!(&mut humidity_str, "{:.*}", SENSOR_MODEL.decimals(), reading.humidity).map_err(|_| Error::Overflow)?;
+    write!(&mut age_str, "{}", age.as_secs()).map_err(|_| Error::Overflow)?;
+    match sntp::now_unix() {
+        Some(now) => write!(&mut time_str, "{}", Iso8601(now)).map_err(|_| Error::Overflow)?,
+        None => time_str.push_str("--").map_err(|_| Error::Overflow)?,
+    }
+    let link_info = wifi::link_info();
+    match link_info {
+        Some(info) => write!(&mut rssi_str, "{}", info.rssi_dbm).map_err(|_| Error::Overflow)?,
+        None => rssi_str.push_str("--").map_err(|_| Error::Overflow)?,
+    }
+    write!(&mut decimals_str, "{}", SENSOR_MODEL.decimals()).map_err(|_| Error::Overflow)?;
+    write!(&mut interval_str, "{}", sensor::sample_interval().as_secs()).map_err(|_| Error::Overflow)?;
+    for (index, active) in alert::alerts().iter().enumerate() {
+        alert_str.push_str(if index == 0 { "Alert: " } else { ", " }).map_err(|_| Error::Overflow)?;
+        alert_str.push_str(active.describe()).map_err(|_| Error::Overflow)?;
+    }
+    if let (true, Some((error, _))) = (stale, last_error) {
+        write!(&mut stale_str, "(last reading failed: {}, showing an older value)", error.describe())
+            .map_err(|_| Error::Overflow)?;
+    }
+
+    match derived::dew_point(reading.temperature, reading.humidity) {
+        Some(dew_point) => unit.format(dew_point, &mut dew_point_str).map_err(|_| Error::Overflow)?,
+        None => dew_point_str.push_str("--").map_err(|_| Error::Overflow)?,
+    }
+    unit.format(derived::heat_index(reading.temperature, reading.humidity), &mut heat_index_str)
+        .map_err(|_| Error::Overflow)?;
+    write!(&mut abs_humidity_str, "{:.1}", derived::absolute_humidity(reading.temperature, reading.humidity))
+        .map_err(|_| Error::Overflow)?;
+
+    match sensor::chip_temperature() {
+        Some(chip_temp) => unit.format(chip_temp, &mut chip_temp_str).map_err(|_| Error::Overflow)?,
+        None => chip_temp_str.push_str("--").map_err(|_| Error::Overflow)?,
+    }
+
+    if fan::PRESENT {
+        let state = fan::state();
+        let mode = if state.failsafe { "failsafe" } else { state.mode.as_str() };
+        write!(&mut fan_str, "{} % ({}", state.duty, mode).map_err(|_| Error::Overflow)?;
+        if let Some(rpm) = state.rpm {
+            write!(&mut fan_str, ", {} RPM", rpm).map_err(|_| Error::Overflow)?;
+        }
+        fan_str.push(')').map_err(|_| Error::Overflow)?;
+    }
+    if servo::PRESENT {
+        let state = servo::state();
+        match state.angle {
+            Some(angle) => write!(&mut servo_str, "{}° ({})", angle, state.mode.as_str()),
+            None => write!(&mut servo_str, "-- ({})", state.mode.as_str()),
+        }.map_err(|_| Error::Overflow)?;
+    }
+
+    let alarm = alert::alarm();
+    if buzzer::PRESENT && alarm.active {
+        alarm_str.push_str(if alarm.sounding() { "sounding" } else { "silenced until the alert clears" })
+            .map_err(|_| Error::Overflow)?;
+    }
+
+    let calibration = sensor::calibration();
+    if calibration.is_active() {
+        write!(&mut calibration_str, "Calibration: {:+.1} °C, {:+.1} % RH", calibration.temperature, calibration.humidity)
+            .map_err(|_| Error::Overflow)?;
+    }
+
+    // Only empty right after a reset, until the next good reading
+    match sensor::extremes(sensor::PRIMARY_SENSOR) {
+        Some(extremes) => {
+            unit.format(extremes.temperature_min, &mut temp_min_str).map_err(|_| Error::Overflow)?;
+            unit.format(extremes.temperature_max, &mut temp_max_str).map_err(|_| Error::Overflow)?;
+            write!(&mut humidity_min_str, "{:.*}", SENSOR_MODEL.decimals(), extremes.humidity_min)
+                .map_err(|_| Error::Overflow)?;
+            write!(&mut humidity_max_str, "{:.*}", SENSOR_MODEL.decimals(), extremes.humidity_max)
+                .map_err(|_| Error::Overflow)?;
+        },
+        None => {
+            for value in [&mut temp_min_str, &mut temp_max_str, &mut humidity_min_str, &mut humidity_max_str] {
+                value.push_str("-").map_err(|_| Error::Overflow)?;
+            }
+        },
+    }
+    let name = settings::device_name();
+
+    // Indexed placeholders like {{TEMP1}} for every sensor, the plain ones describe the primary sensor
+    let mut indexed_tags = Vec::<[String<24>; 3], SENSOR_COUNT>::new();
+    let mut indexed_values = Vec::<[String<32>; 2], SENSOR_COUNT>::new();
+
+    for id in 0..SENSOR_COUNT {
+        let mut temp = String::new();
+        let mut humidity = String::new();
+
+        match sensor::status(id) {
+            sensor::Status::Ready { reading, .. } => {
+                unit.format(reading.temperature, &mut temp).map_err(|_| Error::Overflow)?;
+                write!(&mut humidity, "{:.*}", SENSOR_MODEL.decimals(), reading.humidity).map_err(|_| Error::Overflow)?;
+            },
+            sensor::Status::NotReady => {
+                temp.push_str("--").map_err(|_| Error::Overflow)?;
+                humidity.push_str("--").map_err(|_| Error::Overflow)?;
+            },
+        }
+
+        let tags = [indexed_tag(TEMP_TAG, id)?, indexed_tag(HUMID_TAG, id)?, indexed_tag(LABEL_TAG, id)?];
+        indexed_tags.push(tags).map_err(|_| Error::Overflow)?;
+        indexed_values.push([temp, humidity]).map_err(|_| Error::Overflow)?;
+    }
+
+    // {{GPIONAME0}} and {{GPIO0}} for every output
+    let mut gpio_tags = Vec::<[String<24>; 2], GPIO_COUNT>::new();
+    for (index, _) in GPIO_OUTPUTS.iter().enumerate() {
+        gpio_tags.push([indexed_tag(GPIO_NAME_TAG, index)?, indexed_tag(GPIO_TAG, index)?]).map_err(|_| Error::Overflow)?;
+    }
+
+    let mut tags = Vec::<(&str, &str), { INDEX_TAG_COUNT + 3 * SENSOR_COUNT + 2 * GPIO_COUNT }>::new();
+    tags.extend_from_slice(&[
+        (TEMP_TAG, temp_str.as_str()),
+        (TEMP_UNIT_TAG, unit.suffix()),
+        (UNIT_MODE_TAG, unit.as_str()),
+        (DECIMALS_TAG, decimals_str.as_str()),
+        (HUMID_TAG, humidity_str.as_str()),
+        (DEW_POINT_TAG, dew_point_str.as_str()),
+        (HEAT_INDEX_TAG, heat_index_str.as_str()),
+        (ABS_HUMID_TAG, abs_humidity_str.as_str()),
+        (CALIBRATION_TAG, calibration_str.as_str()),
+        (CHIP_TEMP_TAG, chip_temp_str.as_str()),
+        (INTERVAL_TAG, interval_str.as_str()),
+        (TEMP_TREND_TAG, trends.temperature.arrow()),
+        (HUMID_TREND_TAG, trends.humidity.arrow()),
+        (ALERT_TAG, alert_str.as_str()),
+        (TMIN_TAG, temp_min_str.as_str()),
+        (TMAX_TAG, temp_max_str.as_str()),
+        (HMIN_TAG, humidity_min_str.as_str()),
+        (HMAX_TAG, humidity_max_str.as_str()),
+        (AGE_TAG, age_str.as_str()),
+        (STALE_TAG, stale_str.as_str()),
+        (TIME_TAG, time_str.as_str()),
+        (RSSI_TAG, rssi_str.as_str()),
+        (SIGNAL_TAG, link_info.map_or("unknown", |info| info.quality().as_str())),
+        (HOSTNAME_TAG, ctx.hostname),
+        (NAME_TAG, name.as_str()),
+        (MAC_TAG, mac_str.as_str()),
+        (IP_TAG, ip_str.as_str()),
+        (SSID_TAG, ssid.as_deref().unwrap_or("--")),
+        (UPTIME_TAG, uptime_str.as_str()),
+        (LED_TAG, if led::manual_state() { "ON" } else { "OFF" }),
+        (FAN_TAG, fan_str.as_str()),
+        (SERVO_TAG, servo_str.as_str()),
+        (ALARM_TAG, alarm_str.as_str()),
+        (ALARM_SOUNDING_TAG, if buzzer::PRESENT && alarm.sounding() { "1" } else { "" }),
+        (VERSION_TAG, build_info::SUMMARY),
+    ]).map_err(|_| Error::Overflow)?;
+
+    for (id, (names, values)) in indexed_tags.iter().zip(&indexed_values).enumerate() {
+        let [temp_tag, humid_tag, label_tag] = names;
+        let [temp, humidity] = values;
+        tags.extend_from_slice(&[
+            (temp_tag.as_str(), temp.as_str()),
+            (humid_tag.as_str(), humidity.as_str()),
+            (label_tag.as_str(), DHT_LABELS[id]),
+        ]).map_err(|_| Error::Overflow)?;
+    }
+    for (index, [name_tag, state_tag]) in gpio_tags.iter().enumerate() {
+        tags.extend_from_slice(&[
+            (name_tag.as_str(), GPIO_OUTPUTS[index].0),
+            (state_tag.as_str(), if gpio::is_on(index) { "ON" } else { "OFF" }),
+        ]).map_err(|_| Error::Overflow)?;
+    }
+
+    if ctx.index.is_empty() {
+        return Err(Error::Template);
+    }
+    let body_len = template::rendered_len(ctx.index, &tags);
+
+    // Sensor values must never come from a cache
+    let response = Response::new(Status::Ok)
+        .header("Content-Type", "text/html")
+        .header("Cache-Control", NO_STORE)
+        .body_len(body_len);
+    let mut sent = send_head(socket, Framing::of(request), response).await?;
+
+    if request.method == Method::Head {
+        return Ok(sent);
+    }
+
+    // Send the processed template straight to the socket as it is produced
+    template::render(ctx.index, &tags, socket).await.map_err(Error::Write)?;
+    sent.bytes += body_len;
+
+    Ok(sent)
+}
+
+/// `TEMP` with `id` 1 becomes `TEMP1`
+fn indexed_tag(tag: &str, id: usize) -> Result<String<24>, Error> {
+    let mut indexed = String::new();
+    write!(&mut indexed, "{}{}", tag, id).map_err(|_| Error::Overflow)?;
+    Ok(indexed)
+}
+
+/// Array with one object per sensor, 503 while none of them has a usable reading
+async fn serve_sensor_json(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<SENSOR_JSON_SIZE>::new();
+    let mut any_ready = false;
+
+    body.push('[').map_err(|_| Error::Overflow)?;
+    for id in 0..SENSOR_COUNT {
+        if id > 0 {
+            body.push_str(", ").map_err(|_| Error::Overflow)?;
+        }
+        any_ready |= write_sensor_object(&mut body, id).map_err(|_| Error::Overflow)?;
+    }
+    body.push(']').map_err(|_| Error::Overflow)?;
+
+    let response = if any_ready {
+        Response::json()
+    } else {
+        Response::json().with_status(Status::ServiceUnavailable).header("Retry-After", SENSOR_RETRY_AFTER)
+    };
+
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Failure counters of the sensor task and the current error of every sensor
+async fn serve_sensor_health(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<HEALTH_JSON_SIZE>::new();
+    write_sensor_health(&mut body).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Device level state: uptime, why it last restarted, watchdog resets, firmware, where the readings come from and the
+/// Wi-Fi link
+async fn serve_status(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: &Context) -> Result<Sent, Error> {
+    let mut body = String::<STATUS_JSON_SIZE>::new();
+    write_status(&mut body, ctx).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Every field is present, what isn't known yet is null
+fn write_status<W: CoreWrite>(out: &mut W, ctx: &Context) -> core::fmt::Result {
+    write!(out, "{{\"uptime_s\": {}, \"boot_reason\": \"{}\", \"watchdog_resets\": {}, \"version\": \"{}\", \"hostname\": \"{}\", \"device_name\": \"{}\", \"mac\": \"{}\", ",
+        Instant::now().as_secs(), watchdog::boot_reason().as_str(), watchdog::resets(), env!("CARGO_PKG_VERSION"), ctx.hostname, settings::device_name(),
+        MacAddress(ctx.mac))?;
+    // Up front, so nobody takes made up readings for real ones
+    write!(out, "\"sensor_source\": \"{}\", ", if sensor::SIMULATED { "simulated" } else { "hardware" })?;
+    write!(out, "\"time_source\": \"{}\", ", sntp::time_source().as_str())?;
+    write!(out, "\"sd_logging\": \"{}\", ", sd_log::status().state.as_str())?;
+    write!(out, "\"firmware_update\": \"{}\", ", ota::state().as_str())?;
+    // The setup access point has a fixed address whatever is configured
+    let dhcp = ctx.dhcp && !ctx.setup_mode.load(Ordering::Relaxed);
+    write!(out, "\"addressing\": \"{}\", ", if dhcp { "dhcp" } else { "static" })?;
+    out.write_str("\"fan\": ")?;
+    write_fan(out)?;
+    out.write_str(", \"servo\": ")?;
+    write_servo(out)?;
+    out.write_str(", \"alarm\": ")?;
+    write_alarm(out)?;
+    out.write_str(", ")?;
+    match ctx.stack.config_v4() {
+        Some(config) => {
+            write!(out, "\"ip\": \"{}\", \"prefix_len\": {}, ", config.address.address(), config.address.prefix_len())?;
+            match config.gateway {
+                Some(gateway) => write!(out, "\"gateway\": \"{}\", ", gateway)?,
+                None => out.write_str("\"gateway\": null, ")?,
+            }
+            out.write_str("\"dns_servers\": [")?;
+            for (index, server) in config.dns_servers.iter().enumerate() {
+                write!(out, "{}\"{}\"", if index > 0 { ", " } else { "" }, server)?;
+            }
+            out.write_str("], ")?;
+        },
+        None => out.write_str("\"ip\": null, \"prefix_len\": null, \"gateway\": null, \"dns_servers\": null, ")?,
+    }
+    match wifi::joined_ssid() {
+        Some(ssid) => write!(out, "\"ssid\": \"{}\", ", JsonStr(&ssid))?,
+        None => out.write_str("\"ssid\": null, ")?,
+    }
+    let latency = LATENCY.lock(Cell::get);
+    write!(out, "\"power_mode\": \"{}\", \"request_latency\": {{\"samples\": {}, \"average_ms\": ",
+        wifi::power_mode().as_str(), latency.samples)?;
+    match latency.samples {
+        0 => out.write_str("null}, ")?,
+        samples => write!(out, "{:.1}}}, ", latency.total_micros as f32 / samples as f32 / 1000.0)?,
+    }
+    match wifi::link_info() {
+        Some(info) => write!(out, "\"rssi_dbm\": {}, \"signal_quality\": \"{}\", \"bssid\": \"{}\", \"channel\": {}}}",
+            info.rssi_dbm, info.quality().as_str(), MacAddress(info.bssid), info.channel),
+        None => out.write_str("\"rssi_dbm\": null, \"signal_quality\": null, \"bssid\": null, \"channel\": null}"),
+    }
+}
+
+fn write_sensor_health<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    let stats = sensor::stats();
+    write!(out, "{{\"transient_failures\": {}, \"persistent_failures\": {}, ", stats.transient_failures, stats.persistent_failures)?;
+    write!(out, "\"errors\": {{\"timeout\": {}, \"checksum_mismatch\": {}, \"invalid_data\": {}}}, ",
+        stats.timeouts, stats.checksum_mismatches, stats.invalid_data)?;
+    write!(out, "\"rejected\": {{\"out_of_range\": {}, \"jump\": {}}}, \"sensors\": [",
+        stats.rejected_out_of_range, stats.rejected_jumps)?;
+
+    for (id, label) in DHT_LABELS.iter().enumerate() {
+        if id > 0 {
+            out.write_str(", ")?;
+        }
+        write!(out, "{{\"id\": {}, \"label\": \"{}\", ", id, label)?;
+        match sensor::last_error(id) {
+            Some((error, failures)) => write!(out,
+                "\"ok\": false, \"consecutive_failures\": {}, \"error\": \"{}\", \"message\": \"{}\"}}",
+                failures, error.as_str(), error.describe())?,
+            None => out.write_str("\"ok\": true, \"consecutive_failures\": 0, \"error\": null}")?,
+        }
+    }
+
+    out.write_str("]}")
+}
+
+/// JSON object describing sensor `id`, returns whether it had a usable reading
+fn write_sensor_object<W: CoreWrite>(out: &mut W, id: usize) -> Result<bool, core::fmt::Error> {
+    write!(out, "{{\"id\": {}, \"label\": \"{}\", \"sensor_model\": \"{}\", ", id, DHT_LABELS[id], SENSOR_MODEL.name())?;
+
+    let sensor::Status::Ready { reading, raw, age, stale } = sensor::status(id) else {
+        out.write_str("\"ok\": false, \"error\": \"sensor not ready\"}")?;
+        return Ok(false);
+    };
+
+    // Both units regardless of the display setting
+    write!(out, "\"temperature_c\": {:.1}, \"temperature_c_raw\": {:.1}, \"temperature_f\": {:.1}, ",
+        reading.temperature, raw.temperature, celsius_to_fahrenheit(reading.temperature))?;
+    write!(out, "\"humidity_pct\": {:.1}, \"humidity_pct_raw\": {:.1}, \"age_seconds\": {}, \"stale\": {}, ",
+        reading.humidity, raw.humidity, age.as_secs(), stale)?;
+    match sntp::now_unix() {
+        Some(now) => write!(out, "\"timestamp\": {}, ", now.saturating_sub(age.as_secs()))?,
+        None => out.write_str("\"timestamp\": null, ")?,
+    }
+    match derived::dew_point(reading.temperature, reading.humidity) {
+        Some(dew_point) => write!(out, "\"dew_point_c\": {:.1}, ", dew_point)?,
+        None => out.write_str("\"dew_point_c\": null, ")?,
+    }
+    write!(out, "\"heat_index_c\": {:.1}, \"absolute_humidity_g_m3\": {:.1}, ",
+        derived::heat_index(reading.temperature, reading.humidity),
+        derived::absolute_humidity(reading.temperature, reading.humidity))?;
+    if let Some(extremes) = sensor::extremes(id) {
+        write!(out,
+            "\"temperature_c_min\": {:.1}, \"temperature_c_max\": {:.1}, \"humidity_pct_min\": {:.1}, \"humidity_pct_max\": {:.1}, \"count\": {}, ",
+            extremes.temperature_min, extremes.temperature_max, extremes.humidity_min, extremes.humidity_max, extremes.count)?;
+    }
+    // The history and with it the trend and the alerts only cover the primary sensor
+    if id == sensor::PRIMARY_SENSOR {
+        let trends = sensor::trends();
+        write!(out, "\"temperature_trend\": \"{}\", \"humidity_trend\": \"{}\", \"alerts\": [",
+            trends.temperature.as_str(), trends.humidity.as_str())?;
+        for (index, active) in alert::alerts().iter().enumerate() {
+            write!(out, "{}\"{}\"", if index == 0 { "" } else { ", " }, active.as_str())?;
+        }
+        out.write_str("], ")?;
+    }
+    // A board value, repeated in every object so the array stays one object per sensor
+    if let Some(chip_temp) = sensor::chip_temperature() {
+        write!(out, "\"chip_temperature_c\": {:.1}, ", chip_temp)?;
+    }
+    out.write_str("\"ok\": true}")?;
+
+    Ok(true)
+}
+
+/// Current runtime settings, also the answer to a successful `POST /api/config`
+async fn serve_config_json(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let calibration = sensor::calibration();
+    let mut body = String::<384>::new();
+    write!(&mut body, "{{\"sample_interval_s\": {}, \"temp_offset\": {:.1}, \"humid_offset\": {:.1}, \"wifi_pm\": \"{}\", \"temp_unit\": \"{}\", \"device_name\": \"{}\"",
+        sensor::sample_interval().as_secs(), calibration.temperature, calibration.humidity, wifi::power_mode().as_str(),
+        TempUnit::configured().as_str(), settings::device_name())
+        .map_err(|_| Error::Overflow)?;
+    for (name, limit) in LIMIT_FIELDS.iter().zip(alert::thresholds().limits()) {
+        match limit {
+            Some(limit) => write!(&mut body, ", \"{}\": {:.1}", name, limit),
+            None => write!(&mut body, ", \"{}\": null", name),
+        }.map_err(|_| Error::Overflow)?;
+    }
+    body.push('}').map_err(|_| Error::Overflow)?;
+
+    send(socket, Framing::of(request), Response::json(), body.as_bytes()).await
+}
+
+/// The current filter with `level` and `modules` of the body applied, like `level=debug&modules=access%3Dwarn` or
+/// `{"level": "debug", "modules": "access=warn mqtt=trace"}`. A field that is missing stays as it is.
+fn parse_log_filter(body: &[u8]) -> Result<LogFilter, &'static str> {
+    let body = from_utf8(body).map_err(|_| "body is not UTF-8")?.trim();
+    let field = |name| http::body_field(body, name).map(|value| http::percent_decode::<160>(value.trim_matches('"')));
+
+    let mut filter = syslog::filter();
+    if let Some(level) = field("level") {
+        filter.level = level.as_deref().and_then(log_filter::parse_level).ok_or("invalid level")?;
+    }
+    if let Some(modules) = field("modules") {
+        filter.set_modules(&modules.ok_or("invalid modules")?)?;
+    }
+    Ok(filter)
+}
+
+fn write_log_filter<W: CoreWrite>(out: &mut W, filter: &LogFilter) -> core::fmt::Result {
+    let mut modules = String::<{ log_filter::MODULE_RULES * (log_filter::MODULE_LEN + 7) }>::new();
+    write!(&mut modules, "{}", filter.modules())?;
+    write!(out, "{{\"level\": \"{}\", \"modules\": \"{}\"}}", log_filter::as_str(filter.level), JsonStr(&modules))
+}
+
+/// Active log level and module rules, also the answer to a successful `POST /api/log-level`
+async fn serve_log_level(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<256>::new();
+    write_log_filter(&mut body, &syslog::filter()).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Mode, duty and speed of the fan, `null` without `FAN_PIN`
+fn write_fan<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    if !fan::PRESENT {
+        return out.write_str("null");
+    }
+    let state = fan::state();
+    write!(out, "{{\"mode\": \"{}\", \"duty\": {}, \"failsafe\": {}, \"rpm\": ", state.mode.as_str(), state.duty, state.failsafe)?;
+    match state.rpm {
+        Some(rpm) => write!(out, "{}}}", rpm),
+        None => out.write_str("null}"),
+    }
+}
+
+/// The fan and its curve, also the answer to a successful `POST /api/fan`
+async fn serve_fan(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<192>::new();
+    body.push_str("{\"fan\": ").map_err(|_| Error::Overflow)?;
+    write_fan(&mut body).map_err(|_| Error::Overflow)?;
+    match fan::curve() {
+        Some(curve) => write!(&mut body, ", \"curve\": \"{}\"}}", curve),
+        None => write!(&mut body, ", \"curve\": null}}"),
+    }.map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Mode and commanded angle of the servo, `null` without `SERVO_PIN`
+fn write_servo<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    if !servo::PRESENT {
+        return out.write_str("null");
+    }
+    let state = servo::state();
+    write!(out, "{{\"mode\": \"{}\", \"angle\": ", state.mode.as_str())?;
+    match state.angle {
+        Some(angle) => write!(out, "{}", angle)?,
+        None => out.write_str("null")?,
+    }
+    write!(out, ", \"attached\": {}}}", state.attached)
+}
+
+/// The servo, also the answer to a successful `POST /api/servo`
+async fn serve_servo(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<96>::new();
+    body.push_str("{\"servo\": ").map_err(|_| Error::Overflow)?;
+    write_servo(&mut body).map_err(|_| Error::Overflow)?;
+    body.push('}').map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// The status pixels, also the answer to a successful `POST /api/pixel`. `indication` is what the status shows,
+/// `color` the manual color hiding it or null.
+async fn serve_pixel(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<160>::new();
+    let manual = pixel::manual();
+    write!(&mut body, "{{\"mode\": \"{}\", \"indication\": \"{}\", \"color\": ",
+        if manual.is_some() { "manual" } else { "auto" }, pixel::indication().as_str()).map_err(|_| Error::Overflow)?;
+    match manual {
+        Some(color) => write!(&mut body, "\"{}\"", color),
+        None => write!(&mut body, "null"),
+    }.map_err(|_| Error::Overflow)?;
+    write!(&mut body, ", \"brightness\": {}, \"count\": {}}}", PIXEL_BRIGHTNESS, PIXEL_COUNT).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Whether the alerts sound the buzzer, `null` without `BUZZER_PIN`
+fn write_alarm<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    if !buzzer::PRESENT {
+        return out.write_str("null");
+    }
+    let alarm = alert::alarm();
+    write!(out, "{{\"active\": {}, \"acknowledged\": {}, \"sounding\": {}}}", alarm.active, alarm.acknowledged, alarm.sounding())
+}
+
+/// The answer to `POST /api/alarm/ack`
+async fn serve_alarm(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<96>::new();
+    body.push_str("{\"alarm\": ").map_err(|_| Error::Overflow)?;
+    write_alarm(&mut body).map_err(|_| Error::Overflow)?;
+    body.push('}').map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Unix seconds of `POST /api/time`, within the years the RTC counts so the chip can always hold it
+fn parse_time(body: &[u8]) -> Option<u64> {
+    let body = from_utf8(body).ok()?.trim();
+    http::body_field(body, "unix")?.parse::<u64>().ok().filter(|unix| (ds3231::FIRST_UNIX..ds3231::END_UNIX).contains(unix))
+}
+
+/// The clock, where its time came from and whether the RTC answers, `rtc_ok` is null without one
+async fn serve_time(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<160>::new();
+    match sntp::now_unix() {
+        Some(now) => write!(&mut body, "{{\"unix\": {}, \"time\": \"{}\", ", now, Iso8601(now)),
+        None => write!(&mut body, "{{\"unix\": null, \"time\": null, "),
+    }.map_err(|_| Error::Overflow)?;
+    write!(&mut body, "\"time_source\": \"{}\", \"uptime_s\": {}, \"rtc_ok\": ", sntp::time_source().as_str(), Instant::now().as_secs())
+        .map_err(|_| Error::Overflow)?;
+    match rtc::healthy() {
+        Some(ok) => write!(&mut body, "{}}}", ok),
+        None => write!(&mut body, "null}}"),
+    }.map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// The SD card logger, `error` says why it stopped once `state` is failed
+async fn serve_logging(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<320>::new();
+    write_logging(&mut body).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+fn write_logging<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    let status = sd_log::status();
+    write!(out, "{{\"state\": \"{}\", \"error\": ", status.state.as_str())?;
+    match status.error {
+        Some(error) => write!(out, "\"{}\"", error)?,
+        None => out.write_str("null")?,
+    }
+    write!(out, ", \"file\": \"{}\", \"file_size_bytes\": ", FILE_NAME)?;
+    match status.file_size {
+        Some(size) => write!(out, "{}", size)?,
+        None => out.write_str("null")?,
+    }
+    out.write_str(", \"last_write_s\": ")?;
+    match status.last_write {
+        Some(secs) => write!(out, "{}", secs)?,
+        None => out.write_str("null")?,
+    }
+    out.write_str(", \"last_write\": ")?;
+    match status.last_write.and_then(sntp::unix_at) {
+        Some(unix) => write!(out, "\"{}\"", Iso8601(unix))?,
+        None => out.write_str("null")?,
+    }
+    write!(out, ", \"buffered\": {}, \"flush_every\": {}}}", status.buffered, SD_FLUSH_EVERY)
+}
+
+fn write_gpio_output<W: CoreWrite>(out: &mut W, index: usize, on: bool) -> core::fmt::Result {
+    let (name, pin) = GPIO_OUTPUTS[index];
+    write!(out, "{{\"name\": \"{}\", \"pin\": {}, \"on\": {}}}", name, pin, on)
+}
+
+/// Every named output and what its pin drives
+async fn serve_gpio(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<GPIO_JSON_SIZE>::new();
+    body.push_str("{\"outputs\": [").map_err(|_| Error::Overflow)?;
+    for (index, _) in GPIO_OUTPUTS.iter().enumerate() {
+        if index > 0 {
+            body.push_str(", ").map_err(|_| Error::Overflow)?;
+        }
+        write_gpio_output(&mut body, index, gpio::is_on(index)).map_err(|_| Error::Overflow)?;
+    }
+    body.push_str("]}").map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// One output after it was switched
+async fn serve_gpio_output(socket: &mut TcpSocket<'_>, request: &Request<'_>, index: usize, on: bool) -> Result<Sent, Error> {
+    let mut body = String::<64>::new();
+    write_gpio_output(&mut body, index, on).map_err(|_| Error::Overflow)?;
+    send(socket, Framing::of(request), Response::json(), body.as_bytes()).await
+}
+
+/// Which firmware this is, never changes while the device runs
+async fn serve_version(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<160>::new();
+    write!(&mut body, "{{\"version\": \"{}\", \"git_hash\": \"{}\", \"build_timestamp\": \"{}\", \"board\": \"{}\"}}",
+        build_info::VERSION, build_info::GIT_HASH, build_info::BUILD_TIMESTAMP, board::NAME)
+        .map_err(|_| Error::Overflow)?;
+
+    send(socket, Framing::of(request), Response::json(), body.as_bytes()).await
+}
+
+/// Memory and socket usage, for sizing the buffers from what the device actually needs
+async fn serve_diag(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<DIAG_JSON_SIZE>::new();
+    write_diag(&mut body).map_err(|_| Error::Overflow)?;
+
+    let response = Response::json().header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+/// Every size is in bytes
+fn write_diag<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    let (stack_size, headroom) = (diag::stack_size(), diag::stack_headroom());
+    write!(out, "{{\"stack\": {{\"size\": {}, \"peak_used\": {}, \"headroom\": {}}}, ",
+        stack_size, stack_size - headroom, headroom)?;
+    write!(out, "\"buffers\": {{\"connection\": {}, \"connections\": {}, \"stack_resources\": {}, \"history\": {}, \"status_json\": {}, \"metrics\": {}}}, ",
+        size_of::<crate::ConnectionBuffers>(), crate::HTTP_TASKS, size_of::<StackResources<{ crate::SOCKET_COUNT }>>(),
+        size_of_val(&HISTORY), STATUS_JSON_SIZE, METRICS_SIZE)?;
+    write!(out, "\"sockets\": {{\"open\": {}, \"capacity\": {}}}, \"connections_accepted\": {}, ",
+        diag::open_sockets(), crate::SOCKET_COUNT, diag::accepted())?;
+    out.write_str("\"log\": ")?;
+    write_log_filter(out, &syslog::filter())?;
+    out.write_str(", ")?;
+
+    let stats = sensor::stats();
+    write!(out, "\"sensor_errors\": {{\"transient\": {}, \"persistent\": {}, \"timeout\": {}, \"checksum_mismatch\": {}, ",
+        stats.transient_failures, stats.persistent_failures, stats.timeouts, stats.checksum_mismatches)?;
+    write!(out, "\"invalid_data\": {}, \"out_of_range\": {}, \"jump\": {}}}}}",
+        stats.invalid_data, stats.rejected_out_of_range, stats.rejected_jumps)
+}
+
+/// Gauges in the Prometheus text format, sensors without a usable reading are left out
+async fn serve_metrics(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<METRICS_SIZE>::new();
+    write_metrics(&mut body).map_err(|_| Error::Overflow)?;
+
+    let response = Response::new(Status::Ok).header("Content-Type", METRICS_CONTENT_TYPE).header("Cache-Control", NO_STORE);
+    send(socket, Framing::of(request), response, body.as_bytes()).await
+}
+
+fn write_metrics<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    out.write_str("# HELP firmware_info Firmware running on the device.\n# TYPE firmware_info gauge\n")?;
+    writeln!(out, "firmware_info{{version=\"{}\",git_hash=\"{}\",build_timestamp=\"{}\"}} 1",
+        build_info::VERSION, build_info::GIT_HASH, build_info::BUILD_TIMESTAMP)?;
+    out.write_str("# HELP uptime_seconds Time since the device booted.\n# TYPE uptime_seconds counter\n")?;
+    writeln!(out, "uptime_seconds {}", Instant::now().as_secs())?;
+    out.write_str("# HELP stack_headroom_bytes Stack never used since boot.\n# TYPE stack_headroom_bytes gauge\n")?;
+    writeln!(out, "stack_headroom_bytes {}", diag::stack_headroom())?;
+    out.write_str("# HELP sockets_open Sockets of the firmware's tasks, out of sockets_capacity.\n# TYPE sockets_open gauge\n")?;
+    writeln!(out, "sockets_open {}", diag::open_sockets())?;
+    out.write_str("# HELP sockets_capacity Sockets the network stack has room for.\n# TYPE sockets_capacity gauge\n")?;
+    writeln!(out, "sockets_capacity {}", crate::SOCKET_COUNT)?;
+    out.write_str("# HELP http_connections_accepted_total Connections accepted since boot.\n# TYPE http_connections_accepted_total counter\n")?;
+    writeln!(out, "http_connections_accepted_total {}", diag::accepted())?;
+
+    out.write_str("# HELP dht_temperature_celsius Smoothed air temperature.\n# TYPE dht_temperature_celsius gauge\n")?;
+    for (id, label) in DHT_LABELS.iter().enumerate() {
+        if let sensor::Status::Ready { reading, .. } = sensor::status(id) {
+            writeln!(out, "dht_temperature_celsius{{sensor=\"{}\"}} {:.1}", label, reading.temperature)?;
+        }
+    }
+
+    out.write_str("# HELP dht_humidity_percent Smoothed relative humidity.\n# TYPE dht_humidity_percent gauge\n")?;
+    for (id, label) in DHT_LABELS.iter().enumerate() {
+        if let sensor::Status::Ready { reading, .. } = sensor::status(id) {
+            writeln!(out, "dht_humidity_percent{{sensor=\"{}\"}} {:.1}", label, reading.humidity)?;
+        }
+    }
+
+    out.write_str("# HELP dht22_sample_interval_seconds Time between two samples.\n# TYPE dht22_sample_interval_seconds gauge\n")?;
+    writeln!(out, "dht22_sample_interval_seconds {}", sensor::sample_interval().as_secs())?;
+
+    let stats = sensor::stats();
+    out.write_str("# HELP dht_rejected_readings_total Readings dropped by the spike filter.\n# TYPE dht_rejected_readings_total counter\n")?;
+    writeln!(out, "dht_rejected_readings_total{{reason=\"out_of_range\"}} {}", stats.rejected_out_of_range)?;
+    writeln!(out, "dht_rejected_readings_total{{reason=\"jump\"}} {}", stats.rejected_jumps)?;
+    out.write_str("# HELP dht_read_errors_total Failed read attempts by cause.\n# TYPE dht_read_errors_total counter\n")?;
+    for (error, count) in [
+        (sensor::SensorError::Timeout, stats.timeouts),
+        (sensor::SensorError::ChecksumMismatch, stats.checksum_mismatches),
+        (sensor::SensorError::InvalidData, stats.invalid_data),
+    ] {
+        writeln!(out, "dht_read_errors_total{{kind=\"{}\"}} {}", error.as_str(), count)?;
+    }
+
+    if syslog::enabled() {
+        out.write_str("# HELP syslog_dropped_messages_total Log lines not forwarded to the syslog collector.\n# TYPE syslog_dropped_messages_total counter\n")?;
+        writeln!(out, "syslog_dropped_messages_total {}", syslog::dropped())?;
+    }
+
+    if let Some(info) = wifi::link_info() {
+        out.write_str("# HELP wifi_rssi_dbm Signal strength of the joined access point.\n# TYPE wifi_rssi_dbm gauge\n")?;
+        writeln!(out, "wifi_rssi_dbm {}", info.rssi_dbm)?;
+    }
+
+    if let Some(chip_temp) = sensor::chip_temperature() {
+        out.write_str("# HELP chip_temperature_celsius RP2040 internal temperature sensor.\n# TYPE chip_temperature_celsius gauge\n")?;
+        writeln!(out, "chip_temperature_celsius {:.1}", chip_temp)?;
+    }
+
+    Ok(())
+}
+
+/// Recorded samples as a JSON array, newest last, `?n=` limits it to the newest `n`
+async fn serve_history_json(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let limit = request.query_param("n").and_then(|n| n.parse::<u32>().ok());
+
+    // Up to HISTORY_CAPACITY samples don't fit any buffer, the array is streamed until the connection closes
+    let framing = Framing { keep_alive: false, ..Framing::of(request) };
+    let mut sent = send_head(socket, framing, Response::json()).await?;
+
+    if framing.head_only {
+        return Ok(sent);
+    }
+
+    let (oldest, end) = HISTORY.lock(|history| history.borrow().seq_range());
+    let mut seq = match limit {
+        Some(n) if n < end.wrapping_sub(oldest) => end.wrapping_sub(n),
+        _ => oldest,
+    };
+    let mut separator = "";
+
+    socket.write_all(b"[").await.map_err(Error::Write)?;
+    sent.bytes += 1;
+
+    while seq != end {
+        // Samples dropped while streaming are skipped
+        if let Some(sample) = HISTORY.lock(|history| history.borrow().get(seq)) {
+            let mut item = String::<128>::new();
+            write!(&mut item, "{}{{\"secs_since_boot\": {}, ", separator, sample.secs_since_boot).map_err(|_| Error::Truncated)?;
+            match history::timestamp(&sample) {
+                Some(timestamp) => write!(&mut item, "\"timestamp\": {}, ", timestamp),
+                None => write!(&mut item, "\"timestamp\": null, "),
+            }.map_err(|_| Error::Truncated)?;
+            write!(&mut item, "\"temperature_c\": {:.1}, \"humidity_pct\": {:.1}}}", sample.temperature(), sample.humidity())
+                .map_err(|_| Error::Truncated)?;
+            socket.write_all(item.as_bytes()).await.map_err(Error::Write)?;
+            sent.bytes += item.len();
+            separator = ", ";
+        }
+        seq = seq.wrapping_add(1);
+    }
+
+    socket.write_all(b"]").await.map_err(Error::Write)?;
+    sent.bytes += 1;
+
+    Ok(sent)
+}
+
+/// Minimum, maximum and average of the primary sensor per UTC day, oldest first, from the hours kept in flash and
+/// the one in progress. Empty until the clock is synced once.
+async fn serve_daily_json(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let mut body = String::<{ 224 * daily::DAILY_DAYS }>::new();
+    write_daily(&mut body).map_err(|_| Error::Overflow)?;
+    send(socket, Framing::of(request), Response::json(), body.as_bytes()).await
+}
+
+fn write_daily<W: CoreWrite>(out: &mut W) -> core::fmt::Result {
+    let tenths = |value: f32| value / 10.0;
+    out.write_str("{\"days\": [")?;
+    for (index, day) in daily::days().iter().enumerate() {
+        let summary = &day.summary;
+        write!(out, "{}{{\"date\": \"{}\", \"hours\": {}, \"count\": {}, ", if index == 0 { "" } else { ", " },
+            IsoDate(day.day), day.hours, summary.count)?;
+        write!(out, "\"temperature_c_min\": {:.1}, \"temperature_c_max\": {:.1}, \"temperature_c_avg\": {:.1}, ",
+            tenths(summary.temp_min_dc.into()), tenths(summary.temp_max_dc.into()), tenths(summary.temp_avg_dc().into()))?;
+        write!(out, "\"humidity_pct_min\": {:.1}, \"humidity_pct_max\": {:.1}, \"humidity_pct_avg\": {:.1}}}",
+            tenths(summary.rh_min_dp.into()), tenths(summary.rh_max_dp.into()), tenths(summary.rh_avg_dp().into()))?;
+    }
+    out.write_str("]}")
+}
+
+/// Recorded samples as CSV, streamed row by row and delimited by closing the connection
+async fn serve_history_csv(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<Sent, Error> {
+    let framing = Framing { keep_alive: false, ..Framing::of(request) };
+    let response = Response::new(Status::Ok)
+        .header("Content-Type", "text/csv")
+        .header("Content-Disposition", "attachment; filename=\"dht22.csv\"");
+    let mut sent = send_head(socket, framing, response).await?;
+
+    if framing.head_only {
+        return Ok(sent);
+    }
+
+    // `unix_time` stays empty for samples while the clock isn't synced
+    let columns = b"timestamp_s,temperature_c,humidity_pct,unix_time\r\n";
+    socket.write_all(columns).await.map_err(Error::Write)?;
+    sent.bytes += columns.len();
+
+    let (oldest, end) = HISTORY.lock(|history| history.borrow().seq_range());
+    let mut seq = oldest;
+
+    while seq != end {
+        // Samples dropped while streaming are skipped
+        if let Some(sample) = HISTORY.lock(|history| history.borrow().get(seq)) {
+            let mut row = String::<48>::new();
+            write!(&mut row, "{},{:.1},{:.1},", sample.secs_since_boot, sample.temperature(), sample.humidity())
+                .map_err(|_| Error::Truncated)?;
+            if let Some(timestamp) = history::timestamp(&sample) {
+                write!(&mut row, "{}", timestamp).map_err(|_| Error::Truncated)?;
+            }
+            row.push_str("\r\n").map_err(|_| Error::Truncated)?;
+            socket.write_all(row.as_bytes()).await.map_err(Error::Write)?;
+            sent.bytes += row.len();
+        }
+        seq = seq.wrapping_add(1);
+    }
+
+    Ok(sent)
+}
+
+/// Server-Sent Events stream of new readings, only returns once the client goes away
+async fn serve_events(socket: &mut TcpSocket<'_>, request: &Request<'_>, task: Subsystem) -> Result<Sent, Error> {
+    let Some(mut readings) = READINGS.receiver() else {
+        return send(socket, Framing::of(request), Response::text(Status::ServiceUnavailable), b"Too many event streams").await;
+    };
+
+    // The stream never ends by itself so the connection is not reused afterwards
+    let framing = Framing { keep_alive: false, ..Framing::of(request) };
+    let response = Response::new(Status::Ok)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache");
+    send_head(socket, framing, response).await?;
+
+    loop {
+        // Every frame is bounded by the keep-alive interval and the write timeout, however long the stream runs
+        watchdog::check_in(task);
+        let mut frame = String::<96>::new();
+
+        match select(readings.changed(), Timer::after(SSE_KEEPALIVE_INTERVAL)).await {
+            Either::First(reading) => {
+                write!(&mut frame,
+                    "data: {{\"temperature\": {:.1}, \"humidity\": {:.1}}}\n\n",
+                    reading.temperature,
+                    reading.humidity)
+                    .map_err(|_| Error::Truncated)?;
+            },
+            // Comment line so proxies don't drop an idle stream
+            Either::Second(_) => frame.push_str(": keepalive\n\n").map_err(|_| Error::Truncated)?,
+        }
+
+        // A failed write means the client is gone, the error releases the socket
+        socket.write_all(frame.as_bytes()).await.map_err(Error::Write)?;
+        socket.flush().await.map_err(Error::Write)?;
+    }
+}
+
+/// Connection level properties of a response, derived from the request it answers
+#[derive(Clone, Copy)]
+struct Framing {
+    version: Version,
+    head_only: bool,
+    keep_alive: bool,
+    cors: bool,
+}
+
+impl Framing {
+    /// Used when there is no parsed request to derive the framing from
+    const CLOSE: Self = Self { version: Version::Http11, head_only: false, keep_alive: false, cors: false };
+
+    fn of(request: &Request) -> Self {
+        Self {
+            version: request.version,
+            head_only: request.method == Method::Head,
+            // An unread rest of the body would be taken for the next request
+            keep_alive: request.keep_alive && request.body_complete(),
+            cors: request.path.starts_with("/api/"),
+        }
+    }
+}
+
+/// Send `response` with `body`, which is skipped for HEAD
+async fn send(socket: &mut TcpSocket<'_>, framing: Framing, response: Response<'_>, body: &[u8]) -> Result<Sent, Error> {
+    let mut sent = send_head(socket, framing, response.body_len(body.len())).await?;
+
+    // Content-Length above already describes the body a GET would have received
+    if framing.head_only {
+        return Ok(sent);
+    }
+
+    for chunk in body.chunks(CHUNK_SIZE) {
+        socket.write_all(chunk).await.map_err(Error::Write)?;
+    }
+    sent.bytes += body.len();
+
+    Ok(sent)
+}
+
+/// Write the status line and headers completed with the connection level ones
+async fn send_head(socket: &mut TcpSocket<'_>, framing: Framing, response: Response<'_>) -> Result<Sent, Error> {
+    let keep_alive = response.keeps_alive(framing.keep_alive);
+    let mut response = response.header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+    if framing.cors {
+        response = response.header("Access-Control-Allow-Origin", CORS_ORIGIN);
+    }
+
+    let head = response.head(framing.version).ok_or(Error::Overflow)?;
+    socket.write_all(head.as_bytes()).await.map_err(Error::Write)?;
+
+    Ok(Sent { status: response.status().code(), bytes: head.len(), close: !keep_alive })
+}
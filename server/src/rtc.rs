@@ -0,0 +1,80 @@
+use {
+    core::cell::Cell,
+    embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+};
+#[cfg(feature = "rtc")]
+use {
+    core::iter,
+    embassy_rp::i2c,
+    crate::{
+        board::I2cBus,
+        ds3231::{self, ADDRESS, END_UNIX, FIRST_UNIX, OSCILLATOR_STOPPED, STATUS_REGISTER, TIME_REGISTER},
+        sntp::{self, TimeSource, CLOCK_SET},
+    },
+};
+
+/// Whether the last transfer with the DS3231 worked, `None` before the first one or without the `rtc` feature
+static HEALTHY: Mutex<CriticalSectionRawMutex, Cell<Option<bool>>> = Mutex::new(Cell::new(None));
+
+pub fn healthy() -> Option<bool> {
+    HEALTHY.lock(Cell::get)
+}
+
+#[cfg(feature = "rtc")]
+fn track<T>(result: Result<T, i2c::Error>) -> Result<T, i2c::Error> {
+    HEALTHY.lock(|healthy| healthy.set(Some(result.is_ok())));
+    result
+}
+
+/// The chip's time, `None` when its oscillator stopped since it was last set or the registers hold no valid time
+#[cfg(feature = "rtc")]
+async fn read_time(bus: &I2cBus) -> Result<Option<u64>, i2c::Error> {
+    let mut i2c = bus.lock().await;
+    let mut status = [0];
+    track(i2c.write_read_async(ADDRESS, iter::once(STATUS_REGISTER), &mut status).await)?;
+    if status[0] & OSCILLATOR_STOPPED != 0 {
+        return Ok(None);
+    }
+
+    let mut registers = [0; 7];
+    track(i2c.write_read_async(ADDRESS, iter::once(TIME_REGISTER), &mut registers).await)?;
+    Ok(ds3231::decode(&registers))
+}
+
+/// Writes the time and then clears the oscillator stop flag, so a time cut short by a reset still reads as lost
+#[cfg(feature = "rtc")]
+async fn write_time(bus: &I2cBus, unix: u64) -> Result<(), i2c::Error> {
+    let mut i2c = bus.lock().await;
+    track(i2c.write_async(ADDRESS, iter::once(TIME_REGISTER).chain(ds3231::encode(unix))).await)?;
+
+    let mut status = [0];
+    track(i2c.write_read_async(ADDRESS, iter::once(STATUS_REGISTER), &mut status).await)?;
+    track(i2c.write_async(ADDRESS, [STATUS_REGISTER, status[0] & !OSCILLATOR_STOPPED]).await)
+}
+
+/// Sets the clock from the DS3231 at boot, unless something set it first, and writes the time back whenever NTP or
+/// `POST /api/time` sets it. Without the chip answering the timestamps wait for one of those, the task keeps
+/// trying on every write.
+#[cfg(feature = "rtc")]
+#[embassy_executor::task]
+pub async fn rtc_task(bus: &'static I2cBus) -> ! {
+    match read_time(bus).await {
+        Ok(Some(unix)) if sntp::time_source() == TimeSource::Uptime => sntp::set_clock(unix, TimeSource::Rtc),
+        Ok(Some(_)) => {},
+        Ok(None) => log::warn!("The RTC lost its time, it is set again once NTP or POST /api/time sets the clock"),
+        Err(e) => log::warn!("No RTC answering at {:#04x} on I2C0 ({:?}), timestamps wait for NTP", ADDRESS, e),
+    }
+
+    loop {
+        if !matches!(CLOCK_SET.wait().await, TimeSource::Ntp | TimeSource::Manual) {
+            continue;
+        }
+        let Some(now) = sntp::now_unix().filter(|now| (FIRST_UNIX..END_UNIX).contains(now)) else {
+            continue;
+        };
+        match write_time(bus, now).await {
+            Ok(()) => log::debug!("RTC set to {}", sntp::Iso8601(now)),
+            Err(e) => log::warn!("Unable to set the RTC: {:?}", e),
+        }
+    }
+}
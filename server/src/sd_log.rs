@@ -0,0 +1,219 @@
+use {
+    core::{cell::RefCell, fmt::Write, mem},
+    embassy_rp::gpio::{AnyPin, Level, Output},
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    embassy_time::{Delay, Instant},
+    embedded_hal_bus::spi::ExclusiveDevice,
+    embedded_sdmmc::{sdcard::DummyCsPin, BlockDevice, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager},
+    heapless::{String, Vec},
+    server_core::history::Sample,
+    crate::{
+        board::SdSpi,
+        calendar::civil_from_days,
+        config::{SD_CS_PIN, SD_FLUSH_EVERY},
+        history,
+        sntp,
+    },
+};
+
+/// In the root directory of the card's first FAT partition, created with the header when it doesn't exist
+pub const FILE_NAME: &str = "DHT22.CSV";
+/// Cards only answer their initialization at up to 400 kHz
+const INIT_FREQUENCY_HZ: u32 = 400_000;
+const FREQUENCY_HZ: u32 = 16_000_000;
+/// The columns of `/history.csv`
+const HEADER: &[u8] = b"timestamp_s,temperature_c,humidity_pct,unix_time\r\n";
+/// Longest row, a negative temperature and a Unix time included
+const LINE_SIZE: usize = 48;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogState {
+    /// No card configured
+    Off,
+    Starting,
+    Logging,
+    /// Stopped after an error until the next boot, the card may have been removed
+    Failed,
+}
+
+impl LogState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogState::Off => "off",
+            LogState::Starting => "starting",
+            LogState::Logging => "logging",
+            LogState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LogStatus {
+    pub state: LogState,
+    /// Why the logging stopped
+    pub error: Option<&'static str>,
+    /// Size of the file after the last write, `None` before the card was read
+    pub file_size: Option<u32>,
+    /// Seconds since boot of the last write
+    pub last_write: Option<u32>,
+    /// Samples waiting for the next write
+    pub buffered: usize,
+}
+
+struct Logger {
+    status: LogStatus,
+    buffer: Vec<Sample, SD_FLUSH_EVERY>,
+}
+
+static LOGGER: Mutex<CriticalSectionRawMutex, RefCell<Logger>> = Mutex::new(RefCell::new(Logger {
+    status: LogStatus { state: LogState::Off, error: None, file_size: None, last_write: None, buffered: 0 },
+    buffer: Vec::new(),
+}));
+/// Raised once `SD_FLUSH_EVERY` samples are waiting
+static FLUSH: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn status() -> LogStatus {
+    LOGGER.lock(|logger| {
+        let logger = logger.borrow();
+        LogStatus { buffered: logger.buffer.len(), ..logger.status }
+    })
+}
+
+fn update(update: impl FnOnce(&mut LogStatus)) {
+    LOGGER.lock(|logger| update(&mut logger.borrow_mut().status));
+}
+
+/// Queues a sample of the primary sensor, they are written `SD_FLUSH_EVERY` at a time so the card sees one write
+/// where it would otherwise see many. Samples are dropped while the card isn't logging.
+pub fn add(sample: &Sample) {
+    let full = LOGGER.lock(|logger| {
+        let mut logger = logger.borrow_mut();
+        // A full buffer is still being written, the sample doesn't fit in
+        logger.status.state == LogState::Logging && (logger.buffer.push(*sample).is_err() || logger.buffer.is_full())
+    });
+    if full {
+        FLUSH.signal(());
+    }
+}
+
+/// The file's modification times, 1980-01-01 while the clock isn't set
+struct Clock;
+
+impl TimeSource for Clock {
+    fn get_timestamp(&self) -> Timestamp {
+        let Some(now) = sntp::now_unix() else {
+            return Timestamp { year_since_1970: 10, zero_indexed_month: 0, zero_indexed_day: 0, hours: 0, minutes: 0, seconds: 0 };
+        };
+        let (days, secs) = (now / 86_400, now % 86_400);
+        let (year, month, day) = civil_from_days(days);
+        Timestamp {
+            year_since_1970: (year - 1970) as u8,
+            zero_indexed_month: (month - 1) as u8,
+            zero_indexed_day: (day - 1) as u8,
+            hours: (secs / 3600) as u8,
+            minutes: (secs / 60 % 60) as u8,
+            seconds: (secs % 60) as u8,
+        }
+    }
+}
+
+/// Opens the file for every write and closes it right after, the directory entry then always holds the size and a
+/// card pulled between two writes loses nothing written before. Returns the new size.
+fn append<D: BlockDevice, T: TimeSource>(volumes: &mut VolumeManager<D, T>, bytes: &[u8]) -> Result<u32, embedded_sdmmc::Error<D::Error>> {
+    let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+    let directory = volumes.open_root_dir(volume)?;
+    let file = volumes.open_file_in_dir(directory, FILE_NAME, Mode::ReadWriteCreateOrAppend)?;
+    if volumes.file_length(file)? == 0 {
+        volumes.write(file, HEADER)?;
+    }
+    if !bytes.is_empty() {
+        volumes.write(file, bytes)?;
+    }
+    let size = volumes.file_length(file)?;
+    volumes.close_file(file)?;
+    volumes.close_dir(directory)?;
+    volumes.close_volume(volume)?;
+    Ok(size)
+}
+
+fn describe<E: core::fmt::Debug>(error: &embedded_sdmmc::Error<E>) -> &'static str {
+    match error {
+        embedded_sdmmc::Error::DeviceError(_) => "card not answering",
+        embedded_sdmmc::Error::FormatError(_) | embedded_sdmmc::Error::NoSuchVolume => "no FAT volume on the card",
+        embedded_sdmmc::Error::NotEnoughSpace => "card full",
+        _ => "file system error",
+    }
+}
+
+fn fail(error: &'static str) {
+    LOGGER.lock(|logger| {
+        let mut logger = logger.borrow_mut();
+        logger.status.state = LogState::Failed;
+        logger.status.error = Some(error);
+        logger.buffer.clear();
+    });
+}
+
+/// Appends the primary sensor's samples to `FILE_NAME` on the SD card. The card and the file system are driven in
+/// blocking mode, an append of `SD_FLUSH_EVERY` rows holds up the other tasks for a few milliseconds. Any error ends
+/// the logging until the next boot, it is logged and shown in `/api/status` and `/api/logging`.
+#[embassy_executor::task]
+pub async fn sd_task(mut spi: SdSpi, cs: AnyPin) {
+    update(|status| status.state = LogState::Starting);
+    spi.set_frequency(INIT_FREQUENCY_HZ);
+    let card = SdCard::new(ExclusiveDevice::new(spi, DummyCsPin, Delay), Output::new(cs, Level::High), Delay);
+    match card.num_bytes() {
+        Ok(bytes) => log::info!("SD card of {} MB", bytes / 1_000_000),
+        Err(e) => {
+            log::warn!("No SD card answering on GPIO{} ({:?}), logging to it is off", SD_CS_PIN.unwrap_or(0), e);
+            fail("no card answering");
+            return;
+        },
+    }
+    card.spi(|device| device.bus_mut().set_frequency(FREQUENCY_HZ));
+
+    let mut volumes = VolumeManager::new(card, Clock);
+    match append(&mut volumes, &[]) {
+        Ok(size) => {
+            log::info!("Logging to {} on the SD card, {} bytes so far", FILE_NAME, size);
+            update(|status| {
+                status.state = LogState::Logging;
+                status.file_size = Some(size);
+            });
+        },
+        Err(e) => {
+            log::warn!("Unable to open {} on the SD card ({:?}), logging to it is off", FILE_NAME, e);
+            fail(describe(&e));
+            return;
+        },
+    }
+
+    loop {
+        FLUSH.wait().await;
+        let samples = LOGGER.lock(|logger| mem::take(&mut logger.borrow_mut().buffer));
+
+        let mut rows = String::<{ LINE_SIZE * SD_FLUSH_EVERY }>::new();
+        for sample in &samples {
+            let _ = write!(rows, "{},{:.1},{:.1},", sample.secs_since_boot, sample.temperature(), sample.humidity());
+            if let Some(timestamp) = history::timestamp(sample) {
+                let _ = write!(rows, "{}", timestamp);
+            }
+            let _ = rows.push_str("\r\n");
+        }
+
+        match append(&mut volumes, rows.as_bytes()) {
+            Ok(size) => update(|status| {
+                status.file_size = Some(size);
+                status.last_write = Some(Instant::now().as_secs() as u32);
+            }),
+            Err(e) => {
+                log::error!("Writing to {} on the SD card failed ({:?}), logging to it is off", FILE_NAME, e);
+                fail(describe(&e));
+                return;
+            },
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use {
+    core::cell::Cell,
+    embassy_futures::select::select,
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+        watch::Watch,
+    },
+    embassy_time::{with_timeout, Duration, Instant, Timer},
+    embassy_rp::{
+        adc::{self, Adc, Async},
+        gpio::AnyPin,
+    },
+    heapless::Deque,
+    server_core::history::Sample,
+    crate::{
+        alert,
+        daily,
+        fan,
+        sd_log,
+        servo,
+        derived::{Trend, HUMID_TREND_DEADBAND, TEMP_TREND_DEADBAND},
+        history::HISTORY,
+        reading::{Calibration, Conditioned, Ema, ReadSensor, Reading, Rejection, SensorModel, SpikeFilter, SpikeLimits, ThrottledSensor},
+        config::DHT_PINS,
+        watchdog::{self, Subsystem},
+        HTTP_TASKS,
+    },
+};
+#[cfg(feature = "pico-w")]
+use embassy_dht_sensor::DHTSensorError;
+#[cfg(not(feature = "sim-sensor"))]
+use {embassy_dht_sensor::DHTSensor, embassy_rp::gpio::Flex};
+
+// The DHT driver is built against the RP2040 only
+#[cfg(all(feature = "pico2-w", not(feature = "sim-sensor")))]
+compile_error!("The DHT driver doesn't support the RP2350 yet, build the `pico2-w` board with `sim-sensor`");
+#[cfg(feature = "sim-sensor")]
+use {embassy_rp::clocks::RoscRng, rand::RngCore};
+
+/// One sensor per entry of `DHT_PINS`, indexed by position
+pub const SENSOR_COUNT: usize = DHT_PINS.len();
+/// The sensor whose readings are recorded in the history and pushed over `/events`
+pub const PRIMARY_SENSOR: usize = 0;
+/// Used until `SAMPLE_INTERVAL_S` or `/api/config` sets another one
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// The DHT22 datasheet requires at least 2 s between two reads, the DHT11 1 s
+pub const MIN_READ_INTERVAL: Duration = Duration::from_secs(2);
+/// Weight of a new raw reading in the exponential moving average
+pub const EMA_ALPHA: f32 = 0.3;
+/// After a gap this long without good readings the average restarts at the next raw value
+pub const EMA_RESET_GAP: Duration = Duration::from_secs(60);
+/// Age after which the last good reading is no longer served and the sensor counts as failed
+pub const STALENESS_LIMIT: Duration = Duration::from_secs(5 * 60);
+/// Attempts per sample before the read counts as failed
+pub const READ_ATTEMPTS: u32 = 3;
+pub const RETRY_DELAY: Duration = Duration::from_millis(2500);
+/// Period the trend of the primary sensor is taken over
+pub const TREND_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Consecutive rejected jumps after which the new level is taken as genuine
+pub const SPIKE_ACCEPT_AFTER: u32 = 3;
+/// Chip temperature samples averaged, one is taken per sample interval
+pub const CHIP_TEMP_WINDOW: usize = 8;
+
+/// Sensor type the firmware is built for, selected with the `dht22` or `dht11` feature
+pub const SENSOR_MODEL: SensorModel = if cfg!(feature = "dht11") { SensorModel::Dht11 } else { SensorModel::Dht22 };
+
+static CALIBRATION: Mutex<CriticalSectionRawMutex, Cell<Calibration>> = Mutex::new(Cell::new(Calibration::NONE));
+
+pub fn calibration() -> Calibration {
+    CALIBRATION.lock(Cell::get)
+}
+
+/// Takes effect from the next reading on
+pub fn set_calibration(calibration: Calibration) {
+    CALIBRATION.lock(|current| current.set(calibration));
+}
+
+static SAMPLE_INTERVAL: Mutex<CriticalSectionRawMutex, Cell<Duration>> = Mutex::new(Cell::new(DEFAULT_SAMPLE_INTERVAL));
+/// Wakes the sensor task early, so a new interval doesn't wait out the old one or for a sample on request
+static WAKE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Raised after every round over the sensors, only `sample_now` waits for it
+static SAMPLED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn sample_interval() -> Duration {
+    SAMPLE_INTERVAL.lock(Cell::get)
+}
+
+/// Never shorter than `MIN_READ_INTERVAL`, returns the interval actually used.
+/// The sensor task samples right away and then waits the new interval.
+pub fn set_sample_interval(interval: Duration) -> Duration {
+    let interval = interval.max(MIN_READ_INTERVAL);
+    SAMPLE_INTERVAL.lock(|current| current.set(interval));
+    WAKE.signal(());
+    interval
+}
+
+/// Sample every sensor right away and wait until the round is done, `false` if it took longer than `timeout`.
+/// A sensor read within `MIN_READ_INTERVAL` answers with that reading again.
+pub async fn sample_now(timeout: Duration) -> bool {
+    SAMPLED.reset();
+    WAKE.signal(());
+    with_timeout(timeout, SAMPLED.wait()).await.is_ok()
+}
+
+/// Why a read failed. The driver reports neither the checksum bytes nor pin faults, a GPIO can't fail on the RP2040.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SensorError {
+    /// The sensor didn't answer or stopped halfway, usually wiring or a missing pull-up
+    Timeout,
+    /// The data arrived corrupted, usually electrical noise or a long cable
+    ChecksumMismatch,
+    /// Valid checksum but an impossible value
+    InvalidData,
+}
+
+impl SensorError {
+    /// Short name for the JSON and metrics labels
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SensorError::Timeout => "timeout",
+            SensorError::ChecksumMismatch => "checksum_mismatch",
+            SensorError::InvalidData => "invalid_data",
+        }
+    }
+
+    /// Explanation for the page
+    pub fn describe(self) -> &'static str {
+        match self {
+            SensorError::Timeout => "no response from sensor, check wiring",
+            SensorError::ChecksumMismatch => "corrupted data from sensor, check for electrical noise",
+            SensorError::InvalidData => "sensor sent impossible values",
+        }
+    }
+}
+
+#[cfg(feature = "pico-w")]
+impl From<DHTSensorError> for SensorError {
+    fn from(error: DHTSensorError) -> Self {
+        match error {
+            DHTSensorError::Timeout => SensorError::Timeout,
+            DHTSensorError::ChecksumError => SensorError::ChecksumMismatch,
+            DHTSensorError::InvalidData => SensorError::InvalidData,
+        }
+    }
+}
+
+/// The DHT driver, wrapped so it can take the `ReadSensor` of server-core
+#[cfg(not(feature = "sim-sensor"))]
+pub struct Dht(DHTSensor<'static>);
+
+#[cfg(not(feature = "sim-sensor"))]
+impl ReadSensor for Dht {
+    type Error = SensorError;
+
+    fn read(&mut self) -> Result<Reading, Self::Error> {
+        // The driver's DHT11 path adds the integral and decimal bytes and divides them by ten like the
+        // 16-bit DHT22 values, undo the division to get whole degrees and percents
+        let scale = match SENSOR_MODEL {
+            SensorModel::Dht22 => 1.0,
+            SensorModel::Dht11 => 10.0,
+        };
+
+        self.0.read()
+            .map(|data| Reading { temperature: data.temperature * scale, humidity: data.humidity * scale })
+            .map_err(SensorError::from)
+    }
+}
+
+/// What the sensor task owns per entry of `DHT_PINS`
+#[cfg(not(feature = "sim-sensor"))]
+pub type Sensor = Dht;
+#[cfg(feature = "sim-sensor")]
+pub type Sensor = SimulatedSensor;
+
+/// Set by the `sim-sensor` feature, readings are then made up and the DHT pins left alone
+pub const SIMULATED: bool = cfg!(feature = "sim-sensor");
+
+/// One sensor per entry of `DHT_PINS`
+#[cfg(not(feature = "sim-sensor"))]
+pub fn new_sensors(pins: [AnyPin; SENSOR_COUNT]) -> [Sensor; SENSOR_COUNT] {
+    pins.map(|pin| Dht(DHTSensor::new(Flex::new(pin))))
+}
+
+/// One simulated sensor per entry of `DHT_PINS`, the pins themselves are never touched
+#[cfg(feature = "sim-sensor")]
+pub fn new_sensors(_pins: [AnyPin; SENSOR_COUNT]) -> [Sensor; SENSOR_COUNT] {
+    core::array::from_fn(SimulatedSensor::new)
+}
+
+/// Center and amplitude of the simulated temperature in °C and humidity in %, humidity falls as it gets warmer
+#[cfg(feature = "sim-sensor")]
+const SIM_TEMPERATURE: (f32, f32) = (22.0, 2.0);
+#[cfg(feature = "sim-sensor")]
+const SIM_HUMIDITY: (f32, f32) = (50.0, 8.0);
+/// One swing takes this long, short enough to see the trend and the history move within minutes
+#[cfg(feature = "sim-sensor")]
+const SIM_PERIOD_S: f32 = 30.0 * 60.0;
+/// Largest random deviation of a reading from the curve
+#[cfg(feature = "sim-sensor")]
+const SIM_NOISE: f32 = 0.2;
+/// Chance that a simulated read fails, so the retries and error paths get exercised too
+#[cfg(feature = "sim-sensor")]
+const SIM_ERROR_PROBABILITY: f32 = 0.05;
+
+/// A slow sine wave with some noise and the occasional failed read, in the resolution of `SENSOR_MODEL`
+#[cfg(feature = "sim-sensor")]
+pub struct SimulatedSensor {
+    /// Every sensor is a bit ahead of the one before
+    phase: f32,
+}
+
+#[cfg(feature = "sim-sensor")]
+impl SimulatedSensor {
+    pub fn new(id: usize) -> Self {
+        Self { phase: id as f32 * 0.5 }
+    }
+}
+
+#[cfg(feature = "sim-sensor")]
+impl ReadSensor for SimulatedSensor {
+    type Error = SensorError;
+
+    fn read(&mut self) -> Result<Reading, Self::Error> {
+        let mut rng = RoscRng;
+        let unit = |rng: &mut RoscRng| rng.next_u32() as f32 / u32::MAX as f32;
+
+        if unit(&mut rng) < SIM_ERROR_PROBABILITY {
+            return Err(match rng.next_u32() % 3 {
+                0 => SensorError::Timeout,
+                1 => SensorError::ChecksumMismatch,
+                _ => SensorError::InvalidData,
+            });
+        }
+
+        let secs = Instant::now().as_millis() as f32 / 1000.0;
+        let wave = libm::sinf(2.0 * core::f32::consts::PI * secs / SIM_PERIOD_S + self.phase);
+        let resolution = libm::powf(10.0, SENSOR_MODEL.decimals() as f32);
+        let quantize = |value: f32| libm::roundf(value * resolution) / resolution;
+        let mut noise = || (unit(&mut rng) * 2.0 - 1.0) * SIM_NOISE;
+
+        Ok(Reading {
+            temperature: quantize(SIM_TEMPERATURE.0 + SIM_TEMPERATURE.1 * wave + noise()),
+            humidity: quantize(SIM_HUMIDITY.0 - SIM_HUMIDITY.1 * wave + noise()),
+        })
+    }
+}
+
+/// One receiver per HTTP task, one for the MQTT publisher and one for the display
+pub const READING_RECEIVERS: usize = HTTP_TASKS + 1 + cfg!(feature = "oled") as usize;
+/// Latest reading of the primary sensor published by the sensor task
+pub static READINGS: Watch<CriticalSectionRawMutex, Reading, READING_RECEIVERS> = Watch::new();
+
+/// Good reading as measured and after smoothing
+#[derive(Clone, Copy)]
+struct Measurement {
+    raw: Reading,
+    smoothed: Reading,
+    taken: Instant,
+}
+
+/// Outcome of the latest reads, written by the sensor task only
+#[derive(Clone, Copy)]
+struct SensorSnapshot {
+    last: Option<Measurement>,
+    /// Failed reads since the last good one
+    failures: u32,
+    /// Cause of the latest failed read, cleared by a good one
+    error: Option<SensorError>,
+}
+
+static SNAPSHOTS: Mutex<CriticalSectionRawMutex, Cell<[SensorSnapshot; SENSOR_COUNT]>> =
+    Mutex::new(Cell::new([SensorSnapshot { last: None, failures: 0, error: None }; SENSOR_COUNT]));
+
+/// What the handlers can show for the sensor
+pub enum Status {
+    /// No good reading yet or the last one is older than `STALENESS_LIMIT`
+    NotReady,
+    /// `reading` is the smoothed value of `raw`, `stale` is set when the latest reads failed and they are older ones
+    Ready { reading: Reading, raw: Reading, age: Duration, stale: bool },
+}
+
+/// Current state of sensor `id` for the handlers, the pins themselves are only touched by the sensor task
+pub fn status(id: usize) -> Status {
+    let snapshot = SNAPSHOTS.lock(Cell::get)[id];
+
+    match snapshot.last {
+        Some(last) if last.taken.elapsed() <= STALENESS_LIMIT => Status::Ready {
+            reading: last.smoothed,
+            raw: last.raw,
+            age: last.taken.elapsed(),
+            stale: snapshot.failures > 0,
+        },
+        _ => Status::NotReady,
+    }
+}
+
+/// Reads failed since the last good one of sensor `id` and the cause of the latest, `None` while it reads fine
+pub fn last_error(id: usize) -> Option<(SensorError, u32)> {
+    let snapshot = SNAPSHOTS.lock(Cell::get)[id];
+    snapshot.error.map(|error| (error, snapshot.failures))
+}
+
+/// Running minimum and maximum of the good readings since boot or the last reset. The primary sensor's start again
+/// from the stored hours of the day once the clock is synced after a reboot.
+#[derive(Clone, Copy)]
+pub struct Extremes {
+    pub temperature_min: f32,
+    pub temperature_max: f32,
+    pub humidity_min: f32,
+    pub humidity_max: f32,
+    pub count: u32,
+}
+
+impl Extremes {
+    /// Starts at the first reading so the initial 0 never counts as a minimum or maximum
+    fn first(reading: &Reading) -> Self {
+        Self {
+            temperature_min: reading.temperature,
+            temperature_max: reading.temperature,
+            humidity_min: reading.humidity,
+            humidity_max: reading.humidity,
+            count: 1,
+        }
+    }
+
+    fn update(&mut self, reading: &Reading) {
+        self.temperature_min = self.temperature_min.min(reading.temperature);
+        self.temperature_max = self.temperature_max.max(reading.temperature);
+        self.humidity_min = self.humidity_min.min(reading.humidity);
+        self.humidity_max = self.humidity_max.max(reading.humidity);
+        self.count = self.count.saturating_add(1);
+    }
+
+    fn merge(&mut self, other: &Extremes) {
+        self.temperature_min = self.temperature_min.min(other.temperature_min);
+        self.temperature_max = self.temperature_max.max(other.temperature_max);
+        self.humidity_min = self.humidity_min.min(other.humidity_min);
+        self.humidity_max = self.humidity_max.max(other.humidity_max);
+        self.count = self.count.saturating_add(other.count);
+    }
+}
+
+/// Failed reads leave them untouched, `None` until the first good reading after boot or a reset
+static EXTREMES: Mutex<CriticalSectionRawMutex, Cell<[Option<Extremes>; SENSOR_COUNT]>> =
+    Mutex::new(Cell::new([None; SENSOR_COUNT]));
+
+pub fn extremes(id: usize) -> Option<Extremes> {
+    EXTREMES.lock(Cell::get)[id]
+}
+
+/// Resets every sensor
+pub fn reset_extremes() {
+    EXTREMES.lock(|extremes| extremes.set([None; SENSOR_COUNT]));
+}
+
+/// Folds in readings from before the boot
+pub fn merge_extremes(id: usize, other: Extremes) {
+    EXTREMES.lock(|extremes| {
+        let mut all = extremes.get();
+        all[id] = match all[id] {
+            Some(mut current) => {
+                current.merge(&other);
+                Some(current)
+            },
+            None => Some(other),
+        };
+        extremes.set(all);
+    });
+}
+
+fn update_extremes(id: usize, reading: &Reading) {
+    EXTREMES.lock(|extremes| {
+        let mut all = extremes.get();
+        all[id] = match all[id] {
+            Some(mut current) => {
+                current.update(reading);
+                Some(current)
+            },
+            None => Some(Extremes::first(reading)),
+        };
+        extremes.set(all);
+    });
+}
+
+/// Failure counters of the sensor task
+#[derive(Clone, Copy)]
+pub struct SensorStats {
+    /// Samples that succeeded only after a retry
+    pub transient_failures: u32,
+    /// Samples where every attempt failed
+    pub persistent_failures: u32,
+    /// Readings dropped by the spike filter for being outside the sensor's range
+    pub rejected_out_of_range: u32,
+    /// Readings dropped by the spike filter for jumping too far from the previous one
+    pub rejected_jumps: u32,
+    /// Failed read attempts by cause, retries included
+    pub timeouts: u32,
+    pub checksum_mismatches: u32,
+    pub invalid_data: u32,
+}
+
+static STATS: Mutex<CriticalSectionRawMutex, Cell<SensorStats>> = Mutex::new(Cell::new(SensorStats {
+    transient_failures: 0,
+    persistent_failures: 0,
+    rejected_out_of_range: 0,
+    rejected_jumps: 0,
+    timeouts: 0,
+    checksum_mismatches: 0,
+    invalid_data: 0,
+}));
+
+pub fn stats() -> SensorStats {
+    STATS.lock(Cell::get)
+}
+
+fn update_stats(update: impl FnOnce(&mut SensorStats)) {
+    STATS.lock(|stats| {
+        let mut current = stats.get();
+        update(&mut current);
+        stats.set(current);
+    });
+}
+
+fn count_error(error: SensorError) {
+    update_stats(|stats| {
+        let counter = match error {
+            SensorError::Timeout => &mut stats.timeouts,
+            SensorError::ChecksumMismatch => &mut stats.checksum_mismatches,
+            SensorError::InvalidData => &mut stats.invalid_data,
+        };
+        *counter = counter.saturating_add(1);
+    });
+}
+
+fn record(id: usize, measurement: Result<Measurement, SensorError>) {
+    SNAPSHOTS.lock(|snapshots| {
+        let mut all = snapshots.get();
+        match measurement {
+            Ok(last) => all[id] = SensorSnapshot { last: Some(last), failures: 0, error: None },
+            Err(error) => {
+                all[id].failures = all[id].failures.saturating_add(1);
+                all[id].error = Some(error);
+            },
+        }
+        snapshots.set(all);
+    });
+}
+
+/// Direction of the primary sensor's readings over `TREND_WINDOW`, steady until the history covers it
+#[derive(Clone, Copy)]
+pub struct Trends {
+    pub temperature: Trend,
+    pub humidity: Trend,
+}
+
+static TRENDS: Mutex<CriticalSectionRawMutex, Cell<Trends>> =
+    Mutex::new(Cell::new(Trends { temperature: Trend::Steady, humidity: Trend::Steady }));
+
+pub fn trends() -> Trends {
+    TRENDS.lock(Cell::get)
+}
+
+fn update_trends() {
+    let change = HISTORY.lock(|history| history.borrow().change_over(TREND_WINDOW.as_secs() as u32));
+    let trends = match change {
+        Some((temperature, humidity)) => Trends {
+            temperature: Trend::classify(temperature, TEMP_TREND_DEADBAND),
+            humidity: Trend::classify(humidity, HUMID_TREND_DEADBAND),
+        },
+        None => Trends { temperature: Trend::Steady, humidity: Trend::Steady },
+    };
+    TRENDS.lock(|current| current.set(trends));
+}
+
+/// The RP2040's internal temperature sensor on ADC channel 4, averaged over the last `CHIP_TEMP_WINDOW` samples
+/// because a single conversion is noisy
+pub struct ChipSensor {
+    adc: Adc<'static, Async>,
+    channel: adc::Channel<'static>,
+    samples: Deque<f32, CHIP_TEMP_WINDOW>,
+}
+
+impl ChipSensor {
+    pub fn new(adc: Adc<'static, Async>, channel: adc::Channel<'static>) -> Self {
+        Self { adc, channel, samples: Deque::new() }
+    }
+
+    /// Take a sample and get the average in °C
+    async fn read(&mut self) -> Result<f32, adc::Error> {
+        let raw = self.adc.read(&mut self.channel).await?;
+
+        // RP2040 datasheet: 0.706 V at 27 °C, -1.721 mV per degree, 12 bit conversion of 3.3 V
+        let voltage = raw as f32 * 3.3 / 4096.0;
+        let celsius = 27.0 - (voltage - 0.706) / 0.001721;
+
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        let _ = self.samples.push_back(celsius);
+
+        Ok(self.samples.iter().sum::<f32>() / self.samples.len() as f32)
+    }
+}
+
+static CHIP_TEMPERATURE: Mutex<CriticalSectionRawMutex, Cell<Option<f32>>> = Mutex::new(Cell::new(None));
+
+/// Averaged chip temperature in °C, `None` until the first conversion
+pub fn chip_temperature() -> Option<f32> {
+    CHIP_TEMPERATURE.lock(Cell::get)
+}
+
+/// Per sensor state of the sensor task
+struct Channel<T> {
+    sensor: ThrottledSensor<T>,
+    filter: SpikeFilter,
+    ema: Ema,
+    published: Option<Instant>,
+}
+
+/// Owns the sensors and samples them one after the other every `sample_interval()`, never below their 2 s minimum
+/// together with the chip temperature
+#[embassy_executor::task]
+pub async fn sensor_task(sensors: [Sensor; SENSOR_COUNT], mut chip: ChipSensor, spike_limits: SpikeLimits) -> ! {
+    let sender = READINGS.sender();
+    let mut channels = sensors.map(|sensor| Channel {
+        sensor: ThrottledSensor::new(sensor, MIN_READ_INTERVAL.as_millis()),
+        filter: SpikeFilter::new(SENSOR_MODEL, spike_limits, SPIKE_ACCEPT_AFTER, EMA_RESET_GAP.as_millis()),
+        ema: Ema::new(EMA_ALPHA, EMA_RESET_GAP.as_millis()),
+        published: None,
+    });
+
+    loop {
+        watchdog::check_in(Subsystem::Sensor);
+        for (id, channel) in channels.iter_mut().enumerate() {
+            match read_with_retry(&mut channel.sensor).await {
+                // A cached value is already recorded and published
+                Ok((_, taken)) if channel.published == Some(taken) => {},
+                Ok((reading, taken)) => {
+                    channel.published = Some(taken);
+                    // A soft error: counted and logged, the last good reading stays as it is
+                    match channel.filter.check(reading, taken.as_millis()) {
+                        Ok(()) => {},
+                        Err(Rejection::OutOfRange) => {
+                            update_stats(|stats| stats.rejected_out_of_range = stats.rejected_out_of_range.saturating_add(1));
+                            log::warn!("Sensor {} reads {:.1} °C {:.1} %, outside the {} range, is the right sensor type configured?",
+                                id, reading.temperature, reading.humidity, SENSOR_MODEL.name());
+                            continue;
+                        },
+                        Err(Rejection::Jump) => {
+                            update_stats(|stats| stats.rejected_jumps = stats.rejected_jumps.saturating_add(1));
+                            log::warn!("Sensor {} rejected implausible jump to {:.1} °C {:.1} %",
+                                id, reading.temperature, reading.humidity);
+                            continue;
+                        },
+                    }
+                    let Conditioned { calibrated: reading, smoothed } = calibration().condition(reading, &mut channel.ema, taken.as_millis());
+                    record(id, Ok(Measurement { raw: reading, smoothed, taken }));
+                    update_extremes(id, &reading);
+
+                    if id == PRIMARY_SENSOR {
+                        let sample = Sample::new(taken.as_secs() as u32, &reading);
+                        HISTORY.lock(|history| history.borrow_mut().push(sample));
+                        daily::add(&sample);
+                        sd_log::add(&sample);
+                        update_trends();
+                        sender.send(smoothed);
+                        alert::update(&smoothed);
+                        fan::update(Some(&smoothed));
+                        servo::update(&smoothed);
+                    }
+                },
+                Err(e) => {
+                    update_stats(|stats| stats.persistent_failures = stats.persistent_failures.saturating_add(1));
+                    let stats = stats();
+                    log::warn!("Sensor {} read failed after {} attempts: {} ({} transient, {} persistent failures so far)",
+                        id, READ_ATTEMPTS, e.describe(), stats.transient_failures, stats.persistent_failures);
+                    record(id, Err(e));
+                    if id == PRIMARY_SENSOR {
+                        fan::update(None);
+                    }
+                },
+            }
+        }
+
+        match chip.read().await {
+            Ok(celsius) => CHIP_TEMPERATURE.lock(|current| current.set(Some(celsius))),
+            Err(e) => log::warn!("Chip temperature read failed: {:?}", e),
+        }
+
+        SAMPLED.signal(());
+        watchdog::idle(Subsystem::Sensor, select(Timer::after(sample_interval()), WAKE.wait())).await;
+    }
+}
+
+/// Read up to `READ_ATTEMPTS` times, the snapshot keeps serving the last good value in between
+async fn read_with_retry<T: ReadSensor<Error = SensorError>>(sensor: &mut ThrottledSensor<T>) -> Result<(Reading, Instant), SensorError> {
+    let mut attempt = 1;
+
+    loop {
+        match sensor.read(Instant::now().as_millis()) {
+            Ok((reading, taken)) => {
+                if attempt > 1 {
+                    update_stats(|stats| stats.transient_failures = stats.transient_failures.saturating_add(1));
+                }
+                return Ok((reading, Instant::from_millis(taken)));
+            },
+            Err(e) if attempt < READ_ATTEMPTS => {
+                count_error(e);
+                log::debug!("Sensor read attempt {} failed: {}", attempt, e.describe());
+                attempt += 1;
+                Timer::after(RETRY_DELAY).await;
+            },
+            Err(e) => {
+                count_error(e);
+                return Err(e);
+            },
+        }
+    }
+}
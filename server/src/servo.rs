@@ -0,0 +1,150 @@
+use {
+    core::cell::Cell,
+    embassy_futures::select::{select, Either},
+    embassy_rp::{
+        clocks::clk_sys_freq,
+        pwm::{self, Pwm},
+    },
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    embassy_time::{Duration, Timer},
+    server_core::servo::{auto_angle, pulse_us, should_move, MAX_ANGLE, PERIOD_US},
+    crate::{
+        config::{SERVO_GAIN, SERVO_HOLD_MS, SERVO_MAX_PULSE_US, SERVO_MIN_CHANGE, SERVO_MIN_PULSE_US, SERVO_PIN, SERVO_SETPOINT},
+        reading::Reading,
+    },
+};
+
+/// Set by `SERVO_PIN`, without it `/api/servo` answers 404 and nothing is driven
+pub const PRESENT: bool = SERVO_PIN.is_some();
+const HOLD: Duration = Duration::from_millis(SERVO_HOLD_MS as u64);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    /// The angle follows the primary sensor's humidity above `SERVO_SETPOINT`
+    Auto,
+    /// The angle set through `POST /api/servo`
+    Manual,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct State {
+    pub mode: Mode,
+    /// The last angle commanded, `None` until the first one, the servo isn't moved at boot
+    pub angle: Option<u8>,
+    /// Whether pulses are being sent, only for `SERVO_HOLD_MS` after each move
+    pub attached: bool,
+}
+
+// Auto from the start when there is a setpoint to follow
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State {
+    mode: if SERVO_SETPOINT.is_some() { Mode::Auto } else { Mode::Manual },
+    angle: None,
+    attached: false,
+}));
+/// The angle `servo_task` moves to next
+static COMMAND: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+
+pub fn state() -> State {
+    STATE.lock(Cell::get)
+}
+
+fn update_state(f: impl FnOnce(&mut State)) {
+    STATE.lock(|state| {
+        let mut current = state.get();
+        f(&mut current);
+        state.set(current);
+    });
+}
+
+fn command(angle: u8) {
+    update_state(|state| state.angle = Some(angle));
+    COMMAND.signal(angle);
+}
+
+/// Fixed angle until `set_auto`, clamped to 180°
+pub fn set_manual(angle: u8) {
+    let angle = angle.min(MAX_ANGLE);
+    update_state(|state| state.mode = Mode::Manual);
+    command(angle);
+    log::info!("Servo set to {}°", angle);
+}
+
+/// Back to following the humidity from the latest reading, `false` without `SERVO_SETPOINT`
+pub fn set_auto(latest: Option<&Reading>) -> bool {
+    let Some(setpoint) = SERVO_SETPOINT else {
+        return false;
+    };
+    update_state(|state| state.mode = Mode::Auto);
+    log::info!("Servo following the humidity");
+    // Whatever the last move was, the rule's angle is driven right away
+    if let Some(reading) = latest {
+        move_to(auto_angle(reading.humidity, setpoint, SERVO_GAIN));
+    }
+    true
+}
+
+fn move_to(angle: u8) {
+    command(angle);
+    log::debug!("Servo at {}°", angle);
+}
+
+/// Called by the sensor task for the primary sensor on every good reading. In auto mode the angle follows the
+/// proportional rule, only in steps of `SERVO_MIN_CHANGE`. Without a reading the flap stays where it is.
+pub fn update(reading: &Reading) {
+    let (true, Some(setpoint)) = (PRESENT, SERVO_SETPOINT) else {
+        return;
+    };
+    let current = state();
+    if current.mode != Mode::Auto {
+        return;
+    }
+    let target = auto_angle(reading.humidity, setpoint, SERVO_GAIN);
+    if should_move(current.angle, target, SERVO_MIN_CHANGE) {
+        move_to(target);
+    }
+}
+
+fn set_pulse(pwm: &mut Pwm<'static>, config: &mut pwm::Config, pulse_us: u16) {
+    config.compare_a = pulse_us;
+    config.compare_b = pulse_us;
+    pwm.set_config(config);
+}
+
+/// Sends 50 Hz pulses for each commanded angle and stops them `SERVO_HOLD_MS` after the last one. A servo without
+/// pulses lets go, so it doesn't buzz holding the flap against its load, the flap has to stay put by friction.
+#[embassy_executor::task]
+pub async fn servo_task(mut pwm: Pwm<'static>) -> ! {
+    let mut config = pwm::Config::default();
+    // One count per µs, the divider is 125 on the RP2040 and 150 on the RP2350
+    config.divider = ((clk_sys_freq() / 1_000_000) as u8).into();
+    config.top = PERIOD_US - 1;
+    // A compare of 0 keeps the output low, no pulses
+    set_pulse(&mut pwm, &mut config, 0);
+    log::info!("Servo on GPIO{}, {} to {} µs", SERVO_PIN.unwrap_or(0), SERVO_MIN_PULSE_US, SERVO_MAX_PULSE_US);
+
+    loop {
+        let mut angle = COMMAND.wait().await;
+        update_state(|state| state.attached = true);
+        loop {
+            set_pulse(&mut pwm, &mut config, pulse_us(angle, SERVO_MIN_PULSE_US, SERVO_MAX_PULSE_US));
+            match select(Timer::after(HOLD), COMMAND.wait()).await {
+                Either::First(()) => break,
+                Either::Second(next) => angle = next,
+            }
+        }
+        set_pulse(&mut pwm, &mut config, 0);
+        update_state(|state| state.attached = false);
+    }
+}
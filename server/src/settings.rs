@@ -0,0 +1,295 @@
+use {
+    core::{
+        cell::Cell,
+        fmt::{self, Write},
+    },
+    defmt::unwrap,
+    embassy_futures::select::{select, Either},
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+        watch::Watch,
+    },
+    embassy_time::{Duration, Timer},
+    heapless::{String, Vec},
+    crate::{
+        alert::{self, Thresholds},
+        config::{self, DEVICE_NAME, HUMID_HIGH, HUMID_LOW, HUMID_OFFSET, SAMPLE_INTERVAL_S, TEMP_HIGH, TEMP_LOW, TEMP_OFFSET, TEMP_UNIT},
+        device_name::DeviceName,
+        form::{Fields, Kind, Value, VALUE_LEN},
+        reading::Calibration,
+        router::{Context, TempUnit},
+        sensor,
+        storage::RuntimeSettings,
+        wifi,
+    },
+};
+
+/// A change is written once no other one followed for this long, so a burst of `POST /api/config` costs one erase
+const SAVE_DELAY: Duration = Duration::from_secs(10);
+
+enum Persist {
+    Save,
+    /// The sector has been wiped, a save still waiting out `SAVE_DELAY` is dropped
+    Forget,
+}
+
+static PERSIST: Signal<CriticalSectionRawMutex, Persist> = Signal::new();
+
+/// The mDNS responder and the MQTT client
+pub const NAME_RECEIVERS: usize = 2;
+
+/// Every change of the device name, the current one is `device_name`
+pub static NAME_CHANGES: Watch<CriticalSectionRawMutex, DeviceName, NAME_RECEIVERS> = Watch::new();
+
+/// `None` until the settings are applied at boot, the build time name stands in until then
+static NAME: Mutex<CriticalSectionRawMutex, Cell<Option<DeviceName>>> = Mutex::new(Cell::new(None));
+
+pub fn device_name() -> DeviceName {
+    NAME.lock(Cell::get).unwrap_or_else(default_name)
+}
+
+pub fn set_device_name(name: DeviceName) {
+    if NAME.lock(|current| current.replace(Some(name))) != Some(name) {
+        NAME_CHANGES.sender().send(name);
+        log::info!("Device name {}", name);
+    }
+}
+
+fn default_name() -> DeviceName {
+    // Checked by build.rs
+    unwrap!(DeviceName::parse(DEVICE_NAME))
+}
+
+impl RuntimeSettings {
+    /// The build time settings, used while nothing valid is stored
+    pub fn defaults() -> Self {
+        Self {
+            sample_interval: Duration::from_secs(SAMPLE_INTERVAL_S),
+            calibration: Calibration { temperature: TEMP_OFFSET, humidity: HUMID_OFFSET },
+            power_mode: config::wifi_power_mode(),
+            temp_unit: TempUnit::parse(TEMP_UNIT).unwrap_or(TempUnit::Celsius),
+            thresholds: Thresholds {
+                temperature_high: TEMP_HIGH,
+                temperature_low: TEMP_LOW,
+                humidity_high: HUMID_HIGH,
+                humidity_low: HUMID_LOW,
+            },
+            device_name: default_name(),
+        }
+    }
+
+    /// What is in effect now
+    pub fn current() -> Self {
+        Self {
+            sample_interval: sensor::sample_interval(),
+            calibration: sensor::calibration(),
+            power_mode: wifi::power_mode(),
+            temp_unit: TempUnit::configured(),
+            thresholds: alert::thresholds(),
+            device_name: device_name(),
+        }
+    }
+
+    pub async fn apply(&self, ctx: &Context) {
+        sensor::set_sample_interval(self.sample_interval);
+        sensor::set_calibration(self.calibration);
+        TempUnit::set_configured(self.temp_unit);
+        alert::set_thresholds(self.thresholds);
+        set_device_name(self.device_name);
+        wifi::set_power_mode(ctx, self.power_mode).await;
+    }
+}
+
+/// A field of the `/settings` form. Its name is the form's and the placeholder the page is pre-filled through.
+pub struct FormField {
+    pub name: &'static str,
+    /// For the error banner
+    pub label: &'static str,
+    pub kind: Kind,
+    current: fn(&mut String<VALUE_LEN>) -> fmt::Result,
+    /// Only ever given a value that passed `kind`
+    apply: fn(Value),
+}
+
+/// A threshold in the order of `Thresholds::limits`, empty means off
+fn write_limit(index: usize, out: &mut String<VALUE_LEN>) -> fmt::Result {
+    match alert::thresholds().limits()[index] {
+        Some(limit) => write!(out, "{}", limit),
+        None => Ok(()),
+    }
+}
+
+fn set_limit(index: usize, value: Value) {
+    if let Value::Optional(limit) = value {
+        let mut limits = alert::thresholds().limits();
+        limits[index] = limit;
+        alert::set_thresholds(Thresholds::from_limits(limits));
+    }
+}
+
+fn set_offsets(temperature: Option<f32>, humidity: Option<f32>) {
+    let current = sensor::calibration();
+    sensor::set_calibration(Calibration {
+        temperature: temperature.unwrap_or(current.temperature),
+        humidity: humidity.unwrap_or(current.humidity),
+    });
+}
+
+/// Everything the settings page changes, a new setting is one more entry here and an input on the page.
+/// Temperatures are in °C whatever the display unit.
+pub const FORM_FIELDS: [FormField; 9] = [
+    FormField {
+        name: "device_name",
+        label: "Device name",
+        kind: Kind::Name,
+        current: |out| out.write_str(device_name().as_str()),
+        apply: |value| if let Value::Name(name) = value {
+            set_device_name(name);
+        },
+    },
+    FormField {
+        name: "sample_interval_s",
+        label: "Sample interval",
+        kind: Kind::Whole { min: 2, max: 3600 },
+        current: |out| write!(out, "{}", sensor::sample_interval().as_secs()),
+        apply: |value| if let Value::Whole(secs) = value {
+            sensor::set_sample_interval(Duration::from_secs(u64::from(secs)));
+        },
+    },
+    FormField {
+        name: "temp_unit",
+        label: "Temperature unit",
+        kind: Kind::Choice(&["c", "f", "both"]),
+        current: |out| out.write_str(TempUnit::configured().as_str()),
+        apply: |value| if let Value::Choice(unit) = value {
+            TempUnit::set_configured(TempUnit::parse(unit).unwrap_or(TempUnit::Celsius));
+        },
+    },
+    FormField {
+        name: "temp_offset",
+        label: "Temperature offset",
+        kind: Kind::Number { min: -20.0, max: 20.0 },
+        current: |out| write!(out, "{}", sensor::calibration().temperature),
+        apply: |value| if let Value::Number(offset) = value {
+            set_offsets(Some(offset), None);
+        },
+    },
+    FormField {
+        name: "humid_offset",
+        label: "Humidity offset",
+        kind: Kind::Number { min: -20.0, max: 20.0 },
+        current: |out| write!(out, "{}", sensor::calibration().humidity),
+        apply: |value| if let Value::Number(offset) = value {
+            set_offsets(None, Some(offset));
+        },
+    },
+    FormField {
+        name: "temp_high",
+        label: "High temperature alert",
+        kind: Kind::Optional { min: -40.0, max: 80.0 },
+        current: |out| write_limit(0, out),
+        apply: |value| set_limit(0, value),
+    },
+    FormField {
+        name: "temp_low",
+        label: "Low temperature alert",
+        kind: Kind::Optional { min: -40.0, max: 80.0 },
+        current: |out| write_limit(1, out),
+        apply: |value| set_limit(1, value),
+    },
+    FormField {
+        name: "humid_high",
+        label: "High humidity alert",
+        kind: Kind::Optional { min: 0.0, max: 100.0 },
+        current: |out| write_limit(2, out),
+        apply: |value| set_limit(2, value),
+    },
+    FormField {
+        name: "humid_low",
+        label: "Low humidity alert",
+        kind: Kind::Optional { min: 0.0, max: 100.0 },
+        current: |out| write_limit(3, out),
+        apply: |value| set_limit(3, value),
+    },
+];
+
+/// The value of every form field in effect now, in the order of `FORM_FIELDS`
+pub fn form_values() -> Vec<String<VALUE_LEN>, { FORM_FIELDS.len() }> {
+    FORM_FIELDS.iter().map(|field| {
+        let mut value = String::new();
+        // Every value fits, a whole number, an `f32` written the short way or the name
+        let _ = (field.current)(&mut value);
+        value
+    }).collect()
+}
+
+/// Check every field of a submitted form and only then change anything and schedule the save. Unknown names are
+/// ignored and missing fields stay as they are. The error is the first field that didn't pass.
+pub fn apply_form(fields: &Fields) -> Result<(), &'static FormField> {
+    let mut checked = Vec::<(&FormField, Value), { FORM_FIELDS.len() }>::new();
+
+    for field in &FORM_FIELDS {
+        let Some((_, text)) = fields.iter().find(|(name, _)| *name == field.name) else {
+            continue;
+        };
+        let value = field.kind.check(text).ok_or(field)?;
+        // Each field is looked up once, there is room for all of them
+        let _ = checked.push((field, value));
+    }
+
+    for (field, value) in checked {
+        (field.apply)(value);
+    }
+    request_save();
+    Ok(())
+}
+
+/// Store the settings in effect once they have stopped changing for `SAVE_DELAY`
+pub fn request_save() {
+    PERSIST.signal(Persist::Save);
+}
+
+/// Wipe the stored settings and go back to the build time ones
+pub async fn reset(ctx: &Context) -> Result<(), embassy_rp::flash::Error> {
+    ctx.storage.lock().await.clear_settings()?;
+    PERSIST.signal(Persist::Forget);
+    RuntimeSettings::defaults().apply(ctx).await;
+    log::info!("Stored settings cleared, back to the build time ones");
+    Ok(())
+}
+
+/// Writes the settings after changes, only when they differ from what the sector holds
+#[embassy_executor::task]
+pub async fn save_task(ctx: &'static Context, mut stored: Option<RuntimeSettings>) -> ! {
+    loop {
+        if let Persist::Forget = PERSIST.wait().await {
+            stored = None;
+            continue;
+        }
+        // Every further change restarts the wait
+        let forget = loop {
+            match select(Timer::after(SAVE_DELAY), PERSIST.wait()).await {
+                Either::First(()) => break false,
+                Either::Second(Persist::Save) => {},
+                Either::Second(Persist::Forget) => break true,
+            }
+        };
+        if forget {
+            stored = None;
+            continue;
+        }
+
+        let current = RuntimeSettings::current();
+        if stored == Some(current) {
+            continue;
+        }
+        match ctx.storage.lock().await.save_settings(&current) {
+            Ok(()) => {
+                log::info!("Stored the settings");
+                stored = Some(current);
+            },
+            Err(e) => log::error!("Unable to store the settings: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use {
+    core::sync::atomic::Ordering,
+    cortex_m::peripheral::SCB,
+    embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe},
+    embassy_time::{Duration, Instant, Timer},
+    embassy_usb_logger::ReceiverHandler,
+    crate::{
+        config::{DHT_LABELS, WIFI_NETWORKS},
+        history::HISTORY,
+        control::LedCommand,
+        led,
+        log_filter,
+        router::Context,
+        sensor,
+        sntp::{self, Iso8601},
+        storage::Credentials,
+        syslog,
+        uptime::Uptime,
+        wifi,
+    },
+    server_core::shell::{self, Command, Key, LineEditor, ParseError, HELP},
+};
+
+const PROMPT: &str = "> ";
+/// Longest wait for `read`, a round retries a failing sensor a few times
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for the last answer to reach the host before `reboot` resets
+const REBOOT_DELAY: Duration = Duration::from_millis(200);
+
+/// Bytes typed into the USB serial port, the receiver waits while the shell is busy with a command
+static INPUT: Pipe<CriticalSectionRawMutex, 64> = Pipe::new();
+
+/// Hands what arrives on the USB serial port to `shell_task`
+pub struct Input;
+
+impl ReceiverHandler for Input {
+    async fn handle_data(&self, data: &[u8]) {
+        INPUT.write_all(data).await;
+    }
+
+    fn new() -> Self {
+        Self
+    }
+}
+
+/// Like `println!`, to the USB serial port only
+macro_rules! reply {
+    ($($arg:tt)*) => {
+        syslog::console(format_args!("{}\r\n", format_args!($($arg)*)))
+    };
+}
+
+/// Reads command lines from the USB serial port and answers on it, works without any network. Log lines still go
+/// out in between, the shell doesn't redraw the line being typed.
+#[embassy_executor::task]
+pub async fn shell_task(ctx: &'static Context) -> ! {
+    let mut editor = LineEditor::new();
+    let mut buf = [0; 16];
+
+    loop {
+        let len = INPUT.read(&mut buf).await;
+        for byte in &buf[..len] {
+            match editor.feed(*byte) {
+                Key::Insert(char) => syslog::console(format_args!("{}", char as char)),
+                Key::Erase => syslog::console(format_args!("\x08 \x08")),
+                Key::Enter => {
+                    syslog::console(format_args!("\r\n"));
+                    run(ctx, editor.line()).await;
+                    editor.clear();
+                    syslog::console(format_args!("{}", PROMPT));
+                },
+                Key::Ignore => {},
+            }
+        }
+    }
+}
+
+async fn run(ctx: &Context, line: &str) {
+    let command = match shell::parse(line) {
+        Ok(Some(command)) => command,
+        Ok(None) => return,
+        Err(ParseError::Unknown) => {
+            reply!("Unknown command {:?}", line.trim());
+            reply!("{}", HELP);
+            return;
+        },
+        Err(ParseError::Usage(usage)) => {
+            reply!("Usage: {}", usage);
+            return;
+        },
+    };
+
+    match command {
+        Command::Help => reply!("{}", HELP),
+        Command::Status => status(ctx),
+        Command::Read => read().await,
+        Command::Led(on) => {
+            led::command(if on { LedCommand::On } else { LedCommand::Off }).await;
+            reply!("LED {}", if on { "on" } else { "off" });
+        },
+        Command::WifiRejoin if ctx.setup_mode.load(Ordering::Relaxed) => {
+            reply!("The setup access point is up, there is no network to rejoin");
+        },
+        Command::WifiRejoin => {
+            wifi::request_rejoin();
+            reply!("Rejoining, the log shows how it goes");
+        },
+        Command::WifiShow => wifi_show(ctx).await,
+        Command::WifiSet { ssid, password } => wifi_set(ctx, ssid, password).await,
+        Command::WifiClear => match ctx.storage.lock().await.clear_credentials() {
+            Ok(()) => reply!("Forgot the stored network, only the built in ones are joined from the next boot on"),
+            Err(e) => reply!("Unable to clear the stored network: {:?}", e),
+        },
+        Command::History(count) => history(count),
+        Command::Reboot => {
+            reply!("Rebooting");
+            Timer::after(REBOOT_DELAY).await;
+            SCB::sys_reset()
+        },
+    }
+}
+
+fn status(ctx: &Context) {
+    match ctx.stack.config_v4() {
+        Some(config) => reply!("IP       {}", config.address),
+        None if ctx.setup_mode.load(Ordering::Relaxed) => reply!("IP       none, the setup access point is up"),
+        None => reply!("IP       none, {}", if ctx.stack.is_link_up() { "waiting for DHCP" } else { "no link" }),
+    }
+    match (wifi::joined_ssid(), wifi::link_info()) {
+        (Some(ssid), Some(info)) => reply!("Wi-Fi    {}, {} dBm ({})", ssid, info.rssi_dbm, info.quality().as_str()),
+        (Some(ssid), None) => reply!("Wi-Fi    {}, signal not measured yet", ssid),
+        (None, _) => reply!("Wi-Fi    not joined"),
+    }
+    reply!("Uptime   {}", Uptime(Instant::now().as_secs()));
+    reply!("Log      {}", log_filter::as_str(syslog::filter().level));
+}
+
+/// A password as its length only, it never goes out on the serial port
+struct Masked<'a>(&'a str);
+
+impl core::fmt::Display for Masked<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0.len() {
+            0 => f.write_str("open"),
+            len => write!(f, "password of {} characters", len),
+        }
+    }
+}
+
+async fn wifi_show(ctx: &Context) {
+    match ctx.storage.lock().await.load_credentials() {
+        Some(credentials) => reply!("Stored   {}, {}", credentials.ssid, Masked(&credentials.password)),
+        None => reply!("Stored   none"),
+    }
+    for (ssid, password) in WIFI_NETWORKS {
+        reply!("Built in {}, {}", ssid, Masked(password));
+    }
+    if let Some(ssid) = wifi::joined_ssid() {
+        reply!("Joined   {}", ssid);
+    }
+}
+
+/// Stores the network in the record the setup page writes, which wins over the built in networks from the next
+/// boot on
+async fn wifi_set(ctx: &Context, ssid: &str, password: &str) {
+    let Some(credentials) = Credentials::new(ssid, password) else {
+        reply!("The SSID needs 1 to 32 characters and the password 8 to 64 or none");
+        return;
+    };
+    match ctx.storage.lock().await.save_credentials(&credentials) {
+        Ok(()) => {
+            log::info!("Stored the credentials for {} from the shell", credentials.ssid);
+            reply!("Stored {}, {}, joined from the next boot on: reboot to use it now", credentials.ssid, Masked(&credentials.password));
+        },
+        Err(e) => reply!("Unable to store the network: {:?}", e),
+    }
+}
+
+async fn read() {
+    if !sensor::sample_now(READ_TIMEOUT).await {
+        reply!("The sensor task didn't finish a round within {} s", READ_TIMEOUT.as_secs());
+    }
+
+    for (id, label) in DHT_LABELS.iter().enumerate() {
+        match (sensor::status(id), sensor::last_error(id)) {
+            (sensor::Status::Ready { reading, raw, age, stale }, _) => {
+                reply!("{}: {:.1} °C {:.1} % (raw {:.1} °C {:.1} %, {} ms old{})", label, reading.temperature,
+                    reading.humidity, raw.temperature, raw.humidity, age.as_millis(), if stale { ", stale" } else { "" });
+            },
+            (sensor::Status::NotReady, Some((error, failures))) => {
+                reply!("{}: no reading, {} failed reads: {}", label, failures, error.describe());
+            },
+            (sensor::Status::NotReady, None) => reply!("{}: no reading yet", label),
+        }
+    }
+}
+
+/// The latest samples of the primary sensor, oldest first
+fn history(count: usize) {
+    let (oldest, next) = HISTORY.lock(|history| history.borrow().seq_range());
+    let count = count.min(next.wrapping_sub(oldest) as usize) as u32;
+    if count == 0 {
+        reply!("No samples yet");
+        return;
+    }
+
+    let now = Instant::now().as_secs() as u32;
+    for seq in (0..count).map(|back| next.wrapping_sub(count - back)) {
+        let Some(sample) = HISTORY.lock(|history| history.borrow().get(seq)) else {
+            continue;
+        };
+        match sntp::unix_at(sample.secs_since_boot) {
+            Some(unix) => reply!("{}  {:.1} °C {:.1} %", Iso8601(unix), sample.temperature(), sample.humidity()),
+            None => reply!("{:>6} s ago  {:.1} °C {:.1} %", now.saturating_sub(sample.secs_since_boot),
+                sample.temperature(), sample.humidity()),
+        }
+    }
+}
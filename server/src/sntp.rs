@@ -0,0 +1,197 @@
+use {
+    core::{cell::Cell, fmt, str::FromStr},
+    embassy_net::{
+        dns::DnsQueryType,
+        udp::{PacketMetadata, UdpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    embassy_time::{with_timeout, Duration, Instant, Timer},
+    defmt::unwrap,
+    crate::{calendar::civil_from_days, diag},
+};
+
+const NTP_PORT: u16 = 123;
+const PACKET_SIZE: usize = 48;
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u32 = 2_208_988_800;
+/// Leap indicator 0, version 4, client mode
+const CLIENT_REQUEST: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+const RESYNC_INTERVAL: Duration = Duration::from_secs(4 * 60 * 60);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait after the first failed sync, doubled after every further one
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// Where the wall clock time comes from, a later source replaces an earlier one
+#[derive(Clone, Copy, PartialEq)]
+pub enum TimeSource {
+    /// Nothing set the clock yet, timestamps are left out and only the uptime is known
+    Uptime,
+    /// Read from the DS3231 at boot
+    #[cfg(feature = "rtc")]
+    Rtc,
+    /// Set through `POST /api/time`
+    Manual,
+    Ntp,
+}
+
+impl TimeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeSource::Uptime => "uptime",
+            #[cfg(feature = "rtc")]
+            TimeSource::Rtc => "rtc",
+            TimeSource::Manual => "manual",
+            TimeSource::Ntp => "ntp",
+        }
+    }
+}
+
+/// Unix time of `Instant` zero in microseconds and where it came from, `None` until the clock was set
+static BOOT_UNIX_MICROS: Mutex<CriticalSectionRawMutex, Cell<Option<(u64, TimeSource)>>> = Mutex::new(Cell::new(None));
+/// Raised whenever the clock is set, the RTC task writes NTP and manual time back to the chip
+pub static CLOCK_SET: Signal<CriticalSectionRawMutex, TimeSource> = Signal::new();
+
+/// Current Unix time in seconds, `None` until the clock was set once
+pub fn now_unix() -> Option<u64> {
+    let (boot, _) = BOOT_UNIX_MICROS.lock(Cell::get)?;
+    Some((boot + Instant::now().as_micros()) / 1_000_000)
+}
+
+/// Unix time of a moment given in seconds since boot, samples taken before the clock was set get one once it is
+pub fn unix_at(secs_since_boot: u32) -> Option<u64> {
+    let (boot, _) = BOOT_UNIX_MICROS.lock(Cell::get)?;
+    Some(boot / 1_000_000 + secs_since_boot as u64)
+}
+
+pub fn time_source() -> TimeSource {
+    BOOT_UNIX_MICROS.lock(Cell::get).map_or(TimeSource::Uptime, |(_, source)| source)
+}
+
+/// Set the clock to `unix_secs` now
+pub fn set_clock(unix_secs: u64, source: TimeSource) {
+    set_boot((unix_secs * 1_000_000).saturating_sub(Instant::now().as_micros()), source);
+}
+
+fn set_boot(boot_unix_micros: u64, source: TimeSource) {
+    let previous = BOOT_UNIX_MICROS.lock(|boot| boot.replace(Some((boot_unix_micros, source))));
+    if previous.is_none_or(|(_, previous)| previous != source) {
+        log::info!("Clock set from {}: {}", source.as_str(), Iso8601(boot_unix_micros / 1_000_000 + Instant::now().as_secs()));
+    }
+    CLOCK_SET.signal(source);
+}
+
+/// Unix time formatted as ISO-8601 UTC like `2024-05-01T12:34:56Z`
+pub struct Iso8601(pub u64);
+
+impl fmt::Display for Iso8601 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (days, secs) = (self.0 / 86_400, self.0 % 86_400);
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// Day count since the Unix epoch formatted as an ISO-8601 date like `2024-05-01`
+pub struct IsoDate(pub u32);
+
+impl fmt::Display for IsoDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.0.into());
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[derive(Debug)]
+enum SyncError {
+    Resolve,
+    Send,
+    Timeout,
+    InvalidReply,
+}
+
+/// Keeps the offset between `Instant` and Unix time, `server` is a hostname or an IPv4 address
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>, server: &'static str) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let _open = diag::OpenSocket::new();
+    unwrap!(socket.bind(0));
+
+    let mut backoff = RETRY_MIN;
+
+    loop {
+        match sync(stack, &mut socket, server).await {
+            Ok(boot_unix_micros) => {
+                log::debug!("Clock synced with {}", server);
+                set_boot(boot_unix_micros, TimeSource::Ntp);
+                backoff = RETRY_MIN;
+                Timer::after(RESYNC_INTERVAL).await;
+            },
+            Err(e) => {
+                log::warn!("SNTP sync with {} failed: {:?}, retrying in {} s", server, e, backoff.as_secs());
+                Timer::after(backoff).await;
+                backoff = (backoff * 2).min(RETRY_MAX);
+            },
+        }
+    }
+}
+
+/// One request and reply, returns the Unix time of `Instant` zero in microseconds
+async fn sync(stack: Stack<'static>, socket: &mut UdpSocket<'_>, server: &str) -> Result<u64, SyncError> {
+    let address = match Ipv4Address::from_str(server) {
+        Ok(address) => IpAddress::Ipv4(address),
+        Err(_) => *stack.dns_query(server, DnsQueryType::A).await.map_err(|_| SyncError::Resolve)?
+            .first()
+            .ok_or(SyncError::Resolve)?,
+    };
+    let endpoint = IpEndpoint::new(address, NTP_PORT);
+
+    // The server echoes the transmit timestamp as originate timestamp, which ties its reply to this request
+    let sent_at = Instant::now();
+    let nonce = sent_at.as_ticks().to_be_bytes();
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = CLIENT_REQUEST;
+    packet[40..48].copy_from_slice(&nonce);
+    socket.send_to(&packet, endpoint).await.map_err(|_| SyncError::Send)?;
+
+    with_timeout(REPLY_TIMEOUT, async {
+        loop {
+            let Ok((len, meta)) = socket.recv_from(&mut packet).await else {
+                continue;
+            };
+            // Late replies to an earlier request are skipped
+            if meta.endpoint != endpoint || len < PACKET_SIZE || packet[24..32] != nonce {
+                continue;
+            }
+            break;
+        }
+    }).await.map_err(|_| SyncError::Timeout)?;
+    let received_at = Instant::now();
+
+    // Stratum 0 is a kiss-o'-death, the server asks to back off
+    if packet[0] & 0x07 != MODE_SERVER || packet[1] == 0 {
+        return Err(SyncError::InvalidReply);
+    }
+
+    let secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]);
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]);
+    // NTP seconds wrap in 2036, wrapping the subtraction too keeps the result right until 2106
+    let unix_secs = secs.wrapping_sub(NTP_UNIX_OFFSET) as u64;
+    let unix_micros = unix_secs * 1_000_000 + ((fraction as u64 * 1_000_000) >> 32);
+
+    // The server stamped its reply about half the round trip before it arrived
+    let half_round_trip = (received_at - sent_at).as_micros() / 2;
+    Ok((unix_micros + half_round_trip).saturating_sub(received_at.as_micros()))
+}
@@ -0,0 +1,335 @@
+use {
+    core::ptr,
+    embassy_rp::{
+        flash::{self, Blocking, Flash, ERASE_SIZE},
+        peripherals::FLASH,
+    },
+    embassy_time::Duration,
+    heapless::String,
+    server_core::ota::{self, UpdateState},
+    crate::{
+        alert::Thresholds,
+        board::{FLASH_SIZE, SLOT_SIZE},
+        crc::{crc32, crc32_continue},
+        device_name::{self, DeviceName},
+        hourly::{self, HourRecord},
+        reading::Calibration,
+        router::TempUnit,
+        wifi::PowerMode,
+    },
+};
+
+/// Last sector of the flash, kept out of the firmware's reach in the board's memory layout
+const RECORD_OFFSET: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+const RECORD_MAGIC: [u8; 4] = *b"DHTC";
+const RECORD_VERSION: u8 = 1;
+pub const SSID_LEN: usize = 32;
+pub const PASSWORD_LEN: usize = 64;
+/// Magic, version, the two lengths, a padding byte, both fields padded to their maximum and the CRC
+const RECORD_SIZE: usize = 4 + 4 + SSID_LEN + PASSWORD_LEN + 4;
+
+/// The sector below the credentials, also outside the memory layout's flash region
+const SETTINGS_OFFSET: u32 = RECORD_OFFSET - ERASE_SIZE as u32;
+const SETTINGS_MAGIC: [u8; 4] = *b"DHTS";
+const SETTINGS_VERSION: u8 = 2;
+/// Magic, version, the threshold flags, power mode and unit, the interval, both offsets, the four thresholds, the
+/// name's length and three padding bytes, the name padded to its maximum and the CRC
+const SETTINGS_SIZE: usize = 4 + 4 + 4 + 2 * 4 + 4 * 4 + 4 + device_name::MAX_LEN + 4;
+/// The first layout ended after the thresholds, it is still read with the build time device name
+const SETTINGS_V1_SIZE: usize = 4 + 4 + 4 + 2 * 4 + 4 * 4 + 4;
+/// Where the name's length goes
+const NAME_OFFSET: usize = 4 + 4 + 4 + 2 * 4 + 4 * 4;
+/// The two sectors below the settings hold the hourly records, appended one after the other and the older sector
+/// erased once the newer one is full. 24 records a day and 128 per sector erase each sector every 10.7 days, about
+/// 35 times a year against the 100 000 cycles the flash is rated for. At boot the ring covers the last 5 to 10
+/// days.
+const RING_OFFSET: u32 = SETTINGS_OFFSET - RING_SECTORS * ERASE_SIZE as u32;
+const RING_SECTORS: u32 = 2;
+const SLOTS_PER_SECTOR: u32 = (ERASE_SIZE / hourly::RECORD_SIZE) as u32;
+const RING_SLOTS: u32 = RING_SECTORS * SLOTS_PER_SECTOR;
+/// The sector below the hourly records holds the state of the firmware update
+const UPDATE_STATE_OFFSET: u32 = RING_OFFSET - ERASE_SIZE as u32;
+/// Uploads go to the slot past the running firmware's, the same size as it
+pub const UPDATE_SLOT_OFFSET: u32 = SLOT_SIZE as u32;
+const _: () = assert!(UPDATE_SLOT_OFFSET + SLOT_SIZE as u32 <= UPDATE_STATE_OFFSET, "The update slot overlaps the storage sectors");
+/// Where the flash is mapped, the memory layout's addresses are in this window
+pub const XIP_BASE: usize = 0x1000_0000;
+
+extern "C" {
+    /// Set by the memory layout right past the firmware's flash region
+    static __storage_start: u8;
+}
+
+/// Network to join, an empty password joins an open network
+pub struct Credentials {
+    pub ssid: String<SSID_LEN>,
+    pub password: String<PASSWORD_LEN>,
+}
+
+impl Credentials {
+    /// `None` when a value doesn't fit or the password is too short for WPA2
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        if ssid.is_empty() || (!password.is_empty() && password.len() < 8) {
+            return None;
+        }
+
+        Some(Self { ssid: String::try_from(ssid).ok()?, password: String::try_from(password).ok()? })
+    }
+
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut record = [0xff; RECORD_SIZE];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+        record[4] = RECORD_VERSION;
+        record[5] = self.ssid.len() as u8;
+        record[6] = self.password.len() as u8;
+        record[7] = 0;
+        record[8..8 + self.ssid.len()].copy_from_slice(self.ssid.as_bytes());
+        record[8 + SSID_LEN..8 + SSID_LEN + self.password.len()].copy_from_slice(self.password.as_bytes());
+
+        let crc = crc32(&record[..RECORD_SIZE - 4]);
+        record[RECORD_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<Self> {
+        if record[..4] != RECORD_MAGIC || record[4] != RECORD_VERSION {
+            return None;
+        }
+
+        let crc = u32::from_le_bytes(record[RECORD_SIZE - 4..].try_into().ok()?);
+        if crc != crc32(&record[..RECORD_SIZE - 4]) {
+            return None;
+        }
+
+        let ssid = record[8..8 + SSID_LEN].get(..record[5] as usize)?;
+        let password = record[8 + SSID_LEN..8 + SSID_LEN + PASSWORD_LEN].get(..record[6] as usize)?;
+
+        Self::new(core::str::from_utf8(ssid).ok()?, core::str::from_utf8(password).ok()?)
+    }
+}
+
+/// What `POST /api/config` changes, stored so it outlives a power cycle
+#[derive(Clone, Copy, PartialEq)]
+pub struct RuntimeSettings {
+    pub sample_interval: Duration,
+    pub calibration: Calibration,
+    pub power_mode: PowerMode,
+    pub temp_unit: TempUnit,
+    pub thresholds: Thresholds,
+    pub device_name: DeviceName,
+}
+
+impl RuntimeSettings {
+    fn encode(&self) -> [u8; SETTINGS_SIZE] {
+        let thresholds = self.thresholds.limits();
+        let mut record = [0xff; SETTINGS_SIZE];
+        record[..4].copy_from_slice(&SETTINGS_MAGIC);
+        record[4] = SETTINGS_VERSION;
+        record[5] = thresholds.iter().enumerate().fold(0, |flags, (index, limit)| flags | (limit.is_some() as u8) << index);
+        record[6] = PowerMode::ALL.iter().position(|mode| *mode == self.power_mode).unwrap_or(0) as u8;
+        record[7] = TempUnit::ALL.iter().position(|unit| *unit == self.temp_unit).unwrap_or(0) as u8;
+        let interval = self.sample_interval.as_secs().min(u32::MAX.into()) as u32;
+        record[8..12].copy_from_slice(&interval.to_le_bytes());
+        record[12..16].copy_from_slice(&self.calibration.temperature.to_le_bytes());
+        record[16..20].copy_from_slice(&self.calibration.humidity.to_le_bytes());
+        for (index, limit) in thresholds.iter().enumerate() {
+            record[20 + 4 * index..24 + 4 * index].copy_from_slice(&limit.unwrap_or(0.0).to_le_bytes());
+        }
+        let name = self.device_name.as_str().as_bytes();
+        record[NAME_OFFSET] = name.len() as u8;
+        record[NAME_OFFSET + 4..NAME_OFFSET + 4 + name.len()].copy_from_slice(name);
+
+        let crc = crc32(&record[..SETTINGS_SIZE - 4]);
+        record[SETTINGS_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8; SETTINGS_SIZE]) -> Option<Self> {
+        let size = match record[4] {
+            1 => SETTINGS_V1_SIZE,
+            SETTINGS_VERSION => SETTINGS_SIZE,
+            _ => return None,
+        };
+        if record[..4] != SETTINGS_MAGIC {
+            return None;
+        }
+
+        let crc = u32::from_le_bytes(record[size - 4..size].try_into().ok()?);
+        if crc != crc32(&record[..size - 4]) {
+            return None;
+        }
+
+        let device_name = match size {
+            SETTINGS_SIZE => {
+                let name = record[NAME_OFFSET + 4..].get(..usize::from(record[NAME_OFFSET]))?;
+                DeviceName::parse(core::str::from_utf8(name).ok()?)?
+            },
+            _ => RuntimeSettings::defaults().device_name,
+        };
+
+        let field = |offset: usize| f32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+        let limits = core::array::from_fn(|index| (record[5] & 1 << index != 0).then(|| field(20 + 4 * index)));
+        Some(Self {
+            sample_interval: Duration::from_secs(u32::from_le_bytes(record[8..12].try_into().ok()?).into()),
+            calibration: Calibration { temperature: field(12), humidity: field(16) },
+            power_mode: *PowerMode::ALL.get(usize::from(record[6]))?,
+            temp_unit: *TempUnit::ALL.get(usize::from(record[7]))?,
+            thresholds: Thresholds::from_limits(limits),
+            device_name,
+        })
+    }
+}
+
+/// Where the next hourly record goes
+#[derive(Clone, Copy)]
+struct RingPosition {
+    slot: u32,
+    seq: u32,
+}
+
+/// The reserved flash sectors holding the update slot and state, the hourly records, the runtime settings and the
+/// Wi-Fi credentials
+pub struct Storage {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+    ring: RingPosition,
+}
+
+impl Storage {
+    /// Stops at boot when the memory layout lets the firmware reach into the update slot, erasing it would wipe code
+    pub fn new(flash: FLASH) -> Self {
+        let storage_start = ptr::addr_of!(__storage_start) as usize - XIP_BASE;
+        assert!(storage_start <= UPDATE_SLOT_OFFSET as usize, "The firmware's flash region overlaps the update slot");
+        Self { flash: Flash::new_blocking(flash), ring: RingPosition { slot: 0, seq: 0 } }
+    }
+
+    fn read_slot(&mut self, slot: u32) -> Option<[u8; hourly::RECORD_SIZE]> {
+        let mut record = [0; hourly::RECORD_SIZE];
+        self.flash.blocking_read(RING_OFFSET + slot * hourly::RECORD_SIZE as u32, &mut record).ok()?;
+        Some(record)
+    }
+
+    /// Hands every valid hourly record to `each`, oldest first, and appends after the newest from then on.
+    /// Erased slots, torn writes and records of another layout are skipped.
+    pub fn load_hours(&mut self, mut each: impl FnMut(&HourRecord)) -> usize {
+        let mut newest: Option<(u32, u32)> = None;
+        for slot in 0..RING_SLOTS {
+            let Some((seq, _)) = self.read_slot(slot).as_ref().and_then(HourRecord::decode) else { continue };
+            if newest.is_none_or(|(newest_seq, _)| seq > newest_seq) {
+                newest = Some((seq, slot));
+            }
+        }
+        let Some((seq, slot)) = newest else {
+            return 0;
+        };
+        self.ring = RingPosition { slot: (slot + 1) % RING_SLOTS, seq: seq + 1 };
+
+        // The sector after the newest record's one was filled before it
+        let oldest_sector = (slot / SLOTS_PER_SECTOR + 1) % RING_SECTORS;
+        let mut count = 0;
+        for offset in 0..RING_SLOTS {
+            let slot = (oldest_sector * SLOTS_PER_SECTOR + offset) % RING_SLOTS;
+            if let Some((_, record)) = self.read_slot(slot).as_ref().and_then(HourRecord::decode) {
+                each(&record);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// The record's CRC goes in last like the settings'. Entering a sector erases it, which drops its oldest hours,
+    /// and a slot left dirty by a torn write is skipped.
+    pub fn append_hour(&mut self, record: &HourRecord) -> Result<(), flash::Error> {
+        let mut slot = self.ring.slot;
+        loop {
+            if slot.is_multiple_of(SLOTS_PER_SECTOR) {
+                let start = RING_OFFSET + slot / SLOTS_PER_SECTOR * ERASE_SIZE as u32;
+                self.flash.blocking_erase(start, start + ERASE_SIZE as u32)?;
+                break;
+            }
+            if self.read_slot(slot).is_some_and(|bytes| bytes.iter().all(|byte| *byte == 0xff)) {
+                break;
+            }
+            slot = (slot + 1) % RING_SLOTS;
+        }
+
+        let bytes = record.encode(self.ring.seq);
+        let offset = RING_OFFSET + slot * hourly::RECORD_SIZE as u32;
+        self.ring = RingPosition { slot: (slot + 1) % RING_SLOTS, seq: self.ring.seq + 1 };
+        self.flash.blocking_write(offset, &bytes[..hourly::RECORD_SIZE - 4])?;
+        self.flash.blocking_write(offset + (hourly::RECORD_SIZE - 4) as u32, &bytes[hourly::RECORD_SIZE - 4..])
+    }
+
+    /// Stored credentials, `None` when the sector is erased, from an older layout or corrupted
+    pub fn load_credentials(&mut self) -> Option<Credentials> {
+        let mut record = [0; RECORD_SIZE];
+        self.flash.blocking_read(RECORD_OFFSET, &mut record).ok()?;
+        Credentials::decode(&record)
+    }
+
+    /// Stored settings, `None` when the sector is erased, from an older layout or corrupted
+    pub fn load_settings(&mut self) -> Option<RuntimeSettings> {
+        let mut record = [0; SETTINGS_SIZE];
+        self.flash.blocking_read(SETTINGS_OFFSET, &mut record).ok()?;
+        RuntimeSettings::decode(&record)
+    }
+
+    /// The CRC goes in last, a write cut short by a reset leaves a record `load_settings` rejects
+    pub fn save_settings(&mut self, settings: &RuntimeSettings) -> Result<(), flash::Error> {
+        let record = settings.encode();
+        self.clear_settings()?;
+        self.flash.blocking_write(SETTINGS_OFFSET, &record[..SETTINGS_SIZE - 4])?;
+        self.flash.blocking_write(SETTINGS_OFFSET + (SETTINGS_SIZE - 4) as u32, &record[SETTINGS_SIZE - 4..])
+    }
+
+    pub fn clear_settings(&mut self) -> Result<(), flash::Error> {
+        self.flash.blocking_erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)
+    }
+
+    pub fn save_credentials(&mut self, credentials: &Credentials) -> Result<(), flash::Error> {
+        self.flash.blocking_erase(RECORD_OFFSET, RECORD_OFFSET + ERASE_SIZE as u32)?;
+        self.flash.blocking_write(RECORD_OFFSET, &credentials.encode())
+    }
+
+    /// Erases the sector, the built in networks are all that is left to join
+    pub fn clear_credentials(&mut self) -> Result<(), flash::Error> {
+        self.flash.blocking_erase(RECORD_OFFSET, RECORD_OFFSET + ERASE_SIZE as u32)
+    }
+
+    /// `Idle` when the sector is erased, from an older layout or corrupted
+    pub fn load_update_state(&mut self) -> UpdateState {
+        let mut record = [0; ota::RECORD_SIZE];
+        match self.flash.blocking_read(UPDATE_STATE_OFFSET, &mut record) {
+            Ok(()) => UpdateState::decode(&record),
+            Err(_) => UpdateState::Idle,
+        }
+    }
+
+    /// The CRC goes in last like for the settings, a torn write reads back as `Idle`
+    pub fn save_update_state(&mut self, state: UpdateState) -> Result<(), flash::Error> {
+        let record = state.encode();
+        self.flash.blocking_erase(UPDATE_STATE_OFFSET, UPDATE_STATE_OFFSET + ERASE_SIZE as u32)?;
+        self.flash.blocking_write(UPDATE_STATE_OFFSET, &record[..ota::RECORD_SIZE - 4])?;
+        self.flash.blocking_write(UPDATE_STATE_OFFSET + (ota::RECORD_SIZE - 4) as u32, &record[ota::RECORD_SIZE - 4..])
+    }
+
+    /// Erases sector `index` of the update slot and writes it, the firmware keeps running from the other slot
+    pub fn write_update_sector(&mut self, index: u32, sector: &[u8; ERASE_SIZE]) -> Result<(), flash::Error> {
+        let offset = UPDATE_SLOT_OFFSET + index * ERASE_SIZE as u32;
+        self.flash.blocking_erase(offset, offset + ERASE_SIZE as u32)?;
+        self.flash.blocking_write(offset, sector)
+    }
+
+    /// CRC-32 of the first `len` bytes of the update slot, as they read back from the flash
+    pub fn update_crc(&mut self, len: u32) -> Result<u32, flash::Error> {
+        let mut chunk = [0; 256];
+        let mut crc = 0;
+        let mut offset = 0;
+        while offset < len {
+            let part = &mut chunk[..(len - offset).min(256) as usize];
+            self.flash.blocking_read(UPDATE_SLOT_OFFSET + offset, part)?;
+            crc = crc32_continue(crc, part);
+            offset += part.len() as u32;
+        }
+        Ok(crc)
+    }
+}
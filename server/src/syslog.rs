@@ -0,0 +1,220 @@
+use {
+    core::{
+        cell::{Cell, RefCell},
+        fmt::Write as CoreWrite,
+        str::FromStr,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    embassy_net::{
+        dns::DnsQueryType,
+        udp::{PacketMetadata, UdpSocket},
+        IpAddress,
+        IpEndpoint,
+        Ipv4Address,
+        Stack,
+    },
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        channel::Channel,
+    },
+    embassy_time::Instant,
+    embassy_usb_logger::{UsbLogger, Writer},
+    heapless::String,
+    log::{Level, LevelFilter, Log, Metadata, Record},
+    defmt::unwrap,
+    server_core::log_filter::LogFilter,
+    crate::{
+        diag,
+        settings,
+        shell,
+        sntp::{self, Iso8601},
+    },
+};
+
+/// Longest message text, the rest of a longer one is cut off
+const MESSAGE_LEN: usize = 160;
+/// Lines waiting to be sent, further ones are dropped
+const QUEUE_LEN: usize = 16;
+const APP_NAME: &str = "dht22-server";
+/// User-level messages
+const FACILITY: u8 = 1;
+/// Header fields and the message, the timestamp and hostname are the longest
+const PACKET_SIZE: usize = 96 + MESSAGE_LEN;
+/// Bytes waiting for the USB host, lines that don't fit are dropped
+const USB_BUFFER: usize = 1024;
+/// Target of the shell's output, it skips the filter, the line ending and the collector
+const CONSOLE_TARGET: &str = "console";
+
+struct Line {
+    level: Level,
+    secs_since_boot: u32,
+    message: String<MESSAGE_LEN>,
+}
+
+static LINES: Channel<CriticalSectionRawMutex, Line, QUEUE_LEN> = Channel::new();
+/// Set once the sink runs, nothing is queued before
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static DROPPED: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+/// Lines below it are dropped before they reach the USB port or the collector
+static FILTER: Mutex<CriticalSectionRawMutex, RefCell<LogFilter>> = Mutex::new(RefCell::new(LogFilter::new(LevelFilter::Info)));
+
+/// Writes every line to the USB serial port and, once `syslog_task` runs, queues it for the collector. What is typed
+/// into the port goes to the shell.
+pub struct Logger {
+    pub usb: UsbLogger<USB_BUFFER, shell::Input>,
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        let mut usb = UsbLogger::with_custom_style(usb_style);
+        usb.with_handler(shell::Input);
+        Self { usb }
+    }
+}
+
+/// Log lines end with CR LF, the shell writes its output as it is
+fn usb_style(record: &Record, out: &mut Writer<'_, USB_BUFFER>) {
+    let _ = match record.target() {
+        CONSOLE_TARGET => write!(out, "{}", record.args()),
+        _ => write!(out, "{}\r\n", record.args()),
+    };
+}
+
+/// Write to the USB serial port only, whatever the log level, for the shell's echo and answers
+pub fn console(args: core::fmt::Arguments) {
+    // Straight to the logger, the log macros would drop it below the level
+    log::logger().log(&Record::builder().target(CONSOLE_TARGET).args(args).build());
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.lock(|filter| filter.borrow().enabled(module(metadata.target()), metadata.level()))
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target() == CONSOLE_TARGET {
+            self.usb.log(record);
+            return;
+        }
+        // The macros only check the highest level of any module
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.usb.log(record);
+        if ENABLED.load(Ordering::Relaxed) {
+            enqueue(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Module path of a log target without the crate name, so a rule reads `mqtt=debug`. Targets given explicitly, like
+/// `access`, stay as they are.
+fn module(target: &str) -> &str {
+    target.strip_prefix(env!("CARGO_CRATE_NAME")).and_then(|path| path.strip_prefix("::")).unwrap_or(target)
+}
+
+/// Applies a new level and module rules to every following line
+pub fn set_filter(filter: LogFilter) {
+    FILTER.lock(|current| {
+        // Safety: inside the critical section nothing else can run, let alone read the level halfway through the store
+        unsafe { log::set_max_level_racy(filter.max_level()) };
+        *current.borrow_mut() = filter;
+    });
+}
+
+pub fn filter() -> LogFilter {
+    FILTER.lock(|filter| filter.borrow().clone())
+}
+
+/// Never waits, a full queue counts as a dropped message
+fn enqueue(record: &Record) {
+    let mut message = String::new();
+    // A message that doesn't fit is sent up to where it was cut
+    let _ = write!(&mut message, "{}", record.args());
+    let line = Line { level: record.level(), secs_since_boot: Instant::now().as_secs() as u32, message };
+
+    if LINES.try_send(line).is_err() {
+        count_dropped();
+    }
+}
+
+fn count_dropped() {
+    DROPPED.lock(|dropped| dropped.set(dropped.get().wrapping_add(1)));
+}
+
+/// Whether log lines are forwarded at all
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Lines not forwarded since boot because the queue was full or the collector unreachable
+pub fn dropped() -> u32 {
+    DROPPED.lock(Cell::get)
+}
+
+/// RFC 5424 severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// RFC 5424 message without structured data, the timestamp is the nil value `-` until the clock is synced
+fn write_packet<W: CoreWrite>(out: &mut W, line: &Line, hostname: &str) -> core::fmt::Result {
+    write!(out, "<{}>1 ", FACILITY * 8 + severity(line.level))?;
+    match sntp::unix_at(line.secs_since_boot) {
+        Some(unix) => write!(out, "{}", Iso8601(unix))?,
+        None => out.write_str("-")?,
+    }
+    write!(out, " {} {} - - - {}", hostname, APP_NAME, line.message)
+}
+
+/// Forwards the queued log lines as UDP datagrams to `server`, a hostname or IPv4 address, under the device name.
+/// Lines are dropped while the link is down or the name doesn't resolve, this task itself never logs so it can't
+/// feed its own queue.
+#[embassy_executor::task]
+pub async fn syslog_task(stack: Stack<'static>, server: &'static str, port: u16) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let _open = diag::OpenSocket::new();
+    unwrap!(socket.bind(0));
+
+    let mut collector: Option<IpEndpoint> = None;
+    ENABLED.store(true, Ordering::Relaxed);
+
+    loop {
+        let line = LINES.receive().await;
+        if !stack.is_link_up() {
+            count_dropped();
+            continue;
+        }
+
+        if collector.is_none() {
+            let address = match Ipv4Address::from_str(server) {
+                Ok(address) => Some(IpAddress::Ipv4(address)),
+                Err(_) => stack.dns_query(server, DnsQueryType::A).await.ok().and_then(|addresses| addresses.first().copied()),
+            };
+            collector = address.map(|address| IpEndpoint::new(address, port));
+        }
+        let Some(endpoint) = collector else {
+            count_dropped();
+            continue;
+        };
+
+        let mut packet = String::<PACKET_SIZE>::new();
+        let _ = write_packet(&mut packet, &line, settings::device_name().as_str());
+        if socket.send_to(packet.as_bytes(), endpoint).await.is_err() {
+            count_dropped();
+            // Resolved again for the next line, the collector may have moved
+            collector = None;
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use {
+    core::{
+        cell::Cell,
+        fmt,
+        future::Future,
+        pin::pin,
+        sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    embassy_futures::select::{select, Either},
+    embassy_rp::{
+        peripherals::WATCHDOG,
+        watchdog::{ResetReason, Watchdog},
+    },
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+    },
+    embassy_time::{Duration, Instant, Timer},
+    crate::{board, HTTP_TASKS},
+};
+
+/// The hardware resets the device this long after the last feed
+const TIMEOUT: Duration = Duration::from_secs(8);
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+/// A subsystem that hasn't checked in for this long is taken as stalled and the watchdog is no longer fed
+const STALL_LIMIT: Duration = Duration::from_secs(30);
+/// How often a subsystem waiting in `idle` checks in
+const IDLE_CHECK_IN_INTERVAL: Duration = Duration::from_secs(5);
+/// Watchdog scratch registers survive its reset, 4 to 7 are taken by the boot ROM
+const RESETS_SCRATCH: usize = 0;
+/// One more than the index of the subsystem that stalled, 0 when the feeder didn't find one
+const STALLED_SCRATCH: usize = 1;
+/// `SETUP_MAGIC` when the device should come up with the setup access point instead of joining
+const SETUP_SCRATCH: usize = 2;
+const SETUP_MAGIC: u32 = 0x5345_5455;
+
+/// The parts of the firmware the watchdog is only fed for while each of them keeps checking in
+#[derive(Clone, Copy, PartialEq)]
+pub enum Subsystem {
+    Sensor,
+    /// The CYW43 runner, probed through the control handle since every call goes through it
+    Radio,
+    /// One of the connection handlers with its accept loop
+    Http(usize),
+}
+
+impl Subsystem {
+    const COUNT: usize = 2 + HTTP_TASKS;
+
+    fn index(self) -> usize {
+        match self {
+            Subsystem::Sensor => 0,
+            Subsystem::Radio => 1,
+            Subsystem::Http(id) => 2 + id,
+        }
+    }
+
+    fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(Subsystem::Sensor),
+            1 => Some(Subsystem::Radio),
+            index if index < Self::COUNT => Some(Subsystem::Http(index - 2)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subsystem::Sensor => f.write_str("the sensor task"),
+            Subsystem::Radio => f.write_str("the CYW43 radio"),
+            Subsystem::Http(id) => write!(f, "HTTP task {}", id),
+        }
+    }
+}
+
+/// Why the device last restarted, from the watchdog and chip reset registers
+#[derive(Clone, Copy, PartialEq)]
+pub enum BootReason {
+    /// Power was applied or the supply browned out
+    PowerOn,
+    /// The RUN pin was pulled low, e.g. a reset button
+    RunPin,
+    /// A debugger restarted the chip through the debug port
+    Debugger,
+    /// The watchdog wasn't fed in time
+    Watchdog,
+    /// The firmware asked for it, e.g. after the setup page stored new credentials
+    Software,
+}
+
+impl BootReason {
+    /// The watchdog reason comes first, the chip reset flags keep naming the last chip level reset after it
+    fn read(watchdog: &Watchdog) -> Self {
+        match watchdog.reset_reason() {
+            Some(ResetReason::TimedOut) => return BootReason::Watchdog,
+            Some(ResetReason::Forced) => return BootReason::Software,
+            None => {},
+        }
+
+        let chip_reset = board::chip_reset();
+        if chip_reset.debugger {
+            BootReason::Debugger
+        } else if chip_reset.run_pin {
+            BootReason::RunPin
+        } else if chip_reset.power_on {
+            BootReason::PowerOn
+        } else {
+            BootReason::Software
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BootReason::PowerOn => "power_on",
+            BootReason::RunPin => "run_pin",
+            BootReason::Debugger => "debugger",
+            BootReason::Watchdog => "watchdog",
+            BootReason::Software => "software",
+        }
+    }
+}
+
+/// Seconds since boot of the last check in of every subsystem, 0 while one isn't watched
+static HEARTBEATS: [AtomicU32; Subsystem::COUNT] = [const { AtomicU32::new(0) }; Subsystem::COUNT];
+static RESETS: AtomicU32 = AtomicU32::new(0);
+static BOOT_REASON: Mutex<CriticalSectionRawMutex, Cell<BootReason>> = Mutex::new(Cell::new(BootReason::PowerOn));
+static SETUP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Asks the watchdog task for a reset into the setup access point
+static REBOOT_INTO_SETUP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// When the watchdog task resets the device, set by `reboot_after`
+static REBOOT_AT: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+fn now_secs() -> u32 {
+    (Instant::now().as_secs() as u32).max(1)
+}
+
+/// Record progress, a subsystem is only watched from its first check in
+pub fn check_in(subsystem: Subsystem) {
+    HEARTBEATS[subsystem.index()].store(now_secs(), Ordering::Relaxed);
+}
+
+/// Stop watching a subsystem that has nothing left to do, until it checks in again
+pub fn release(subsystem: Subsystem) {
+    HEARTBEATS[subsystem.index()].store(0, Ordering::Relaxed);
+}
+
+/// Await a wait that may rightly last forever, like an accept or the next sample, checking in meanwhile
+pub async fn idle<F: Future>(subsystem: Subsystem, future: F) -> F::Output {
+    let mut future = pin!(future);
+    loop {
+        check_in(subsystem);
+        if let Either::First(output) = select(&mut future, Timer::after(IDLE_CHECK_IN_INTERVAL)).await {
+            return output;
+        }
+    }
+}
+
+/// Resets by the watchdog since the device was powered on
+pub fn resets() -> u32 {
+    RESETS.load(Ordering::Relaxed)
+}
+
+pub fn boot_reason() -> BootReason {
+    BOOT_REASON.lock(Cell::get)
+}
+
+/// Whether the reset came from `reboot_into_setup`, known once the watchdog task has started
+pub fn setup_requested() -> bool {
+    SETUP_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Resets the device through the watchdog, it comes up with the setup access point whatever networks it knows
+pub fn reboot_into_setup() {
+    REBOOT_INTO_SETUP.signal(());
+}
+
+/// Resets the device through the watchdog once `delay` has passed, a later call moves the time
+pub fn reboot_after(delay: Duration) {
+    REBOOT_AT.lock(|at| at.set(Some(Instant::now() + delay)));
+}
+
+/// The first watched subsystem that hasn't checked in within `STALL_LIMIT`, with the seconds since it last did
+fn stalled() -> Option<(Subsystem, u32)> {
+    let now = now_secs();
+    HEARTBEATS.iter().enumerate().find_map(|(index, heartbeat)| {
+        let last = heartbeat.load(Ordering::Relaxed);
+        let silent = now.wrapping_sub(last);
+        if last == 0 || silent as u64 <= STALL_LIMIT.as_secs() {
+            return None;
+        }
+        Some((Subsystem::from_index(index)?, silent))
+    })
+}
+
+/// Records why the device restarted and counts it if the watchdog caused it, then feeds the watchdog for as long as every
+/// watched subsystem keeps checking in. Once one stalls it is logged and the watchdog resets the device.
+#[embassy_executor::task]
+pub async fn watchdog_task(watchdog: WATCHDOG) -> ! {
+    let mut watchdog = Watchdog::new(watchdog);
+    let reason = BootReason::read(&watchdog);
+    BOOT_REASON.lock(|current| current.set(reason));
+    log::info!("Last restart: {}", reason.as_str());
+
+    let mut resets = watchdog.get_scratch(RESETS_SCRATCH);
+    if reason == BootReason::Watchdog {
+        resets = resets.wrapping_add(1);
+        watchdog.set_scratch(RESETS_SCRATCH, resets);
+        let stalled = (watchdog.get_scratch(STALLED_SCRATCH) as usize).checked_sub(1).and_then(Subsystem::from_index);
+        match stalled {
+            Some(subsystem) => log::warn!("Restarted by the watchdog after {} stalled, {} resets so far", subsystem, resets),
+            None => log::warn!("Restarted by the watchdog, {} resets so far", resets),
+        }
+    }
+    RESETS.store(resets, Ordering::Relaxed);
+    watchdog.set_scratch(STALLED_SCRATCH, 0);
+    // Only for the one boot right after the request
+    SETUP_REQUESTED.store(watchdog.get_scratch(SETUP_SCRATCH) == SETUP_MAGIC, Ordering::Relaxed);
+    watchdog.set_scratch(SETUP_SCRATCH, 0);
+
+    // A debugger halting the core shouldn't reset it
+    watchdog.pause_on_debug(true);
+    watchdog.start(TIMEOUT);
+
+    loop {
+        if let Some((subsystem, silent)) = stalled() {
+            // Best effort, the USB and syslog tasks have until the reset to get it out
+            log::error!("{} has not checked in for {} s, the watchdog resets the device", subsystem, silent);
+            watchdog.set_scratch(STALLED_SCRATCH, subsystem.index() as u32 + 1);
+            core::future::pending::<()>().await;
+        }
+        watchdog.feed();
+        let reboot_at = REBOOT_AT.lock(Cell::get);
+        let wait = reboot_at.map_or(FEED_INTERVAL, |at| FEED_INTERVAL.min(at.saturating_duration_since(Instant::now())));
+        if let Either::Second(()) = select(Timer::after(wait), REBOOT_INTO_SETUP.wait()).await {
+            log::warn!("Rebooting into the setup access point");
+            watchdog.set_scratch(SETUP_SCRATCH, SETUP_MAGIC);
+            watchdog.trigger_reset();
+        }
+        if reboot_at.is_some_and(|at| at <= Instant::now()) {
+            log::warn!("Rebooting as requested");
+            watchdog.trigger_reset();
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use {
+    core::cell::{Cell, RefCell},
+    core::sync::atomic::Ordering,
+    cyw43::{JoinOptions, PowerManagementMode, ScanOptions, ScanType},
+    embassy_executor::Spawner,
+    embassy_futures::select::select,
+    embassy_net::{ConfigV4, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4},
+    embassy_rp::clocks::RoscRng,
+    embassy_sync::{
+        blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+        signal::Signal,
+        watch::Watch,
+    },
+    embassy_time::{with_timeout, Duration, Instant, Timer},
+    heapless::{String, Vec},
+    rand::RngCore,
+    defmt::{unwrap, info},
+    crate::{
+        config::SETUP_AP_PASSWORD,
+        dhcp_server,
+        join_error::join_error_message,
+        led::{self, LinkStatus},
+        router::{self, Context},
+        storage::{Credentials, SSID_LEN},
+        watchdog::{self, Subsystem},
+    },
+};
+
+/// The built in networks plus the one saved through the setup page
+pub const MAX_NETWORKS: usize = 5;
+
+/// How often the link is measured, every query is a short scan for the joined SSID
+const LINK_INFO_INTERVAL: Duration = Duration::from_secs(30);
+const SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+/// A scan across every channel, run before joining when there is more than one network to pick from
+const FULL_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+/// A measurement older than this is not shown, the last queries failed
+const LINK_INFO_STALE_AFTER: Duration = Duration::from_secs(3 * 30);
+/// Signal strength at or above which the link counts as good or ok, in dBm
+const RSSI_GOOD: i16 = -60;
+const RSSI_OK: i16 = -70;
+/// Access point started when the network can't be joined at boot
+pub const SETUP_AP_SSID: &str = "Pico-W-Setup";
+const SETUP_AP_CHANNEL: u8 = 6;
+const SETUP_AP_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+/// Failed joins at boot before the setup access point is started instead, a lost link later is retried forever
+pub const JOIN_ATTEMPTS_BEFORE_SETUP: u32 = 5;
+/// Wait after the first failed join, doubled after every further one
+const JOIN_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const JOIN_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// How often the Wi-Fi supervisor checks the link
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Measured state of the joined network
+#[derive(Clone, Copy)]
+pub struct LinkInfo {
+    pub rssi_dbm: i16,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    measured: Instant,
+}
+
+impl LinkInfo {
+    pub fn quality(&self) -> SignalQuality {
+        SignalQuality::classify(self.rssi_dbm)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SignalQuality {
+    Good,
+    Ok,
+    Poor,
+}
+
+impl SignalQuality {
+    pub fn classify(rssi_dbm: i16) -> Self {
+        match rssi_dbm {
+            rssi if rssi >= RSSI_GOOD => SignalQuality::Good,
+            rssi if rssi >= RSSI_OK => SignalQuality::Ok,
+            _ => SignalQuality::Poor,
+        }
+    }
+
+    /// Also the CSS class suffix of the page's signal line
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignalQuality::Good => "good",
+            SignalQuality::Ok => "ok",
+            SignalQuality::Poor => "poor",
+        }
+    }
+}
+
+/// Power management modes of the radio selectable through `WIFI_PM` and `POST /api/config`. Power saving lets the
+/// radio sleep between beacons, so the first packet of a request can wait for the next wake-up.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PowerMode {
+    PowerSave,
+    Performance,
+    Aggressive,
+    None,
+}
+
+impl PowerMode {
+    pub const ALL: [PowerMode; 4] = [PowerMode::PowerSave, PowerMode::Performance, PowerMode::Aggressive, PowerMode::None];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "powersave" => Some(PowerMode::PowerSave),
+            "performance" => Some(PowerMode::Performance),
+            "aggressive" => Some(PowerMode::Aggressive),
+            "none" => Some(PowerMode::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PowerMode::PowerSave => "powersave",
+            PowerMode::Performance => "performance",
+            PowerMode::Aggressive => "aggressive",
+            PowerMode::None => "none",
+        }
+    }
+
+    fn driver_mode(self) -> PowerManagementMode {
+        match self {
+            PowerMode::PowerSave => PowerManagementMode::PowerSave,
+            PowerMode::Performance => PowerManagementMode::Performance,
+            PowerMode::Aggressive => PowerManagementMode::Aggressive,
+            PowerMode::None => PowerManagementMode::None,
+        }
+    }
+}
+
+static POWER_MODE: Mutex<CriticalSectionRawMutex, Cell<PowerMode>> = Mutex::new(Cell::new(PowerMode::PowerSave));
+
+pub fn power_mode() -> PowerMode {
+    POWER_MODE.lock(Cell::get)
+}
+
+/// Switch the radio to `mode`, the request latency is measured anew so the modes can be compared
+pub async fn set_power_mode(ctx: &Context, mode: PowerMode) {
+    ctx.control.lock().await.set_power_management(mode.driver_mode()).await;
+    POWER_MODE.lock(|current| current.set(mode));
+    router::reset_latency();
+    log::info!("Wi-Fi power management: {}", mode.as_str());
+}
+
+/// The mDNS responder and the MQTT publisher
+pub const ADDRESS_RECEIVERS: usize = 2;
+
+/// Every change of the device's address after the network came up, `None` while the configuration is lost
+pub static ADDRESS_CHANGES: Watch<CriticalSectionRawMutex, Option<Ipv4Address>, ADDRESS_RECEIVERS> = Watch::new();
+
+/// Networks to try, in order of preference
+pub type Networks = Vec<Credentials, MAX_NETWORKS>;
+
+static LINK_INFO: Mutex<CriticalSectionRawMutex, Cell<Option<LinkInfo>>> = Mutex::new(Cell::new(None));
+static JOINED_SSID: Mutex<CriticalSectionRawMutex, RefCell<Option<String<SSID_LEN>>>> = Mutex::new(RefCell::new(None));
+
+/// SSID of the network the device is on, `None` while it isn't joined
+pub fn joined_ssid() -> Option<String<SSID_LEN>> {
+    JOINED_SSID.lock(|ssid| ssid.borrow().clone())
+}
+
+pub fn set_joined_ssid(ssid: Option<&String<SSID_LEN>>) {
+    JOINED_SSID.lock(|joined| *joined.borrow_mut() = ssid.cloned());
+    if ssid.is_none() {
+        LINK_INFO.lock(|info| info.set(None));
+    }
+}
+
+/// Indices of `networks` in the order to try them: the ones a scan found first, strongest first, then the rest
+/// in their configured order since a hidden network never shows up. A single network is tried without a scan.
+pub async fn join_order(ctx: &Context, networks: &[Credentials]) -> Vec<usize, MAX_NETWORKS> {
+    let mut strongest: [Option<i16>; MAX_NETWORKS] = [None; MAX_NETWORKS];
+
+    if networks.len() > 1 {
+        let mut control = ctx.control.lock().await;
+        let mut scanner = control.scan(ScanOptions::default()).await;
+        let scanned = with_timeout(FULL_SCAN_TIMEOUT, async {
+            while let Some(bss) = scanner.next().await {
+                let rssi_dbm = bss.rssi;
+                let ssid = bss.ssid.get(..bss.ssid_len as usize).unwrap_or(&[]);
+                if let Some(index) = networks.iter().position(|network| network.ssid.as_bytes() == ssid) {
+                    if strongest[index].is_none_or(|best| rssi_dbm > best) {
+                        strongest[index] = Some(rssi_dbm);
+                    }
+                }
+            }
+        }).await;
+        if scanned.is_err() {
+            log::warn!("Network scan timed out");
+        }
+    }
+
+    let mut order: Vec<usize, MAX_NETWORKS> = (0..networks.len()).collect();
+    // Ties, like all the networks the scan missed, keep their configured order
+    order.sort_unstable_by_key(|index| (core::cmp::Reverse(strongest[*index].map_or(i32::MIN, i32::from)), *index));
+    order
+}
+
+/// Latest measurement, `None` before the first one or once it is stale
+pub fn link_info() -> Option<LinkInfo> {
+    LINK_INFO.lock(Cell::get).filter(|info| info.measured.elapsed() < LINK_INFO_STALE_AFTER)
+}
+
+/// Measures the link by scanning for the joined SSID, the strongest access point with it is taken
+#[embassy_executor::task]
+pub async fn link_info_task(ctx: &'static Context) -> ! {
+    loop {
+        Timer::after(LINK_INFO_INTERVAL).await;
+        let Some(ssid) = joined_ssid() else {
+            continue;
+        };
+
+        let mut options = ScanOptions::default();
+        options.ssid = Some(ssid.clone());
+        options.scan_type = ScanType::Active;
+
+        let mut control = ctx.control.lock().await;
+        let mut scanner = control.scan(options).await;
+        let mut strongest: Option<LinkInfo> = None;
+        // The scanner has to be drained to the end, it only then stops listening for results
+        let scanned = with_timeout(SCAN_TIMEOUT, async {
+            while let Some(bss) = scanner.next().await {
+                let rssi_dbm = bss.rssi;
+                if strongest.is_none_or(|best| rssi_dbm > best.rssi_dbm) {
+                    strongest = Some(LinkInfo {
+                        rssi_dbm,
+                        bssid: bss.bssid,
+                        channel: (bss.chanspec & 0xff) as u8,
+                        measured: Instant::now(),
+                    });
+                }
+            }
+        }).await;
+        drop(scanner);
+        drop(control);
+
+        match (scanned, strongest) {
+            (Ok(()), Some(info)) => LINK_INFO.lock(|current| current.set(Some(info))),
+            (Ok(()), None) => log::warn!("Link query found no access point for {}", ssid),
+            (Err(_), _) => log::warn!("Link query timed out"),
+        }
+    }
+}
+
+/// LED pattern for a failed join, status 1 is what a wrong password ends with
+fn join_failure(status: u32) -> LinkStatus {
+    match status {
+        1 => LinkStatus::AuthFailed,
+        3 => LinkStatus::NoNetwork,
+        _ => LinkStatus::JoinFailed,
+    }
+}
+
+/// Join one of the networks, retrying with exponential backoff until it works or `attempts` rounds failed, then
+/// wait for DHCP unless the address is static. Every round tries each network once, the visible ones first. The
+/// control lock is only held for each attempt so the LED task can show why joining fails. Returns whether a network
+/// was joined.
+pub async fn connect_wifi(ctx: &Context, stack: Stack<'static>, networks: &[Credentials], use_dhcp: bool, attempts: Option<u32>) -> bool {
+    let mut backoff = JOIN_BACKOFF_MIN;
+    let mut failed = 0;
+    led::set_link_status(LinkStatus::Joining);
+
+    // Connecting to the Network
+    'join: loop {
+        for index in join_order(ctx, networks).await {
+            let network = &networks[index];
+            let options = match network.password.as_str() {
+                "" => JoinOptions::new_open(),
+                password => JoinOptions::new(password.as_bytes()),
+            };
+            log::info!("Joining {}, attempt {}", network.ssid, failed + 1);
+            let joined = ctx.control.lock().await.join(&network.ssid, options).await;
+            // A failed join still went through the runner
+            watchdog::check_in(Subsystem::Radio);
+            match joined {
+                Ok(_) => {
+                    log::info!("Joined {}", network.ssid);
+                    set_joined_ssid(Some(&network.ssid));
+                    Timer::after_millis(100).await;
+                    break 'join
+                },
+                Err(err) => {
+                    log::warn!("Joining {} failed (attempt {}): {}", network.ssid, failed + 1, join_error_message(err.status));
+                    led::set_link_status(join_failure(err.status));
+                }
+            }
+        }
+
+        failed += 1;
+        if attempts.is_some_and(|attempts| failed >= attempts) {
+            return false;
+        }
+
+        // Up to a quarter more so several devices don't retry in lockstep after a power cut
+        let jitter = Duration::from_millis(RoscRng.next_u32() as u64 % (backoff.as_millis() / 4 + 1));
+        log::info!("Retrying in {} ms", (backoff + jitter).as_millis());
+        watchdog::idle(Subsystem::Radio, Timer::after(backoff + jitter)).await;
+        backoff = (backoff * 2).min(JOIN_BACKOFF_MAX);
+    }
+
+    // Wait for DHCP, not necessary when using static IP
+    if use_dhcp {
+        info!("Waiting for DHCP...");
+        // A missing DHCP server is no stall of the radio
+        watchdog::idle(Subsystem::Radio, async {
+            while !stack.is_config_up() {
+                Timer::after_millis(100).await;
+            }
+        }).await;
+        log::info!("DHCP is Now Up!");
+    }
+    led::set_link_status(LinkStatus::Connected);
+
+    match stack.config_v4(){
+        Some(value) => {
+            log::info!("Server Address: {:?}", value.address.address());
+            Timer::after_millis(100).await;
+        },
+        None => log::warn!("Unable to Get the Adrress")
+    }
+
+    true
+}
+
+/// Start the setup access point with its own address and DHCP server, the router then only serves the Wi-Fi form
+pub async fn start_setup(spawner: Spawner, ctx: &'static Context, stack: Stack<'static>) {
+    log::warn!("Starting the setup access point {}", SETUP_AP_SSID);
+    // Nothing probes the radio while it only serves the access point
+    watchdog::release(Subsystem::Radio);
+    ctx.control.lock().await.start_ap_wpa2(SETUP_AP_SSID, SETUP_AP_PASSWORD, SETUP_AP_CHANNEL).await;
+    stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(SETUP_AP_ADDRESS, 24),
+        gateway: None,
+        dns_servers: heapless::Vec::new(),
+    }));
+    ctx.setup_mode.store(true, Ordering::Relaxed);
+    led::set_link_status(LinkStatus::Setup);
+    unwrap!(spawner.spawn(dhcp_server::dhcp_server_task(stack, SETUP_AP_ADDRESS)));
+    log::info!("Setup page at http://{}/", SETUP_AP_ADDRESS);
+}
+
+/// Asks `wifi_task` to leave and join again even though the link looks fine
+static REJOIN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Leave the network and join it again right away, e.g. to switch to a stronger access point
+pub fn request_rejoin() {
+    REJOIN.signal(());
+}
+
+/// Rejoins the network after the link dropped or `request_rejoin` asked for it, e.g. when the access point rebooted, and reports address changes,
+/// e.g. a new DHCP lease after the router rebooted
+#[embassy_executor::task]
+pub async fn wifi_task(stack: Stack<'static>, ctx: &'static Context, networks: &'static Networks, use_dhcp: bool) -> ! {
+    let mut address = stack.config_v4().map(|config| config.address.address());
+
+    loop {
+        let requested = select(Timer::after(LINK_CHECK_INTERVAL), REJOIN.wait()).await.is_second();
+        // Any call goes through the runner, answering one shows it still turns
+        ctx.control.lock().await.address().await;
+        watchdog::check_in(Subsystem::Radio);
+
+        if requested || !stack.is_link_up() {
+            if requested {
+                log::info!("Rejoining on request");
+            } else {
+                log::warn!("Wi-Fi link lost, rejoining");
+            }
+            set_joined_ssid(None);
+            ctx.control.lock().await.leave().await;
+            connect_wifi(ctx, stack, networks, use_dhcp, None).await;
+            log::info!("Wi-Fi link restored");
+        }
+
+        let current = stack.config_v4().map(|config| config.address.address());
+        if current == address {
+            continue;
+        }
+        match current {
+            Some(current) => {
+                log::info!("Network address changed from {:?} to {}", address, current);
+                led::set_link_status(LinkStatus::Connected);
+            },
+            None => {
+                log::warn!("Network configuration of {:?} lost, waiting for the network", address);
+                led::set_link_status(LinkStatus::WaitingForNetwork);
+            },
+        }
+        address = current;
+        ADDRESS_CHANGES.sender().send(current);
+    }
+}